@@ -1,5 +1,7 @@
 // Copyright x39
 
 pub mod parser;
-mod parser_string;
-pub mod compiler;
\ No newline at end of file
+pub mod parser_string;
+pub mod checker;
+pub mod compiler;
+pub mod formatter;
\ No newline at end of file
@@ -0,0 +1,860 @@
+use std::fmt::{Display, Formatter};
+use tracing::trace;
+
+use crate::assembler::parser::parser::{AssignmentStatement, AssignmentType, AssignStatementData, AwaitCallOrIdentProduction, AwaitStatement, Call, CallValue, DeclarationStatement, DestructuringStatement, ElseStatement, ForLoopInstruction, ForLoopStatement, IfElseStatement, IfStatementCondition, Located, LocatedStatement, Statement, TypeAnnotation, Value, X39File};
+
+/// The signature of a function callable from scripts, as declared in the function manifest
+/// or reported by the function hosts.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FunctionSignature {
+    pub name: String,
+    /// The types of the arguments, in order.
+    pub parameters: Vec<TypeAnnotation>,
+    /// The names of the arguments, in order, empty if the function does not advertise them.
+    pub parameter_names: Vec<String>,
+    /// How many of the leading parameters must be passed, the others being optional.
+    pub arguments_required: usize,
+    pub result: TypeAnnotation,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CheckError {
+    /// A value was stored in a variable annotated with an incompatible type.
+    TypeMismatch { name: String, expected: TypeAnnotation, found: TypeAnnotation },
+    /// A value that is not a job was awaited or aborted.
+    NotAJob { name: String, found: TypeAnnotation },
+    /// `await all`, `await any` or `abort all` was used on a value that is not an array of jobs.
+    NotAnArrayOfJobs { name: String, found: TypeAnnotation },
+    /// The condition of an `if` is not a boolean.
+    NotABoolean { found: TypeAnnotation },
+    /// A `for` loop iterates over a value that is neither an array nor an object.
+    NotIterable { found: TypeAnnotation },
+    /// `+=` was used with values that cannot be added.
+    InvalidAddition { name: String, left: TypeAnnotation, right: TypeAnnotation },
+    /// A function was passed an argument not matching its signature.
+    ArgumentMismatch { function: String, expected: TypeAnnotation, found: TypeAnnotation },
+    /// A function was called that no signature is known for, only reported by [check_strict].
+    UnknownFunction { function: String },
+    /// A function was passed fewer or more arguments than its signature allows.
+    ArityMismatch { function: String, required: usize, allowed: usize, found: usize },
+    /// A value that is not an array was destructured.
+    NotDestructurable { found: TypeAnnotation },
+    /// A function was passed an argument by a name its signature does not declare.
+    UnknownArgument { function: String, name: String },
+    /// A required argument of a function was neither passed by position nor by name.
+    MissingArgument { function: String, name: String },
+    /// An argument was passed more than once, by name or by position and name.
+    DuplicateArgument { function: String, name: String },
+    /// Arguments were passed by name to a function routing calls to versions declaring
+    /// different parameters, which would bind them differently depending on the version.
+    AmbiguousArguments { function: String },
+}
+
+impl Display for CheckError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckError::TypeMismatch { name, expected, found } =>
+                write!(f, "Cannot store {} in '{}' which is annotated as {}", found, name, expected),
+            CheckError::NotAJob { name, found } =>
+                write!(f, "'{}' is {} but a job was expected", name, found),
+            CheckError::NotAnArrayOfJobs { name, found } =>
+                write!(f, "'{}' is {} but an array of jobs was expected", name, found),
+            CheckError::NotABoolean { found } =>
+                write!(f, "Condition is {} but a boolean was expected", found),
+            CheckError::NotIterable { found } =>
+                write!(f, "Cannot iterate over {}", found),
+            CheckError::InvalidAddition { name, left, right } =>
+                write!(f, "Cannot add {} to '{}' which is {}", right, name, left),
+            CheckError::ArgumentMismatch { function, expected, found } =>
+                write!(f, "Function '{}' expects {} but was passed {}", function, expected, found),
+            CheckError::UnknownFunction { function } =>
+                write!(f, "Function '{}' is not provided by any function host", function),
+            CheckError::ArityMismatch { function, required, allowed, found } if required == allowed =>
+                write!(f, "Function '{}' expects {} argument(s) but was passed {}", function, required, found),
+            CheckError::ArityMismatch { function, required, allowed, found } =>
+                write!(f, "Function '{}' expects {} to {} arguments but was passed {}", function, required, allowed, found),
+            CheckError::NotDestructurable { found } =>
+                write!(f, "Cannot destructure {}, an array was expected", found),
+            CheckError::UnknownArgument { function, name } =>
+                write!(f, "Function '{}' has no argument named '{}'", function, name),
+            CheckError::MissingArgument { function, name } =>
+                write!(f, "Function '{}' requires the argument '{}'", function, name),
+            CheckError::DuplicateArgument { function, name } =>
+                write!(f, "Function '{}' was passed the argument '{}' more than once", function, name),
+            CheckError::AmbiguousArguments { function } =>
+                write!(f, "Function '{}' routes calls to versions with different parameters, select a version to pass arguments by name", function),
+        }
+    }
+}
+
+impl std::error::Error for CheckError {}
+
+struct Variable {
+    name: String,
+    value_type: TypeAnnotation,
+    annotated: bool,
+}
+
+struct Checker<'s> {
+    signatures: &'s [FunctionSignature],
+    /// Whether the signatures are complete, every other function being unknown.
+    strict: bool,
+    frames: Vec<Vec<Variable>>,
+    /// The statement being checked, locating the errors found, see [Located].
+    statement: usize,
+    errors: Vec<Located<CheckError>>,
+}
+
+/// Checks the types of a file before it is compiled, inferring the types of variables through
+/// assignments, awaits and loops. Only misuse that is certain is reported, values of unknown
+/// type (`any`) are checked at runtime instead.
+///
+/// Undeclared variables are not reported, as that is done by the compiler.
+pub fn check(file: &X39File, signatures: &[FunctionSignature]) -> Result<(), Vec<CheckError>> {
+    check_located(file, signatures, false).map_err(without_location)
+}
+
+/// Checks the file like [check], treating the signatures as complete manifest of the functions
+/// available, reporting calls of any other function.
+pub fn check_strict(file: &X39File, signatures: &[FunctionSignature]) -> Result<(), Vec<CheckError>> {
+    check_located(file, signatures, true).map_err(without_location)
+}
+
+/// Checks the file like [check] or, if `strict`, like [check_strict], locating every error
+/// in the statement it was found in.
+pub fn check_located(file: &X39File, signatures: &[FunctionSignature], strict: bool) -> Result<(), Vec<Located<CheckError>>> {
+    let mut checker = Checker {
+        signatures,
+        strict,
+        frames: vec!(vec!()),
+        statement: 0,
+        errors: vec!(),
+    };
+    trace!("Entering check with {} statements", file.statements.len());
+    checker.check_statements(&file.statements);
+    trace!("Exiting check with {} errors", checker.errors.len());
+    match checker.errors.is_empty() {
+        true => Ok(()),
+        false => Err(checker.errors),
+    }
+}
+
+fn without_location(errors: Vec<Located<CheckError>>) -> Vec<CheckError> {
+    errors.into_iter().map(|it| it.error).collect()
+}
+
+/// The name of the function called, without the version or alias it may select, eg.
+/// `resize` for `resize@stable`.
+pub fn function_name(ident: &str) -> &str {
+    ident.split_once('@').map_or(ident, |(name, _)| name)
+}
+
+/// The signatures a call is checked against, being those of the version or alias it selects,
+/// eg. `resize@1`, if declared, and those of the function otherwise. Calls not selecting a
+/// version may be routed to several versions, each having its own signature.
+pub fn signatures_of<'s>(signatures: &'s [FunctionSignature], ident: &str) -> Vec<&'s FunctionSignature> {
+    let selected: Vec<&FunctionSignature> = signatures.iter().filter(|it| it.name == ident).collect();
+    if !selected.is_empty() {
+        return selected;
+    }
+    signatures.iter().filter(|it| it.name == function_name(ident)).collect()
+}
+
+/// Whether the signatures bind named arguments differently, see [CheckError::AmbiguousArguments].
+pub fn is_binding_ambiguous(signatures: &[&FunctionSignature]) -> bool {
+    signatures.iter().any(|it| it.parameter_names != signatures[0].parameter_names)
+}
+
+/// Maps the named arguments of a call onto the positions declared by the signature, which
+/// must advertise its parameter names. Optional arguments skipped are `None`, to be passed
+/// as `null`, while trailing ones are omitted.
+pub fn bind_arguments<'c, 'a>(call: &'c Call<'a>, signature: &FunctionSignature) -> Result<Vec<Option<&'c CallValue<'a>>>, CheckError> {
+    let mut bound: Vec<Option<&CallValue>> = call.arguments.iter().map(Some).collect();
+    for named_argument in call.named_arguments.iter() {
+        let index = signature.parameter_names.iter()
+            .position(|it| it == named_argument.name)
+            .ok_or_else(|| CheckError::UnknownArgument {
+                function: call.ident.to_string(),
+                name: named_argument.name.to_string(),
+            })?;
+        if bound.len() <= index {
+            bound.resize(index + 1, None);
+        }
+        if bound[index].is_some() {
+            return Err(CheckError::DuplicateArgument {
+                function: call.ident.to_string(),
+                name: named_argument.name.to_string(),
+            });
+        }
+        bound[index] = Some(&named_argument.value);
+    }
+    let required = signature.arguments_required.min(signature.parameter_names.len());
+    if let Some(index) = (0..required).find(|index| bound.get(*index).is_none_or(|it| it.is_none())) {
+        return Err(CheckError::MissingArgument {
+            function: call.ident.to_string(),
+            name: signature.parameter_names[index].clone(),
+        });
+    }
+    Ok(bound)
+}
+
+/// Whether a value of type `found` may be stored where `expected` is required.
+fn is_assignable(found: &TypeAnnotation, expected: &TypeAnnotation) -> bool {
+    match (found, expected) {
+        (_, TypeAnnotation::Any) | (TypeAnnotation::Any, _) => true,
+        (TypeAnnotation::Array(found), TypeAnnotation::Array(expected)) => is_assignable(found, expected),
+        (TypeAnnotation::Job(found), TypeAnnotation::Job(expected)) => is_assignable(found, expected),
+        (found, expected) => found == expected,
+    }
+}
+
+/// The most specific type covering both types provided.
+fn join(left: &TypeAnnotation, right: &TypeAnnotation) -> TypeAnnotation {
+    match (left, right) {
+        (TypeAnnotation::Array(left), TypeAnnotation::Array(right)) => TypeAnnotation::Array(Box::new(join(left, right))),
+        (TypeAnnotation::Job(left), TypeAnnotation::Job(right)) => TypeAnnotation::Job(Box::new(join(left, right))),
+        (left, right) if left == right => left.clone(),
+        _ => TypeAnnotation::Any,
+    }
+}
+
+/// The type resulting from `left += right`, following `VmValue::add_assign`.
+fn add(left: &TypeAnnotation, right: &TypeAnnotation) -> Option<TypeAnnotation> {
+    match (left, right) {
+        (TypeAnnotation::Any, _) => Some(TypeAnnotation::Any),
+        (TypeAnnotation::Array(element), right) => Some(TypeAnnotation::Array(Box::new(join(element, right)))),
+        (TypeAnnotation::Integer, TypeAnnotation::Any) => Some(TypeAnnotation::Any),
+        (left, TypeAnnotation::Any) => match left {
+            TypeAnnotation::String | TypeAnnotation::Bytes | TypeAnnotation::Number
+            | TypeAnnotation::Timestamp => Some(left.clone()),
+            _ => None,
+        },
+        (TypeAnnotation::String, TypeAnnotation::String) => Some(TypeAnnotation::String),
+        (TypeAnnotation::Bytes, TypeAnnotation::Bytes) => Some(TypeAnnotation::Bytes),
+        (TypeAnnotation::Integer, TypeAnnotation::Integer) => Some(TypeAnnotation::Integer),
+        (TypeAnnotation::Integer | TypeAnnotation::Number, TypeAnnotation::Integer | TypeAnnotation::Number) => Some(TypeAnnotation::Number),
+        (TypeAnnotation::Timestamp, TypeAnnotation::Integer) => Some(TypeAnnotation::Timestamp),
+        _ => None,
+    }
+}
+
+fn value_type(value: &Value) -> TypeAnnotation {
+    match value {
+        Value::NumericRange(_) => TypeAnnotation::Array(Box::new(TypeAnnotation::Integer)),
+        Value::Number(_) => TypeAnnotation::Number,
+        Value::Integer(_) => TypeAnnotation::Integer,
+        Value::Null => TypeAnnotation::Null,
+        Value::String(_) => TypeAnnotation::String,
+        Value::Bytes(_) => TypeAnnotation::Bytes,
+        Value::Timestamp(_) => TypeAnnotation::Timestamp,
+        Value::Boolean(_) => TypeAnnotation::Boolean,
+        Value::Object(_) => TypeAnnotation::Object,
+        Value::Array(array) => {
+            let element = array.iter()
+                .map(value_type)
+                .reduce(|left, right| join(&left, &right))
+                .unwrap_or(TypeAnnotation::Any);
+            TypeAnnotation::Array(Box::new(element))
+        }
+    }
+}
+
+fn is_array_of_jobs(value_type: &TypeAnnotation) -> bool {
+    match value_type {
+        TypeAnnotation::Any => true,
+        TypeAnnotation::Array(element) => matches!(element.as_ref(), TypeAnnotation::Any | TypeAnnotation::Job(_)),
+        _ => false,
+    }
+}
+
+impl<'s> Checker<'s> {
+    fn lookup(&self, name: &str) -> Option<&Variable> {
+        self.frames.iter().rev()
+            .flat_map(|frame| frame.iter().rev())
+            .find(|variable| variable.name == name)
+    }
+    fn lookup_mut(&mut self, name: &str) -> Option<&mut Variable> {
+        self.frames.iter_mut().rev()
+            .flat_map(|frame| frame.iter_mut().rev())
+            .find(|variable| variable.name == name)
+    }
+    fn type_of(&self, name: &str) -> TypeAnnotation {
+        match self.lookup(name) {
+            Some(variable) => variable.value_type.clone(),
+            None => TypeAnnotation::Any,
+        }
+    }
+    fn declare(&mut self, name: &str, value_type: TypeAnnotation, annotated: bool) {
+        let frame = self.frames.last_mut().expect("The root scope is never popped");
+        frame.push(Variable { name: name.to_string(), value_type, annotated });
+    }
+    fn declare_annotated(&mut self, name: &str, annotation: &TypeAnnotation, found: TypeAnnotation) {
+        if !is_assignable(&found, annotation) {
+            self.error(CheckError::TypeMismatch {
+                name: name.to_string(),
+                expected: annotation.clone(),
+                found,
+            });
+        }
+        self.declare(name, annotation.clone(), true);
+    }
+
+    fn error(&mut self, error: CheckError) {
+        self.errors.push(Located { remaining: self.statement, error });
+    }
+
+    fn check_statements(&mut self, statements: &[LocatedStatement]) {
+        let enclosing = self.statement;
+        for statement in statements {
+            self.statement = statement.remaining;
+            match &statement.statement {
+                Statement::Await(await_statement) => self.check_await(await_statement),
+                Statement::Abort(ident) => {
+                    let found = self.type_of(ident);
+                    if !is_assignable(&found, &TypeAnnotation::Job(Box::new(TypeAnnotation::Any))) {
+                        self.error(CheckError::NotAJob { name: ident.to_string(), found });
+                    }
+                }
+                Statement::AbortAll(ident) => self.check_array_of_jobs(ident),
+                Statement::Exit => {}
+                Statement::Comment(_) => {}
+                Statement::Start(call) => { self.check_call(call); }
+                Statement::IfElse(if_else_statement) => self.check_if_else(if_else_statement),
+                Statement::ForLoop(for_loop_statement) => self.check_for_loop(for_loop_statement),
+                Statement::Assignment(assignment_statement) => self.check_assignment(assignment_statement),
+                Statement::Declaration(declaration_statement) => self.check_declaration(declaration_statement),
+                Statement::Destructuring(destructuring_statement) => self.check_destructuring(destructuring_statement),
+                Statement::Print(_) => {}
+            }
+        }
+        // Errors found after a block belong to the statement enclosing it
+        self.statement = enclosing;
+    }
+
+    fn check_block(&mut self, statements: &[LocatedStatement]) {
+        self.frames.push(vec!());
+        self.check_statements(statements);
+        self.frames.pop();
+    }
+
+    fn check_await(&mut self, await_statement: &AwaitStatement) {
+        match await_statement {
+            AwaitStatement::AwaitAny(ident) => self.check_array_of_jobs(ident),
+            AwaitStatement::AwaitAll(ident) => self.check_array_of_jobs(ident),
+            AwaitStatement::AwaitCallOrIdent(await_call_or_ident) => { self.check_await_call_or_ident(await_call_or_ident); }
+        }
+    }
+
+    fn check_array_of_jobs(&mut self, ident: &str) {
+        let found = self.type_of(ident);
+        if !is_array_of_jobs(&found) {
+            self.error(CheckError::NotAnArrayOfJobs { name: ident.to_string(), found });
+        }
+    }
+
+    fn call_value_type(&self, call_value: &CallValue) -> TypeAnnotation {
+        match call_value {
+            CallValue::Ident(ident) => self.type_of(ident),
+            CallValue::Value(value) => value_type(value),
+        }
+    }
+
+    /// Checks the call and returns the type of the job it creates.
+    fn check_call(&mut self, call: &Call) -> TypeAnnotation {
+        let signatures = signatures_of(self.signatures, call.ident);
+        if signatures.is_empty() {
+            if self.strict {
+                self.error(CheckError::UnknownFunction { function: call.ident.to_string() });
+            }
+            return TypeAnnotation::Job(Box::new(TypeAnnotation::Any));
+        }
+        if !call.named_arguments.is_empty() && is_binding_ambiguous(&signatures) {
+            self.error(CheckError::AmbiguousArguments { function: call.ident.to_string() });
+            return TypeAnnotation::Job(Box::new(TypeAnnotation::Any));
+        }
+        // The call must be valid for every version it may be routed to
+        let first_error = self.errors.len();
+        let mut result: Option<TypeAnnotation> = None;
+        for signature in signatures {
+            let checked = self.check_call_against(call, signature);
+            result = Some(match result {
+                Some(result) => join(&result, &checked),
+                None => checked,
+            });
+        }
+        for error in self.errors.split_off(first_error) {
+            if !self.errors[first_error..].contains(&error) {
+                self.errors.push(error);
+            }
+        }
+        TypeAnnotation::Job(Box::new(result.unwrap_or(TypeAnnotation::Any)))
+    }
+
+    /// Checks the call against the signature provided and returns the type of its result.
+    fn check_call_against(&mut self, call: &Call, signature: &FunctionSignature) -> TypeAnnotation {
+        let arguments: Vec<TypeAnnotation> = match !call.named_arguments.is_empty() && !signature.parameter_names.is_empty() {
+            true => match bind_arguments(call, signature) {
+                Ok(bound) => bound.into_iter()
+                    .map(|argument| argument.map_or(TypeAnnotation::Any, |it| self.call_value_type(it)))
+                    .collect(),
+                Err(error) => {
+                    self.error(error);
+                    return signature.result.clone();
+                }
+            },
+            false => {
+                let mut arguments: Vec<TypeAnnotation> = call.arguments.iter()
+                    .map(|argument| self.call_value_type(argument))
+                    .collect();
+                // Named arguments are passed as a single object without advertised names
+                if !call.named_arguments.is_empty() {
+                    arguments.push(TypeAnnotation::Object);
+                }
+                arguments
+            }
+        };
+        let found = arguments.len();
+        if found < signature.arguments_required || found > signature.parameters.len() {
+            self.error(CheckError::ArityMismatch {
+                function: call.ident.to_string(),
+                required: signature.arguments_required,
+                allowed: signature.parameters.len(),
+                found,
+            });
+        }
+        for (expected, found) in signature.parameters.iter().zip(arguments) {
+            if !is_assignable(&found, expected) {
+                self.error(CheckError::ArgumentMismatch {
+                    function: call.ident.to_string(),
+                    expected: expected.clone(),
+                    found,
+                });
+            }
+        }
+        signature.result.clone()
+    }
+
+    /// Checks the await and returns the type of the awaited result.
+    fn check_await_call_or_ident(&mut self, await_call_or_ident: &AwaitCallOrIdentProduction) -> TypeAnnotation {
+        let (name, job) = match await_call_or_ident {
+            AwaitCallOrIdentProduction::Call(call) => (call.ident, self.check_call(call)),
+            AwaitCallOrIdentProduction::Ident(ident) => (*ident, self.type_of(ident)),
+        };
+        match job {
+            TypeAnnotation::Any => TypeAnnotation::Any,
+            TypeAnnotation::Job(result) => *result,
+            found => {
+                self.error(CheckError::NotAJob { name: name.to_string(), found });
+                TypeAnnotation::Any
+            }
+        }
+    }
+
+    fn check_assign_statement_data(&mut self, data: &AssignStatementData) -> TypeAnnotation {
+        match data {
+            AssignStatementData::Value(value) => value_type(value),
+            AssignStatementData::Ident(ident) => self.type_of(ident),
+            AssignStatementData::Await(await_call_or_ident) => self.check_await_call_or_ident(await_call_or_ident),
+            AssignStatementData::Start(call) => self.check_call(call),
+        }
+    }
+
+    fn check_if_else(&mut self, if_else_statement: &IfElseStatement) {
+        let found = match &if_else_statement.if_statement.condition {
+            IfStatementCondition::Await(await_call_or_ident) => self.check_await_call_or_ident(await_call_or_ident),
+            IfStatementCondition::Ident(ident) => self.type_of(ident),
+        };
+        if !is_assignable(&found, &TypeAnnotation::Boolean) {
+            self.error(CheckError::NotABoolean { found });
+        }
+        self.check_block(&if_else_statement.if_statement.code);
+        match &if_else_statement.else_statement {
+            None => {}
+            Some(ElseStatement::Code(code)) => self.check_block(code),
+            Some(ElseStatement::IfElse(if_else_statement)) => self.check_if_else(if_else_statement),
+        }
+    }
+
+    fn check_for_loop(&mut self, for_loop_statement: &ForLoopStatement) {
+        let over = match &for_loop_statement.over {
+            ForLoopInstruction::Ident(ident) => self.type_of(ident),
+            ForLoopInstruction::Await(await_call_or_ident) => self.check_await_call_or_ident(await_call_or_ident),
+            ForLoopInstruction::Stream(await_call_or_ident) => {
+                // Partial results are untyped, only the job itself is checked
+                self.check_await_call_or_ident(await_call_or_ident);
+                TypeAnnotation::Array(Box::new(TypeAnnotation::Any))
+            }
+            ForLoopInstruction::Value(value) => value_type(value),
+        };
+        let (key, element) = match over {
+            TypeAnnotation::Any => (TypeAnnotation::Any, TypeAnnotation::Any),
+            TypeAnnotation::Array(element) => (TypeAnnotation::Integer, *element),
+            TypeAnnotation::Object => (TypeAnnotation::String, TypeAnnotation::Any),
+            found => {
+                self.error(CheckError::NotIterable { found });
+                (TypeAnnotation::Any, TypeAnnotation::Any)
+            }
+        };
+        self.frames.push(vec!());
+        self.declare(for_loop_statement.ident, element, false);
+        if let Some(key_ident) = for_loop_statement.key {
+            self.declare(key_ident, key, false);
+        }
+        self.check_block(&for_loop_statement.code);
+        self.frames.pop();
+    }
+
+    fn check_declaration(&mut self, declaration_statement: &DeclarationStatement) {
+        let found = self.check_assign_statement_data(&declaration_statement.value);
+        match &declaration_statement.annotation {
+            Some(annotation) => self.declare_annotated(declaration_statement.ident, annotation, found),
+            None => self.declare(declaration_statement.ident, found, false),
+        }
+    }
+
+    fn check_assignment(&mut self, assignment_statement: &AssignmentStatement) {
+        let name = assignment_statement.ident;
+        match &assignment_statement.value {
+            AssignmentType::Assign(assign) => {
+                let found = self.check_assign_statement_data(assign);
+                if let Some(annotation) = &assignment_statement.annotation {
+                    self.declare_annotated(name, annotation, found);
+                    return;
+                }
+                self.assign(name, found);
+            }
+            AssignmentType::Append(append) => {
+                let right = self.check_assign_statement_data(append);
+                let (left, annotated) = match self.lookup(name) {
+                    Some(variable) => (variable.value_type.clone(), variable.annotated),
+                    None => return,
+                };
+                let error = match add(&left, &right) {
+                    None => Some(CheckError::InvalidAddition { name: name.to_string(), left, right }),
+                    Some(result) if annotated => match is_assignable(&result, &left) {
+                        true => None,
+                        false => Some(CheckError::TypeMismatch { name: name.to_string(), expected: left, found: result }),
+                    },
+                    Some(result) => {
+                        // Like assignments, the addition may happen conditionally
+                        if let Some(variable) = self.lookup_mut(name) {
+                            variable.value_type = join(&variable.value_type, &result);
+                        }
+                        None
+                    }
+                };
+                if let Some(error) = error {
+                    self.error(error);
+                }
+            }
+        }
+    }
+
+    fn check_destructuring(&mut self, destructuring_statement: &DestructuringStatement) {
+        let element = match self.check_assign_statement_data(&destructuring_statement.value) {
+            TypeAnnotation::Any => TypeAnnotation::Any,
+            TypeAnnotation::Array(element) => *element,
+            found => {
+                self.error(CheckError::NotDestructurable { found });
+                TypeAnnotation::Any
+            }
+        };
+        for ident in destructuring_statement.idents.iter() {
+            self.assign(ident, element.clone());
+        }
+    }
+
+    /// Stores a value in a variable without annotation, implicitly declaring it if unknown.
+    fn assign(&mut self, name: &str, found: TypeAnnotation) {
+        let mismatch = match self.lookup_mut(name) {
+            None => {
+                self.declare(name, found, false);
+                None
+            }
+            Some(variable) if variable.annotated => {
+                match is_assignable(&found, &variable.value_type) {
+                    true => None,
+                    false => Some(CheckError::TypeMismatch {
+                        name: name.to_string(),
+                        expected: variable.value_type.clone(),
+                        found,
+                    }),
+                }
+            }
+            Some(variable) => {
+                // Unannotated variables may hold different types over time
+                variable.value_type = join(&variable.value_type, &found);
+                None
+            }
+        };
+        if let Some(error) = mismatch {
+            self.error(error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_test::traced_test;
+    use crate::assembler::checker::{check, check_located, check_strict, CheckError, FunctionSignature};
+    use crate::assembler::parser::parser::TypeAnnotation;
+
+    fn check_str(input: &str, signatures: &[FunctionSignature]) -> Result<(), Vec<CheckError>> {
+        let (remainder, file) = crate::assembler::parser::parser::parse_x39file(input).unwrap();
+        assert!(remainder.is_empty(), "Failed to fully parse input: {:?}", remainder);
+        check(&file, signatures)
+    }
+
+    fn array_of(element: TypeAnnotation) -> TypeAnnotation {
+        TypeAnnotation::Array(Box::new(element))
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_await_all_on_non_job_array_errors() -> Result<(), Box<dyn std::error::Error>> {
+        let result = check_str("list = [1, 2]; await all list;", &[]);
+        assert_eq!(result, Err(vec![CheckError::NotAnArrayOfJobs {
+            name: "list".to_string(),
+            found: array_of(TypeAnnotation::Integer),
+        }]));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_errors_are_located_in_their_statement() -> Result<(), Box<dyn std::error::Error>> {
+        let input = "x: integer = 1;\nif true {\n    x = \"text\";\n}\nabort x;";
+        let (_, file) = crate::assembler::parser::parser::parse_x39file(input)?;
+        let errors = check_located(&file, &[], false).err().ok_or("Expected errors")?;
+        let positions: Vec<(usize, usize)> = errors.iter().map(|error| error.position_in(input)).collect();
+        assert_eq!(positions, vec!((3, 5), (5, 1)));
+        assert!(matches!(errors[1].error, CheckError::NotAJob { .. }));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_jobs_appended_in_loop_pass() -> Result<(), Box<dyn std::error::Error>> {
+        check_str(r#"
+            list: array<job> = [];
+            for it in 0..20 {
+                list += start handleIt(it);
+            }
+            await all list;
+            abort all list;
+            other = [];
+            other += start handleIt(1);
+            await any other;
+        "#, &[]).map_err(|errors| format!("{:?}", errors))?;
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_annotation_mismatch_errors() -> Result<(), Box<dyn std::error::Error>> {
+        let result = check_str(r#"let count: integer = "many"; count = 1.5;"#, &[]);
+        assert_eq!(result, Err(vec![
+            CheckError::TypeMismatch {
+                name: "count".to_string(),
+                expected: TypeAnnotation::Integer,
+                found: TypeAnnotation::String,
+            },
+            CheckError::TypeMismatch {
+                name: "count".to_string(),
+                expected: TypeAnnotation::Integer,
+                found: TypeAnnotation::Number,
+            },
+        ]));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_types_flow_through_signatures_and_awaits() -> Result<(), Box<dyn std::error::Error>> {
+        let signatures = [
+            FunctionSignature { name: "count".to_string(), parameters: vec!(TypeAnnotation::String), parameter_names: vec!(), arguments_required: 1, result: TypeAnnotation::Integer },
+            FunctionSignature { name: "isEven".to_string(), parameters: vec!(TypeAnnotation::Integer), parameter_names: vec!(), arguments_required: 1, result: TypeAnnotation::Boolean },
+        ];
+        check_str(r#"
+            let job: job<integer> = start count("abc");
+            let n = await job;
+            if await isEven(n) { exit; }
+        "#, &signatures).map_err(|errors| format!("{:?}", errors))?;
+
+        let result = check_str(r#"
+            let n = await count(1);
+            if n { exit; }
+            await n;
+            for it in n { }
+        "#, &signatures);
+        assert_eq!(result, Err(vec![
+            CheckError::ArgumentMismatch {
+                function: "count".to_string(),
+                expected: TypeAnnotation::String,
+                found: TypeAnnotation::Integer,
+            },
+            CheckError::NotABoolean { found: TypeAnnotation::Integer },
+            CheckError::NotAJob { name: "n".to_string(), found: TypeAnnotation::Integer },
+            CheckError::NotIterable { found: TypeAnnotation::Integer },
+        ]));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_loop_variables_take_element_type() -> Result<(), Box<dyn std::error::Error>> {
+        let result = check_str(r#"
+            let jobs: array<job<integer>> = [];
+            for i, it in jobs {
+                let result: string = await it;
+                await i;
+            }
+        "#, &[]);
+        assert_eq!(result, Err(vec![
+            CheckError::TypeMismatch {
+                name: "result".to_string(),
+                expected: TypeAnnotation::String,
+                found: TypeAnnotation::Integer,
+            },
+            CheckError::NotAJob {
+                name: "i".to_string(),
+                found: TypeAnnotation::Integer,
+            },
+        ]));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_invalid_addition_errors() -> Result<(), Box<dyn std::error::Error>> {
+        let result = check_str(r#"let flag = true; flag += 1; let n: integer = 1; n += 0.5;"#, &[]);
+        assert_eq!(result, Err(vec![
+            CheckError::InvalidAddition {
+                name: "flag".to_string(),
+                left: TypeAnnotation::Boolean,
+                right: TypeAnnotation::Integer,
+            },
+            CheckError::TypeMismatch {
+                name: "n".to_string(),
+                expected: TypeAnnotation::Integer,
+                found: TypeAnnotation::Number,
+            },
+        ]));
+        // Additions to unannotated variables may be conditional, keeping the types possible
+        assert_eq!(check_str("let f = false; let n = 1; if f { n += 0.5; } let m: integer = n;", &[]), Ok(()));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_unknown_functions_and_arity_errors() -> Result<(), Box<dyn std::error::Error>> {
+        let signatures = [
+            FunctionSignature { name: "now".to_string(), parameters: vec!(), parameter_names: vec!(), arguments_required: 0, result: TypeAnnotation::Timestamp },
+            FunctionSignature { name: "fetch".to_string(), parameters: vec!(TypeAnnotation::String), parameter_names: vec!(), arguments_required: 1, result: TypeAnnotation::Any },
+        ];
+        let input = r#"
+            a = await now();
+            b = start fetch("https://example.com");
+            c = start now(1);
+            d = await fetch();
+            e = start missing(1);
+        "#;
+        // Functions without signature may be provided by hosts connected later
+        assert_eq!(check_str(input, &signatures), Err(vec![
+            CheckError::ArityMismatch { function: "now".to_string(), required: 0, allowed: 0, found: 1 },
+            CheckError::ArityMismatch { function: "fetch".to_string(), required: 1, allowed: 1, found: 0 },
+        ]));
+        let (_, file) = crate::assembler::parser::parser::parse_x39file(input).unwrap();
+        assert_eq!(check_strict(&file, &signatures), Err(vec![
+            CheckError::ArityMismatch { function: "now".to_string(), required: 0, allowed: 0, found: 1 },
+            CheckError::ArityMismatch { function: "fetch".to_string(), required: 1, allowed: 1, found: 0 },
+            CheckError::UnknownFunction { function: "missing".to_string() },
+        ]));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_arguments_and_destructuring() -> Result<(), Box<dyn std::error::Error>> {
+        let signatures = [
+            FunctionSignature { name: "add".to_string(), parameters: vec!(TypeAnnotation::Integer, TypeAnnotation::Integer), parameter_names: vec!(), arguments_required: 2, result: TypeAnnotation::Integer },
+            FunctionSignature { name: "pair".to_string(), parameters: vec!(), parameter_names: vec!(), arguments_required: 0, result: array_of(TypeAnnotation::String) },
+        ];
+        assert_eq!(check_str("sum = await add(1, 2); [a, b] = await pair(); let c: string = a;", &signatures), Ok(()));
+        assert_eq!(check_str("sum = await add(1, \"two\"); [a, b] = sum; let c: integer = 1; [c] = await pair();", &signatures), Err(vec![
+            CheckError::ArgumentMismatch { function: "add".to_string(), expected: TypeAnnotation::Integer, found: TypeAnnotation::String },
+            CheckError::NotDestructurable { found: TypeAnnotation::Integer },
+            CheckError::TypeMismatch { name: "c".to_string(), expected: TypeAnnotation::Integer, found: TypeAnnotation::String },
+        ]));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_named_arguments() -> Result<(), Box<dyn std::error::Error>> {
+        let signatures = [
+            FunctionSignature {
+                name: "resize".to_string(),
+                parameters: vec!(TypeAnnotation::Bytes, TypeAnnotation::Integer, TypeAnnotation::String),
+                parameter_names: vec!("image".to_string(), "width".to_string(), "mode".to_string()),
+                arguments_required: 2,
+                result: TypeAnnotation::Bytes,
+            },
+            FunctionSignature { name: "configure".to_string(), parameters: vec!(TypeAnnotation::Object), parameter_names: vec!(), arguments_required: 1, result: TypeAnnotation::Null },
+        ];
+        let input = r#"
+            a = await resize(x"00", width: 640);
+            b = await resize(width: 640, image: x"00", mode: "fit");
+            c = await configure(verbose: true);
+        "#;
+        assert_eq!(check_str(input, &signatures), Ok(()));
+        assert_eq!(check_str(r#"
+            a = await resize(x"00", height: 480);
+            b = await resize(mode: "fit", image: x"00");
+            c = await resize(x"00", image: x"00", width: 1);
+            d = await resize(image: x"00", width: "wide");
+            e = await configure({}, verbose: true);
+        "#, &signatures), Err(vec![
+            CheckError::UnknownArgument { function: "resize".to_string(), name: "height".to_string() },
+            CheckError::MissingArgument { function: "resize".to_string(), name: "width".to_string() },
+            CheckError::DuplicateArgument { function: "resize".to_string(), name: "image".to_string() },
+            CheckError::ArgumentMismatch { function: "resize".to_string(), expected: TypeAnnotation::Integer, found: TypeAnnotation::String },
+            CheckError::ArityMismatch { function: "configure".to_string(), required: 1, allowed: 1, found: 2 },
+        ]));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_calls_are_checked_against_versions_routed_to() -> Result<(), Box<dyn std::error::Error>> {
+        let signature = |name: &str, parameter_names: [&str; 2], parameters: [TypeAnnotation; 2]| FunctionSignature {
+            name: name.to_string(),
+            parameters: parameters.to_vec(),
+            parameter_names: parameter_names.iter().map(|it| it.to_string()).collect(),
+            arguments_required: 2,
+            result: TypeAnnotation::Null,
+        };
+        // Calls not selecting a version may be routed to either version
+        let signatures = [
+            signature("resize", ["image", "width"], [TypeAnnotation::String, TypeAnnotation::Integer]),
+            signature("resize", ["width", "image"], [TypeAnnotation::Integer, TypeAnnotation::String]),
+            signature("resize@1", ["width", "image"], [TypeAnnotation::Integer, TypeAnnotation::String]),
+        ];
+        assert_eq!(check_str(r#"
+            a = await resize@1(image: "a", width: 1);
+            b = await resize@1(1, "b");
+        "#, &signatures), Ok(()));
+        assert_eq!(check_str(r#"
+            a = await resize(image: "a", width: 1);
+            b = await resize@1("b", 1);
+            c = await resize(1, "c");
+        "#, &signatures), Err(vec![
+            CheckError::AmbiguousArguments { function: "resize".to_string() },
+            CheckError::ArgumentMismatch { function: "resize@1".to_string(), expected: TypeAnnotation::Integer, found: TypeAnnotation::String },
+            CheckError::ArgumentMismatch { function: "resize@1".to_string(), expected: TypeAnnotation::String, found: TypeAnnotation::Integer },
+            CheckError::ArgumentMismatch { function: "resize".to_string(), expected: TypeAnnotation::String, found: TypeAnnotation::Integer },
+            CheckError::ArgumentMismatch { function: "resize".to_string(), expected: TypeAnnotation::Integer, found: TypeAnnotation::String },
+        ]));
+        Ok(())
+    }
+}
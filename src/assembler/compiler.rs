@@ -1,126 +1,372 @@
 pub mod compiler {
     use std::borrow::{Borrow, BorrowMut};
+    use std::fmt::{Display, Formatter};
     use tracing::trace;
 
-    use crate::assembler::parser::parser::{AssignmentStatement, AssignmentType, AssignStatementData, AwaitCallOrIdentProduction, AwaitStatement, Call, CallValue, ElseStatement, ForLoopInstruction, ForLoopStatement, IfElseStatement, IfStatementCondition, NumericRange, Property, Statement, Value, X39File};
-    use crate::machine::{Instruction, InstructionArg, VmState, VmValue, VmValueType};
+    use crate::assembler::checker::{bind_arguments, check_strict, is_binding_ambiguous, signatures_of, CheckError, FunctionSignature};
+    use crate::assembler::parser::parser::{AssignmentStatement, AssignmentType, AssignStatementData, AwaitCallOrIdentProduction, AwaitStatement, Call, CallValue, DeclarationKind, DeclarationStatement, DestructuringStatement, ElseStatement, ForLoopInstruction, ForLoopStatement, IfElseStatement, IfStatementCondition, Located, LocatedStatement, NamedArgument, NumericRange, Property, Statement, Value, X39File};
+    use crate::machine::{Instruction, InstructionArg, VmLineInfo, VmLocalInfo, VmState, VmValue, VmValueType};
 
+    #[derive(Debug, PartialEq)]
+    pub enum CompileError {
+        /// A variable was read before it was declared in any visible scope.
+        UndeclaredVariable(String),
+        /// A variable declared using `const` was assigned to.
+        ConstReassignment(String),
+        /// A variable was declared twice in the same scope.
+        AlreadyDeclared(String),
+        /// An object literal contains the same key more than once.
+        DuplicateKey(String),
+        /// A `for` loop over a stream declares a key, which streams do not provide.
+        KeyedStream(String),
+        /// The file does not match the signatures of the functions available, see [compile_checked].
+        Check(Vec<CheckError>),
+    }
+
+    impl Display for CompileError {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            match self {
+                CompileError::UndeclaredVariable(name) => write!(f, "Variable '{}' is not declared", name),
+                CompileError::ConstReassignment(name) => write!(f, "Cannot assign to '{}' because it is a constant", name),
+                CompileError::AlreadyDeclared(name) => write!(f, "Variable '{}' is already declared in this scope", name),
+                CompileError::DuplicateKey(key) => write!(f, "Object literal contains the key '{}' more than once", key),
+                CompileError::KeyedStream(key) => write!(f, "Streams cannot be iterated with a key, but '{}' was declared", key),
+                CompileError::Check(errors) => {
+                    let messages: Vec<String> = errors.iter().map(|it| it.to_string()).collect();
+                    write!(f, "{}", messages.join("\n"))
+                }
+            }
+        }
+    }
+
+    impl std::error::Error for CompileError {}
+
+    struct Symbol {
+        name: String,
+        constant: bool,
+        /// The runtime type every value stored in the variable is asserted to have.
+        value_type: Option<VmValueType>,
+        slot: u16,
+        from: usize,
+    }
+
+    /// Compile-time view on the variable scopes, resolving every variable to a local slot.
+    /// Slots of closed scopes are reused by later declarations.
+    struct Scopes<'s> {
+        frames: Vec<Vec<Symbol>>,
+        /// The signatures of the functions available, used to pass named arguments by position.
+        signatures: Vec<FunctionSignature>,
+        /// The source of the file compiled, locating statements in the debug information.
+        source: &'s str,
+        /// The statement being compiled, locating the error failing the compilation, see [Located].
+        statement: usize,
+    }
+
+    impl<'s> Scopes<'s> {
+        fn new(signatures: &[FunctionSignature], source: &'s str) -> Scopes<'s> {
+            Scopes { frames: vec!(vec!()), signatures: signatures.to_vec(), source, statement: 0 }
+        }
+        fn push(&mut self) {
+            self.frames.push(vec!());
+        }
+        fn pop(&mut self, vm: &mut VmState) {
+            let to = vm.instructions().len();
+            self.pop_until(vm, to);
+        }
+        fn pop_until(&mut self, vm: &mut VmState, to: usize) {
+            let frame = self.frames.pop().unwrap_or_default();
+            for symbol in frame {
+                vm.push_local_info(VmLocalInfo {
+                    name: symbol.name,
+                    slot: symbol.slot,
+                    from: symbol.from,
+                    to,
+                });
+            }
+        }
+        fn lookup(&self, name: &str) -> Option<&Symbol> {
+            self.frames.iter().rev()
+                .flat_map(|frame| frame.iter().rev())
+                .find(|symbol| symbol.name == name)
+        }
+        fn declare(&mut self, name: &str, constant: bool, value_type: Option<VmValueType>, from: usize) -> Result<u16, CompileError> {
+            let slot = self.frames.iter().map(|frame| frame.len()).sum::<usize>() as u16;
+            let frame = self.frames.last_mut().expect("The root scope is never popped");
+            if frame.iter().any(|symbol| symbol.name == name) {
+                return Err(CompileError::AlreadyDeclared(name.to_string()));
+            }
+            frame.push(Symbol { name: name.to_string(), constant, value_type, slot, from });
+            Ok(slot)
+        }
+        fn resolve(&self, name: &str) -> Result<u16, CompileError> {
+            match self.lookup(name) {
+                Some(symbol) => Ok(symbol.slot),
+                None => Err(CompileError::UndeclaredVariable(name.to_string())),
+            }
+        }
+    }
+
+    /// Compiles the file without checking it. Named arguments are passed as a single object,
+    /// as no signatures are known.
+    pub fn compile(file: X39File) -> Result<VmState, CompileError> {
+        compile_located(file, &[]).map_err(|it| it.error)
+    }
+
+    /// Checks the file against the signatures of all functions available, eg. taken from a cached
+    /// capability manifest, before compiling it. Calls of unknown functions or with the wrong
+    /// number of arguments are reported instead of failing at runtime.
+    ///
+    /// Named arguments are passed by position to functions advertising their parameter names.
+    pub fn compile_checked(file: X39File, signatures: &[FunctionSignature]) -> Result<VmState, CompileError> {
+        check_strict(&file, signatures).map_err(CompileError::Check)?;
+        compile_located(file, signatures).map_err(|it| it.error)
+    }
 
-    pub fn compile(file: X39File) -> VmState {
+    /// Compiles the file without checking it, locating the error in the statement failing to
+    /// compile. Named arguments are passed by position to functions whose signature provided
+    /// advertises their parameter names, see [compile_checked].
+    pub fn compile_located(file: X39File, signatures: &[FunctionSignature]) -> Result<VmState, Located<CompileError>> {
         let mut vm = VmState::new();
-        compile_statements(file.statements.borrow(), vm.borrow_mut());
-        vm
+        let mut scopes = Scopes::new(signatures, file.source);
+        compile_statements(file.statements.borrow(), vm.borrow_mut(), &mut scopes)
+            .map_err(|error| Located { remaining: scopes.statement, error })?;
+        // Top-level variables stay visible after the last instruction
+        scopes.pop_until(vm.borrow_mut(), usize::MAX);
+        Ok(vm)
     }
 
-    fn compile_statements(statements: &[Statement], vm: &mut VmState) {
+    fn compile_statements(statements: &[LocatedStatement], vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
+        let enclosing = scopes.statement;
         for statement in statements {
-            match statement {
-                Statement::Await(await_statement) => compile_await(await_statement, vm.borrow_mut()),
-                Statement::Abort(abort_ident) => compile_abort(abort_ident, vm.borrow_mut()),
-                Statement::AbortAll(abort_ident) => compile_abort_all(abort_ident, vm.borrow_mut()),
+            scopes.statement = statement.remaining;
+            let (line, column) = statement.position_in(scopes.source);
+            vm.push_line_info(VmLineInfo { from: vm.instructions().len(), line, column });
+            match &statement.statement {
+                Statement::Await(await_statement) => compile_await(await_statement, vm.borrow_mut(), scopes)?,
+                Statement::Abort(abort_ident) => compile_abort(abort_ident, vm.borrow_mut(), scopes)?,
+                Statement::AbortAll(abort_ident) => compile_abort_all(abort_ident, vm.borrow_mut(), scopes)?,
                 Statement::Exit => compile_exit(vm.borrow_mut()),
-                Statement::Comment => {}
+                Statement::Comment(_) => {}
                 Statement::Start(call) => {
-                    compile_start(call, vm.borrow_mut());
+                    compile_start(call, vm.borrow_mut(), scopes)?;
                     vm.push_instruction(Instruction::op_pop());
                 }
-                Statement::IfElse(if_else_statement) => compile_if_else(if_else_statement, vm.borrow_mut()),
-                Statement::ForLoop(for_loop_statement) => compile_for_loop(for_loop_statement, vm.borrow_mut()),
-                Statement::Assignment(assignment_statement) => compile_assignment(assignment_statement, vm.borrow_mut()),
-                Statement::Print(ident) => compile_print(ident, vm.borrow_mut()),
+                Statement::IfElse(if_else_statement) => compile_if_else(if_else_statement, vm.borrow_mut(), scopes)?,
+                Statement::ForLoop(for_loop_statement) => compile_for_loop(for_loop_statement, vm.borrow_mut(), scopes)?,
+                Statement::Assignment(assignment_statement) => compile_assignment(assignment_statement, vm.borrow_mut(), scopes)?,
+                Statement::Declaration(declaration_statement) => compile_declaration(declaration_statement, vm.borrow_mut(), scopes)?,
+                Statement::Destructuring(destructuring_statement) => compile_destructuring(destructuring_statement, vm.borrow_mut(), scopes)?,
+                Statement::Print(ident) => compile_print(ident, vm.borrow_mut(), scopes)?,
             }
         }
+        // The enclosing statement may still fail once its block compiled
+        scopes.statement = enclosing;
+        Ok(())
     }
 
-    fn compile_assignment(assignment_statement: &AssignmentStatement, vm: &mut VmState) {
+    fn compile_block(statements: &[LocatedStatement], vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
+        trace!("Entering compile_block with {} instructions", vm.instructions().len());
+        scopes.push();
+        compile_statements(statements, vm, scopes)?;
+        scopes.pop(vm);
+        trace!("Exiting compile_block with {} instructions", vm.instructions().len());
+        Ok(())
+    }
+
+    fn compile_declaration(declaration_statement: &DeclarationStatement, vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
+        trace!("Entering compile_declaration with {} instructions", vm.instructions().len());
+        // The value is compiled before declaring, so `let x = x;` refers to the outer x.
+        compile_assign_statement_data(declaration_statement.value.borrow(), vm, scopes)?;
+        let constant = declaration_statement.kind == DeclarationKind::Const;
+        let value_type = declaration_statement.annotation.as_ref().and_then(|it| it.vm_value_type());
+        if let Some(value_type) = value_type.clone() {
+            vm.push_instruction(Instruction::op_assert_type(value_type));
+        }
+        let slot = scopes.declare(declaration_statement.ident, constant, value_type, vm.instructions().len())?;
+        vm.push_instruction(Instruction::op_store_local(slot));
+        trace!("Exiting compile_declaration with {} instructions", vm.instructions().len());
+        Ok(())
+    }
+
+    fn compile_assignment(assignment_statement: &AssignmentStatement, vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
         trace!("Entering compile_assignment with {} instructions", vm.instructions().len());
         let key = assignment_statement.ident.to_string();
+        if let (Some(annotation), AssignmentType::Assign(assign)) = (&assignment_statement.annotation, &assignment_statement.value) {
+            // An annotated assignment declares the variable in the current scope
+            compile_assign_statement_data(assign, vm, scopes)?;
+            let value_type = annotation.vm_value_type();
+            if let Some(value_type) = value_type.clone() {
+                vm.push_instruction(Instruction::op_assert_type(value_type));
+            }
+            let slot = scopes.declare(&key, false, value_type, vm.instructions().len())?;
+            vm.push_instruction(Instruction::op_store_local(slot));
+            trace!("Exiting compile_assignment with {} instructions", vm.instructions().len());
+            return Ok(());
+        }
+        if let Some(symbol) = scopes.lookup(&key) {
+            if symbol.constant {
+                return Err(CompileError::ConstReassignment(key));
+            }
+        }
         match assignment_statement.value.borrow() {
-            AssignmentType::Append(append) => compile_assignment_append(append, key, vm),
-            AssignmentType::Assign(assign) => compile_assignment_assign(assign, key, vm),
+            AssignmentType::Append(append) => compile_assignment_append(append, key, vm, scopes)?,
+            AssignmentType::Assign(assign) => compile_assignment_assign(assign, key, vm, scopes)?,
         }
         trace!("Exiting compile_assignment with {} instructions", vm.instructions().len());
+        Ok(())
     }
 
-    fn compile_assignment_assign(assign: &AssignStatementData, ident: String, vm: &mut VmState) {
-        trace!("Entering compile_assignment_assign with {} instructions", vm.instructions().len());
-        // Reserve variable name index
-        let value_index = vm.value_index(VmValue::String(ident));
-        // PUSH the value to append on the stack
-        match assign {
-            AssignStatementData::Value(value) => compile_value(value, vm),
-            AssignStatementData::Await(await_call_or_ident) => compile_await_call_or_ident(await_call_or_ident, vm),
-            AssignStatementData::Start(start) => compile_start(start, vm),
+    fn compile_assign_statement_data(data: &AssignStatementData, vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
+        match data {
+            AssignStatementData::Value(value) => compile_value(value, vm)?,
+            AssignStatementData::Ident(ident) => compile_ident(ident, vm, scopes)?,
+            AssignStatementData::Await(await_call_or_ident) => compile_await_call_or_ident(await_call_or_ident, vm, scopes)?,
+            AssignStatementData::Start(start) => compile_start(start, vm, scopes)?,
         }
-        // PUSH variable name to stack for assignment in the end
-        vm.push_instruction(Instruction::op_push_value_u16(value_index));
-        // Assign array to variable
-        vm.push_instruction(Instruction::op_assign());
+        Ok(())
+    }
+
+    fn compile_assignment_assign(assign: &AssignStatementData, ident: String, vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
+        trace!("Entering compile_assignment_assign with {} instructions", vm.instructions().len());
+        // PUSH the value to assign on the stack
+        compile_assign_statement_data(assign, vm, scopes)?;
+        compile_store(&ident, vm, scopes)?;
         trace!("Exiting compile_assignment_assign with {} instructions", vm.instructions().len());
+        Ok(())
+    }
+
+    /// POPs the value on top of the stack into the variable provided.
+    fn compile_store(ident: &str, vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
+        // Assigning to an unknown variable implicitly declares it in the current scope
+        let slot = match scopes.lookup(ident) {
+            Some(symbol) => {
+                if let Some(value_type) = symbol.value_type.clone() {
+                    vm.push_instruction(Instruction::op_assert_type(value_type));
+                }
+                symbol.slot
+            }
+            None => scopes.declare(ident, false, None, vm.instructions().len())?,
+        };
+        // Assign value to variable
+        vm.push_instruction(Instruction::op_store_local(slot));
+        Ok(())
+    }
+
+    fn compile_destructuring(destructuring_statement: &DestructuringStatement, vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
+        trace!("Entering compile_destructuring with {} instructions", vm.instructions().len());
+        for ident in destructuring_statement.idents.iter() {
+            if scopes.lookup(ident).is_some_and(|symbol| symbol.constant) {
+                return Err(CompileError::ConstReassignment(ident.to_string()));
+            }
+        }
+        // PUSH the elements of the array to destructure, the last one on top
+        compile_assign_statement_data(&destructuring_statement.value, vm, scopes)?;
+        vm.push_instruction(Instruction::op_unpack(destructuring_statement.idents.len() as u16));
+        for ident in destructuring_statement.idents.iter().rev() {
+            compile_store(ident, vm, scopes)?;
+        }
+        trace!("Exiting compile_destructuring with {} instructions", vm.instructions().len());
+        Ok(())
     }
 
-    fn compile_assignment_append(append: &AssignStatementData, ident: String, vm: &mut VmState) {
+    fn compile_assignment_append(append: &AssignStatementData, ident: String, vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
         trace!("Entering compile_assignment_append with {} instructions", vm.instructions().len());
-        // Reserve variable name value index
-        let value_index = vm.value_index(VmValue::String(ident));
-        // PUSH array in variable to stack
-        vm.push_instruction(Instruction::op_push_value_u16(value_index));
-        vm.push_instruction(Instruction::op_get_variable_of_type(VmValueType::Array));
+        let slot = scopes.resolve(&ident)?;
         // PUSH the value to append on the stack
-        match append {
-            AssignStatementData::Value(value) => compile_value(value, vm),
-            AssignStatementData::Await(await_call_or_ident) => compile_await_call_or_ident(await_call_or_ident, vm),
-            AssignStatementData::Start(start) => compile_start(start, vm),
-        }
-        // Append the value to the array
-        vm.push_instruction(Instruction::op_append_array_push());
-        // PUSH variable name to stack for assignment in the end
-        vm.push_instruction(Instruction::op_push_value_u16(value_index));
-        // Assign array to variable
-        vm.push_instruction(Instruction::op_assign());
+        compile_assign_statement_data(append, vm, scopes)?;
+        // Add the value to the variable in place, appending if it is an array
+        vm.push_instruction(Instruction::op_add_local(slot));
+        // Adding may change the type of the variable, eg. integer += number
+        if let Some(value_type) = scopes.lookup(&ident).and_then(|symbol| symbol.value_type.clone()) {
+            vm.push_instruction(Instruction::op_load_local(slot));
+            vm.push_instruction(Instruction::op_assert_type(value_type));
+            vm.push_instruction(Instruction::op_pop());
+        }
         trace!("Exiting compile_assignment_append with {} instructions", vm.instructions().len());
+        Ok(())
     }
 
-    fn compile_for_loop(for_loop_statement: &ForLoopStatement, vm: &mut VmState) {
+    fn compile_for_loop(for_loop_statement: &ForLoopStatement, vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
         trace!("Entering compile_for_loop with {} instructions", vm.instructions().len());
         // PUSH value to iterate over
         match for_loop_statement.over.borrow() {
-            ForLoopInstruction::Ident(ident) => compile_ident(ident, vm),
-            ForLoopInstruction::Await(await_call_or_ident) => compile_await_call_or_ident(await_call_or_ident, vm),
-            ForLoopInstruction::Value(value) => compile_value(value, vm),
+            ForLoopInstruction::Ident(ident) => compile_ident(ident, vm, scopes)?,
+            ForLoopInstruction::Await(await_call_or_ident) => compile_await_call_or_ident(await_call_or_ident, vm, scopes)?,
+            ForLoopInstruction::Stream(job) => return compile_for_stream(for_loop_statement, job, vm, scopes),
+            ForLoopInstruction::Value(value) => compile_value(value, vm)?,
         }
         // PUSH index
-        let value_index = vm.value_index(VmValue::Number(0.0));
+        let value_index = vm.value_index(VmValue::Integer(0));
         vm.push_instruction(Instruction::op_push_value_u16(value_index));
         // Prepare jump instruction
         let jump_offset = vm.instructions().len();
-        vm.push_instruction(Instruction::op_jump_iterate(0));
-        // PUSH variable name to stack for assignment in the end
-        let ident = for_loop_statement.ident.to_string();
-        let value_index = vm.value_index(VmValue::String(ident));
-        vm.push_instruction(Instruction::op_push_value_u16(value_index));
-        // Assign iterated element to variable
-        vm.push_instruction(Instruction::op_assign());
+        match for_loop_statement.key {
+            None => vm.push_instruction(Instruction::op_jump_iterate(0)),
+            Some(_) => vm.push_instruction(Instruction::op_jump_iterate_pair(0)),
+        }
+        // The loop variables live in their own scope, enclosing the loop body
+        scopes.push();
+        let from = vm.instructions().len();
+        let slot = scopes.declare(for_loop_statement.ident, false, None, from)?;
+        let key_slot = match for_loop_statement.key {
+            None => None,
+            Some(key) => Some(scopes.declare(key, false, None, from)?),
+        };
+        // Store iterated element (and key) in loop variables
+        vm.push_instruction(Instruction::op_store_local(slot));
+        if let Some(key_slot) = key_slot {
+            vm.push_instruction(Instruction::op_store_local(key_slot));
+        }
         // Emit code
-        compile_statements(for_loop_statement.code.borrow(), vm);
+        compile_block(for_loop_statement.code.borrow(), vm, scopes)?;
+        scopes.pop(vm);
         // Emit jump back to loop
         let break_jump_offset = vm.instructions().len();
-        vm.push_instruction(Instruction::op_jump(-((break_jump_offset - jump_offset) as i16)));
+        vm.push_instruction(Instruction::op_jump(-((break_jump_offset - jump_offset + 1) as i16)));
         // Update skip
         let next_offset = vm.instructions().len();
-        vm.get_instruction(jump_offset).unwrap().arg = InstructionArg::Signed((next_offset - jump_offset) as i16);
+        vm.get_instruction(jump_offset).unwrap().arg = InstructionArg::Signed((next_offset - jump_offset - 1) as i16);
         trace!("Exiting compile_for_loop with {} instructions", vm.instructions().len());
+        Ok(())
     }
 
-    fn compile_if_else(if_else_statement: &IfElseStatement, vm: &mut VmState) {
+    fn compile_for_stream(for_loop_statement: &ForLoopStatement, job: &AwaitCallOrIdentProduction, vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
+        trace!("Entering compile_for_stream with {} instructions", vm.instructions().len());
+        if let Some(key) = for_loop_statement.key {
+            return Err(CompileError::KeyedStream(key.to_string()));
+        }
+        // PUSH job to stream, which stays on the stack while iterating
+        match job {
+            AwaitCallOrIdentProduction::Call(call) => compile_call(call, vm, scopes)?,
+            AwaitCallOrIdentProduction::Ident(ident) => compile_ident_job(ident, vm, scopes)?,
+        }
+        // Prepare jump instruction
+        let jump_offset = vm.instructions().len();
+        vm.push_instruction(Instruction::op_jump_stream(0));
+        scopes.push();
+        let slot = scopes.declare(for_loop_statement.ident, false, None, vm.instructions().len())?;
+        vm.push_instruction(Instruction::op_store_local(slot));
+        compile_block(for_loop_statement.code.borrow(), vm, scopes)?;
+        scopes.pop(vm);
+        // Emit jump back to loop
+        let break_jump_offset = vm.instructions().len();
+        vm.push_instruction(Instruction::op_jump(-((break_jump_offset - jump_offset + 1) as i16)));
+        // Update skip
+        let next_offset = vm.instructions().len();
+        vm.get_instruction(jump_offset).unwrap().arg = InstructionArg::Signed((next_offset - jump_offset - 1) as i16);
+        trace!("Exiting compile_for_stream with {} instructions", vm.instructions().len());
+        Ok(())
+    }
+
+    fn compile_if_else(if_else_statement: &IfElseStatement, vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
         trace!("Entering compile_if_else with {} instructions", vm.instructions().len());
         // PUSH condition
-        compile_if_statement_condition(if_else_statement.if_statement.condition.borrow(), vm);
+        compile_if_statement_condition(if_else_statement.if_statement.condition.borrow(), vm, scopes)?;
         // Prepare jump instruction
         let true_offset = vm.instructions().len();
         vm.push_instruction(Instruction::op_jump_if_false(0));
         // Write out if code
-        compile_statements(if_else_statement.if_statement.code.borrow(), vm);
+        compile_block(if_else_statement.if_statement.code.borrow(), vm, scopes)?;
 
         if let Some(else_statement) = if_else_statement.else_statement.borrow() {
             // Prepare else skip-jump
@@ -131,8 +377,8 @@ pub mod compiler {
             vm.get_instruction(true_offset).unwrap().arg = InstructionArg::Signed((after_true_code_offset - true_offset - 1) as i16);
             // Write out else code
             match else_statement {
-                ElseStatement::Code(else_code) => compile_statements(else_code, vm),
-                ElseStatement::IfElse(if_else) => compile_if_else(if_else, vm),
+                ElseStatement::Code(else_code) => compile_block(else_code, vm, scopes)?,
+                ElseStatement::IfElse(if_else) => compile_if_else(if_else, vm, scopes)?,
             }
             // Modify prepared jump instruction to correct offset
             let after_else_code_offset = vm.instructions().len();
@@ -143,133 +389,196 @@ pub mod compiler {
             vm.get_instruction(true_offset).unwrap().arg = InstructionArg::Signed((after_true_code_offset - true_offset - 1) as i16);
         }
         trace!("Exiting compile_if_else with {} instructions", vm.instructions().len());
+        Ok(())
     }
 
-    fn compile_if_statement_condition(condition: &IfStatementCondition, vm: &mut VmState) {
+    fn compile_if_statement_condition(condition: &IfStatementCondition, vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
         trace!("Entering compile_if_statement_condition with {} instructions", vm.instructions().len());
         match condition {
-            IfStatementCondition::Await(await_call_or_ident) => compile_await_call_or_ident(await_call_or_ident, vm),
-            IfStatementCondition::Ident(ident) => compile_ident(*ident, vm),
+            IfStatementCondition::Await(await_call_or_ident) => compile_await_call_or_ident(await_call_or_ident, vm, scopes)?,
+            IfStatementCondition::Ident(ident) => compile_ident(ident, vm, scopes)?,
         }
         trace!("Exiting compile_if_statement_condition with {} instructions", vm.instructions().len());
+        Ok(())
     }
 
-    fn compile_start(call: &Call, vm: &mut VmState) {
+    fn compile_start(call: &Call, vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
         trace!("Entering compile_start with {} instructions", vm.instructions().len());
-        compile_call(call, vm);
+        compile_call(call, vm, scopes)?;
         trace!("Exiting compile_start with {} instructions", vm.instructions().len());
+        Ok(())
     }
 
-    fn compile_abort(abort_ident: &&str, vm: &mut VmState) {
+    fn compile_abort(abort_ident: &&str, vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
         trace!("Entering compile_abort with {} instructions", vm.instructions().len());
-        let value_index = vm.value_index(VmValue::String(abort_ident.to_string()));
-        vm.push_instruction(Instruction::op_push_value_u16(value_index));
-        vm.push_instruction(Instruction::op_get_variable_of_type(VmValueType::Job));
+        compile_ident_job(abort_ident, vm, scopes)?;
         vm.push_instruction(Instruction::op_abort());
         trace!("Exiting compile_abort with {} instructions", vm.instructions().len());
+        Ok(())
     }
 
-    fn compile_print(ident: &&str, vm: &mut VmState) {
+    fn compile_print(ident: &&str, vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
         trace!("Entering compile_print with {} instructions", vm.instructions().len());
-        let value_index = vm.value_index(VmValue::String(ident.to_string()));
-        vm.push_instruction(Instruction::op_push_value_u16(value_index));
-        vm.push_instruction(Instruction::op_get_variable());
+        compile_ident(ident, vm, scopes)?;
         vm.push_instruction(Instruction::op_print_to_console());
         trace!("Exiting compile_print with {} instructions", vm.instructions().len());
+        Ok(())
     }
 
-    fn compile_abort_all(abort_ident: &&str, vm: &mut VmState) {
+    fn compile_abort_all(abort_ident: &&str, vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
         trace!("Entering compile_abort with {} instructions", vm.instructions().len());
-        let value_index = vm.value_index(VmValue::String(abort_ident.to_string()));
-        vm.push_instruction(Instruction::op_push_value_u16(value_index));
-        vm.push_instruction(Instruction::op_get_variable_of_type(VmValueType::Job));
+        let slot = scopes.resolve(abort_ident)?;
+        vm.push_instruction(Instruction::op_load_local(slot));
+        vm.push_instruction(Instruction::op_assert_type(VmValueType::ArrayOfJobs));
         vm.push_instruction(Instruction::op_abort_all());
         trace!("Exiting compile_abort with {} instructions", vm.instructions().len());
+        Ok(())
     }
 
-    fn compile_await(await_statement: &AwaitStatement, vm: &mut VmState) {
+    fn compile_await(await_statement: &AwaitStatement, vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
         trace!("Entering compile_await with {} instructions", vm.instructions().len());
         match await_statement {
-            AwaitStatement::AwaitAny(await_any) => compile_await_any(await_any, vm),
-            AwaitStatement::AwaitAll(await_all) => compile_await_all(await_all, vm),
-            AwaitStatement::AwaitCallOrIdent(await_call_or_ident) => compile_await_call_or_ident(await_call_or_ident, vm),
+            AwaitStatement::AwaitAny(await_any) => compile_await_any(await_any, vm, scopes)?,
+            AwaitStatement::AwaitAll(await_all) => compile_await_all(await_all, vm, scopes)?,
+            AwaitStatement::AwaitCallOrIdent(await_call_or_ident) => compile_await_call_or_ident(await_call_or_ident, vm, scopes)?,
         }
         trace!("Exiting compile_await with {} instructions", vm.instructions().len());
+        Ok(())
     }
 
-    fn compile_await_call_or_ident(await_call_or_ident: &AwaitCallOrIdentProduction, vm: &mut VmState) {
+    fn compile_await_call_or_ident(await_call_or_ident: &AwaitCallOrIdentProduction, vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
         trace!("Entering compile_await_call_or_ident with {} instructions", vm.instructions().len());
         match await_call_or_ident {
-            AwaitCallOrIdentProduction::Call(call) => compile_call(call, vm),
-            AwaitCallOrIdentProduction::Ident(ident) => compile_ident_job(ident, vm),
+            AwaitCallOrIdentProduction::Call(call) => compile_call(call, vm, scopes)?,
+            AwaitCallOrIdentProduction::Ident(ident) => compile_ident_job(ident, vm, scopes)?,
         }
         vm.push_instruction(Instruction::op_await());
         trace!("Exiting compile_await_call_or_ident with {} instructions", vm.instructions().len());
+        Ok(())
     }
 
-    fn compile_call(call: &Call, vm: &mut VmState) {
+    fn compile_call(call: &Call, vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
         trace!("Entering compile_call with {} instructions", vm.instructions().len());
-        let value_index = vm.value_index(VmValue::String(call.ident.to_string()));
+        let value_index = vm.value_index(VmValue::string(call.ident));
         vm.push_instruction(Instruction::op_push_value_u16(value_index));
-        if let Some(value) = call.value.borrow() {
-            compile_call_value(value, vm);
-            vm.push_instruction(Instruction::op_call())
-        } else {
+        let signatures = signatures_of(&scopes.signatures, call.ident);
+        if !call.named_arguments.is_empty() && !signatures.is_empty() && is_binding_ambiguous(&signatures) {
+            return Err(CompileError::Check(vec!(CheckError::AmbiguousArguments { function: call.ident.to_string() })));
+        }
+        let signature = signatures.first()
+            .filter(|it| !it.parameter_names.is_empty())
+            .map(|it| (*it).clone());
+        let arguments_count = match signature {
+            Some(signature) if !call.named_arguments.is_empty() => {
+                let arguments = bind_arguments(call, &signature).map_err(|error| CompileError::Check(vec!(error)))?;
+                // PUSH the arguments in order, the last one on top
+                for argument in arguments.iter() {
+                    match argument {
+                        Some(value) => compile_call_value(value, vm, scopes)?,
+                        None => compile_null(vm),
+                    }
+                }
+                arguments.len()
+            }
+            _ => {
+                // PUSH the arguments in order, the last one on top
+                for value in call.arguments.iter() {
+                    compile_call_value(value, vm, scopes)?;
+                }
+                if call.named_arguments.is_empty() {
+                    call.arguments.len()
+                } else {
+                    compile_named_arguments(call.named_arguments.as_slice(), vm, scopes)?;
+                    call.arguments.len() + 1
+                }
+            }
+        };
+        if arguments_count == 0 {
             vm.push_instruction(Instruction::op_call_no_arg())
+        } else {
+            vm.push_instruction(Instruction::op_call(arguments_count as u16))
         }
         trace!("Exiting compile_call with {} instructions", vm.instructions().len());
+        Ok(())
+    }
+
+    /// PUSHes the named arguments as a single object, keyed by their names.
+    fn compile_named_arguments(named_arguments: &[NamedArgument], vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
+        trace!("Entering compile_named_arguments with {} instructions", vm.instructions().len());
+        vm.push_instruction(Instruction::op_push_empty_object());
+        for (index, it) in named_arguments.iter().enumerate() {
+            if named_arguments[..index].iter().any(|other| other.name == it.name) {
+                return Err(CompileError::DuplicateKey(it.name.to_string()));
+            }
+            let value_index = vm.value_index(VmValue::string(it.name));
+            vm.push_instruction(Instruction::op_push_value_u16(value_index));
+            compile_call_value(&it.value, vm, scopes)?;
+            vm.push_instruction(Instruction::op_append_property_push());
+        }
+        trace!("Exiting compile_named_arguments with {} instructions", vm.instructions().len());
+        Ok(())
     }
 
-    fn compile_call_value(call_value: &CallValue, vm: &mut VmState) {
+    fn compile_call_value(call_value: &CallValue, vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
         trace!("Entering compile_call_value with {} instructions", vm.instructions().len());
         match call_value {
-            CallValue::Ident(ident) => compile_ident(ident, vm),
-            CallValue::Value(value) => compile_value(value, vm),
+            CallValue::Ident(ident) => compile_ident(ident, vm, scopes)?,
+            CallValue::Value(value) => compile_value(value, vm)?,
         }
         trace!("Exiting compile_call_value with {} instructions", vm.instructions().len());
+        Ok(())
     }
 
-    fn compile_value(value: &Value, vm: &mut VmState) {
+    fn compile_value(value: &Value, vm: &mut VmState) -> Result<(), CompileError> {
         trace!("Entering compile_value with {} instructions", vm.instructions().len());
         match value {
             Value::NumericRange(numeric_range) => compile_numeric_range(numeric_range, vm),
             Value::Number(number) => compile_number(*number, vm),
+            Value::Integer(integer) => compile_literal(VmValue::Integer(*integer), vm),
+            Value::Bytes(bytes) => compile_literal(VmValue::bytes(bytes.clone()), vm),
+            Value::Timestamp(timestamp) => compile_literal(VmValue::Timestamp(*timestamp), vm),
             Value::Null => compile_null(vm),
             Value::String(string) => compile_string(string.to_string(), vm),
             Value::Boolean(boolean) => compile_boolean(*boolean, vm),
-            Value::Object(object) => compile_object(object, vm),
-            Value::Array(array) => compile_array(array, vm),
+            Value::Object(object) => compile_object(object, vm)?,
+            Value::Array(array) => compile_array(array, vm)?,
         }
         trace!("Exiting compile_value with {} instructions", vm.instructions().len());
+        Ok(())
     }
 
-    fn compile_array(array: &Vec<Value>, vm: &mut VmState) {
+    fn compile_array(array: &[Value], vm: &mut VmState) -> Result<(), CompileError> {
         trace!("Entering compile_array with {} instructions", vm.instructions().len());
         vm.push_instruction(Instruction::op_push_empty_array());
 
         for it in array.iter() {
-            compile_value(it, vm);
+            compile_value(it, vm)?;
             vm.push_instruction(Instruction::op_append_array_push());
         }
         trace!("Exiting compile_array with {} instructions", vm.instructions().len());
+        Ok(())
     }
 
-    fn compile_object(object: &Vec<Property>, vm: &mut VmState) {
+    fn compile_object(object: &[Property], vm: &mut VmState) -> Result<(), CompileError> {
         trace!("Entering compile_object with {} instructions", vm.instructions().len());
         vm.push_instruction(Instruction::op_push_empty_object());
 
-        for it in object.iter() {
+        for (index, it) in object.iter().enumerate() {
+            if object[..index].iter().any(|other| other.key == it.key) {
+                return Err(CompileError::DuplicateKey(it.key.to_string()));
+            }
             // Push Key
             let key = it.key.to_string();
-            let value_index = vm.value_index(VmValue::String(key));
+            let value_index = vm.value_index(VmValue::string(key));
             vm.push_instruction(Instruction::op_push_value_u16(value_index));
 
             // Push Value
             let value = it.value.borrow();
-            compile_value(value, vm);
-            vm.push_instruction(Instruction::op_append_array_push());
+            compile_value(value, vm)?;
+            vm.push_instruction(Instruction::op_append_property_push());
         }
         trace!("Exiting compile_object with {} instructions", vm.instructions().len());
+        Ok(())
     }
 
     fn compile_numeric_range(numeric_range: &NumericRange, vm: &mut VmState) {
@@ -277,7 +586,7 @@ pub mod compiler {
         vm.push_instruction(Instruction::op_push_empty_array());
 
         for i in numeric_range.from as i64..numeric_range.to as i64 {
-            let value_index = vm.value_index(VmValue::Number(i as f64));
+            let value_index = vm.value_index(VmValue::Integer(i));
             vm.push_instruction(Instruction::op_push_value_u16(value_index));
             vm.push_instruction(Instruction::op_append_array_push());
         }
@@ -291,9 +600,16 @@ pub mod compiler {
         trace!("Exiting compile_number with {} instructions", vm.instructions().len());
     }
 
+    fn compile_literal(value: VmValue, vm: &mut VmState) {
+        trace!("Entering compile_literal with {} instructions", vm.instructions().len());
+        let value_index = vm.value_index(value);
+        vm.push_instruction(Instruction::op_push_value_u16(value_index));
+        trace!("Exiting compile_literal with {} instructions", vm.instructions().len());
+    }
+
     fn compile_string(string: String, vm: &mut VmState) {
         trace!("Entering compile_string with {} instructions", vm.instructions().len());
-        let value_index = vm.value_index(VmValue::String(string));
+        let value_index = vm.value_index(VmValue::string(string));
         vm.push_instruction(Instruction::op_push_value_u16(value_index));
         trace!("Exiting compile_string with {} instructions", vm.instructions().len());
     }
@@ -310,38 +626,41 @@ pub mod compiler {
         trace!("Exiting compile_boolean with {} instructions", vm.instructions().len());
     }
 
-    fn compile_ident(ident: &str, vm: &mut VmState) {
+    fn compile_ident(ident: &str, vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
         trace!("Entering compile_ident with {} instructions", vm.instructions().len());
-        let value_index = vm.value_index(VmValue::String(ident.to_string()));
-        vm.push_instruction(Instruction::op_push_value_u16(value_index));
-        vm.push_instruction(Instruction::op_get_variable());
+        let slot = scopes.resolve(ident)?;
+        vm.push_instruction(Instruction::op_load_local(slot));
         trace!("Exiting compile_ident with {} instructions", vm.instructions().len());
+        Ok(())
     }
 
-    fn compile_ident_job(ident: &str, vm: &mut VmState) {
+    fn compile_ident_job(ident: &str, vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
         trace!("Entering compile_ident_job with {} instructions", vm.instructions().len());
-        let value_index = vm.value_index(VmValue::String(ident.to_string()));
-        vm.push_instruction(Instruction::op_push_value_u16(value_index));
-        vm.push_instruction(Instruction::op_get_variable_of_type(VmValueType::Job));
+        let slot = scopes.resolve(ident)?;
+        vm.push_instruction(Instruction::op_load_local(slot));
+        vm.push_instruction(Instruction::op_assert_type(VmValueType::Job));
         trace!("Exiting compile_ident_job with {} instructions", vm.instructions().len());
+        Ok(())
     }
 
-    fn compile_await_all(await_all: &str, vm: &mut VmState) {
+    fn compile_await_all(await_all: &str, vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
         trace!("Entering compile_await_all with {} instructions", vm.instructions().len());
-        let value_index = vm.value_index(VmValue::String(await_all.to_string()));
-        vm.push_instruction(Instruction::op_push_value_u16(value_index));
-        vm.push_instruction(Instruction::op_get_variable_of_type(VmValueType::ArrayOfJobs));
+        let slot = scopes.resolve(await_all)?;
+        vm.push_instruction(Instruction::op_load_local(slot));
+        vm.push_instruction(Instruction::op_assert_type(VmValueType::ArrayOfJobs));
         vm.push_instruction(Instruction::op_await_all());
         trace!("Exiting compile_await_all with {} instructions", vm.instructions().len());
+        Ok(())
     }
 
-    fn compile_await_any(await_any: &str, vm: &mut VmState) {
+    fn compile_await_any(await_any: &str, vm: &mut VmState, scopes: &mut Scopes) -> Result<(), CompileError> {
         trace!("Entering compile_await_any with {} instructions", vm.instructions().len());
-        let value_index = vm.value_index(VmValue::String(await_any.to_string()));
-        vm.push_instruction(Instruction::op_push_value_u16(value_index));
-        vm.push_instruction(Instruction::op_get_variable_of_type(VmValueType::ArrayOfJobs));
+        let slot = scopes.resolve(await_any)?;
+        vm.push_instruction(Instruction::op_load_local(slot));
+        vm.push_instruction(Instruction::op_assert_type(VmValueType::ArrayOfJobs));
         vm.push_instruction(Instruction::op_await_any());
         trace!("Exiting compile_await_any with {} instructions", vm.instructions().len());
+        Ok(())
     }
 
     fn compile_exit(vm: &mut VmState) {
@@ -354,9 +673,36 @@ pub mod compiler {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
     use tracing::trace;
     use tracing_test::traced_test;
-    use crate::machine::{Instruction};
+    use crate::assembler::checker::{CheckError, FunctionSignature};
+    use crate::assembler::compiler::compiler::CompileError;
+    use crate::assembler::parser::parser::TypeAnnotation;
+    use crate::controllers::{MockController, VmLocalController};
+    use crate::io::capability_manifest::{CapabilityManifest, FunctionCapabilities};
+    use crate::machine::{Instruction, VmPair, VmState, VmStack, VmValue};
+
+    fn compile_str(input: &str) -> Result<VmState, CompileError> {
+        let (remainder, file) = crate::assembler::parser::parser::parse_x39file(input).unwrap();
+        assert!(remainder.is_empty(), "Failed to fully parse input: {:?}", remainder);
+        super::compiler::compile(file)
+    }
+
+    fn run_str(input: &str) -> Result<(VmState, VmStack), Box<dyn std::error::Error>> {
+        let mut vm_state = compile_str(input)?;
+        let mut vm_stack = VmStack::new();
+        let controller = VmLocalController::new();
+        while !vm_state.is_done() {
+            vm_state.step(&mut vm_stack, &controller)?;
+        }
+        Ok((vm_state, vm_stack))
+    }
+
+    fn get_variable(vm_state: &VmState, vm_stack: &VmStack, name: &str) -> Option<VmValue> {
+        let slot = vm_state.find_local(name, vm_state.instructions().len())?;
+        vm_stack.get_local(slot).cloned()
+    }
 
     const TEST_FILE1: &str = r#"
     # comment
@@ -396,7 +742,7 @@ mod tests {
     #[traced_test]
     fn test_file1() -> Result<(), Box<dyn std::error::Error>> {
         let (_, file) = crate::assembler::parser::parser::parse_x39file(TEST_FILE1)?;
-        let vm_state = super::compiler::compile(file);
+        let vm_state = super::compiler::compile(file)?;
         trace!("{:?}", vm_state);
         Ok(())
     }
@@ -412,7 +758,7 @@ mod tests {
     #[traced_test]
     fn test_file2() -> Result<(), Box<dyn std::error::Error>> {
         let (_, file) = crate::assembler::parser::parser::parse_x39file(TEST_FILE2)?;
-        let vm_state = super::compiler::compile(file);
+        let vm_state = super::compiler::compile(file)?;
         trace!("{:?}", vm_state);
         Ok(())
     }
@@ -433,7 +779,7 @@ mod tests {
     #[traced_test]
     fn test_file3() -> Result<(), Box<dyn std::error::Error>> {
         let (_, file) = crate::assembler::parser::parser::parse_x39file(TEST_FILE3)?;
-        let vm_state = super::compiler::compile(file);
+        let vm_state = super::compiler::compile(file)?;
         trace!("{:?}", vm_state);
         Ok(())
     }
@@ -464,7 +810,7 @@ mod tests {
     #[traced_test]
     fn test_file4() -> Result<(), Box<dyn std::error::Error>> {
         let (_, file) = crate::assembler::parser::parser::parse_x39file(TEST_FILE4)?;
-        let vm_state = super::compiler::compile(file);
+        let vm_state = super::compiler::compile(file)?;
         trace!("{:?}", vm_state);
         Ok(())
     }
@@ -487,7 +833,7 @@ mod tests {
     #[traced_test]
     fn test_if_else() -> Result<(), Box<dyn std::error::Error>> {
         let (_, file) = crate::assembler::parser::parser::parse_x39file(TEST_FILE_IF_ELSE)?;
-        let vm_state = super::compiler::compile(file);
+        let vm_state = super::compiler::compile(file)?;
         let expected_code = vec![
             // exit;
             Instruction::op_push_null(),
@@ -549,7 +895,7 @@ mod tests {
     #[traced_test]
     fn test_if_else_if_else_if_else() -> Result<(), Box<dyn std::error::Error>> {
         let (_, file) = crate::assembler::parser::parser::parse_x39file(TEST_FILE_IF_ELSE_IF_ELSE_IF_ELSE)?;
-        let vm_state = super::compiler::compile(file);
+        let vm_state = super::compiler::compile(file)?;
         let expected_code = vec![
             // exit;
             Instruction::op_push_null(),
@@ -608,4 +954,477 @@ mod tests {
         trace!("{:?}", vm_state);
         Ok(())
     }
+
+    #[test]
+    #[traced_test]
+    fn test_read_undeclared_errors() -> Result<(), Box<dyn std::error::Error>> {
+        match compile_str("print foo;") {
+            Err(CompileError::UndeclaredVariable(name)) if name == "foo" => Ok(()),
+            other => Err(format!("Expected UndeclaredVariable but got {:?}", other.err()).into()),
+        }
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_append_undeclared_errors() -> Result<(), Box<dyn std::error::Error>> {
+        match compile_str("foo += 1;") {
+            Err(CompileError::UndeclaredVariable(name)) if name == "foo" => Ok(()),
+            other => Err(format!("Expected UndeclaredVariable but got {:?}", other.err()).into()),
+        }
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_const_reassignment_errors() -> Result<(), Box<dyn std::error::Error>> {
+        match compile_str("const foo = true; if foo { foo = false; }") {
+            Err(CompileError::ConstReassignment(name)) if name == "foo" => Ok(()),
+            other => Err(format!("Expected ConstReassignment but got {:?}", other.err()).into()),
+        }
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_const_append_errors() -> Result<(), Box<dyn std::error::Error>> {
+        match compile_str("const foo = []; foo += 1;") {
+            Err(CompileError::ConstReassignment(name)) if name == "foo" => Ok(()),
+            other => Err(format!("Expected ConstReassignment but got {:?}", other.err()).into()),
+        }
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_redeclaration_in_same_scope_errors() -> Result<(), Box<dyn std::error::Error>> {
+        match compile_str("let foo = 1; let foo = 2;") {
+            Err(CompileError::AlreadyDeclared(name)) if name == "foo" => Ok(()),
+            other => Err(format!("Expected AlreadyDeclared but got {:?}", other.err()).into()),
+        }
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_loop_variable_does_not_leak() -> Result<(), Box<dyn std::error::Error>> {
+        match compile_str("for it in [1, 2] { } print it;") {
+            Err(CompileError::UndeclaredVariable(name)) if name == "it" => {}
+            other => return Err(format!("Expected UndeclaredVariable but got {:?}", other.err()).into()),
+        }
+        let (state, stack) = run_str("list = []; for it in [1, 2, 3] { list += it; }")?;
+        assert_eq!(get_variable(&state, &stack, "it"), None);
+        assert_eq!(get_variable(&state, &stack, "list"), Some(VmValue::array(vec![
+            VmValue::Integer(1),
+            VmValue::Integer(2),
+            VmValue::Integer(3),
+        ])));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_keyed_stream_errors() -> Result<(), Box<dyn std::error::Error>> {
+        match compile_str("for i, chunk in stream job { }") {
+            Err(CompileError::KeyedStream(key)) if key == "i" => Ok(()),
+            other => Err(format!("Expected KeyedStream but got {:?}", other.err()).into()),
+        }
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_calls_are_checked_against_manifest() -> Result<(), Box<dyn std::error::Error>> {
+        let mut manifest = CapabilityManifest::new();
+        manifest.insert(FunctionCapabilities { name: "double".to_string(), arguments_required: 1, arguments_count: 1, results_count: 1, signature: None });
+        let compile = |input: &str| {
+            let (_, file) = crate::assembler::parser::parser::parse_x39file(input).unwrap();
+            super::compiler::compile_checked(file, &manifest.signatures())
+        };
+        compile("job = start double(2); value = await job;")?;
+        match compile("job = start double(); other = start triple(1);") {
+            Err(CompileError::Check(errors)) => assert_eq!(errors, vec!(
+                CheckError::ArityMismatch { function: "double".to_string(), required: 1, allowed: 1, found: 0 },
+                CheckError::UnknownFunction { function: "triple".to_string() },
+            )),
+            other => return Err(format!("Expected Check but got {:?}", other.err()).into()),
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_block_variable_does_not_leak() -> Result<(), Box<dyn std::error::Error>> {
+        match compile_str("flag = true; if flag { let inner = 1; } print inner;") {
+            Err(CompileError::UndeclaredVariable(name)) if name == "inner" => Ok(()),
+            other => Err(format!("Expected UndeclaredVariable but got {:?}", other.err()).into()),
+        }
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_shadowing_in_nested_block() -> Result<(), Box<dyn std::error::Error>> {
+        let (state, stack) = run_str(r#"
+            const flag = true;
+            let outer = "outer";
+            let assigned = "before";
+            if flag {
+                let outer = "inner";
+                const flag = false;
+                assigned = outer;
+            }
+        "#)?;
+        assert_eq!(get_variable(&state, &stack, "outer"), Some(VmValue::string("outer")));
+        assert_eq!(get_variable(&state, &stack, "assigned"), Some(VmValue::string("inner")));
+        assert_eq!(get_variable(&state, &stack, "flag"), Some(VmValue::Boolean(true)));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_variables_resolve_to_slots() -> Result<(), Box<dyn std::error::Error>> {
+        let vm_state = compile_str("list = []; if list { let inner = list; list += inner; } let other = list;")?;
+        let expected_code = vec![
+            // list = [];
+            Instruction::op_push_empty_array(),
+            Instruction::op_store_local(0),
+            // if list { ... }
+            Instruction::op_load_local(0),
+            Instruction::op_jump_if_false(4),
+            // let inner = list;
+            Instruction::op_load_local(0),
+            Instruction::op_store_local(1),
+            // list += inner;
+            Instruction::op_load_local(1),
+            Instruction::op_add_local(0),
+            // let other = list;
+            Instruction::op_load_local(0),
+            Instruction::op_store_local(1),
+        ];
+        assert_eq!(vm_state.instructions(), expected_code);
+        // Names are kept in debug information only
+        assert!(vm_state.values().is_empty());
+        assert_eq!(vm_state.find_local("inner", 6), Some(1));
+        assert_eq!(vm_state.find_local("inner", 9), None);
+        assert_eq!(vm_state.find_local("other", 9), Some(1));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_append_preserves_value_semantics() -> Result<(), Box<dyn std::error::Error>> {
+        let (state, stack) = run_str("let list = [1]; let copy = list; copy += 2;")?;
+        assert_eq!(get_variable(&state, &stack, "list"), Some(VmValue::array(vec![
+            VmValue::Integer(1),
+        ])));
+        assert_eq!(get_variable(&state, &stack, "copy"), Some(VmValue::array(vec![
+            VmValue::Integer(1),
+            VmValue::Integer(2),
+        ])));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_append_to_unshared_array_does_not_copy() -> Result<(), Box<dyn std::error::Error>> {
+        fn array_ptr(vm_stack: &VmStack) -> Option<*const Vec<VmValue>> {
+            match vm_stack.get_local(0) {
+                Some(VmValue::Array(array)) => Some(Arc::as_ptr(array)),
+                _ => None,
+            }
+        }
+        let mut vm_state = compile_str("let list = [1]; list += 2;")?;
+        let mut vm_stack = VmStack::new();
+        let controller = VmLocalController::new();
+        while vm_stack.locals_len() == 0 {
+            vm_state.step(&mut vm_stack, &controller)?;
+        }
+        let before = array_ptr(&vm_stack);
+        while !vm_state.is_done() {
+            vm_state.step(&mut vm_stack, &controller)?;
+        }
+        assert!(before.is_some());
+        assert_eq!(array_ptr(&vm_stack), before);
+        assert_eq!(vm_stack.get_local(0).cloned(), Some(VmValue::array(vec![
+            VmValue::Integer(1),
+            VmValue::Integer(2),
+        ])));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_duplicate_object_key_errors() -> Result<(), Box<dyn std::error::Error>> {
+        match compile_str(r#"let obj = { "a": 1, "b": [{ "c": 2, "c": 3 }] };"#) {
+            Err(CompileError::DuplicateKey(key)) if key == "c" => Ok(()),
+            other => Err(format!("Expected DuplicateKey but got {:?}", other.err()).into()),
+        }
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_object_literal_keeps_key_order() -> Result<(), Box<dyn std::error::Error>> {
+        let (state, stack) = run_str(r#"let obj = { "b": 1, "a": null };"#)?;
+        assert_eq!(get_variable(&state, &stack, "obj"), Some(VmValue::object(vec![
+            VmPair { key: "b".into(), value: VmValue::Integer(1) },
+            VmPair { key: "a".into(), value: VmValue::Null },
+        ])));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_for_key_value_over_object_and_array() -> Result<(), Box<dyn std::error::Error>> {
+        let (state, stack) = run_str(r#"
+            let keys = [];
+            let values = [];
+            for k, v in { "x": 1, "y": true } {
+                keys += k;
+                values += v;
+            }
+            let indices = [];
+            for i, it in ["a", "b"] {
+                indices += i;
+            }
+        "#)?;
+        assert_eq!(get_variable(&state, &stack, "keys"), Some(VmValue::array(vec![
+            VmValue::string("x"),
+            VmValue::string("y"),
+        ])));
+        assert_eq!(get_variable(&state, &stack, "values"), Some(VmValue::array(vec![
+            VmValue::Integer(1),
+            VmValue::Boolean(true),
+        ])));
+        assert_eq!(get_variable(&state, &stack, "indices"), Some(VmValue::array(vec![
+            VmValue::Integer(0),
+            VmValue::Integer(1),
+        ])));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_typed_literals() -> Result<(), Box<dyn std::error::Error>> {
+        let (state, stack) = run_str(r#"
+            let id = 9007199254740993;
+            let ratio = 1.5;
+            let payload = x"00fF";
+            let at = t"1970-01-01T00:00:01.5Z";
+        "#)?;
+        assert_eq!(get_variable(&state, &stack, "id"), Some(VmValue::Integer(9007199254740993)));
+        assert_eq!(get_variable(&state, &stack, "ratio"), Some(VmValue::Number(1.5)));
+        assert_eq!(get_variable(&state, &stack, "payload"), Some(VmValue::bytes(vec![0x00, 0xFF])));
+        assert_eq!(get_variable(&state, &stack, "at"), Some(VmValue::Timestamp(1_500_000)));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_add_promotion() -> Result<(), Box<dyn std::error::Error>> {
+        let (state, stack) = run_str(r#"
+            let integer = 1;
+            integer += 2;
+            let number = 1;
+            number += 0.5;
+            let string = "foo";
+            string += "bar";
+            let bytes = x"01";
+            bytes += x"02";
+            let at = t"1970-01-01T00:00:00Z";
+            at += 1000000;
+        "#)?;
+        assert_eq!(get_variable(&state, &stack, "integer"), Some(VmValue::Integer(3)));
+        assert_eq!(get_variable(&state, &stack, "number"), Some(VmValue::Number(1.5)));
+        assert_eq!(get_variable(&state, &stack, "string"), Some(VmValue::string("foobar")));
+        assert_eq!(get_variable(&state, &stack, "bytes"), Some(VmValue::bytes(vec![0x01, 0x02])));
+        assert_eq!(get_variable(&state, &stack, "at"), Some(VmValue::Timestamp(1_000_000)));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_add_incompatible_or_overflowing_errors() -> Result<(), Box<dyn std::error::Error>> {
+        assert!(run_str("let flag = true; flag += 1;").is_err());
+        assert!(run_str("let string = \"foo\"; string += 1;").is_err());
+        assert!(run_str("let integer = 9223372036854775807; integer += 1;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_annotations_assert_type_at_runtime() -> Result<(), Box<dyn std::error::Error>> {
+        let (state, stack) = run_str("jobs: array<job> = []; let count: integer = 1; count = 2;")?;
+        assert_eq!(get_variable(&state, &stack, "count"), Some(VmValue::Integer(2)));
+        let message = |input: &str| run_str(input).err().map(|error| error.to_string());
+        assert_eq!(message("let count: integer = 1; count = \"two\";"),
+            Some("AssertType found a value not matching the expected type Integer of variable 'count'.".to_string()));
+        assert_eq!(message("let count: integer = 1; count += 0.5;"),
+            Some("AssertType found a value not matching the expected type Integer of variable 'count'.".to_string()));
+        assert_eq!(message("let name: string = 1;"),
+            Some("AssertType found a value not matching the expected type String of variable 'name'.".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_annotated_assignment_declares_in_scope() -> Result<(), Box<dyn std::error::Error>> {
+        let (state, stack) = run_str("const value = 1; let flag = true; if flag { value: string = \"inner\"; } let after = value;")?;
+        assert_eq!(get_variable(&state, &stack, "after"), Some(VmValue::Integer(1)));
+        match compile_str("let value = 1; value: integer = 2;") {
+            Err(CompileError::AlreadyDeclared(name)) if name == "value" => Ok(()),
+            other => Err(format!("Expected AlreadyDeclared but got {:?}", other.err()).into()),
+        }
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_destructuring_assigns_in_order() -> Result<(), Box<dyn std::error::Error>> {
+        let (state, stack) = run_str("let x = 0; [x, y] = [1, \"two\"];")?;
+        assert_eq!(get_variable(&state, &stack, "x"), Some(VmValue::Integer(1)));
+        assert_eq!(get_variable(&state, &stack, "y"), Some(VmValue::string("two")));
+        assert!(run_str("[x, y] = [1, 2, 3];").is_err());
+        assert!(run_str("[x, y] = 1;").is_err());
+        match compile_str("const x = 0; [x, y] = [1, 2];") {
+            Err(CompileError::ConstReassignment(name)) if name == "x" => Ok(()),
+            other => Err(format!("Expected ConstReassignment but got {:?}", other.err()).into()),
+        }
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_call_pushes_every_argument() -> Result<(), Box<dyn std::error::Error>> {
+        let state = compile_str("a = 1; job = start f(a, 2, \"c\"); other = start g();")?;
+        assert!(state.instructions().contains(&Instruction::op_call(3)));
+        assert!(state.instructions().contains(&Instruction::op_call_no_arg()));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_named_arguments_are_lowered() -> Result<(), Box<dyn std::error::Error>> {
+        // Without signature the named arguments are passed as trailing object
+        let state = compile_str("img = 1; job = start resize(img, width: 640, mode: \"fit\");")?;
+        assert!(state.instructions().contains(&Instruction::op_push_empty_object()));
+        assert!(state.instructions().contains(&Instruction::op_call(2)));
+        match compile_str("job = start resize(width: 640, width: 480);") {
+            Err(CompileError::DuplicateKey(key)) if key == "width" => {}
+            other => return Err(format!("Expected DuplicateKey but got {:?}", other.err()).into()),
+        }
+        let signatures = [FunctionSignature {
+            name: "resize".to_string(),
+            parameters: vec!(TypeAnnotation::Any, TypeAnnotation::Integer, TypeAnnotation::String),
+            parameter_names: vec!("image".to_string(), "width".to_string(), "mode".to_string()),
+            arguments_required: 1,
+            result: TypeAnnotation::Any,
+        }];
+        let compile = |input: &str| {
+            let (_, file) = crate::assembler::parser::parser::parse_x39file(input).unwrap();
+            super::compiler::compile_checked(file, &signatures)
+        };
+        // Skipped optional arguments are passed as null
+        let state = compile("img = 1; job = start resize(mode: \"fit\", image: img);")?;
+        assert!(!state.instructions().contains(&Instruction::op_push_empty_object()));
+        assert!(state.instructions().contains(&Instruction::op_push_null()));
+        assert!(state.instructions().contains(&Instruction::op_call(3)));
+        match compile("job = start resize(width: 640);") {
+            Err(CompileError::Check(errors)) => assert_eq!(errors, vec!(
+                CheckError::MissingArgument { function: "resize".to_string(), name: "image".to_string() },
+            )),
+            other => return Err(format!("Expected Check but got {:?}", other.err()).into()),
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_named_arguments_bind_to_version_called() -> Result<(), Box<dyn std::error::Error>> {
+        // Both versions take the same parameters in a different order
+        let file = crate::io::lambda_file::LambdaFile::parse(concat!(
+            "[functions.resize]\ncommand = \"resize-v1\"\nparameters = { width = \"integer\", image = \"string\" }\n",
+            "[functions.resize.3]\ncommand = \"resize-v3\"\nparameters = { image = \"string\", width = \"integer\" }\n",
+            "[routes.resize]\naliases = { stable = 1 }\nweights = { 1 = 1, 3 = 1 }\n",
+        ))?;
+        let signatures = file.signatures();
+        let run = |input: &str| -> Result<MockController, Box<dyn std::error::Error>> {
+            let (_, file) = crate::assembler::parser::parser::parse_x39file(input).map_err(|error| error.to_string())?;
+            let mut vm_state = super::compiler::compile_checked(file, &signatures)?;
+            let mut vm_stack = VmStack::new();
+            let controller = MockController::new();
+            while !vm_state.is_done() {
+                vm_state.step(&mut vm_stack, &controller)?;
+            }
+            Ok(controller)
+        };
+        let calls = run(r#"
+            a = start resize@1(image: "a", width: 1);
+            b = start resize@3(image: "b", width: 2);
+            c = start resize@stable(image: "c", width: 3);
+        "#)?;
+        assert_eq!(calls.calls(), vec!(
+            ("resize@1".to_string(), vec!(VmValue::Integer(1), VmValue::string("a"))),
+            ("resize@3".to_string(), vec!(VmValue::string("b"), VmValue::Integer(2))),
+            ("resize@stable".to_string(), vec!(VmValue::Integer(3), VmValue::string("c"))),
+        ));
+        // Calls routed to either version cannot bind arguments by name
+        match run(r#"job = start resize(image: "e", width: 5);"#) {
+            Err(error) => assert_eq!(error.to_string(), CompileError::Check(vec!(
+                CheckError::AmbiguousArguments { function: "resize".to_string() },
+            )).to_string()),
+            Ok(controller) => return Err(format!("Expected an error but got {:?}", controller.calls()).into()),
+        }
+        Ok(())
+    }
+
+    const BENCH_FILE_LOOP_HEAVY: &str = r#"
+        let a = 1;
+        let b = "b";
+        let c = [];
+        let last = null;
+        for i in 0..300 {
+            for j in 0..300 {
+                last = j;
+                let copy = last;
+                c = a;
+                a = copy;
+            }
+        }
+    "#;
+
+    /// The loops of [BENCH_FILE_LOOP_HEAVY] without their body, being the baseline the cost of
+    /// accessing variables is measured against.
+    const BENCH_FILE_LOOP_BASELINE: &str = r#"
+        for i in 0..300 {
+            for j in 0..300 {}
+        }
+    "#;
+
+    fn bench_per_run(input: &str, iterations: u32) -> Result<std::time::Duration, Box<dyn std::error::Error>> {
+        let start = std::time::Instant::now();
+        for _ in 0..iterations {
+            run_str(input)?;
+        }
+        Ok(start.elapsed() / iterations)
+    }
+
+    /// Guards the cost of accessing variables through slots against the name lookups they replaced.
+    ///
+    /// Measured in release builds, variables were looked up by name up to `dd5ec32` and are
+    /// accessed through slots since:
+    ///
+    /// | Commit    | Per run | Loops alone | Variable accesses |
+    /// |-----------|---------|-------------|-------------------|
+    /// | `dd5ec32` | ~57ms   | ~12ms       | ~45ms             |
+    /// | slots     | ~21ms   | ~11ms       | ~10ms             |
+    ///
+    /// The baseline is reproduced by copying both scripts, [bench_per_run] and this test into a
+    /// worktree of `dd5ec32` and running the command below there. As the loops are unchanged
+    /// between both, the accesses are asserted to cost less than twice the loops alone, which
+    /// the name lookups, at about four times, did not.
+    #[test]
+    #[traced_test]
+    #[ignore]
+    fn bench_loop_heavy_script() -> Result<(), Box<dyn std::error::Error>> {
+        // cargo test --release bench_loop_heavy_script -- --ignored
+        let iterations = 10;
+        let baseline = bench_per_run(BENCH_FILE_LOOP_BASELINE, iterations)?;
+        let loop_heavy = bench_per_run(BENCH_FILE_LOOP_HEAVY, iterations)?;
+        let accesses = loop_heavy.saturating_sub(baseline);
+        tracing::info!("{:?} per run, {:?} for the loops alone, {:?} for the variable accesses", loop_heavy, baseline, accesses);
+        assert!(accesses < baseline * 2, "Variable accesses took {:?} against {:?} for the loops alone", accesses, baseline);
+        Ok(())
+    }
 }
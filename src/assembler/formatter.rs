@@ -0,0 +1,241 @@
+use crate::assembler::parser::parser::{AssignStatementData, AssignmentStatement, AssignmentType, AwaitCallOrIdentProduction, AwaitStatement, Call, CallValue, DeclarationKind, DeclarationStatement, ElseStatement, ForLoopInstruction, IfElseStatement, IfStatementCondition, LocatedStatement, Statement, Value, X39File};
+use crate::machine::timestamp::format_rfc3339;
+
+const INDENT: &str = "    ";
+
+/// Formats a parsed file in the canonical style, one statement per line, indenting blocks
+/// by four spaces. Comments are kept, blank lines are not.
+pub fn format(file: &X39File) -> String {
+    let mut output = String::new();
+    format_statements(&file.statements, 0, &mut output);
+    output
+}
+
+fn format_statements(statements: &[LocatedStatement], depth: usize, output: &mut String) {
+    for statement in statements {
+        output.push_str(&INDENT.repeat(depth));
+        format_statement(&statement.statement, depth, output);
+        output.push('\n');
+    }
+}
+
+fn format_statement(statement: &Statement, depth: usize, output: &mut String) {
+    match statement {
+        Statement::Await(AwaitStatement::AwaitAny(ident)) => output.push_str(&format!("await any {};", ident)),
+        Statement::Await(AwaitStatement::AwaitAll(ident)) => output.push_str(&format!("await all {};", ident)),
+        Statement::Await(AwaitStatement::AwaitCallOrIdent(production)) => {
+            output.push_str(&format!("await {};", format_production(production)));
+        }
+        Statement::Abort(ident) => output.push_str(&format!("abort {};", ident)),
+        Statement::AbortAll(ident) => output.push_str(&format!("abort all {};", ident)),
+        Statement::Exit => output.push_str("exit;"),
+        Statement::Comment(text) => output.push_str(&format!("#{}", text.trim_end())),
+        Statement::Start(call) => output.push_str(&format!("start {};", format_call(call))),
+        Statement::IfElse(if_else) => format_if_else(if_else, depth, output),
+        Statement::ForLoop(for_loop) => {
+            output.push_str("for ");
+            if let Some(key) = for_loop.key {
+                output.push_str(&format!("{}, ", key));
+            }
+            let over = match &for_loop.over {
+                ForLoopInstruction::Ident(ident) => ident.to_string(),
+                ForLoopInstruction::Await(production) => format!("await {}", format_production(production)),
+                ForLoopInstruction::Stream(production) => format!("stream {}", format_production(production)),
+                ForLoopInstruction::Value(value) => format_value(value),
+            };
+            output.push_str(&format!("{} in {} ", for_loop.ident, over));
+            format_code(&for_loop.code, depth, output);
+        }
+        Statement::Assignment(assignment) => output.push_str(&format_assignment(assignment)),
+        Statement::Declaration(declaration) => output.push_str(&format_declaration(declaration)),
+        Statement::Destructuring(destructuring) => {
+            output.push_str(&format!("[{}] = {};", destructuring.idents.join(", "), format_assign_data(&destructuring.value)));
+        }
+        Statement::Print(ident) => output.push_str(&format!("print {};", ident)),
+    }
+}
+
+fn format_if_else(if_else: &IfElseStatement, depth: usize, output: &mut String) {
+    let condition = match &if_else.if_statement.condition {
+        IfStatementCondition::Await(production) => format!("await {}", format_production(production)),
+        IfStatementCondition::Ident(ident) => ident.to_string(),
+    };
+    output.push_str(&format!("if {} ", condition));
+    format_code(&if_else.if_statement.code, depth, output);
+    match &if_else.else_statement {
+        None => {}
+        Some(ElseStatement::Code(code)) => {
+            output.push_str(" else ");
+            format_code(code, depth, output);
+        }
+        Some(ElseStatement::IfElse(if_else)) => {
+            output.push_str(" else ");
+            format_if_else(if_else, depth, output);
+        }
+    }
+}
+
+fn format_code(code: &[LocatedStatement], depth: usize, output: &mut String) {
+    if code.is_empty() {
+        output.push_str("{}");
+        return;
+    }
+    output.push_str("{\n");
+    format_statements(code, depth + 1, output);
+    output.push_str(&INDENT.repeat(depth));
+    output.push('}');
+}
+
+fn format_assignment(assignment: &AssignmentStatement) -> String {
+    match &assignment.value {
+        AssignmentType::Append(data) => format!("{} += {};", assignment.ident, format_assign_data(data)),
+        AssignmentType::Assign(data) => match &assignment.annotation {
+            Some(annotation) => format!("{}: {} = {};", assignment.ident, annotation, format_assign_data(data)),
+            None => format!("{} = {};", assignment.ident, format_assign_data(data)),
+        },
+    }
+}
+
+fn format_declaration(declaration: &DeclarationStatement) -> String {
+    let kind = match declaration.kind {
+        DeclarationKind::Let => "let",
+        DeclarationKind::Const => "const",
+    };
+    match &declaration.annotation {
+        Some(annotation) => format!("{} {}: {} = {};", kind, declaration.ident, annotation, format_assign_data(&declaration.value)),
+        None => format!("{} {} = {};", kind, declaration.ident, format_assign_data(&declaration.value)),
+    }
+}
+
+fn format_assign_data(data: &AssignStatementData) -> String {
+    match data {
+        AssignStatementData::Value(value) => format_value(value),
+        AssignStatementData::Ident(ident) => ident.to_string(),
+        AssignStatementData::Await(production) => format!("await {}", format_production(production)),
+        AssignStatementData::Start(call) => format!("start {}", format_call(call)),
+    }
+}
+
+fn format_production(production: &AwaitCallOrIdentProduction) -> String {
+    match production {
+        AwaitCallOrIdentProduction::Call(call) => format_call(call),
+        AwaitCallOrIdentProduction::Ident(ident) => ident.to_string(),
+    }
+}
+
+fn format_call(call: &Call) -> String {
+    let arguments: Vec<String> = call.arguments.iter()
+        .map(format_call_value)
+        .chain(call.named_arguments.iter().map(|it| format!("{}: {}", it.name, format_call_value(&it.value))))
+        .collect();
+    format!("{}({})", call.ident, arguments.join(", "))
+}
+
+fn format_call_value(value: &CallValue) -> String {
+    match value {
+        CallValue::Ident(ident) => ident.to_string(),
+        CallValue::Value(value) => format_value(value),
+    }
+}
+
+/// Formats a value as literal, eg. `x"00ff"` for bytes.
+pub fn format_value(value: &Value) -> String {
+    match value {
+        Value::NumericRange(range) => format!("{}..{}", range.from, range.to),
+        Value::Number(number) => format_number(*number),
+        Value::Integer(integer) => integer.to_string(),
+        Value::Null => "null".to_string(),
+        Value::String(string) => quote(string),
+        Value::Bytes(bytes) => format!("x\"{}\"", bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>()),
+        Value::Timestamp(micros) => format!("t\"{}\"", format_rfc3339(*micros)),
+        Value::Boolean(boolean) => boolean.to_string(),
+        Value::Object(properties) if properties.is_empty() => "{}".to_string(),
+        Value::Object(properties) => {
+            let properties: Vec<String> = properties.iter()
+                .map(|property| format!("{}: {}", quote(&property.key), format_value(&property.value)))
+                .collect();
+            format!("{{ {} }}", properties.join(", "))
+        }
+        Value::Array(values) => {
+            let values: Vec<String> = values.iter().map(format_value).collect();
+            format!("[{}]", values.join(", "))
+        }
+    }
+}
+
+/// Numbers keep their fraction, as integers would be parsed as such.
+fn format_number(number: f64) -> String {
+    let formatted = number.to_string();
+    match formatted.contains('.') {
+        true => formatted,
+        false => format!("{}.0", formatted),
+    }
+}
+
+/// Quotes a string, escaping it as understood by [crate::assembler::parser_string::parse_string].
+pub fn quote(string: &str) -> String {
+    let mut quoted = String::with_capacity(string.len() + 2);
+    quoted.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_test::traced_test;
+    use crate::assembler::formatter::format;
+    use crate::assembler::parser::parser::parse_x39file;
+
+    fn format_str(input: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let (remainder, file) = parse_x39file(input).map_err(|error| error.to_string())?;
+        assert!(remainder.is_empty(), "Failed to fully parse input: {:?}", remainder);
+        Ok(format(&file))
+    }
+
+    #[test]
+    #[traced_test]
+    fn statements_are_formatted() -> Result<(), Box<dyn std::error::Error>> {
+        let formatted = format_str("# resize all images\n\
+            let   images:array<bytes>=[x\"00FF\"];const limit=3;\
+            for key,value in {\"a\":1.50,\"b\":\"q\\\"\"}{print value;}\
+            if await ready(){job=start resize(images,width:640);}else if done{exit;}else {}\
+            [a,b]=await pair@stable();at=t\"2022-10-24T11:37:00Z\";for i in 1..3{}")?;
+        assert_eq!(formatted, "\
+# resize all images
+let images: array<bytes> = [x\"00ff\"];
+const limit = 3;
+for key, value in { \"a\": 1.5, \"b\": \"q\\\"\" } {
+    print value;
+}
+if await ready() {
+    job = start resize(images, width: 640);
+} else if done {
+    exit;
+} else {}
+[a, b] = await pair@stable();
+at = t\"2022-10-24T11:37:00Z\";
+for i in 1..3 {}
+");
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn formatting_is_idempotent() -> Result<(), Box<dyn std::error::Error>> {
+        let formatted = format_str("x=[1,2.5,null,true,{}];x+=\"tab\\there\";\nif x{ # inner\nawait all jobs;abort all jobs;}\n\
+            for v in stream numbers(){}for v in await numbers(){}")?;
+        assert_eq!(format_str(&formatted)?, formatted);
+        Ok(())
+    }
+}
@@ -2,7 +2,48 @@ pub mod parser {
     /// X39 File
     #[derive(Debug)]
     pub struct X39File<'a> {
-        pub statements: Vec<Statement<'a>>,
+        pub statements: Vec<LocatedStatement<'a>>,
+        /// The input the file was parsed from, locating its statements.
+        pub source: &'a str,
+    }
+
+    /// A statement and where it starts, given as the length of the input remaining, as the
+    /// input parsed is only known to the file, see [LocatedStatement::position_in].
+    #[derive(Debug)]
+    pub struct LocatedStatement<'a> {
+        pub remaining: usize,
+        pub statement: Statement<'a>,
+    }
+
+    impl<'a> LocatedStatement<'a> {
+        /// The line and column the statement starts at in the source of its file, both
+        /// starting at 1.
+        pub fn position_in(&self, source: &str) -> (usize, usize) {
+            position_in(source, self.remaining)
+        }
+    }
+
+    /// An error found in the statement starting with `remaining` bytes of the input left,
+    /// see [LocatedStatement].
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Located<E> {
+        pub remaining: usize,
+        pub error: E,
+    }
+
+    impl<E> Located<E> {
+        /// The line and column the statement the error was found in starts at, see
+        /// [LocatedStatement::position_in].
+        pub fn position_in(&self, source: &str) -> (usize, usize) {
+            position_in(source, self.remaining)
+        }
+    }
+
+    fn position_in(source: &str, remaining: usize) -> (usize, usize) {
+        let offset = source.len().saturating_sub(remaining);
+        let line_start = source[..offset].rfind('\n').map(|index| index + 1).unwrap_or(0);
+        let line = source[..offset].matches('\n').count() + 1;
+        (line, source[line_start..offset].chars().count() + 1)
     }
 
     /// X39 Statement
@@ -12,25 +53,32 @@ pub mod parser {
         Abort(&'a str),
         AbortAll(&'a str),
         Exit,
-        Comment,
+        /// The text of a comment, following the `#`.
+        Comment(&'a str),
         Start(Call<'a>),
         IfElse(IfElseStatement<'a>),
         ForLoop(ForLoopStatement<'a>),
         Assignment(AssignmentStatement<'a>),
+        Declaration(DeclarationStatement<'a>),
+        Destructuring(DestructuringStatement<'a>),
         Print(&'a str),
     }
 
     #[derive(Debug)]
     pub struct ForLoopStatement<'a> {
+        /// Receives the key of objects or index of arrays if present.
+        pub key: Option<&'a str>,
         pub ident: &'a str,
         pub over: ForLoopInstruction<'a>,
-        pub code: Vec<Statement<'a>>,
+        pub code: Vec<LocatedStatement<'a>>,
     }
 
     #[derive(Debug)]
     pub enum ForLoopInstruction<'a> {
         Ident(&'a str),
         Await(AwaitCallOrIdentProduction<'a>),
+        /// Iterates the partial results of a job while it runs.
+        Stream(AwaitCallOrIdentProduction<'a>),
         Value(Value),
     }
 
@@ -43,12 +91,95 @@ pub mod parser {
     #[derive(Debug)]
     pub struct AssignmentStatement<'a> {
         pub ident: &'a str,
+        /// An annotated assignment declares the variable in the current scope, like `let`.
+        pub annotation: Option<TypeAnnotation>,
         pub value: AssignmentType<'a>,
     }
 
+    /// Assigns the elements of an array to the variables provided, in order, eg. `[x, y] = await g();`.
+    /// The array must have exactly as many elements as variables are provided.
+    #[derive(Debug)]
+    pub struct DestructuringStatement<'a> {
+        pub idents: Vec<&'a str>,
+        pub value: AssignStatementData<'a>,
+    }
+
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    pub enum DeclarationKind {
+        Let,
+        Const,
+    }
+
+    #[derive(Debug)]
+    pub struct DeclarationStatement<'a> {
+        pub kind: DeclarationKind,
+        pub ident: &'a str,
+        pub annotation: Option<TypeAnnotation>,
+        pub value: AssignStatementData<'a>,
+    }
+
+    /// The type of a variable as annotated in scripts, eg. `array<job>`.
+    #[derive(Debug, PartialEq, Clone)]
+    pub enum TypeAnnotation {
+        Any,
+        Null,
+        Boolean,
+        Integer,
+        Number,
+        String,
+        Bytes,
+        Timestamp,
+        Object,
+        Array(Box<TypeAnnotation>),
+        /// A job, resulting in a value of the type provided once awaited.
+        Job(Box<TypeAnnotation>),
+    }
+
+    impl TypeAnnotation {
+        /// The type a value must have at runtime to satisfy this annotation,
+        /// None if it cannot be checked at runtime.
+        pub fn vm_value_type(&self) -> Option<VmValueType> {
+            match self {
+                TypeAnnotation::Any => None,
+                TypeAnnotation::Null => Some(VmValueType::Null),
+                TypeAnnotation::Boolean => Some(VmValueType::Boolean),
+                TypeAnnotation::Integer => Some(VmValueType::Integer),
+                TypeAnnotation::Number => Some(VmValueType::Number),
+                TypeAnnotation::String => Some(VmValueType::String),
+                TypeAnnotation::Bytes => Some(VmValueType::Bytes),
+                TypeAnnotation::Timestamp => Some(VmValueType::Timestamp),
+                TypeAnnotation::Object => Some(VmValueType::Object),
+                TypeAnnotation::Array(element) => match element.as_ref() {
+                    TypeAnnotation::Job(_) => Some(VmValueType::ArrayOfJobs),
+                    _ => Some(VmValueType::Array),
+                },
+                TypeAnnotation::Job(_) => Some(VmValueType::Job),
+            }
+        }
+    }
+
+    impl std::fmt::Display for TypeAnnotation {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                TypeAnnotation::Any => write!(f, "any"),
+                TypeAnnotation::Null => write!(f, "null"),
+                TypeAnnotation::Boolean => write!(f, "boolean"),
+                TypeAnnotation::Integer => write!(f, "integer"),
+                TypeAnnotation::Number => write!(f, "number"),
+                TypeAnnotation::String => write!(f, "string"),
+                TypeAnnotation::Bytes => write!(f, "bytes"),
+                TypeAnnotation::Timestamp => write!(f, "timestamp"),
+                TypeAnnotation::Object => write!(f, "object"),
+                TypeAnnotation::Array(element) => write!(f, "array<{}>", element),
+                TypeAnnotation::Job(result) => write!(f, "job<{}>", result),
+            }
+        }
+    }
+
     #[derive(Debug)]
     pub enum AssignStatementData<'a> {
         Value(Value),
+        Ident(&'a str),
         Await(AwaitCallOrIdentProduction<'a>),
         Start(Call<'a>),
     }
@@ -75,7 +206,7 @@ pub mod parser {
     #[derive(Debug)]
     pub struct IfStatement<'a> {
         pub condition: IfStatementCondition<'a>,
-        pub code: Vec<Statement<'a>>,
+        pub code: Vec<LocatedStatement<'a>>,
     }
 
     #[derive(Debug)]
@@ -86,7 +217,7 @@ pub mod parser {
 
     #[derive(Debug)]
     pub enum ElseStatement<'a> {
-        Code(Vec<Statement<'a>>),
+        Code(Vec<LocatedStatement<'a>>),
         IfElse(Box<IfElseStatement<'a>>),
     }
 
@@ -94,8 +225,12 @@ pub mod parser {
     pub enum Value {
         NumericRange(NumericRange),
         Number(f64),
+        Integer(i64),
         Null,
         String(String),
+        Bytes(Vec<u8>),
+        /// Microseconds since the unix epoch (UTC).
+        Timestamp(i64),
         Boolean(bool),
         Object(Vec<Property>),
         Array(Vec<Value>),
@@ -110,7 +245,15 @@ pub mod parser {
     #[derive(Debug)]
     pub struct Call<'a> {
         pub ident: &'a str,
-        pub value: Option<CallValue<'a>>,
+        pub arguments: Vec<CallValue<'a>>,
+        /// The arguments passed by name, following the positional ones.
+        pub named_arguments: Vec<NamedArgument<'a>>,
+    }
+
+    #[derive(Debug)]
+    pub struct NamedArgument<'a> {
+        pub name: &'a str,
+        pub value: CallValue<'a>,
     }
 
     #[derive(Debug)]
@@ -128,8 +271,8 @@ pub mod parser {
     use std::str::FromStr;
     use nom::branch::alt;
     use nom::bytes::complete::tag;
+    use nom::bytes::complete::take_till;
     use nom::character::complete::alpha1;
-    use nom::character::complete::anychar;
     use nom::character::complete::char;
     use nom::character::complete::digit1;
     use nom::character::complete::newline;
@@ -140,13 +283,15 @@ pub mod parser {
     use nom::InputTakeAtPosition;
     use nom::IResult;
     use nom::character::complete::alphanumeric0;
-    use nom::combinator::{complete, opt};
+    use nom::character::complete::alphanumeric1;
+    use nom::combinator::{complete, not, opt};
     use nom::combinator::map_res;
+    use nom::combinator::map_opt;
     use nom::combinator::map;
     use nom::combinator::recognize;
     use nom::multi::many0;
-    use nom::multi::many_till;
     use nom::multi::separated_list0;
+    use nom::multi::separated_list1;
     use nom::sequence::{delimited, pair};
     use nom::sequence::preceded;
     use nom::sequence::separated_pair;
@@ -154,6 +299,8 @@ pub mod parser {
     use nom::sequence::tuple;
     use tracing::trace;
     use crate::assembler::parser_string::parse_string;
+    use crate::machine::timestamp::parse_rfc3339;
+    use crate::machine::VmValueType;
 
     #[macro_export]
     macro_rules! delO {
@@ -196,16 +343,22 @@ pub mod parser {
 
     pub fn parse_x39file(input: &str) -> IResult<&str, X39File> {
         // file ::= statements |;
+        let source = input;
         let (input, statements) = complete(parse_statements)(input)?;
         Ok((input, X39File {
             statements,
+            source,
         }))
     }
 
-    pub fn parse_statements(input: &str) -> IResult<&str, Vec<Statement>> {
+    pub fn parse_statements(input: &str) -> IResult<&str, Vec<LocatedStatement>> {
         // statements ::= statement statements | statement;
         trace!("Entering parse_statements with {:?}", input);
-        let (input, statements) = many0(delO!(parse_statement))(input)?;
+        let located = |input| {
+            let remaining = str::len(input);
+            map(parse_statement, move |statement| LocatedStatement { remaining, statement })(input)
+        };
+        let (input, statements) = many0(delO!(located))(input)?;
         trace!("Exiting parse_statements with {:?}", statements);
         Ok((input, statements))
     }
@@ -222,7 +375,9 @@ pub mod parser {
             terminated(parse_start, semicolon!()),
             map(parse_if_else, |v| Statement::IfElse(v)),
             map(parse_for, |v| Statement::ForLoop(v)),
+            map(terminated(parse_declaration, semicolon!()), |v| Statement::Declaration(v)),
             map(terminated(parse_assign, semicolon!()), |v| Statement::Assignment(v)),
+            map(terminated(parse_destructuring, semicolon!()), |v| Statement::Destructuring(v)),
         ))(input)?;
         trace!("Exiting parse_statement with {:?}", statement);
         Ok((input, statement))
@@ -231,9 +386,9 @@ pub mod parser {
     pub fn parse_comment(input: &str) -> IResult<&str, Statement> {
         // comment ::= # { ANY } NEWLINE
         trace!("Entering parse_comment with {:?}", input);
-        let (input, _) = preceded(char('#'), many_till(anychar, newline))(input)?;
+        let (input, text) = delimited(char('#'), take_till(|c| c == '\n'), newline)(input)?;
         trace!("Exiting parse_comment");
-        Ok((input, Statement::Comment))
+        Ok((input, Statement::Comment(text)))
     }
 
     pub fn parse_ident(input: &str) -> IResult<&str, &str> {
@@ -247,31 +402,54 @@ pub mod parser {
         Ok((input, value))
     }
 
+    pub fn parse_function_ident(input: &str) -> IResult<&str, &str> {
+        // function_ident ::= IDENT AT version_selector | IDENT;
+        // A function may be selected by version or alias, eg. `resize@3` or `resize@stable`
+        trace!("Entering parse_function_ident with {:?}", input);
+        let (input, value) = recognize(
+            pair(
+                parse_ident,
+                opt(pair(char('@'), alphanumeric1)),
+            ))(input)?;
+        trace!("Exiting parse_function_ident");
+        Ok((input, value))
+    }
+
     pub fn parse_for<'a>(input: &'a str) -> IResult<&str, ForLoopStatement<'a>> {
-        // for ::= FOR IDENT IN for_variant code;
+        // for ::= FOR for_idents IN for_variant code;
+        // for_idents ::= IDENT COMMA IDENT | IDENT;
         trace!("Entering parse_for with {:?}", input);
         let (input, value) = tuple((
-            preceded(delR!(tag("for")), delR!(parse_ident)),
+            preceded(delR!(tag("for")), alt((
+                map(separated_pair(delO!(parse_ident), char(','), delR!(parse_ident)), |(k, v)| (Some(k), v)),
+                map(delR!(parse_ident), |v| (None, v)),
+            ))),
             preceded(delR!(tag("in")), parse_for_instruction),
             parse_code,
         ))(input)?;
         trace!("Exiting parse_for with {:?}", value);
         Ok((input, ForLoopStatement {
-            ident: value.0,
+            key: value.0.0,
+            ident: value.0.1,
             over: value.1,
             code: value.2,
         }))
     }
 
     pub fn parse_for_instruction<'a>(input: &'a str) -> IResult<&str, ForLoopInstruction<'a>> {
-        // for_variant ::=  array | for_variant_await | IDENT;
+        // for_variant ::=  array | for_variant_await | for_variant_stream | IDENT;
         // for_variant_await ::= AWAIT await_call_or_ident;
+        // for_variant_stream ::= STREAM await_call_or_ident;
         trace!("Entering parse_for_instruction with {:?}", input);
         let (input, value) = alt((
             map(parse_await_call_or_ident, |v| ForLoopInstruction::Await(match v {
                 AwaitStatement::AwaitCallOrIdent(s) => s,
                 _ => panic!("Invalid program"),
             })),
+            map(preceded(delR!(tag("stream")), alt((
+                parse_await_call,
+                parse_await_ident,
+            ))), ForLoopInstruction::Stream),
             map(parse_ident, |v| ForLoopInstruction::Ident(v)),
             map(parse_value, |v| ForLoopInstruction::Value(v)),
         ))(input)?;
@@ -280,23 +458,101 @@ pub mod parser {
     }
 
     pub fn parse_assign(input: &str) -> IResult<&str, AssignmentStatement> {
-        // assignment ::= IDENT PLUSEQUALS assignment_value | IDENT EQUALS assignment_value;
+        // assignment ::= IDENT PLUSEQUALS assignment_value | IDENT annotation EQUALS assignment_value | IDENT EQUALS assignment_value;
         trace!("Entering parse_assign with {:?}", input);
         let (input, value) = tuple((
             delO!(parse_ident),
             alt((
-                preceded(tag("+="), map(parse_assign_value, |v| AssignmentType::Append(v))),
-                preceded(tag("="), map(parse_assign_value, |v| AssignmentType::Assign(v))),
+                preceded(tag("+="), map(parse_assign_value, |v| (None, AssignmentType::Append(v)))),
+                map(
+                    separated_pair(parse_annotation, tag("="), parse_assign_value),
+                    |(annotation, v)| (Some(annotation), AssignmentType::Assign(v))),
+                preceded(tag("="), map(parse_assign_value, |v| (None, AssignmentType::Assign(v)))),
             ))))(input)?;
         trace!("Exiting parse_assign with {:?}", value);
         Ok((input, AssignmentStatement {
             ident: value.0,
+            annotation: value.1.0,
+            value: value.1.1,
+        }))
+    }
+
+    pub fn parse_destructuring(input: &str) -> IResult<&str, DestructuringStatement<'_>> {
+        // destructuring ::= SQUAREOPEN destructuring_idents SQUARECLOSE EQUALS assignment_value;
+        // destructuring_idents ::= IDENT COMMA destructuring_idents | IDENT;
+        trace!("Entering parse_destructuring with {:?}", input);
+        let (input, value) = separated_pair(
+            delimited(
+                delO!(char('[')),
+                separated_list1(delO!(char(',')), parse_ident),
+                delO!(char(']'))),
+            tag("="),
+            parse_assign_value,
+        )(input)?;
+        trace!("Exiting parse_destructuring with {:?}", value);
+        Ok((input, DestructuringStatement {
+            idents: value.0,
             value: value.1,
         }))
     }
 
+    pub fn parse_declaration(input: &str) -> IResult<&str, DeclarationStatement<'_>> {
+        // declaration ::= LET IDENT annotation EQUALS assignment_value | LET IDENT EQUALS assignment_value
+        //               | CONST IDENT annotation EQUALS assignment_value | CONST IDENT EQUALS assignment_value;
+        trace!("Entering parse_declaration with {:?}", input);
+        let (input, value) = tuple((
+            alt((
+                map(delR!(tag("let")), |_| DeclarationKind::Let),
+                map(delR!(tag("const")), |_| DeclarationKind::Const),
+            )),
+            delO!(parse_ident),
+            opt(parse_annotation),
+            preceded(tag("="), parse_assign_value),
+        ))(input)?;
+        trace!("Exiting parse_declaration with {:?}", value);
+        Ok((input, DeclarationStatement {
+            kind: value.0,
+            ident: value.1,
+            annotation: value.2,
+            value: value.3,
+        }))
+    }
+
+    pub fn parse_annotation(input: &str) -> IResult<&str, TypeAnnotation> {
+        // annotation ::= COLON type;
+        trace!("Entering parse_annotation with {:?}", input);
+        let (input, value) = preceded(char(':'), parse_type)(input)?;
+        trace!("Exiting parse_annotation with {:?}", value);
+        Ok((input, value))
+    }
+
+    pub fn parse_type(input: &str) -> IResult<&str, TypeAnnotation> {
+        // type ::= ARRAY ANGLEOPEN type ANGLECLOSE | JOB ANGLEOPEN type ANGLECLOSE | IDENT;
+        trace!("Entering parse_type with {:?}", input);
+        let (input, value) = map_opt(
+            pair(
+                delO!(parse_ident),
+                opt(delimited(delO!(char('<')), parse_type, delO!(char('>'))))),
+            |(name, inner): (&str, Option<TypeAnnotation>)| match (name, inner) {
+                ("any", None) => Some(TypeAnnotation::Any),
+                ("null", None) => Some(TypeAnnotation::Null),
+                ("boolean", None) => Some(TypeAnnotation::Boolean),
+                ("integer", None) => Some(TypeAnnotation::Integer),
+                ("number", None) => Some(TypeAnnotation::Number),
+                ("string", None) => Some(TypeAnnotation::String),
+                ("bytes", None) => Some(TypeAnnotation::Bytes),
+                ("timestamp", None) => Some(TypeAnnotation::Timestamp),
+                ("object", None) => Some(TypeAnnotation::Object),
+                ("array", inner) => Some(TypeAnnotation::Array(Box::new(inner.unwrap_or(TypeAnnotation::Any)))),
+                ("job", inner) => Some(TypeAnnotation::Job(Box::new(inner.unwrap_or(TypeAnnotation::Any)))),
+                _ => None,
+            })(input)?;
+        trace!("Exiting parse_type with {:?}", value);
+        Ok((input, value))
+    }
+
     pub fn parse_assign_value(input: &str) -> IResult<&str, AssignStatementData> {
-        // assignment_value ::= value | AWAIT await_call_or_ident | start;
+        // assignment_value ::= value | AWAIT await_call_or_ident | start | IDENT;
         trace!("Entering parse_assign_value with {:?}", input);
         let (input, data) = alt((
             map(parse_await_call_or_ident, |v| AssignStatementData::Await(match v {
@@ -308,6 +564,7 @@ pub mod parser {
                 _ => panic!("Invalid program"),
             })),
             map(parse_value, |v| AssignStatementData::Value(v)),
+            map(delO!(parse_ident), |v| AssignStatementData::Ident(v)),
         ))(input)?;
         trace!("Exiting parse_assign_value with {:?}", data);
         Ok((input, data))
@@ -402,39 +659,72 @@ pub mod parser {
     }
 
     pub fn parse_call(input: &str) -> IResult<&str, Call> {
-        // call ::= IDENT ROUNDOPEN value ROUNDCLOSE | IDENT ROUNDOPEN ROUNDCLOSE;
+        // call ::= function_ident ROUNDOPEN call_arguments ROUNDCLOSE | function_ident ROUNDOPEN ROUNDCLOSE;
         trace!("Entering parse_call with {:?}", input);
-        let (input, ident) = parse_ident(input)?;
-        let (input, value) = alt((
-            parse_call_with_value,
+        let (input, ident) = parse_function_ident(input)?;
+        let (input, (arguments, named_arguments)) = alt((
+            parse_call_with_values,
             parse_call_without_value,
         ))(input)?;
-        trace!("Exiting parse_call with {:?} and {:?}", ident, value);
+        trace!("Exiting parse_call with {:?}, {:?} and {:?}", ident, arguments, named_arguments);
         Ok((input, Call {
             ident,
-            value,
+            arguments,
+            named_arguments,
         }))
     }
 
-    pub fn parse_call_with_value(input: &str) -> IResult<&str, Option<CallValue>> {
-        trace!("Entering parse_call_with_value with {:?}", input);
-        let (input, value) = delimited(
-            delO!(char('(')),
-            alt((
-                map(parse_value, |v| CallValue::Value(v)),
-                map(parse_ident, |v| CallValue::Ident(v)),
-            )),
-            delO!(char(')')),
+    pub fn parse_call_with_values(input: &str) -> IResult<&str, (Vec<CallValue<'_>>, Vec<NamedArgument<'_>>)> {
+        // call_arguments ::= positional_arguments COMMA named_arguments | positional_arguments | named_arguments;
+        // positional_arguments ::= call_argument COMMA positional_arguments | call_argument;
+        // named_arguments ::= named_argument COMMA named_arguments | named_argument;
+        trace!("Entering parse_call_with_values with {:?}", input);
+        let (input, _) = delO!(char('('))(input)?;
+        // An identifier followed by a colon starts the named arguments
+        let (input, values) = separated_list0(
+            delO!(char(',')),
+            terminated(parse_call_value, not(char(':'))),
         )(input)?;
-        trace!("Exiting parse_call_with_value with {:?}", value);
-        Ok((input, Some(value)))
+        let (input, named_values) = match values.is_empty() {
+            true => separated_list1(delO!(char(',')), parse_named_argument)(input)?,
+            false => map(
+                opt(preceded(delO!(char(',')), separated_list1(delO!(char(',')), parse_named_argument))),
+                |v| v.unwrap_or_default(),
+            )(input)?,
+        };
+        let (input, _) = delO!(char(')'))(input)?;
+        trace!("Exiting parse_call_with_values with {:?} and {:?}", values, named_values);
+        Ok((input, (values, named_values)))
+    }
+
+    pub fn parse_call_value(input: &str) -> IResult<&str, CallValue<'_>> {
+        // call_argument ::= value | IDENT;
+        trace!("Entering parse_call_value with {:?}", input);
+        let (input, value) = alt((
+            map(parse_value, CallValue::Value),
+            map(delO!(parse_ident), CallValue::Ident),
+        ))(input)?;
+        trace!("Exiting parse_call_value with {:?}", value);
+        Ok((input, value))
     }
 
-    pub fn parse_call_without_value(input: &str) -> IResult<&str, Option<CallValue>> {
+    pub fn parse_named_argument(input: &str) -> IResult<&str, NamedArgument<'_>> {
+        // named_argument ::= IDENT COLON call_argument;
+        trace!("Entering parse_named_argument with {:?}", input);
+        let (input, (name, value)) = separated_pair(
+            delO!(parse_ident),
+            char(':'),
+            parse_call_value,
+        )(input)?;
+        trace!("Exiting parse_named_argument with {:?} and {:?}", name, value);
+        Ok((input, NamedArgument { name, value }))
+    }
+
+    pub fn parse_call_without_value(input: &str) -> IResult<&str, (Vec<CallValue<'_>>, Vec<NamedArgument<'_>>)> {
         trace!("Entering parse_call_without_value with {:?}", input);
         let (input, _) = tuple((char('('), char(')')))(input)?;
         trace!("Exiting parse_call_without_value");
-        Ok((input, None))
+        Ok((input, (vec!(), vec!())))
     }
 
     pub fn parse_await_ident(input: &str) -> IResult<&str, AwaitCallOrIdentProduction> {
@@ -508,7 +798,7 @@ pub mod parser {
         Ok((input, value))
     }
 
-    pub fn parse_code(input: &str) -> IResult<&str, Vec<Statement>> {
+    pub fn parse_code(input: &str) -> IResult<&str, Vec<LocatedStatement>> {
         // code ::= CURLYOPEN statements CURLYCLOSE | CURLYOPEN CURLYCLOSE;
         trace!("Entering parse_code with {:?}", input);
         let (input, value) =
@@ -564,18 +854,25 @@ pub mod parser {
         trace!("Entering parse_numeric with {:?}", input);
         let (input, value) = delO!(alt((
             parse_numeric_range,
-            map(parse_numeric_literal, |v| Value::Number(v)),
+            map_res(parse_numeric_literal_double, |s: &str| f64::from_str(s).map(Value::Number)),
+            map_res(parse_numeric_literal_integer, |s: &str| match i64::from_str(s) {
+                Ok(integer) => Ok(Value::Integer(integer)),
+                // Integers beyond the range of i64 are kept as numbers, as done when decoding JSON
+                Err(_) => f64::from_str(s).map(Value::Number),
+            }),
         )))(input)?;
         trace!("Exiting parse_numeric with {:?}", value);
         Ok((input, value))
     }
 
     pub fn parse_constant(input: &str) -> IResult<&str, Value> {
-        // constant ::= NULL | STRING | TRUE | FALSE;
+        // constant ::= NULL | STRING | BYTES | TIMESTAMP | TRUE | FALSE;
         trace!("Entering parse_constant with {:?}", input);
         let (input, value) = delO!(alt((
             parse_constant_null,
             parse_constant_string,
+            parse_constant_bytes,
+            parse_constant_timestamp,
             parse_constant_true,
             parse_constant_false,
         )))(input)?;
@@ -597,6 +894,31 @@ pub mod parser {
         Ok((input, Value::String(value)))
     }
 
+    pub fn parse_constant_bytes(input: &str) -> IResult<&str, Value> {
+        // BYTES ::= 'x' STRING; with the string holding pairs of hexadecimal digits
+        trace!("Entering parse_constant_bytes with {:?}", input);
+        let (input, value) = map_opt(preceded(char('x'), parse_string), |s: String| {
+            if !s.len().is_multiple_of(2) {
+                return None;
+            }
+            (0..s.len()).step_by(2)
+                .map(|i| s.get(i..i + 2).and_then(|hex| u8::from_str_radix(hex, 16).ok()))
+                .collect::<Option<Vec<u8>>>()
+        })(input)?;
+        trace!("Exiting parse_constant_bytes with {:?}", value);
+        Ok((input, Value::Bytes(value)))
+    }
+
+    pub fn parse_constant_timestamp(input: &str) -> IResult<&str, Value> {
+        // TIMESTAMP ::= 't' STRING; with the string holding an RFC 3339 date-time
+        trace!("Entering parse_constant_timestamp with {:?}", input);
+        let (input, value) = map_opt(
+            preceded(char('t'), parse_string),
+            |s: String| parse_rfc3339(&s))(input)?;
+        trace!("Exiting parse_constant_timestamp with {:?}", value);
+        Ok((input, Value::Timestamp(value)))
+    }
+
     pub fn parse_constant_true(input: &str) -> IResult<&str, Value> {
         trace!("Entering parse_constant_true with {:?}", input);
         let (input, value) = tag("true")(input)?;
@@ -665,6 +987,7 @@ pub mod parser {
 #[cfg(test)]
 mod tests {
     use tracing_test::traced_test;
+    use crate::assembler::parser::parser::{AssignStatementData, AwaitCallOrIdentProduction, CallValue, ForLoopInstruction, Statement, TypeAnnotation, Value};
 
     const TEST_FILE1: &str = r#"
     # comment
@@ -774,7 +1097,7 @@ mod tests {
         let file = super::parser::parse_comment("#asdasdasdasd\n")?;
         if !file.0.is_empty()
         { return Err(Box::from("File not fully yielded")); }
-        println!("{:?}", file.1);
+        assert!(matches!(file.1, Statement::Comment("asdasdasdasd")));
         Ok(())
     }
 
@@ -946,6 +1269,20 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    #[traced_test]
+    fn test_parse_numeric_integer_out_of_range() -> Result<(), Box<dyn std::error::Error>> {
+        let (remainder, value) = super::parser::parse_numeric("99999999999999999999")?;
+        assert!(remainder.is_empty(), "Failed to fully parse input: {:?}", remainder);
+        assert!(matches!(value, super::parser::Value::Number(number) if number == 1e20), "{:?}", value);
+        let (remainder, value) = super::parser::parse_numeric("9223372036854775807")?;
+        assert!(remainder.is_empty(), "Failed to fully parse input: {:?}", remainder);
+        assert!(matches!(value, super::parser::Value::Integer(i64::MAX)), "{:?}", value);
+        let (remainder, _) = super::parser::parse_x39file("x = 99999999999999999999;")?;
+        assert!(remainder.is_empty(), "Failed to fully parse input: {:?}", remainder);
+        Ok(())
+    }
+
     #[test]
     #[traced_test]
     fn test_parse_numeric_literal_float() -> Result<(), Box<dyn std::error::Error>> {
@@ -1237,6 +1574,53 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    #[traced_test]
+    fn test_parse_constant_bytes_requires_hex_pairs() -> Result<(), Box<dyn std::error::Error>> {
+        assert!(super::parser::parse_constant(r#"x"0a1""#).is_err());
+        assert!(super::parser::parse_constant(r#"x"0g""#).is_err());
+        assert!(super::parser::parse_constant(r#"t"yesterday""#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_parse_type_annotations() -> Result<(), Box<dyn std::error::Error>> {
+        let file = super::parser::parse_declaration("let jobs : array< job<integer> > = []")?;
+        if !file.0.is_empty()
+        { return Err(Box::from("File not fully yielded")); }
+        let expected = TypeAnnotation::Array(Box::new(TypeAnnotation::Job(Box::new(TypeAnnotation::Integer))));
+        assert_eq!(file.1.annotation, Some(expected));
+        let file = super::parser::parse_assign("list: array = []")?;
+        assert_eq!(file.1.annotation, Some(TypeAnnotation::Array(Box::new(TypeAnnotation::Any))));
+        assert!(super::parser::parse_type("integer<string>").is_err());
+        assert!(super::parser::parse_type("float").is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_parse_for_key_value() -> Result<(), Box<dyn std::error::Error>> {
+        let file = super::parser::parse_for(r#"for k, v in obj { print v; }"#)?;
+        if !file.0.is_empty()
+        { return Err(Box::from("File not fully yielded")); }
+        assert_eq!(file.1.key, Some("k"));
+        assert_eq!(file.1.ident, "v");
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_parse_for_stream() -> Result<(), Box<dyn std::error::Error>> {
+        let file = super::parser::parse_for(r#"for chunk in stream job { print chunk; }"#)?;
+        if !file.0.is_empty()
+        { return Err(Box::from("File not fully yielded")); }
+        assert!(matches!(file.1.over, ForLoopInstruction::Stream(AwaitCallOrIdentProduction::Ident("job"))));
+        let file = super::parser::parse_for(r#"for chunk in stream { print chunk; }"#)?;
+        assert!(matches!(file.1.over, ForLoopInstruction::Ident("stream")));
+        Ok(())
+    }
+
     #[test]
     #[traced_test]
     fn test_parse_for_in_range() -> Result<(), Box<dyn std::error::Error>> {
@@ -1246,4 +1630,63 @@ mod tests {
         println!("{:?}", file.1);
         Ok(())
     }
+
+    #[test]
+    #[traced_test]
+    fn test_parse_call_with_many_arguments() -> Result<(), Box<dyn std::error::Error>> {
+        let (remainder, call) = super::parser::parse_call(r#"f(a , 2, "three",[4])"#)?;
+        assert!(remainder.is_empty());
+        assert_eq!(call.ident, "f");
+        assert!(matches!(call.arguments.as_slice(), [
+            CallValue::Ident("a"),
+            CallValue::Value(Value::Integer(2)),
+            CallValue::Value(Value::String(_)),
+            CallValue::Value(Value::Array(_)),
+        ]));
+        let (_, call) = super::parser::parse_call("g()")?;
+        assert!(call.arguments.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_parse_call_with_named_arguments() -> Result<(), Box<dyn std::error::Error>> {
+        let (remainder, call) = super::parser::parse_call(r#"resize(img, width : 640, mode: "fit")"#)?;
+        assert!(remainder.is_empty());
+        assert!(matches!(call.arguments.as_slice(), [CallValue::Ident("img")]));
+        let names: Vec<&str> = call.named_arguments.iter().map(|it| it.name).collect();
+        assert_eq!(names, vec!("width", "mode"));
+        assert!(matches!(call.named_arguments[0].value, CallValue::Value(Value::Integer(640))));
+        let (_, call) = super::parser::parse_call("resize(image: img)")?;
+        assert!(call.arguments.is_empty());
+        assert!(matches!(call.named_arguments[0].value, CallValue::Ident("img")));
+        // Positional arguments cannot follow named ones
+        assert!(super::parser::parse_call("resize(width: 640, img)").is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_parse_call_selecting_a_version() -> Result<(), Box<dyn std::error::Error>> {
+        let (remainder, call) = super::parser::parse_call("resize@stable(img)")?;
+        assert!(remainder.is_empty());
+        assert_eq!(call.ident, "resize@stable");
+        let (_, call) = super::parser::parse_call("resize@3()")?;
+        assert_eq!(call.ident, "resize@3");
+        assert!(super::parser::parse_call("resize@(img)").is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_parse_destructuring() -> Result<(), Box<dyn std::error::Error>> {
+        let (remainder, statement) = super::parser::parse_statement("[x, y] = await g(1, 2);")?;
+        assert!(remainder.is_empty());
+        let Statement::Destructuring(destructuring) = statement else {
+            return Err(format!("Expected Destructuring but got {:?}", statement).into());
+        };
+        assert_eq!(destructuring.idents, vec!("x", "y"));
+        assert!(matches!(destructuring.value, AssignStatementData::Await(AwaitCallOrIdentProduction::Call(ref call)) if call.arguments.len() == 2));
+        Ok(())
+    }
 }
@@ -0,0 +1,501 @@
+//! The command-line interface of `lambda`, see [USAGE].
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+use crate::assembler::checker::{check_located, FunctionSignature};
+use crate::assembler::compiler::compiler::compile_located;
+use crate::assembler::formatter::format;
+use crate::assembler::parser::parser::{parse_x39file, Located, X39File};
+use crate::controllers::{MockController, PartialResult, VmController, VmLocalController};
+use crate::io::lambda_file::LambdaFile;
+use crate::machine::json::{self, JsonOptions};
+use crate::machine::{VmPair, VmStack, VmState, VmValue};
+
+pub mod repl;
+
+const USAGE: &str = "\
+Usage: lambda <command> [<file>] [<options>]
+
+Reads the script from stdin if no file or `-` is passed.
+
+Commands:
+    run       Runs a script or its bytecode
+    compile   Compiles a script into bytecode
+    check     Parses and type checks a script, failing if it is invalid
+    disasm    Lists the bytecode of a script or bytecode file
+    fmt       Writes a script in the canonical style
+    repl      Runs statements as they are entered, see :help once started
+    help      Shows this message, as do -h and --help
+
+Options:
+    -o, --output <file>        compile: The bytecode file written, `-` for stdout
+                               Defaults to the script with the extension .x39b
+    --controller local|mock    run, repl: Runs functions as local commands (default) or returns null for all calls
+    --manifest <file>          run, compile, check: Checks calls against the functions of a manifest
+    --format text|json         Writes printed values and errors for terminals (default) or as JSON lines
+    --check                    fmt: Fails instead of writing if the script is not formatted
+";
+
+/// The exit code of scripts that failed to run or are invalid.
+const EXIT_FAILURE: i32 = 1;
+/// The exit code of invalid command lines.
+const EXIT_USAGE: i32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OutputFormat {
+    /// Printed values as they are and errors as `file:line:column: error: message`.
+    Text,
+    /// One JSON object per printed value and error, written to stdout.
+    Json,
+}
+
+#[derive(Debug, PartialEq)]
+struct Arguments {
+    command: String,
+    input: Option<String>,
+    output: Option<String>,
+    controller: Option<String>,
+    manifest: Option<String>,
+    format: OutputFormat,
+    check: bool,
+}
+
+impl Arguments {
+    fn parse(args: &[String]) -> Result<Arguments, String> {
+        let mut args = args.iter();
+        let command = match args.next().ok_or("No command passed")?.as_str() {
+            "-h" | "--help" => "help".to_string(),
+            command => command.to_string(),
+        };
+        let mut arguments = Arguments {
+            command,
+            input: None,
+            output: None,
+            controller: None,
+            manifest: None,
+            format: OutputFormat::Text,
+            check: false,
+        };
+        while let Some(arg) = args.next() {
+            let mut value = |option: &str| args.next().cloned().ok_or_else(|| format!("Option {} expects a value", option));
+            match arg.as_str() {
+                "-o" | "--output" => arguments.output = Some(value(arg)?),
+                "--controller" => arguments.controller = Some(value(arg)?),
+                "--manifest" => arguments.manifest = Some(value(arg)?),
+                "--format" => arguments.format = match value(arg)?.as_str() {
+                    "text" => OutputFormat::Text,
+                    "json" => OutputFormat::Json,
+                    format => return Err(format!("Unknown format '{}'", format)),
+                },
+                "--check" => arguments.check = true,
+                // Asking for help takes precedence over the rest of the command line
+                "-h" | "--help" => {
+                    arguments.command = "help".to_string();
+                    return Ok(arguments);
+                }
+                option if option.starts_with('-') && option != "-" => return Err(format!("Unknown option {}", option)),
+                input if arguments.input.is_none() => arguments.input = Some(input.to_string()),
+                input => return Err(format!("Unexpected argument {}", input)),
+            }
+        }
+        arguments.validate()?;
+        Ok(arguments)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let supported = |option: &str, commands: &[&str]| match commands.contains(&self.command.as_str()) {
+            true => Ok(()),
+            false => Err(format!("Option {} is not supported by {}", option, self.command)),
+        };
+        if self.output.is_some() {
+            supported("--output", &["compile"])?;
+        }
+        if self.controller.is_some() {
+            supported("--controller", &["run", "repl"])?;
+        }
+        if self.manifest.is_some() {
+            supported("--manifest", &["run", "compile", "check"])?;
+        }
+        if self.check {
+            supported("--check", &["fmt"])?;
+        }
+        Ok(())
+    }
+}
+
+/// An error reported to the user, located in the file it was found in.
+struct Diagnostic {
+    file: String,
+    /// The line and column, both starting at 1.
+    position: Option<(usize, usize)>,
+    message: String,
+}
+
+impl Diagnostic {
+    fn new(file: &str, message: impl ToString) -> Diagnostic {
+        Diagnostic { file: file.to_string(), position: None, message: message.to_string() }
+    }
+
+    /// A diagnostic located at the start of the remainder of the input provided.
+    fn at(file: &str, input: &str, remainder: &str, message: impl ToString) -> Diagnostic {
+        let offset = input.len() - remainder.len();
+        let line_start = input[..offset].rfind('\n').map(|index| index + 1).unwrap_or(0);
+        let line = input[..offset].matches('\n').count() + 1;
+        let column = input[line_start..offset].chars().count() + 1;
+        Diagnostic { file: file.to_string(), position: Some((line, column)), message: message.to_string() }
+    }
+
+    /// A diagnostic located at the statement the error was found in.
+    fn located<E: std::fmt::Display>(file: &str, source: &str, located: &Located<E>) -> Diagnostic {
+        Diagnostic { position: Some(located.position_in(source)), ..Diagnostic::new(file, &located.error) }
+    }
+}
+
+/// Writes the output of commands in the format requested.
+struct Output<'a> {
+    format: OutputFormat,
+    stdout: RefCell<&'a mut dyn Write>,
+    stderr: RefCell<&'a mut dyn Write>,
+}
+
+impl<'a> Output<'a> {
+    fn print(&self, value: &VmValue) -> Result<(), Box<dyn Error>> {
+        let line = match (self.format, value) {
+            (OutputFormat::Text, VmValue::String(string)) => string.to_string(),
+            (OutputFormat::Text, value) => json::to_string(value, &JsonOptions::default())?,
+            (OutputFormat::Json, value) => json::to_string(&VmValue::object(vec!(
+                VmPair { key: "type".to_string(), value: VmValue::string("print") },
+                VmPair { key: "value".to_string(), value: value.clone() },
+            )), &JsonOptions::default())?,
+        };
+        writeln!(self.stdout.borrow_mut(), "{}", line)?;
+        Ok(())
+    }
+
+    fn error(&self, diagnostic: &Diagnostic) {
+        let _ = match self.format {
+            OutputFormat::Text => {
+                let location = match diagnostic.position {
+                    Some((line, column)) => format!("{}:{}:{}", diagnostic.file, line, column),
+                    None => diagnostic.file.clone(),
+                };
+                writeln!(self.stderr.borrow_mut(), "{}: error: {}", location, diagnostic.message)
+            }
+            OutputFormat::Json => {
+                let mut pairs = vec!(
+                    VmPair { key: "type".to_string(), value: VmValue::string("error") },
+                    VmPair { key: "file".to_string(), value: VmValue::string(diagnostic.file.clone()) },
+                );
+                if let Some((line, column)) = diagnostic.position {
+                    pairs.push(VmPair { key: "line".to_string(), value: VmValue::Integer(line as i64) });
+                    pairs.push(VmPair { key: "column".to_string(), value: VmValue::Integer(column as i64) });
+                }
+                pairs.push(VmPair { key: "message".to_string(), value: VmValue::string(diagnostic.message.clone()) });
+                match json::to_string(&VmValue::object(pairs), &JsonOptions::default()) {
+                    Ok(line) => writeln!(self.stdout.borrow_mut(), "{}", line),
+                    Err(error) => writeln!(self.stderr.borrow_mut(), "{}: error: {}", diagnostic.file, error),
+                }
+            }
+        };
+    }
+}
+
+/// Runs the functions of scripts using another controller, writing printed values to the output.
+struct Console<'a, 'o> {
+    controller: &'a dyn VmController,
+    output: &'a Output<'o>,
+}
+
+impl<'a, 'o> VmController for Console<'a, 'o> {
+    fn call(&self, function: String, arguments: Vec<VmValue>) -> Result<Uuid, Box<dyn Error>> {
+        self.controller.call(function, arguments)
+    }
+
+    fn get_and_remove_result_of(&self, job: Uuid) -> Result<Option<VmValue>, Box<dyn Error>> {
+        self.controller.get_and_remove_result_of(job)
+    }
+
+    fn suspend_until_all(&self, state: &VmState, jobs: Vec<Uuid>) -> Result<(), Box<dyn Error>> {
+        self.controller.suspend_until_all(state, jobs)
+    }
+
+    fn suspend_until_any(&self, state: &VmState, jobs: Vec<Uuid>) -> Result<(), Box<dyn Error>> {
+        self.controller.suspend_until_any(state, jobs)
+    }
+
+    fn abort(&self, jobs: Vec<Uuid>) -> Result<(), Box<dyn Error>> {
+        self.controller.abort(jobs)
+    }
+
+    fn take_partial_result_of(&self, job: Uuid) -> Result<PartialResult, Box<dyn Error>> {
+        self.controller.take_partial_result_of(job)
+    }
+
+    fn suspend_until_partial(&self, state: &VmState, job: Uuid) -> Result<(), Box<dyn Error>> {
+        self.controller.suspend_until_partial(state, job)
+    }
+
+    fn print(&self, value: &VmValue) -> Result<(), Box<dyn Error>> {
+        self.output.print(value)
+    }
+}
+
+/// The input of a command, being a file or stdin.
+struct Input {
+    /// The name errors are reported with.
+    name: String,
+    path: Option<PathBuf>,
+    bytes: Vec<u8>,
+}
+
+impl Input {
+    fn read(input: &Option<String>, stdin: &mut dyn Read) -> Result<Input, Diagnostic> {
+        match input.as_deref() {
+            None | Some("-") => {
+                let mut bytes = vec!();
+                stdin.read_to_end(&mut bytes).map_err(|error| Diagnostic::new("<stdin>", error))?;
+                Ok(Input { name: "<stdin>".to_string(), path: None, bytes })
+            }
+            Some(path) => {
+                let bytes = std::fs::read(path).map_err(|error| Diagnostic::new(path, error))?;
+                Ok(Input { name: path.to_string(), path: Some(PathBuf::from(path)), bytes })
+            }
+        }
+    }
+
+    fn is_bytecode(&self) -> bool {
+        self.bytes.starts_with(b"X39B")
+    }
+
+    fn text(&self) -> Result<&str, Diagnostic> {
+        std::str::from_utf8(&self.bytes).map_err(|_| Diagnostic::new(&self.name, "Script is not valid UTF-8"))
+    }
+
+    fn parse<'a>(&self, text: &'a str) -> Result<X39File<'a>, Diagnostic> {
+        let remainder = match parse_x39file(text) {
+            Ok((remainder, file)) if remainder.trim().is_empty() => return Ok(file),
+            Ok((remainder, _)) => remainder,
+            Err(nom::Err::Error(error) | nom::Err::Failure(error)) => error.input,
+            Err(nom::Err::Incomplete(_)) => "",
+        };
+        Err(Diagnostic::at(&self.name, text, remainder.trim_start(), "Failed to parse statement"))
+    }
+
+    /// The bytecode of the input, compiling it unless it is bytecode already.
+    fn bytecode(&self, signatures: &Option<Vec<FunctionSignature>>) -> Result<VmState, Vec<Diagnostic>> {
+        if self.is_bytecode() {
+            return VmState::read_bytecode(&mut self.bytes.as_slice()).map_err(|error| vec!(Diagnostic::new(&self.name, error)));
+        }
+        let text = self.text().map_err(|diagnostic| vec!(diagnostic))?;
+        let file = self.parse(text).map_err(|diagnostic| vec!(diagnostic))?;
+        // Only signatures read from a manifest are complete, see [check_strict]
+        let strict = signatures.is_some();
+        let signatures = signatures.as_deref().unwrap_or(&[]);
+        check_located(&file, signatures, strict)
+            .map_err(|errors| errors.iter().map(|error| Diagnostic::located(&self.name, text, error)).collect::<Vec<_>>())?;
+        compile_located(file, signatures).map_err(|error| vec!(Diagnostic::located(&self.name, text, &error)))
+    }
+}
+
+/// Runs the command line passed, without the name of the executable, returning the exit code.
+pub fn run(args: &[String], stdin: &mut dyn Read, stdout: &mut dyn Write, stderr: &mut dyn Write) -> i32 {
+    let arguments = match Arguments::parse(args) {
+        Ok(arguments) if arguments.command == "help" => {
+            let _ = write!(stdout, "{}", USAGE);
+            return 0;
+        }
+        Ok(arguments) => arguments,
+        Err(error) => {
+            let _ = write!(stderr, "error: {}\n\n{}", error, USAGE);
+            return EXIT_USAGE;
+        }
+    };
+    let output = Output { format: arguments.format, stdout: RefCell::new(stdout), stderr: RefCell::new(stderr) };
+    let result = match arguments.command.as_str() {
+        "run" => run_command(&arguments, stdin, &output),
+        "compile" => compile_command(&arguments, stdin, &output),
+        "check" => check_command(&arguments, stdin),
+        "disasm" => disasm_command(&arguments, stdin, &output),
+        "fmt" => fmt_command(&arguments, stdin, &output),
+        "repl" => repl::run(&arguments, stdin, &output),
+        command => {
+            let _ = write!(output.stderr.borrow_mut(), "error: Unknown command {}\n\n{}", command, USAGE);
+            return EXIT_USAGE;
+        }
+    };
+    let _ = output.stdout.borrow_mut().flush();
+    match result {
+        Ok(()) => 0,
+        Err(diagnostics) => {
+            for diagnostic in diagnostics.iter() {
+                output.error(diagnostic);
+            }
+            EXIT_FAILURE
+        }
+    }
+}
+
+fn signatures(arguments: &Arguments) -> Result<Option<Vec<FunctionSignature>>, Vec<Diagnostic>> {
+    match &arguments.manifest {
+        None => Ok(None),
+        Some(manifest) => LambdaFile::load(Path::new(manifest))
+            .map(|file| Some(file.signatures()))
+            .map_err(|error| vec!(Diagnostic::new(manifest, error))),
+    }
+}
+
+/// The controller running the functions called, as selected by `--controller`.
+fn controller(arguments: &Arguments) -> Result<Box<dyn VmController>, String> {
+    match arguments.controller.as_deref() {
+        None | Some("local") => Ok(Box::new(VmLocalController::new())),
+        Some("mock") => Ok(Box::new(MockController::new())),
+        Some(controller) => Err(format!("Unknown controller '{}'", controller)),
+    }
+}
+
+fn run_command(arguments: &Arguments, stdin: &mut dyn Read, output: &Output) -> Result<(), Vec<Diagnostic>> {
+    let input = Input::read(&arguments.input, stdin).map_err(|diagnostic| vec!(diagnostic))?;
+    let mut vm_state = input.bytecode(&signatures(arguments)?)?;
+    let controller = controller(arguments).map_err(|error| vec!(Diagnostic::new(&input.name, error)))?;
+    let console = Console { controller: controller.as_ref(), output };
+    let mut vm_stack = VmStack::new();
+    while !vm_state.is_done() {
+        if let Err(error) = vm_state.step(&mut vm_stack, &console) {
+            let position = vm_state.last_position().map(|it| (it.line, it.column));
+            return Err(vec!(Diagnostic { position, ..Diagnostic::new(&input.name, error) }));
+        }
+    }
+    Ok(())
+}
+
+fn compile_command(arguments: &Arguments, stdin: &mut dyn Read, output: &Output) -> Result<(), Vec<Diagnostic>> {
+    let input = Input::read(&arguments.input, stdin).map_err(|diagnostic| vec!(diagnostic))?;
+    let vm_state = input.bytecode(&signatures(arguments)?)?;
+    let path = match (&arguments.output, &input.path) {
+        (Some(path), _) => path.clone(),
+        (None, Some(path)) => path.with_extension("x39b").display().to_string(),
+        (None, None) => return Err(vec!(Diagnostic::new(&input.name, "Scripts read from stdin require --output"))),
+    };
+    let written = match path.as_str() {
+        "-" => vm_state.write_bytecode(*output.stdout.borrow_mut()),
+        path => std::fs::File::create(path)
+            .map_err(|error| error.into())
+            .and_then(|mut file| vm_state.write_bytecode(&mut file)),
+    };
+    written.map_err(|error| vec!(Diagnostic::new(&path, error)))
+}
+
+fn check_command(arguments: &Arguments, stdin: &mut dyn Read) -> Result<(), Vec<Diagnostic>> {
+    let input = Input::read(&arguments.input, stdin).map_err(|diagnostic| vec!(diagnostic))?;
+    input.bytecode(&signatures(arguments)?).map(|_| ())
+}
+
+fn disasm_command(arguments: &Arguments, stdin: &mut dyn Read, output: &Output) -> Result<(), Vec<Diagnostic>> {
+    let input = Input::read(&arguments.input, stdin).map_err(|diagnostic| vec!(diagnostic))?;
+    let vm_state = input.bytecode(&None)?;
+    write!(output.stdout.borrow_mut(), "{}", vm_state.disassemble()).map_err(|error| vec!(Diagnostic::new(&input.name, error)))
+}
+
+fn fmt_command(arguments: &Arguments, stdin: &mut dyn Read, output: &Output) -> Result<(), Vec<Diagnostic>> {
+    let input = Input::read(&arguments.input, stdin).map_err(|diagnostic| vec!(diagnostic))?;
+    let text = input.text().map_err(|diagnostic| vec!(diagnostic))?;
+    let formatted = format(&input.parse(text).map_err(|diagnostic| vec!(diagnostic))?);
+    if arguments.check {
+        return match formatted == text {
+            true => Ok(()),
+            false => Err(vec!(Diagnostic::new(&input.name, "Script is not formatted"))),
+        };
+    }
+    write!(output.stdout.borrow_mut(), "{}", formatted).map_err(|error| vec!(Diagnostic::new(&input.name, error)))
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_test::traced_test;
+    use crate::cli::{run, EXIT_USAGE, USAGE};
+
+    /// Runs the command line, returning the exit code, stdout and stderr.
+    fn lambda(args: &[&str], stdin: &[u8]) -> (i32, Vec<u8>, String) {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let mut stdout = vec!();
+        let mut stderr = vec!();
+        let code = run(&args, &mut &stdin[..], &mut stdout, &mut stderr);
+        (code, stdout, String::from_utf8_lossy(&stderr).to_string())
+    }
+
+    #[test]
+    #[traced_test]
+    fn scripts_are_run_with_prints_written() -> Result<(), Box<dyn std::error::Error>> {
+        let script = b"a = [1, 2]; print a; b = await resize(a); s = \"done\"; print s; print b;";
+        let (code, stdout, stderr) = lambda(&["run", "--controller", "mock"], script);
+        assert_eq!((code, stderr.as_str()), (0, ""));
+        assert_eq!(String::from_utf8(stdout)?, "[1,2]\ndone\nnull\n");
+        let (code, _, stderr) = lambda(&["run", "--controller", "mock"], b"x = 1;\nfor i in [1] {\n    n: integer = await count(i);\n}\n");
+        assert_eq!((code, stderr.as_str()), (1, "<stdin>:3:5: error: AssertType found a value not matching the expected type Integer of variable 'n'.\n"));
+        let (code, stdout, _) = lambda(&["run", "-", "--controller", "mock", "--format", "json"], b"s = \"done\"; print s; n: integer = await count();");
+        assert_eq!(code, 1);
+        assert_eq!(String::from_utf8(stdout)?, "\
+{\"type\":\"print\",\"value\":\"done\"}
+{\"type\":\"error\",\"file\":\"<stdin>\",\"line\":1,\"column\":22,\"message\":\"AssertType found a value not matching the expected type Integer of variable 'n'.\"}
+");
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn help_is_written_to_stdout() -> Result<(), Box<dyn std::error::Error>> {
+        for args in [&["help"][..], &["--help"], &["-h"], &["run", "-h"]] {
+            let (code, stdout, stderr) = lambda(args, b"");
+            assert_eq!((code, String::from_utf8(stdout)?, stderr), (0, USAGE.to_string(), String::new()), "{:?}", args);
+        }
+        let (code, stdout, stderr) = lambda(&["--unknown"], b"");
+        assert_eq!((code, stdout.len()), (EXIT_USAGE, 0));
+        assert!(stderr.ends_with(USAGE), "{}", stderr);
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn check_fails_for_invalid_scripts() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(lambda(&["check"], b"let x: integer = 1;"), (0, vec!(), String::new()));
+        let (code, _, stderr) = lambda(&["check"], b"let x: integer = 1;\nx = \"text\";\n  if {");
+        assert_eq!((code, stderr.as_str()), (1, "<stdin>:3:3: error: Failed to parse statement\n"));
+        let (code, _, stderr) = lambda(&["check"], b"let x: integer = 1; x = \"text\";");
+        assert_eq!((code, stderr.as_str()), (1, "<stdin>:1:21: error: Cannot store string in 'x' which is annotated as integer\n"));
+        let (code, _, stderr) = lambda(&["check"], b"let x = 1;\nfor i in 0..2 {\n    print missing;\n}");
+        assert_eq!((code, stderr.as_str()), (1, "<stdin>:3:5: error: Variable 'missing' is not declared\n"));
+        let (code, _, stderr) = lambda(&["check", "--check"], b"");
+        assert_eq!(code, 2);
+        assert!(stderr.starts_with("error: Option --check is not supported by check\n"), "{}", stderr);
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn compiled_bytecode_is_run_and_disassembled() -> Result<(), Box<dyn std::error::Error>> {
+        let (code, bytecode, stderr) = lambda(&["compile", "-o", "-"], b"x = 40; x += 2; print x;");
+        assert_eq!((code, stderr.as_str()), (0, ""));
+        assert!(bytecode.starts_with(b"X39B"));
+        let (code, stdout, _) = lambda(&["run", "--controller", "mock"], &bytecode);
+        assert_eq!((code, String::from_utf8(stdout)?), (0, "42\n".to_string()));
+        let (_, from_bytecode, _) = lambda(&["disasm"], &bytecode);
+        let (_, from_source, _) = lambda(&["disasm"], b"x = 40; x += 2; print x;");
+        assert_eq!(String::from_utf8(from_bytecode)?, String::from_utf8(from_source.clone())?);
+        assert!(String::from_utf8(from_source)?.contains("\n    0003: AddLocal 0               ; x\n"));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn scripts_are_formatted() -> Result<(), Box<dyn std::error::Error>> {
+        let (code, stdout, _) = lambda(&["fmt"], b"x=1;if x{print x;}");
+        assert_eq!((code, String::from_utf8(stdout.clone())?), (0, "x = 1;\nif x {\n    print x;\n}\n".to_string()));
+        assert_eq!(lambda(&["fmt", "--check"], &stdout).0, 0);
+        let (code, _, stderr) = lambda(&["fmt", "--check"], b"x=1;");
+        assert_eq!((code, stderr.as_str()), (1, "<stdin>: error: Script is not formatted\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+//! The interactive `lambda repl`, running statements as they are entered.
+
+use std::io::{BufRead, BufReader, Read};
+use crate::assembler::checker::check_located;
+use crate::assembler::compiler::compiler::compile_located;
+use crate::assembler::parser::parser::parse_x39file;
+use crate::controllers::VmController;
+use crate::machine::json::{self, JsonOptions};
+use crate::machine::{VmStack, VmState};
+use super::{controller, Arguments, Console, Diagnostic, Input, Output};
+
+const HELP: &str = "\
+Statements run once complete, blocks and statements lacking their `;` continue on the next line.
+An empty line runs the statements entered regardless.
+
+Commands:
+    :vars      Lists the variables and their values
+    :stack     Lists the values and locals of the stack
+    :disasm    Lists the bytecode of all statements entered
+    :reset     Forgets all variables and statements entered
+    :help      Shows this message
+    :quit      Exits, as does the end of input
+";
+
+/// The name errors of statements entered are reported with.
+const INPUT_NAME: &str = "<repl>";
+
+/// The statements entered and the stack they ran on.
+///
+/// As variables are resolved to slots when compiling, all statements entered are compiled with
+/// every input, running only the instructions appended by the input on the stack kept.
+struct Session {
+    /// The source of all statements that ran successfully.
+    source: String,
+    vm_state: VmState,
+    vm_stack: VmStack,
+}
+
+impl Session {
+    fn new() -> Session {
+        Session { source: String::new(), vm_state: VmState::new(), vm_stack: VmStack::new() }
+    }
+
+    /// Runs the statements provided after those entered before. Statements failing to compile
+    /// are discarded, as are those failing to run, though effects of the instructions that ran
+    /// before the failure remain.
+    fn evaluate(&mut self, name: &str, input: &str, controller: &dyn VmController) -> Result<(), Vec<Diagnostic>> {
+        match parse_x39file(input) {
+            Ok((remainder, _)) if remainder.trim().is_empty() => {}
+            Ok((remainder, _)) => return Err(vec!(Diagnostic::at(name, input, remainder.trim_start(), "Failed to parse statement"))),
+            Err(_) => return Err(vec!(Diagnostic::at(name, input, input, "Failed to parse statement"))),
+        }
+        let source = format!("{}{}\n", self.source, input);
+        let (_, file) = parse_x39file(&source).map_err(|error| vec!(Diagnostic::new(name, error)))?;
+        // Positions are relative to the input rather than all statements entered
+        let entered = &source[self.source.len()..];
+        check_located(&file, &[], false)
+            .map_err(|errors| errors.iter().map(|error| Diagnostic::located(name, entered, error)).collect::<Vec<_>>())?;
+        let mut vm_state = compile_located(file, &[]).map_err(|error| vec!(Diagnostic::located(name, entered, &error)))?;
+        vm_state.resume_at(self.vm_state.instructions().len());
+        while !vm_state.is_done() {
+            if let Err(error) = vm_state.step(&mut self.vm_stack, controller) {
+                // Positions are relative to the input rather than all statements compiled
+                let lines_before = self.source.matches('\n').count();
+                let position = vm_state.last_position().map(|it| (it.line.saturating_sub(lines_before), it.column));
+                return Err(vec!(Diagnostic { position, ..Diagnostic::new(name, error) }));
+            }
+        }
+        self.source = source;
+        self.vm_state = vm_state;
+        Ok(())
+    }
+
+    /// The variables visible after the last statement, with their values.
+    fn variables(&self) -> Vec<String> {
+        let end = self.vm_state.instructions().len();
+        let mut locals: Vec<_> = self.vm_state.locals().iter()
+            .filter(|local| local.from <= end && end < local.to)
+            .collect();
+        locals.sort_by_key(|local| local.slot);
+        locals.iter()
+            .map(|local| {
+                let value = match self.vm_stack.get_local(local.slot) {
+                    Some(value) => json::to_string(value, &JsonOptions::default()).unwrap_or_else(|_| format!("{:?}", value)),
+                    None => "null".to_string(),
+                };
+                format!("{} = {}", local.name, value)
+            })
+            .collect()
+    }
+}
+
+/// Whether the input lacks the end of a block, string or statement, to continue reading it on
+/// the next line.
+fn is_incomplete(input: &str) -> bool {
+    let mut depth = 0i32;
+    let mut last = None;
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => {
+                chars.by_ref().find(|c| *c == '\n');
+                continue;
+            }
+            '"' => loop {
+                match chars.next() {
+                    None => return true,
+                    Some('\\') => { chars.next(); }
+                    Some('"') => break,
+                    Some(_) => {}
+                }
+            },
+            '{' | '[' | '(' => depth += 1,
+            '}' | ']' | ')' => depth -= 1,
+            _ => {}
+        }
+        if !c.is_whitespace() {
+            last = Some(c);
+        }
+    }
+    depth > 0 || !matches!(last, None | Some(';') | Some('}'))
+}
+
+/// Reads statements from the input line by line, running them once complete.
+pub(super) fn run(arguments: &Arguments, stdin: &mut dyn Read, output: &Output) -> Result<(), Vec<Diagnostic>> {
+    let controller = controller(arguments).map_err(|error| vec!(Diagnostic::new(INPUT_NAME, error)))?;
+    let console = Console { controller: controller.as_ref(), output };
+    let mut session = Session::new();
+    if arguments.input.is_some() {
+        // The script passed runs first, as if it was entered
+        let input = Input::read(&arguments.input, &mut std::io::empty()).map_err(|diagnostic| vec!(diagnostic))?;
+        let text = input.text().map_err(|diagnostic| vec!(diagnostic))?;
+        session.evaluate(&input.name, text, &console)?;
+    }
+    let mut lines = BufReader::new(stdin).lines();
+    let mut buffer = String::new();
+    loop {
+        let _ = write!(output.stderr.borrow_mut(), "{}", if buffer.is_empty() { "> " } else { "... " });
+        let _ = output.stderr.borrow_mut().flush();
+        let line = match lines.next() {
+            None => break,
+            Some(line) => line.map_err(|error| vec!(Diagnostic::new(INPUT_NAME, error)))?,
+        };
+        if buffer.is_empty() && line.trim_start().starts_with(':') {
+            match line.trim() {
+                ":quit" | ":q" => break,
+                command => run_command(command, &mut session, output),
+            }
+            continue;
+        }
+        if buffer.is_empty() && line.trim().is_empty() {
+            continue;
+        }
+        buffer.push_str(&line);
+        buffer.push('\n');
+        if !line.trim().is_empty() && is_incomplete(&buffer) {
+            continue;
+        }
+        if let Err(diagnostics) = session.evaluate(INPUT_NAME, &buffer, &console) {
+            for diagnostic in diagnostics.iter() {
+                output.error(diagnostic);
+            }
+        }
+        buffer.clear();
+    }
+    let _ = writeln!(output.stderr.borrow_mut());
+    Ok(())
+}
+
+fn run_command(command: &str, session: &mut Session, output: &Output) {
+    let written = match command {
+        ":vars" => session.variables().iter().try_for_each(|variable| writeln!(output.stdout.borrow_mut(), "{}", variable)),
+        ":stack" => write!(output.stdout.borrow_mut(), "{:?}", session.vm_stack),
+        ":disasm" => write!(output.stdout.borrow_mut(), "{}", session.vm_state.disassemble()),
+        ":reset" => {
+            *session = Session::new();
+            Ok(())
+        }
+        ":help" => write!(output.stdout.borrow_mut(), "{}", HELP),
+        command => {
+            output.error(&Diagnostic::new(INPUT_NAME, format!("Unknown command {}, see :help", command)));
+            Ok(())
+        }
+    };
+    if let Err(error) = written {
+        output.error(&Diagnostic::new(INPUT_NAME, error));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_test::traced_test;
+    use crate::cli::repl::is_incomplete;
+    use crate::cli::run;
+
+    /// Runs the repl with the lines provided, returning stdout and stderr.
+    fn repl(args: &[&str], lines: &str) -> (String, String) {
+        let mut args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        args.insert(0, "repl".to_string());
+        let mut stdout = vec!();
+        let mut stderr = vec!();
+        assert_eq!(run(&args, &mut lines.as_bytes(), &mut stdout, &mut stderr), 0);
+        (String::from_utf8_lossy(&stdout).to_string(), String::from_utf8_lossy(&stderr).to_string())
+    }
+
+    #[test]
+    #[traced_test]
+    fn variables_survive_between_inputs() {
+        let (stdout, stderr) = repl(&["--controller", "mock"], "\
+let x = 40;
+x += 2;
+print x;
+job = start resize(x);
+y = await job; text = \"a\";
+print missing;
+n: integer = await count();
+:vars
+:reset
+:vars
+print x;
+");
+        let lines: Vec<&str> = stdout.lines().collect();
+        assert_eq!(lines.len(), 5, "{}", stdout);
+        assert_eq!(&lines[..2], &["42", "x = 42"]);
+        assert!(lines[2].starts_with("job = {\"$job\":"), "{}", lines[2]);
+        assert_eq!(&lines[3..], &["y = null", "text = \"a\""]);
+        assert_eq!(stderr.matches("<repl>:1:1: error: Variable 'missing' is not declared").count(), 1);
+        assert_eq!(stderr.matches("<repl>:1:1: error: Variable 'x' is not declared").count(), 1);
+        assert_eq!(stderr.matches("<repl>:1:1: error: AssertType found a value not matching the expected type Integer of variable 'n'.").count(), 1);
+    }
+
+    #[test]
+    #[traced_test]
+    fn blocks_continue_on_the_next_line() {
+        let (stdout, stderr) = repl(&["--controller", "mock"], "\
+total = 0;
+for i in [1, 2, 3] {
+    total += i;
+}
+text = \"{\"
+;
+print total;
+if true {
+
+:stack
+");
+        assert_eq!(stdout, "6\nValues: 0\nLocals: 2\n    0000: Integer(6)\n    0001: String(\"{\")\n");
+        assert_eq!(stderr, "> > ... ... > ... > > ... <repl>:1:1: error: Failed to parse statement\n> > \n");
+    }
+
+    #[test]
+    #[traced_test]
+    fn incomplete_input_is_detected() {
+        assert!(!is_incomplete("x = 1;\n"));
+        assert!(!is_incomplete("if x {\n}\n"));
+        assert!(!is_incomplete("# only a comment ;(\n"));
+        assert!(is_incomplete("x = 1\n"));
+        assert!(is_incomplete("if x {\n"));
+        assert!(is_incomplete("x = \"unterminated;\n"));
+        assert!(is_incomplete("x = [1,\n"));
+        assert!(!is_incomplete("x = \"}\\\"\";\n"));
+    }
+}
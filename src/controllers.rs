@@ -1,5 +1,12 @@
 pub mod vm_local_controller;
 pub mod vm_controller;
+pub mod protocol_controller;
+pub mod function_registry;
+pub mod reloader;
+pub mod mock_controller;
 
 pub use self::vm_local_controller::*;
 pub use self::vm_controller::*;
+pub use self::protocol_controller::*;
+pub use self::function_registry::*;
+pub use self::mock_controller::*;
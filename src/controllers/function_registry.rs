@@ -0,0 +1,385 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::sync::Mutex;
+use crate::io::lambda_file::{LambdaFunction, Route};
+
+/// The version functions are registered as when discovered at function hosts, rather than
+/// declared by a manifest.
+pub const DISCOVERED_VERSION: u32 = 0;
+
+/// Why the function called could not be resolved to a host serving it.
+#[derive(Debug, PartialEq)]
+pub enum RegistryError {
+    NotProvided { function: String },
+    UnknownVersion { function: String, version: u32 },
+    UnknownAlias { function: String, alias: String },
+    Disabled { function: String },
+}
+
+impl Display for RegistryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegistryError::NotProvided { function } =>
+                write!(f, "Function {} is not provided by any function host", function),
+            RegistryError::UnknownVersion { function, version } =>
+                write!(f, "Function {} has no version {}", function, version),
+            RegistryError::UnknownAlias { function, alias } =>
+                write!(f, "Function {} has no alias '{}'", function, alias),
+            RegistryError::Disabled { function } =>
+                write!(f, "Function {} is disabled", function),
+        }
+    }
+}
+
+impl Error for RegistryError {}
+
+/// The version a call was resolved to.
+#[derive(Debug)]
+pub struct Resolved<'a> {
+    pub function: &'a str,
+    pub version: u32,
+    pub host: usize,
+    /// The declaration of the version, absent for functions only discovered.
+    pub definition: Option<&'a LambdaFunction>,
+}
+
+#[derive(Debug, Default)]
+struct Deployment {
+    /// The host launched for the version, versions declared without one being served by the
+    /// host the function was discovered at.
+    host: Option<usize>,
+    definition: Option<LambdaFunction>,
+}
+
+impl Deployment {
+    fn is_disabled(&self) -> bool {
+        self.definition.as_ref().is_some_and(|definition| definition.disabled)
+    }
+}
+
+#[derive(Debug, Default)]
+struct RegisteredFunction {
+    versions: BTreeMap<u32, Deployment>,
+    aliases: HashMap<String, u32>,
+    weights: Vec<(u32, u32)>,
+    /// The current weights of the smooth weighted round-robin, one per weight.
+    current: Mutex<Vec<i64>>,
+}
+
+impl RegisteredFunction {
+    /// Whether any version was declared, the version discovered only being called otherwise.
+    fn is_declared(&self) -> bool {
+        self.versions.keys().any(|version| *version != DISCOVERED_VERSION)
+    }
+
+    fn host(&self, version: u32) -> Option<usize> {
+        let deployment = self.versions.get(&version)?;
+        deployment.host.or_else(|| self.versions.get(&DISCOVERED_VERSION).and_then(|discovered| discovered.host))
+    }
+
+    fn is_callable(&self, version: u32) -> bool {
+        (version != DISCOVERED_VERSION || !self.is_declared())
+            && self.versions.get(&version).is_some_and(|deployment| !deployment.is_disabled())
+            && self.host(version).is_some()
+    }
+
+    /// Picks the version of a call not selecting one. Calls are split between the versions
+    /// weighted using a smooth weighted round-robin, going to the latest version callable if
+    /// there are no weights.
+    fn pick(&self) -> Option<u32> {
+        let weighted: Vec<(usize, u32, u32)> = self.weights.iter().enumerate()
+            .filter(|(_, (version, weight))| *weight > 0 && self.is_callable(*version))
+            .map(|(index, (version, weight))| (index, *version, *weight))
+            .collect();
+        if weighted.is_empty() {
+            return self.versions.keys().rev().copied().find(|version| self.is_callable(*version));
+        }
+        let mut current = self.current.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        current.resize(self.weights.len(), 0);
+        let total: i64 = weighted.iter().map(|(_, _, weight)| *weight as i64).sum();
+        let mut picked = weighted[0];
+        for entry in weighted.iter() {
+            current[entry.0] += entry.2 as i64;
+            if current[entry.0] > current[picked.0] {
+                picked = *entry;
+            }
+        }
+        current[picked.0] -= total;
+        Some(picked.1)
+    }
+}
+
+/// The functions callable, populated from manifests and capability discovery.
+///
+/// Functions may be deployed in multiple versions, scripts selecting one by number or alias,
+/// eg. `resize@3` or `resize@stable`. Calls not selecting a version are split between versions
+/// by their weights, allowing canary rollouts, or go to the latest version otherwise.
+/// Calling disabled versions fails.
+#[derive(Debug, Default)]
+pub struct FunctionRegistry {
+    functions: HashMap<String, RegisteredFunction>,
+}
+
+impl FunctionRegistry {
+    pub fn new() -> FunctionRegistry {
+        FunctionRegistry::default()
+    }
+
+    /// Declares a version of a function, served by the host provided or, if there is none, by
+    /// the host the function is discovered at.
+    pub fn declare(&mut self, definition: &LambdaFunction, host: Option<usize>) {
+        let function = self.functions.entry(definition.identifier.clone()).or_default();
+        function.versions.insert(definition.version, Deployment {
+            host,
+            definition: Some(definition.clone()),
+        });
+    }
+
+    /// The versions declared and the hosts launched for them.
+    pub fn declarations(&self) -> Vec<(&LambdaFunction, Option<usize>)> {
+        let mut declarations: Vec<(&LambdaFunction, Option<usize>)> = self.functions.values()
+            .flat_map(|function| function.versions.values())
+            .filter_map(|deployment| deployment.definition.as_ref().map(|definition| (definition, deployment.host)))
+            .collect();
+        declarations.sort_by(|a, b| (&a.0.identifier, a.0.version).cmp(&(&b.0.identifier, b.0.version)));
+        declarations
+    }
+
+    /// Removes all versions declared and routes, keeping the functions discovered.
+    pub fn clear_declarations(&mut self) {
+        for function in self.functions.values_mut() {
+            function.versions.retain(|version, _| *version == DISCOVERED_VERSION);
+            function.aliases.clear();
+            function.weights.clear();
+            function.current = Mutex::new(vec!());
+        }
+        self.functions.retain(|_, function| !function.versions.is_empty());
+    }
+
+    /// Sets the host a function was discovered at, removing it if there is none.
+    pub fn discover(&mut self, function: &str, host: Option<usize>) {
+        let registered = self.functions.entry(function.to_string()).or_default();
+        match host {
+            Some(host) => {
+                registered.versions.entry(DISCOVERED_VERSION).or_default().host = Some(host);
+            }
+            None => {
+                registered.versions.remove(&DISCOVERED_VERSION);
+            }
+        }
+    }
+
+    /// The host the function provided was discovered at.
+    pub fn discovered_host(&self, function: &str) -> Option<usize> {
+        self.functions.get(function)?.versions.get(&DISCOVERED_VERSION)?.host
+    }
+
+    /// Unbinds the versions launched at the host provided, returning the functions discovered
+    /// at it.
+    pub fn unbind_host(&mut self, host: usize) -> Vec<String> {
+        let mut discovered = vec!();
+        for (name, function) in self.functions.iter_mut() {
+            for (version, deployment) in function.versions.iter_mut() {
+                if deployment.host != Some(host) {
+                    continue;
+                }
+                if *version == DISCOVERED_VERSION {
+                    discovered.push(name.clone());
+                } else {
+                    deployment.host = None;
+                }
+            }
+        }
+        discovered.sort();
+        discovered
+    }
+
+    /// Replaces the aliases and weights of the function routed.
+    pub fn route(&mut self, route: &Route) {
+        let function = self.functions.entry(route.function.clone()).or_default();
+        function.aliases = route.aliases.iter().cloned().collect();
+        function.weights = route.weights.clone();
+        function.current = Mutex::new(vec!());
+    }
+
+    pub fn set_alias(&mut self, function: &str, alias: &str, version: u32) {
+        let function = self.functions.entry(function.to_string()).or_default();
+        function.aliases.insert(alias.to_string(), version);
+    }
+
+    /// Sets the share of the calls not selecting a version each version receives.
+    pub fn set_weights(&mut self, function: &str, weights: Vec<(u32, u32)>) {
+        let function = self.functions.entry(function.to_string()).or_default();
+        function.weights = weights;
+        function.current = Mutex::new(vec!());
+    }
+
+    /// Disables or enables the declared version of a function provided.
+    pub fn set_disabled(&mut self, function: &str, version: u32, disabled: bool) -> Result<(), RegistryError> {
+        let deployment = self.functions.get_mut(function)
+            .and_then(|registered| registered.versions.get_mut(&version))
+            .ok_or_else(|| RegistryError::UnknownVersion { function: function.to_string(), version })?;
+        let definition = deployment.definition.as_mut()
+            .ok_or_else(|| RegistryError::UnknownVersion { function: function.to_string(), version })?;
+        definition.disabled = disabled;
+        Ok(())
+    }
+
+    /// The names of all functions served by any host, sorted.
+    pub fn names(&self) -> Vec<&String> {
+        let mut names: Vec<&String> = self.functions.keys()
+            .filter(|name| self.is_served(name))
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Whether the function provided is served by any host.
+    pub fn is_served(&self, function: &str) -> bool {
+        self.provider(function).is_some()
+    }
+
+    /// A host serving any version of the function provided, preferring later versions.
+    pub fn provider(&self, function: &str) -> Option<usize> {
+        let registered = self.functions.get(function)?;
+        registered.versions.keys().rev().find_map(|version| registered.host(*version))
+    }
+
+    /// The host serving the version of the function provided.
+    pub fn host(&self, function: &str, version: u32) -> Option<usize> {
+        self.functions.get(function)?.host(version)
+    }
+
+    pub fn definition(&self, function: &str, version: u32) -> Option<&LambdaFunction> {
+        self.functions.get(function)?.versions.get(&version)?.definition.as_ref()
+    }
+
+    /// Resolves the function called by a script, eg. `resize`, `resize@3` or `resize@stable`,
+    /// to the version and host to call.
+    pub fn resolve(&self, name: &str) -> Result<Resolved<'_>, RegistryError> {
+        let (function_name, selector) = match name.split_once('@') {
+            Some((function_name, selector)) => (function_name, Some(selector)),
+            None => (name, None),
+        };
+        let Some((function_name, function)) = self.functions.get_key_value(function_name) else {
+            return Err(RegistryError::NotProvided { function: name.to_string() });
+        };
+        let version = match selector {
+            Some(selector) => {
+                let version = match selector.parse::<u32>() {
+                    Ok(version) => version,
+                    Err(_) => *function.aliases.get(selector).ok_or_else(|| RegistryError::UnknownAlias {
+                        function: function_name.clone(),
+                        alias: selector.to_string(),
+                    })?,
+                };
+                if !function.versions.contains_key(&version) || (version == DISCOVERED_VERSION && function.is_declared()) {
+                    return Err(RegistryError::UnknownVersion { function: function_name.clone(), version });
+                }
+                version
+            }
+            None => match function.pick() {
+                Some(version) => version,
+                None if function.versions.iter().any(|(_, deployment)| deployment.is_disabled()) =>
+                    return Err(RegistryError::Disabled { function: name.to_string() }),
+                None => return Err(RegistryError::NotProvided { function: name.to_string() }),
+            },
+        };
+        let deployment = &function.versions[&version];
+        if deployment.is_disabled() {
+            return Err(RegistryError::Disabled { function: name.to_string() });
+        }
+        let host = function.host(version).ok_or_else(|| RegistryError::NotProvided { function: name.to_string() })?;
+        Ok(Resolved {
+            function: function_name,
+            version,
+            host,
+            definition: deployment.definition.as_ref(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_test::traced_test;
+    use crate::controllers::function_registry::{FunctionRegistry, RegistryError};
+    use crate::io::lambda_file::LambdaFile;
+
+    fn registry() -> Result<FunctionRegistry, Box<dyn std::error::Error>> {
+        let file = LambdaFile::parse(concat!(
+            "[functions.resize]\ncommand = \"resize-v1\"\n",
+            "[functions.resize.2]\ncommand = \"resize-v2\"\n",
+            "[functions.resize.3]\ncommand = \"resize-v3\"\n",
+            "[functions.crop]\ncommand = \"crop\"\ndisabled = true\n",
+            "[routes.resize]\naliases = { stable = 2, canary = 3 }\n",
+        ))?;
+        let mut registry = FunctionRegistry::new();
+        for (host, function) in file.functions.iter().enumerate() {
+            registry.declare(function, Some(host));
+        }
+        for route in file.routes.iter() {
+            registry.route(route);
+        }
+        Ok(registry)
+    }
+
+    #[test]
+    #[traced_test]
+    fn versions_are_selected_by_number_or_alias() -> Result<(), Box<dyn std::error::Error>> {
+        let registry = registry()?;
+        assert_eq!(3, registry.resolve("resize")?.version);
+        assert_eq!(1, registry.resolve("resize@1")?.version);
+        let stable = registry.resolve("resize@stable")?;
+        assert_eq!(("resize", 2, 1), (stable.function, stable.version, stable.host));
+        assert_eq!(Some(RegistryError::UnknownAlias { function: "resize".to_string(), alias: "beta".to_string() }), registry.resolve("resize@beta").err());
+        assert_eq!(Some(RegistryError::UnknownVersion { function: "resize".to_string(), version: 4 }), registry.resolve("resize@4").err());
+        assert_eq!("Function rotate is not provided by any function host", registry.resolve("rotate").unwrap_err().to_string());
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn calls_are_split_by_weight() -> Result<(), Box<dyn std::error::Error>> {
+        let mut registry = registry()?;
+        registry.set_weights("resize", vec!((2, 3), (3, 1)));
+        let mut versions = vec!();
+        for _ in 0..8 {
+            versions.push(registry.resolve("resize")?.version);
+        }
+        assert_eq!(6, versions.iter().filter(|version| **version == 2).count());
+        assert_eq!(2, versions.iter().filter(|version| **version == 3).count());
+        // Disabled versions receive no traffic
+        registry.set_disabled("resize", 3, true)?;
+        for _ in 0..4 {
+            assert_eq!(2, registry.resolve("resize")?.version);
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn disabled_functions_fail_fast() -> Result<(), Box<dyn std::error::Error>> {
+        let mut registry = registry()?;
+        assert_eq!("Function crop is disabled", registry.resolve("crop").unwrap_err().to_string());
+        registry.set_disabled("resize", 2, true)?;
+        assert_eq!("Function resize@stable is disabled", registry.resolve("resize@stable").unwrap_err().to_string());
+        assert_eq!(3, registry.resolve("resize")?.version);
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn discovered_functions_are_served_unless_declared() -> Result<(), Box<dyn std::error::Error>> {
+        let mut registry = registry()?;
+        registry.discover("rotate", Some(7));
+        registry.discover("resize", Some(8));
+        assert_eq!((0, 7), (registry.resolve("rotate")?.version, registry.resolve("rotate")?.host));
+        assert_eq!(3, registry.resolve("resize")?.version);
+        assert!(registry.resolve("resize@0").is_err());
+        assert_eq!(vec!("rotate".to_string()), registry.unbind_host(7));
+        registry.discover("rotate", None);
+        assert!(!registry.is_served("rotate"));
+        Ok(())
+    }
+}
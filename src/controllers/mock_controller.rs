@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Mutex;
+use uuid::Uuid;
+use crate::assembler::checker::function_name;
+use crate::controllers::{PartialResult, VmController};
+use crate::machine::{VmState, VmValue};
+
+/// A [VmController] completing every call immediately, to run scripts without function hosts.
+///
+/// Calls result in the value registered for the function via [MockController::respond], or
+/// `null` if there is none, and are recorded in the order they were made.
+#[derive(Default)]
+pub struct MockController {
+    responses: HashMap<String, VmValue>,
+    results: Mutex<HashMap<Uuid, VmValue>>,
+    calls: Mutex<Vec<(String, Vec<VmValue>)>>,
+}
+
+impl MockController {
+    pub fn new() -> MockController {
+        MockController::default()
+    }
+
+    /// Sets the result of the calls of the function provided. Responses registered for a
+    /// version, eg. `resize@2`, take precedence over those of the function.
+    pub fn respond(mut self, function: &str, value: VmValue) -> Self {
+        self.responses.insert(function.to_string(), value);
+        self
+    }
+
+    /// The functions called and their arguments.
+    pub fn calls(&self) -> Vec<(String, Vec<VmValue>)> {
+        self.calls.lock().map(|calls| calls.clone()).unwrap_or_default()
+    }
+}
+
+impl VmController for MockController {
+    fn call(&self, function: String, arguments: Vec<VmValue>) -> Result<Uuid, Box<dyn Error>> {
+        let result = self.responses.get(&function)
+            .or_else(|| self.responses.get(function_name(&function)))
+            .cloned()
+            .unwrap_or(VmValue::Null);
+        let job = Uuid::new_v4();
+        self.results.lock().map_err(|_| "Result lock poisoned")?.insert(job, result);
+        self.calls.lock().map_err(|_| "Call lock poisoned")?.push((function, arguments));
+        Ok(job)
+    }
+
+    fn get_and_remove_result_of(&self, job: Uuid) -> Result<Option<VmValue>, Box<dyn Error>> {
+        let result = self.results.lock().map_err(|_| "Result lock poisoned")?.remove(&job);
+        result.ok_or_else(|| "Job is unknown".into()).map(Some)
+    }
+
+    fn suspend_until_all(&self, _state: &VmState, _jobs: Vec<Uuid>) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn suspend_until_any(&self, _state: &VmState, _jobs: Vec<Uuid>) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn abort(&self, jobs: Vec<Uuid>) -> Result<(), Box<dyn Error>> {
+        let mut results = self.results.lock().map_err(|_| "Result lock poisoned")?;
+        for job in jobs {
+            results.remove(&job);
+        }
+        Ok(())
+    }
+
+    fn take_partial_result_of(&self, job: Uuid) -> Result<PartialResult, Box<dyn Error>> {
+        match self.results.lock().map_err(|_| "Result lock poisoned")?.contains_key(&job) {
+            true => Ok(PartialResult::Finished),
+            false => Err("Job is unknown".into()),
+        }
+    }
+
+    fn suspend_until_partial(&self, _state: &VmState, _job: Uuid) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_test::traced_test;
+    use crate::controllers::{MockController, VmController};
+    use crate::machine::VmValue;
+
+    #[test]
+    #[traced_test]
+    fn calls_are_recorded_and_answered() -> Result<(), Box<dyn std::error::Error>> {
+        let controller = MockController::new()
+            .respond("resize", VmValue::Integer(1))
+            .respond("resize@2", VmValue::Integer(2));
+        let first = controller.call("resize".to_string(), vec!(VmValue::Null))?;
+        let second = controller.call("resize@2".to_string(), vec!())?;
+        let third = controller.call("resize@stable".to_string(), vec!())?;
+        let other = controller.call("other".to_string(), vec!())?;
+        assert_eq!(controller.get_and_remove_result_of(first)?, Some(VmValue::Integer(1)));
+        assert_eq!(controller.get_and_remove_result_of(second)?, Some(VmValue::Integer(2)));
+        assert_eq!(controller.get_and_remove_result_of(third)?, Some(VmValue::Integer(1)));
+        assert_eq!(controller.get_and_remove_result_of(other)?, Some(VmValue::Null));
+        assert_eq!(controller.calls().len(), 4);
+        assert_eq!(controller.calls()[0], ("resize".to_string(), vec!(VmValue::Null)));
+        Ok(())
+    }
+}
@@ -0,0 +1,1395 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Mutex, RwLock, RwLockReadGuard};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+use crate::controllers::{FunctionRegistry, PartialResult, VmController};
+use crate::io::capability_manifest::{CapabilityManifest, FunctionCapabilities};
+use crate::io::lambda_file::{LambdaFile, LambdaFunction};
+use crate::io::protocol_v1::protocol_v1::{CallOutcome, HostOptions, ProtocolHost};
+use crate::io::transport::{Endpoint, TransportListener};
+use crate::machine::{VmState, VmValue};
+
+/// The time waited for an event of a single host before checking the other hosts.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+/// The time a host has to acknowledge a cancellation by default.
+const CANCEL_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Connection {
+    host: ProtocolHost,
+    /// Incremented on every reconnect, as call-request-ids are only valid per connection.
+    generation: u32,
+}
+
+struct ConnectedHost {
+    connection: RwLock<Connection>,
+    process: Mutex<Option<Child>>,
+    /// The endpoint to reconnect to once the connection dropped.
+    endpoint: Option<Endpoint>,
+    /// Set once the host was asked to quit via [ProtocolController::retire_host].
+    retired: bool,
+    /// Set once the host was replaced, retiring it once the jobs called at it are collected.
+    replaced: bool,
+}
+
+#[derive(Clone)]
+struct Job {
+    host: usize,
+    generation: u32,
+    call_request_id: u32,
+    /// The function called, its version and arguments, kept to reschedule the job.
+    function: String,
+    version: u32,
+    arguments: Vec<VmValue>,
+    /// Set while the call is not made yet, as the job waits for a free slot or to be retried,
+    /// holding the time it may be made at.
+    queued: Option<Instant>,
+    /// When the call was made, to time it out.
+    started: Instant,
+    retries_left: u32,
+}
+
+/// A [VmController] executing functions on function hosts, connected via protocol v1.
+///
+/// Functions are looked up by name in a [FunctionRegistry], functions not declared being served
+/// by the first connected host providing them. Hosts connected via [ProtocolController::connect] are reconnected on
+/// the next call once their connection dropped, jobs running at that time fail unless
+/// rescheduling is enabled. Hosts not answering heartbeats are considered dead, their process
+/// is killed and their jobs fail with a distinct error.
+/// Hosts are shut down gracefully, being granted a grace period to finish the calls running
+/// before they are killed. Aborted jobs are cancelled at hosts supporting it, hosts not
+/// acknowledging in time are disconnected and their process killed.
+/// Functions declared via [ProtocolController::load] are called within their limits, calls
+/// exceeding the concurrency waiting for a slot, calls failing or timing out being retried.
+/// Loading a manifest again swaps in new hosts for the versions changed, hosts replaced
+/// finishing the jobs called at them before they are retired.
+/// Suspending blocks until the jobs awaited completed.
+pub struct ProtocolController {
+    hosts: Vec<ConnectedHost>,
+    registry: FunctionRegistry,
+    jobs: Mutex<HashMap<Uuid, Job>>,
+    /// Calls of aborted jobs, whose outcome is discarded once available or whose
+    /// cancellation is awaited.
+    abandoned: Mutex<Vec<Job>>,
+    options: HostOptions,
+    reschedule: bool,
+    cancel_timeout: Duration,
+}
+
+impl ProtocolController {
+    pub fn new() -> ProtocolController {
+        ProtocolController::with_options(HostOptions::default())
+    }
+
+    /// Creates a controller connecting to hosts using the options provided.
+    pub fn with_options(options: HostOptions) -> ProtocolController {
+        ProtocolController {
+            hosts: vec!(),
+            registry: FunctionRegistry::new(),
+            jobs: Mutex::new(HashMap::new()),
+            abandoned: Mutex::new(vec!()),
+            options,
+            reschedule: false,
+            cancel_timeout: CANCEL_TIMEOUT,
+        }
+    }
+
+    /// Whether jobs of hosts lost are called again once the host reconnected, instead of
+    /// failing. Only hosts connected via [ProtocolController::connect] can be reconnected,
+    /// and functions rescheduled may run more than once.
+    pub fn set_reschedule(&mut self, reschedule: bool) {
+        self.reschedule = reschedule;
+    }
+
+    /// The time hosts have to acknowledge the cancellation of an aborted job before they are
+    /// considered stuck, being disconnected and their process killed.
+    pub fn set_cancel_timeout(&mut self, cancel_timeout: Duration) {
+        self.cancel_timeout = cancel_timeout;
+    }
+
+    /// Starts the command provided as function host, talking to it via its stdio.
+    pub fn launch(&mut self, command: &mut Command) -> Result<(), Box<dyn Error>> {
+        let mut process = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let writer = process.stdin.take().ok_or("Failed to open stdin of function host")?;
+        let reader = process.stdout.take().ok_or("Failed to open stdout of function host")?;
+        match ProtocolHost::connect_with(Box::new(writer), Box::new(reader), &self.options) {
+            Ok(host) => {
+                self.add_host(host, Some(process));
+                Ok(())
+            }
+            Err(error) => {
+                let _ = process.kill();
+                let _ = process.wait();
+                Err(error.into())
+            }
+        }
+    }
+
+    /// Declares the functions and routes of the manifest and launches a function host for every
+    /// distinct command of the function versions enabled, failing if a host does not provide
+    /// its function.
+    /// Loading a manifest again replaces the functions and routes declared before. Hosts are
+    /// kept for the versions whose command did not change, new hosts serving the calls made
+    /// from now on otherwise. Hosts no longer needed are replaced, see
+    /// [ProtocolController::replace_host]. If launching fails, the previous manifest stays
+    /// deployed.
+    pub fn load(&mut self, file: &LambdaFile) -> Result<(), Box<dyn Error>> {
+        let previous: Vec<(LambdaFunction, Option<usize>)> = self.registry.declarations().into_iter()
+            .map(|(definition, host)| (definition.clone(), host))
+            .collect();
+        let mut bound: Vec<(&LambdaFunction, usize)> = vec!();
+        let mut launched: Vec<usize> = vec!();
+        for function in file.functions.iter().filter(|function| !function.disabled) {
+            let kept = previous.iter()
+                .filter_map(|(other, host)| host.map(|host| (other, host)))
+                .chain(bound.iter().map(|(other, host)| (*other, *host)))
+                .find(|(other, host)| !self.hosts[*host].retired && other.shares_host_with(function))
+                .map(|(_, host)| host);
+            let host = match kept {
+                Some(host) => host,
+                None => {
+                    info!("Launching function host {:?} for {}@{}", function.command, function.identifier, function.version);
+                    let result = self.launch(&mut function.command());
+                    match result.map(|_| self.hosts.len() - 1) {
+                        Ok(host) => {
+                            launched.push(host);
+                            host
+                        }
+                        Err(error) => {
+                            self.abandon_launched(launched);
+                            return Err(error);
+                        }
+                    }
+                }
+            };
+            if self.connection(host)?.host.function_index(&function.identifier).is_none() {
+                self.abandon_launched(launched);
+                return Err(format!("Function {} is not provided by its function host", function.identifier).into());
+            }
+            bound.push((function, host));
+        }
+        self.registry.clear_declarations();
+        for function in file.functions.iter() {
+            let host = bound.iter().find(|(other, _)| std::ptr::eq(*other, function)).map(|(_, host)| *host);
+            self.registry.declare(function, host);
+        }
+        for route in file.routes.iter() {
+            self.registry.route(route);
+        }
+        let mut unused: Vec<usize> = previous.iter()
+            .filter_map(|(_, host)| *host)
+            .filter(|host| !bound.iter().any(|(_, bound)| bound == host))
+            .collect();
+        unused.sort();
+        unused.dedup();
+        for host in unused {
+            self.replace_host(host);
+        }
+        Ok(())
+    }
+
+    /// Retires the hosts launched by a load failing.
+    fn abandon_launched(&mut self, launched: Vec<usize>) {
+        for host in launched {
+            if let Err(error) = self.retire_host(host, 0) {
+                warn!("Failed to retire function host {}: {}", host, error);
+            }
+        }
+    }
+
+    /// Declares functions without launching their hosts, applying their limits to the calls
+    /// made from now on. They are served by the hosts providing them. Calling disabled
+    /// functions fails.
+    pub fn define(&mut self, functions: &[LambdaFunction]) {
+        for function in functions {
+            self.registry.declare(function, None);
+        }
+    }
+
+    /// The registry functions are looked up in, allowing to route and disable versions.
+    pub fn registry_mut(&mut self) -> &mut FunctionRegistry {
+        &mut self.registry
+    }
+
+    /// Connects to a function host listening at the endpoint provided.
+    pub fn connect(&mut self, endpoint: Endpoint) -> Result<(), Box<dyn Error>> {
+        let host = ProtocolHost::connect_transport_with(endpoint.connect()?, &self.options)?;
+        self.push_host(host, None, Some(endpoint));
+        Ok(())
+    }
+
+    /// Blocks until a function host registers itself at the listener provided.
+    pub fn accept(&mut self, listener: &TransportListener) -> Result<(), Box<dyn Error>> {
+        let host = ProtocolHost::connect_transport_with(listener.accept()?, &self.options)?;
+        self.push_host(host, None, None);
+        Ok(())
+    }
+
+    /// Adds all function hosts waiting to register at the listener provided, returning
+    /// how many were added.
+    pub fn accept_pending(&mut self, listener: &TransportListener) -> Result<usize, Box<dyn Error>> {
+        let mut count = 0;
+        while let Some(transport) = listener.try_accept()? {
+            let host = ProtocolHost::connect_transport_with(transport, &self.options)?;
+            self.push_host(host, None, None);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Adds a host already connected, optionally owning the process it belongs to.
+    pub fn add_host(&mut self, host: ProtocolHost, process: Option<Child>) {
+        self.push_host(host, process, None);
+    }
+
+    fn push_host(&mut self, host: ProtocolHost, process: Option<Child>, endpoint: Option<Endpoint>) {
+        let host_index = self.hosts.len();
+        for function in host.functions() {
+            if let Some(existing) = self.registry.discovered_host(&function.function_name) {
+                let retired = self.hosts[existing].retired;
+                if !retired && (self.is_host_connected(existing) || self.hosts[existing].endpoint.is_some()) {
+                    warn!("Function {} is provided by multiple hosts, ignoring host {}", function.function_name, host_index);
+                    continue;
+                }
+            }
+            debug!("Function {} is served by host {}", function.function_name, host_index);
+            self.registry.discover(&function.function_name, Some(host_index));
+        }
+        self.hosts.push(ConnectedHost {
+            connection: RwLock::new(Connection { host, generation: 0 }),
+            process: Mutex::new(process),
+            endpoint,
+            retired: false,
+            replaced: false,
+        });
+    }
+
+    pub fn has_function(&self, name: &str) -> bool {
+        self.registry.is_served(name)
+    }
+
+    /// The capabilities of all functions served, to be cached and used to check scripts at
+    /// compile time, see [crate::assembler::compiler::compiler::compile_checked].
+    pub fn capability_manifest(&self) -> Result<CapabilityManifest, Box<dyn Error>> {
+        let mut manifest = CapabilityManifest::new();
+        for name in self.registry.names() {
+            let host = self.registry.provider(name).ok_or("Function is not served")?;
+            let connection = self.connection(host)?;
+            if let Some(function) = connection.host.functions().iter().find(|it| &it.function_name == name) {
+                manifest.insert(FunctionCapabilities::from(function));
+            }
+        }
+        Ok(manifest)
+    }
+
+    pub fn is_host_connected(&self, host: usize) -> bool {
+        self.hosts.get(host)
+            .and_then(|connected| connected.connection.read().ok())
+            .map(|connection| connection.host.is_connected())
+            .unwrap_or(false)
+    }
+
+    /// Asks all hosts to quit and waits for the processes launched to exit, killing those
+    /// not quitting within a second.
+    pub fn shutdown(&mut self) {
+        self.shutdown_with_grace(0);
+    }
+
+    /// Asks all hosts to quit within the seconds provided, allowing them to finish the calls
+    /// running. Hosts may ask for more time, up to 60 seconds, and are killed afterwards.
+    pub fn shutdown_with_grace(&mut self, grace_seconds: u8) {
+        for host in 0..self.hosts.len() {
+            self.begin_drain(host, grace_seconds);
+        }
+        for host in 0..self.hosts.len() {
+            self.finish_drain(host);
+        }
+    }
+
+    /// Gracefully removes the host provided. Its functions are served by other hosts providing
+    /// them from now on, while it is given the seconds provided to finish the calls running.
+    /// Jobs not finished by then are migrated to the hosts now serving their function, or
+    /// fail if there is none.
+    pub fn retire_host(&mut self, host: usize, grace_seconds: u8) -> Result<(), Box<dyn Error>> {
+        if host >= self.hosts.len() {
+            return Err("Host is unknown".into());
+        }
+        self.hosts[host].retired = true;
+        self.hosts[host].replaced = false;
+        self.unbind(host);
+        self.begin_drain(host, grace_seconds);
+        self.finish_drain(host);
+        self.migrate_jobs(host)
+    }
+
+    /// Stops calling functions at the host provided, other hosts providing its functions
+    /// serving them from now on. The jobs called at it are finished, the host being retired
+    /// by [ProtocolController::release_replaced_hosts] once they are collected.
+    pub fn replace_host(&mut self, host: usize) {
+        let Some(connected) = self.hosts.get_mut(host) else {
+            return;
+        };
+        if connected.retired {
+            return;
+        }
+        info!("Function host {} is replaced", host);
+        connected.retired = true;
+        connected.replaced = true;
+        self.unbind(host);
+    }
+
+    /// Retires the hosts replaced once no job refers to them anymore, returning how many
+    /// were retired.
+    pub fn release_replaced_hosts(&mut self) -> Result<usize, Box<dyn Error>> {
+        let idle: Vec<usize> = {
+            let jobs = self.jobs.lock().map_err(|_| "Job lock poisoned")?;
+            let abandoned = self.abandoned.lock().map_err(|_| "Abandoned lock poisoned")?;
+            (0..self.hosts.len())
+                .filter(|host| self.hosts[*host].replaced)
+                .filter(|host| !jobs.values().chain(abandoned.iter()).any(|job| job.host == *host && job.queued.is_none()))
+                .collect()
+        };
+        for host in idle.iter() {
+            info!("Retiring function host {} as it was replaced", host);
+            self.hosts[*host].replaced = false;
+            self.begin_drain(*host, 0);
+            self.finish_drain(*host);
+        }
+        Ok(idle.len())
+    }
+
+    /// Unbinds the functions served by the host provided, rebinding the functions discovered
+    /// at it to other hosts providing them.
+    fn unbind(&mut self, host: usize) {
+        for function in self.registry.unbind_host(host) {
+            let provider = self.find_provider(&function);
+            if let Some(provider) = provider {
+                debug!("Function {} is served by host {}", function, provider);
+            }
+            self.registry.discover(&function, provider);
+        }
+    }
+
+    /// A host, other than those retired, able to serve the function provided.
+    fn find_provider(&self, function: &str) -> Option<usize> {
+        (0..self.hosts.len()).find(|host| {
+            let connected = &self.hosts[*host];
+            !connected.retired
+                && (self.is_host_connected(*host) || connected.endpoint.is_some())
+                && self.connection(*host).map(|it| it.host.function_index(function).is_some()).unwrap_or(false)
+        })
+    }
+
+    fn begin_drain(&self, host: usize, grace_seconds: u8) {
+        if let Ok(connection) = self.connection(host) {
+            if connection.host.is_connected() {
+                if let Err(error) = connection.host.drain(grace_seconds) {
+                    warn!("Failed to ask function host {} to quit: {}", host, error);
+                }
+            }
+        }
+    }
+
+    /// Waits for a host asked to quit to disconnect and its process to exit, killing it once
+    /// its deadline passed.
+    fn finish_drain(&mut self, host: usize) {
+        let connected = &mut self.hosts[host];
+        let Ok(connection) = connected.connection.get_mut() else {
+            return;
+        };
+        let deadline = || connection.host.drain_deadline().unwrap_or_else(Instant::now);
+        while connection.host.is_connected() && Instant::now() < deadline() {
+            connection.host.next_event_timeout(POLL_INTERVAL);
+        }
+        if connection.host.is_connected() {
+            warn!("Function host {} did not quit in time", host);
+            let _ = connection.host.disconnect();
+        }
+        let Ok(Some(process)) = connected.process.get_mut() else {
+            return;
+        };
+        loop {
+            match process.try_wait() {
+                Ok(None) if Instant::now() < deadline() => std::thread::sleep(POLL_INTERVAL),
+                Ok(None) => {
+                    warn!("Killing function host {} as it did not quit in time", host);
+                    let _ = process.kill();
+                    let _ = process.wait();
+                    return;
+                }
+                Ok(Some(_)) | Err(_) => return,
+            }
+        }
+    }
+
+    /// Calls the jobs the retired host provided did not finish again, at the host now serving
+    /// their function.
+    fn migrate_jobs(&self, host: usize) -> Result<(), Box<dyn Error>> {
+        let mut jobs = self.jobs.lock().map_err(|_| "Job lock poisoned")?;
+        // Queued jobs are called at the host serving their function once dispatched
+        for (id, job) in jobs.iter_mut().filter(|(_, job)| job.host == host && job.queued.is_none()) {
+            {
+                let connection = self.connection(host)?;
+                if connection.generation == job.generation && connection.host.is_completed(job.call_request_id) {
+                    continue;
+                }
+            }
+            let Some(target) = self.registry.host(&job.function, job.version) else {
+                continue;
+            };
+            info!("Migrating job {} calling {} to host {}", id, job.function, target);
+            if let Err(error) = self.call_on(target, job) {
+                warn!("Failed to migrate job {}: {}", id, error);
+            }
+        }
+        let mut abandoned = self.abandoned.lock().map_err(|_| "Abandoned lock poisoned")?;
+        abandoned.retain(|job| job.host != host);
+        Ok(())
+    }
+
+    /// Calls the function of the job at the host provided, updating the job to refer to the call.
+    fn call_on(&self, host: usize, job: &mut Job) -> Result<(), Box<dyn Error>> {
+        self.reconnect_if_dropped(host)?;
+        let connection = self.connection(host)?;
+        let function_index = connection.host.function_index(&job.function)
+            .ok_or_else(|| format!("Function {} is no longer provided by its function host", job.function))?;
+        job.call_request_id = connection.host.call(function_index, &job.arguments)?;
+        job.host = host;
+        job.generation = connection.generation;
+        Ok(())
+    }
+
+    fn connection(&self, host: usize) -> Result<RwLockReadGuard<'_, Connection>, Box<dyn Error>> {
+        Ok(self.hosts[host].connection.read().map_err(|_| "Connection lock poisoned")?)
+    }
+
+    /// Reconnects the host provided if its connection dropped and its endpoint is known.
+    fn reconnect_if_dropped(&self, host: usize) -> Result<(), Box<dyn Error>> {
+        let connected = &self.hosts[host];
+        let Some(endpoint) = connected.endpoint.as_ref() else {
+            return Ok(());
+        };
+        if connected.retired {
+            return Ok(());
+        }
+        if self.connection(host)?.host.is_connected() {
+            return Ok(());
+        }
+        let mut connection = connected.connection.write().map_err(|_| "Connection lock poisoned")?;
+        if !connection.host.is_connected() {
+            info!("Reconnecting to function host at {}", endpoint);
+            connection.host = ProtocolHost::connect_transport_with(endpoint.connect()?, &self.options)?;
+            connection.generation = connection.generation.wrapping_add(1);
+        }
+        Ok(())
+    }
+
+    /// Discards the outcomes of abandoned calls that completed or were cancelled in the
+    /// meantime, releasing hosts not acknowledging a cancellation in time.
+    fn collect_abandoned(&self) -> Result<(), Box<dyn Error>> {
+        let mut stuck = vec!();
+        {
+            let mut abandoned = self.abandoned.lock().map_err(|_| "Abandoned lock poisoned")?;
+            abandoned.retain(|job| match self.connection(job.host) {
+                Ok(connection) => {
+                    if connection.generation != job.generation || !connection.host.is_connected() {
+                        return false;
+                    }
+                    if connection.host.is_cancel_overdue(job.call_request_id, self.cancel_timeout) {
+                        stuck.push(job.host);
+                        return false;
+                    }
+                    connection.host.take_outcome(job.call_request_id).is_none() && connection.host.has_call(job.call_request_id)
+                }
+                Err(_) => false,
+            });
+        }
+        stuck.sort();
+        stuck.dedup();
+        for host in stuck {
+            self.release_host(host, "did not acknowledge a cancellation in time");
+        }
+        Ok(())
+    }
+
+    /// Closes the connection of a host which stopped responding and kills its process.
+    fn release_unresponsive(&self, host: usize) {
+        self.release_host(host, "stopped responding");
+    }
+
+    fn release_host(&self, host: usize, reason: &str) {
+        if let Ok(connection) = self.connection(host) {
+            let _ = connection.host.disconnect();
+        }
+        if let Ok(mut process) = self.hosts[host].process.lock() {
+            if let Some(process) = process.as_mut() {
+                warn!("Killing function host {} as it {}", host, reason);
+                let _ = process.kill();
+            }
+        }
+    }
+
+    /// Whether the job is completed. If its host was lost before, the job is rescheduled
+    /// if enabled and errors otherwise.
+    fn is_completed(&self, job_id: Uuid) -> Result<bool, Box<dyn Error>> {
+        let mut jobs = self.jobs.lock().map_err(|_| "Job lock poisoned")?;
+        if jobs.get(&job_id).ok_or("Job is unknown")?.queued.is_some() {
+            self.dispatch(&mut jobs, job_id)?;
+            return Ok(false);
+        }
+        let job = jobs.get_mut(&job_id).ok_or("Job is unknown")?;
+        if self.is_running(job) && self.is_timed_out(job) {
+            let mut abandoned = self.abandoned.lock().map_err(|_| "Abandoned lock poisoned")?;
+            self.cancel(job.clone(), &mut abandoned);
+            if self.schedule_retry(job_id, job) {
+                return Ok(false);
+            }
+            let message = format!("Function {} timed out", job.function);
+            jobs.remove(&job_id);
+            return Err(message.into());
+        }
+        let unresponsive = {
+            let connection = self.connection(job.host)?;
+            if connection.generation == job.generation {
+                if connection.host.is_completed(job.call_request_id) {
+                    return Ok(true);
+                }
+                // Retired hosts are done draining, the calls they did not finish are lost, while
+                // hosts replaced keep running their calls
+                let connected = &self.hosts[job.host];
+                if connection.host.is_connected() && (!connected.retired || connected.replaced) {
+                    return Ok(false);
+                }
+            }
+            connection.generation == job.generation && connection.host.is_unresponsive()
+        };
+        if unresponsive {
+            self.release_unresponsive(job.host);
+        }
+        if self.hosts[job.host].retired {
+            return Err("Function host shut down before the job completed".into());
+        }
+        if self.reschedule && self.hosts[job.host].endpoint.is_some() {
+            info!("Rescheduling job {} calling {}", job_id, job.function);
+            self.call_on(job.host, job)?;
+            return Ok(false);
+        }
+        if unresponsive {
+            return Err("Function host stopped responding before the job completed".into());
+        }
+        Err("Function host disconnected before the job completed".into())
+    }
+
+    /// Makes the call of a queued job once it is due and the concurrency of its function allows.
+    fn dispatch(&self, jobs: &mut HashMap<Uuid, Job>, job_id: Uuid) -> Result<(), Box<dyn Error>> {
+        let job = jobs.get(&job_id).ok_or("Job is unknown")?;
+        if job.queued.is_some_and(|due| Instant::now() < due) {
+            return Ok(());
+        }
+        if let Some(concurrency) = self.registry.definition(&job.function, job.version).and_then(|function| function.concurrency) {
+            let running = jobs.values()
+                .filter(|other| other.function == job.function && other.version == job.version && self.is_running(other))
+                .count();
+            if running >= concurrency {
+                return Ok(());
+            }
+        }
+        let host = self.registry.host(&job.function, job.version)
+            .ok_or_else(|| format!("Function {}@{} is not provided by any function host", job.function, job.version))?;
+        let job = jobs.get_mut(&job_id).ok_or("Job is unknown")?;
+        self.call_on(host, job)?;
+        job.queued = None;
+        job.started = Instant::now();
+        Ok(())
+    }
+
+    /// Whether the call of the job was made and is not completed yet.
+    fn is_running(&self, job: &Job) -> bool {
+        job.queued.is_none() && self.connection(job.host)
+            .map(|connection| connection.generation == job.generation
+                && connection.host.is_connected()
+                && !connection.host.is_completed(job.call_request_id))
+            .unwrap_or(false)
+    }
+
+    fn is_timed_out(&self, job: &Job) -> bool {
+        self.registry.definition(&job.function, job.version)
+            .and_then(|function| function.timeout)
+            .is_some_and(|timeout| job.started.elapsed() >= timeout)
+    }
+
+    /// Queues the job to be called again after the delay of its function, returning whether
+    /// any retries were left.
+    fn schedule_retry(&self, job_id: Uuid, job: &mut Job) -> bool {
+        if job.retries_left == 0 {
+            return false;
+        }
+        let delay = self.registry.definition(&job.function, job.version).map(|function| function.retry.delay).unwrap_or_default();
+        info!("Retrying job {} calling {} in {:?}", job_id, job.function, delay);
+        job.retries_left -= 1;
+        job.queued = Some(Instant::now() + delay);
+        true
+    }
+
+    /// Cancels the call of the job at hosts supporting it, its outcome being discarded once
+    /// available.
+    fn cancel(&self, job: Job, abandoned: &mut Vec<Job>) {
+        if let Ok(connection) = self.connection(job.host) {
+            if connection.generation == job.generation {
+                if let Err(error) = connection.host.cancel(job.call_request_id) {
+                    warn!("Failed to cancel call {}: {}", job.call_request_id, error);
+                }
+            }
+        }
+        abandoned.push(job);
+    }
+
+    /// Blocks until an event arrives at any host with jobs running.
+    fn wait_for_event(&self, jobs: &[Uuid]) -> Result<(), Box<dyn Error>> {
+        let mut hosts: Vec<usize> = {
+            let running = self.jobs.lock().map_err(|_| "Job lock poisoned")?;
+            jobs.iter().filter_map(|job| running.get(job)).map(|job| job.host).collect()
+        };
+        hosts.sort();
+        hosts.dedup();
+        for host in hosts {
+            if self.connection(host)?.host.next_event_timeout(POLL_INTERVAL).is_some() {
+                break;
+            }
+        }
+        self.collect_abandoned()
+    }
+
+    fn outcome_to_value(outcome: CallOutcome) -> Result<VmValue, Box<dyn Error>> {
+        if !outcome.success {
+            let message = match outcome.results.into_iter().next() {
+                Some(VmValue::String(message)) => message.to_string(),
+                Some(value) => format!("{:?}", value),
+                None => "Function failed without reporting an error".to_string(),
+            };
+            return Err(message.into());
+        }
+        let mut results = outcome.results;
+        Ok(match results.len() {
+            0 => VmValue::Null,
+            1 => results.remove(0),
+            _ => VmValue::array(results),
+        })
+    }
+}
+
+impl Default for ProtocolController {
+    fn default() -> Self {
+        ProtocolController::new()
+    }
+}
+
+impl Drop for ProtocolController {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl VmController for ProtocolController {
+    fn call(&self, function: String, arguments: Vec<VmValue>) -> Result<Uuid, Box<dyn Error>> {
+        let resolved = self.registry.resolve(&function)?;
+        let job_id = Uuid::new_v4();
+        let job = Job {
+            host: resolved.host,
+            generation: 0,
+            call_request_id: 0,
+            retries_left: resolved.definition.map(|definition| definition.retry.retries).unwrap_or(0),
+            function: resolved.function.to_string(),
+            version: resolved.version,
+            arguments,
+            queued: Some(Instant::now()),
+            started: Instant::now(),
+        };
+        let mut jobs = self.jobs.lock().map_err(|_| "Job lock poisoned")?;
+        jobs.insert(job_id, job);
+        if let Err(error) = self.dispatch(&mut jobs, job_id) {
+            jobs.remove(&job_id);
+            return Err(error);
+        }
+        Ok(job_id)
+    }
+
+    fn get_and_remove_result_of(&self, job: Uuid) -> Result<Option<VmValue>, Box<dyn Error>> {
+        if !self.is_completed(job)? {
+            return Ok(None);
+        }
+        let mut jobs = self.jobs.lock().map_err(|_| "Job lock poisoned")?;
+        let mut removed = jobs.remove(&job).ok_or("Job is unknown")?;
+        let outcome = self.connection(removed.host)?.host.take_outcome(removed.call_request_id)
+            .ok_or("Outcome of the job is not available")?;
+        if !outcome.success && self.schedule_retry(job, &mut removed) {
+            jobs.insert(job, removed);
+            return Ok(None);
+        }
+        ProtocolController::outcome_to_value(outcome).map(Some)
+    }
+
+    fn suspend_until_all(&self, _state: &VmState, jobs: Vec<Uuid>) -> Result<(), Box<dyn Error>> {
+        loop {
+            let mut all = true;
+            for job in jobs.iter() {
+                all &= self.is_completed(*job)?;
+            }
+            if all {
+                return Ok(());
+            }
+            self.wait_for_event(&jobs)?;
+        }
+    }
+
+    fn suspend_until_any(&self, _state: &VmState, jobs: Vec<Uuid>) -> Result<(), Box<dyn Error>> {
+        if jobs.is_empty() {
+            return Ok(());
+        }
+        loop {
+            for job in jobs.iter() {
+                if self.is_completed(*job)? {
+                    return Ok(());
+                }
+            }
+            self.wait_for_event(&jobs)?;
+        }
+    }
+
+    fn abort(&self, jobs: Vec<Uuid>) -> Result<(), Box<dyn Error>> {
+        // Hosts not supporting cancellation keep running the call, its outcome is discarded once available
+        {
+            let mut running = self.jobs.lock().map_err(|_| "Job lock poisoned")?;
+            let mut abandoned = self.abandoned.lock().map_err(|_| "Abandoned lock poisoned")?;
+            for job in jobs {
+                let Some(job) = running.remove(&job) else {
+                    continue;
+                };
+                if job.queued.is_none() {
+                    self.cancel(job, &mut abandoned);
+                }
+            }
+        }
+        self.collect_abandoned()
+    }
+
+    fn take_partial_result_of(&self, job: Uuid) -> Result<PartialResult, Box<dyn Error>> {
+        let partial = {
+            let jobs = self.jobs.lock().map_err(|_| "Job lock poisoned")?;
+            let job = jobs.get(&job).ok_or("Job is unknown")?;
+            let connection = self.connection(job.host)?;
+            if job.queued.is_none() && connection.generation == job.generation {
+                connection.host.take_partial(job.call_request_id)
+            } else {
+                None
+            }
+        };
+        if let Some(value) = partial {
+            return Ok(PartialResult::Value(value));
+        }
+        Ok(if self.is_completed(job)? { PartialResult::Finished } else { PartialResult::Pending })
+    }
+
+    fn suspend_until_partial(&self, _state: &VmState, job: Uuid) -> Result<(), Box<dyn Error>> {
+        loop {
+            let has_partial = {
+                let jobs = self.jobs.lock().map_err(|_| "Job lock poisoned")?;
+                let job = jobs.get(&job).ok_or("Job is unknown")?;
+                let connection = self.connection(job.host)?;
+                job.queued.is_none() && connection.generation == job.generation && connection.host.has_partial(job.call_request_id)
+            };
+            if has_partial || self.is_completed(job)? {
+                return Ok(());
+            }
+            self.wait_for_event(&[job])?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc::Receiver;
+    use std::time::Duration;
+    use tracing_test::traced_test;
+    use crate::controllers::{ProtocolController, VmController};
+    use crate::io::function_host::FunctionHost;
+    use crate::io::lambda_file::LambdaFile;
+    use crate::io::protocol_v1::protocol_v1::{HostOptions, ProtocolHost, ProtocolMessage};
+    use crate::io::protocol_v1::protocol_v1::data::*;
+    use crate::io::transport::TransportListener;
+    use crate::machine::{VmStack, VmState, VmValue};
+
+    fn fast_heartbeat() -> HostOptions {
+        HostOptions { heartbeat_interval: Some(Duration::from_millis(20)), missed_heartbeats: 2, ..HostOptions::default() }
+    }
+
+    /// Acts as function host providing `double`, which stops responding once called
+    /// until `released` is signalled or dropped, announcing the features provided.
+    fn hanging_host(mut reader: impl Read, mut writer: impl Write, released: Receiver<()>, features: u32) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let ProtocolMessage::Version(version) = ProtocolMessage::read(&mut reader)? else {
+            return Err("Version expected".into());
+        };
+        ProtocolMessage::Version(VersionMessage { major: 1, minor: 0, build: 0, revision: 0, protocol: 1, heartbeat_interval: version.heartbeat_interval, features })
+            .write(&mut writer)?;
+        loop {
+            match ProtocolMessage::read(&mut reader)? {
+                ProtocolMessage::CapabilitiesRequest(_) => ProtocolMessage::CapabilitiesResponse(CapabilitiesResponseMessage { functions_count: 1 })
+                    .write(&mut writer)?,
+                ProtocolMessage::FunctionCapabilitiesRequest(_) => ProtocolMessage::FunctionCapabilitiesResponse(FunctionCapabilitiesResponseMessage {
+                    function_index: 0,
+                    arguments_required: 1,
+                    arguments_count: 1,
+                    results_count: 1,
+                    function_name: "double".to_string(),
+                    signature: None,
+                }).write(&mut writer)?,
+                ProtocolMessage::Ping(ping) => ProtocolMessage::Pong(PongMessage { sequence: ping.sequence }).write(&mut writer)?,
+                ProtocolMessage::Call(_) => {
+                    let _ = released.recv();
+                    return Ok(());
+                }
+                message => return Err(format!("Unexpected message {:?}", message).into()),
+            }
+        }
+    }
+
+    fn connect(controller: &mut ProtocolController, function_host: FunctionHost) -> Result<(), Box<dyn std::error::Error>> {
+        let (client_reader, host_writer) = std::io::pipe()?;
+        let (host_reader, client_writer) = std::io::pipe()?;
+        std::thread::spawn(move || function_host.serve(Box::new(client_reader), Box::new(client_writer)).unwrap());
+        controller.add_host(ProtocolHost::connect(Box::new(host_writer), Box::new(host_reader))?, None);
+        Ok(())
+    }
+
+    fn double_host() -> FunctionHost {
+        FunctionHost::new(1, 0, 0, 0)
+            .function("double", 1, 1, 1, |arguments| match &arguments[0] {
+                VmValue::Integer(value) => Ok(vec!(VmValue::Integer(value * 2))),
+                _ => Err("double expects an integer".into()),
+            })
+            .function("sleep", 1, 1, 0, |arguments| match &arguments[0] {
+                VmValue::Integer(millis) => {
+                    std::thread::sleep(Duration::from_millis(*millis as u64));
+                    Ok(vec!())
+                }
+                _ => Err("sleep expects an integer".into()),
+            })
+    }
+
+    fn controller() -> Result<ProtocolController, Box<dyn std::error::Error>> {
+        let mut controller = ProtocolController::new();
+        connect(&mut controller, double_host())?;
+        connect(&mut controller, FunctionHost::new(1, 0, 0, 0)
+            .function("pair", 0, 0, 2, |_| Ok(vec!(VmValue::Integer(1), VmValue::Integer(2))))
+            .function("double", 1, 1, 1, |_| Err("the second host must not be called".into())))?;
+        Ok(controller)
+    }
+
+    fn run_str(input: &str, controller: &ProtocolController) -> Result<(VmState, VmStack), Box<dyn std::error::Error>> {
+        let (remainder, file) = crate::assembler::parser::parser::parse_x39file(input).unwrap();
+        assert!(remainder.is_empty(), "Failed to fully parse input: {:?}", remainder);
+        let mut vm_state = crate::assembler::compiler::compiler::compile(file)?;
+        let mut vm_stack = VmStack::new();
+        while !vm_state.is_done() {
+            vm_state.step(&mut vm_stack, controller)?;
+        }
+        Ok((vm_state, vm_stack))
+    }
+
+    fn get_variable(vm_state: &VmState, vm_stack: &VmStack, name: &str) -> Option<VmValue> {
+        let slot = vm_state.find_local(name, vm_state.instructions().len())?;
+        vm_stack.get_local(slot).cloned()
+    }
+
+    #[test]
+    #[traced_test]
+    fn script_awaits_function_results() -> Result<(), Box<dyn std::error::Error>> {
+        let controller = controller()?;
+        let (vm_state, vm_stack) = run_str("\
+        job = start double(21);\
+        value = await job;\
+        both = await pair();", &controller)?;
+        assert_eq!(get_variable(&vm_state, &vm_stack, "value"), Some(VmValue::Integer(42)));
+        assert_eq!(get_variable(&vm_state, &vm_stack, "both"), Some(VmValue::array(vec!(VmValue::Integer(1), VmValue::Integer(2)))));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn scripts_pass_and_destructure_many_values() -> Result<(), Box<dyn std::error::Error>> {
+        let mut controller = controller()?;
+        connect(&mut controller, FunctionHost::new(1, 0, 0, 0)
+            .function("add", 2, 3, 1, |arguments| {
+                let mut sum = 0;
+                for argument in arguments.iter() {
+                    let VmValue::Integer(value) = argument else {
+                        return Err("add expects integers".into());
+                    };
+                    sum += value;
+                }
+                Ok(vec!(VmValue::Integer(sum)))
+            }))?;
+        let (vm_state, vm_stack) = run_str("\
+        a = 1;\
+        job = start add(a, 2, 3);\
+        sum = await job;\
+        [x, y] = await pair();\
+        two = await add(x, y);", &controller)?;
+        assert_eq!(get_variable(&vm_state, &vm_stack, "sum"), Some(VmValue::Integer(6)));
+        assert_eq!(get_variable(&vm_state, &vm_stack, "x"), Some(VmValue::Integer(1)));
+        assert_eq!(get_variable(&vm_state, &vm_stack, "y"), Some(VmValue::Integer(2)));
+        assert_eq!(get_variable(&vm_state, &vm_stack, "two"), Some(VmValue::Integer(3)));
+        assert!(run_str("[x, y, z] = await pair();", &controller).is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn scripts_pass_named_arguments() -> Result<(), Box<dyn std::error::Error>> {
+        let mut controller = ProtocolController::new();
+        connect(&mut controller, FunctionHost::new(1, 0, 0, 0)
+            .function("describe", 1, 2, 1, |arguments| Ok(vec!(VmValue::array(arguments))))
+            .signature(SignatureDescription {
+                description: String::new(),
+                idempotent: true,
+                timeout_hint: 0,
+                parameters: vec!(
+                    ParameterDescription::new("name", r#"{"type":"string"}"#, ""),
+                    ParameterDescription::new("width", r#"{"type":"integer"}"#, ""),
+                ),
+                results: vec!(ParameterDescription::new("description", "{}", "")),
+            })
+            .function("configure", 1, 1, 1, |mut arguments| Ok(vec!(arguments.remove(0)))))?;
+        let (_, file) = crate::assembler::parser::parser::parse_x39file("\
+        positional = await describe(width: 640, name: \"image\");\
+        object = await configure(verbose: true);")?;
+        let signatures = controller.capability_manifest()?.signatures();
+        let mut vm_state = crate::assembler::compiler::compiler::compile_checked(file, &signatures)?;
+        let mut vm_stack = VmStack::new();
+        while !vm_state.is_done() {
+            vm_state.step(&mut vm_stack, &controller)?;
+        }
+        assert_eq!(get_variable(&vm_state, &vm_stack, "positional"), Some(VmValue::array(vec!(VmValue::string("image"), VmValue::Integer(640)))));
+        let Some(VmValue::Object(object)) = get_variable(&vm_state, &vm_stack, "object") else {
+            return Err("Named arguments without advertised names must be passed as object".into());
+        };
+        assert_eq!(object.get("verbose"), Some(&VmValue::Boolean(true)));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn scripts_consume_partial_results() -> Result<(), Box<dyn std::error::Error>> {
+        let mut controller = ProtocolController::new();
+        connect(&mut controller, FunctionHost::new(1, 0, 0, 0)
+            .streaming_function("count", 1, 1, 1, |arguments, partial_results| {
+                let VmValue::Integer(count) = arguments[0] else {
+                    return Err("count expects an integer".into());
+                };
+                for value in 1..=count {
+                    partial_results.emit(&VmValue::Integer(value))?;
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+                Ok(vec!(VmValue::Integer(count)))
+            }))?;
+        let (vm_state, vm_stack) = run_str("\
+        job = start count(4);\
+        chunks = [];\
+        for chunk in stream job { chunks += chunk; }\
+        total = await job;", &controller)?;
+        assert_eq!(get_variable(&vm_state, &vm_stack, "chunks"), Some(VmValue::array((1..=4).map(VmValue::Integer).collect())));
+        assert_eq!(get_variable(&vm_state, &vm_stack, "total"), Some(VmValue::Integer(4)));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn failed_call_errors() -> Result<(), Box<dyn std::error::Error>> {
+        let controller = controller()?;
+        let result = run_str("value = await double(\"text\");", &controller);
+        assert_eq!(result.err().map(|it| it.to_string()), Some("double expects an integer".to_string()));
+        assert!(controller.call("missing".to_string(), vec!()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn suspend_blocks_until_completion() -> Result<(), Box<dyn std::error::Error>> {
+        let controller = controller()?;
+        let state = VmState::new();
+        let slow = controller.call("sleep".to_string(), vec!(VmValue::Integer(50)))?;
+        let fast = controller.call("double".to_string(), vec!(VmValue::Integer(1)))?;
+        controller.suspend_until_any(&state, vec!(slow, fast))?;
+        assert_eq!(controller.get_and_remove_result_of(fast)?, Some(VmValue::Integer(2)));
+        assert_eq!(controller.get_and_remove_result_of(slow)?, None);
+        controller.suspend_until_all(&state, vec!(slow))?;
+        assert_eq!(controller.get_and_remove_result_of(slow)?, Some(VmValue::Null));
+        assert!(controller.get_and_remove_result_of(slow).is_err());
+        Ok(())
+    }
+
+    fn define(controller: &mut ProtocolController, manifest: &str) -> Result<(), Box<dyn std::error::Error>> {
+        controller.define(&LambdaFile::parse(manifest)?.functions);
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn disabled_functions_fail_fast() -> Result<(), Box<dyn std::error::Error>> {
+        let mut controller = controller()?;
+        define(&mut controller, "[functions.double]\ncommand = \"double\"\ndisabled = true\n")?;
+        let result = controller.call("double".to_string(), vec!(VmValue::Integer(1)));
+        assert_eq!(result.err().map(|it| it.to_string()), Some("Function double is disabled".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn calls_are_routed_to_versions() -> Result<(), Box<dyn std::error::Error>> {
+        let mut controller = ProtocolController::new();
+        connect(&mut controller, double_host())?;
+        connect(&mut controller, FunctionHost::new(1, 0, 0, 0)
+            .function("double", 1, 1, 1, |arguments| match &arguments[0] {
+                VmValue::Integer(value) => Ok(vec!(VmValue::Integer(value * 2 + 1))),
+                _ => Err("double expects an integer".into()),
+            }))?;
+        let file = LambdaFile::parse("[functions.double]\ncommand = \"double\"\n[functions.double.2]\ncommand = \"double\"\n")?;
+        let registry = controller.registry_mut();
+        registry.declare(&file.functions[0], Some(0));
+        registry.declare(&file.functions[1], Some(1));
+        registry.set_alias("double", "stable", 1);
+        let (vm_state, vm_stack) = run_str("\
+        latest = await double(1);\
+        stable = await double@stable(1);\
+        second = await double@2(1);", &controller)?;
+        assert_eq!(get_variable(&vm_state, &vm_stack, "latest"), Some(VmValue::Integer(3)));
+        assert_eq!(get_variable(&vm_state, &vm_stack, "stable"), Some(VmValue::Integer(2)));
+        assert_eq!(get_variable(&vm_state, &vm_stack, "second"), Some(VmValue::Integer(3)));
+        controller.registry_mut().set_disabled("double", 2, true)?;
+        let result = controller.call("double@2".to_string(), vec!(VmValue::Integer(1)));
+        assert_eq!(result.err().map(|it| it.to_string()), Some("Function double@2 is disabled".to_string()));
+        let (vm_state, vm_stack) = run_str("latest = await double(1);", &controller)?;
+        assert_eq!(get_variable(&vm_state, &vm_stack, "latest"), Some(VmValue::Integer(2)));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn failed_calls_are_retried() -> Result<(), Box<dyn std::error::Error>> {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counted = attempts.clone();
+        let mut controller = ProtocolController::new();
+        connect(&mut controller, FunctionHost::new(1, 0, 0, 0)
+            .function("flaky", 0, 0, 1, move |_| match counted.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err("not yet".into()),
+                attempt => Ok(vec!(VmValue::Integer(attempt as i64))),
+            }))?;
+        define(&mut controller, "[functions.flaky]\ncommand = \"flaky\"\nretries = 2\nretry_delay = \"10ms\"\n")?;
+        let (vm_state, vm_stack) = run_str("value = await flaky();", &controller)?;
+        assert_eq!(get_variable(&vm_state, &vm_stack, "value"), Some(VmValue::Integer(2)));
+        attempts.store(0, Ordering::SeqCst);
+        define(&mut controller, "[functions.flaky]\ncommand = \"flaky\"\nretries = 1\n")?;
+        let result = run_str("value = await flaky();", &controller);
+        assert_eq!(result.err().map(|it| it.to_string()), Some("not yet".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn calls_time_out() -> Result<(), Box<dyn std::error::Error>> {
+        let mut controller = controller()?;
+        define(&mut controller, "[functions.sleep]\ncommand = \"sleep\"\ntimeout = \"20ms\"\n")?;
+        let result = run_str("value = await sleep(1000);", &controller);
+        assert_eq!(result.err().map(|it| it.to_string()), Some("Function sleep timed out".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn timed_out_jobs_are_removed() -> Result<(), Box<dyn std::error::Error>> {
+        let mut controller = controller()?;
+        define(&mut controller, "[functions.sleep]\ncommand = \"sleep\"\ntimeout = \"20ms\"\n")?;
+        let job = controller.call("sleep".to_string(), vec!(VmValue::Integer(1000)))?;
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(controller.is_completed(job).err().map(|it| it.to_string()), Some("Function sleep timed out".to_string()));
+        assert!(controller.jobs.lock().unwrap().is_empty());
+        assert_eq!(controller.is_completed(job).err().map(|it| it.to_string()), Some("Job is unknown".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn calls_wait_for_a_free_slot() -> Result<(), Box<dyn std::error::Error>> {
+        let mut controller = controller()?;
+        define(&mut controller, "[functions.sleep]\ncommand = \"sleep\"\nconcurrency = 1\n")?;
+        let state = VmState::new();
+        let first = controller.call("sleep".to_string(), vec!(VmValue::Integer(30)))?;
+        let second = controller.call("sleep".to_string(), vec!(VmValue::Integer(1)))?;
+        assert!(controller.jobs.lock().unwrap()[&second].queued.is_some());
+        controller.suspend_until_any(&state, vec!(second))?;
+        assert!(controller.is_completed(first)?);
+        assert_eq!(controller.get_and_remove_result_of(second)?, Some(VmValue::Null));
+        assert_eq!(controller.get_and_remove_result_of(first)?, Some(VmValue::Null));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn manifest_lists_functions_served() -> Result<(), Box<dyn std::error::Error>> {
+        let controller = controller()?;
+        let manifest = controller.capability_manifest()?;
+        let names: Vec<&str> = manifest.functions().iter().map(|it| it.name.as_str()).collect();
+        assert_eq!(names, vec!("double", "pair", "sleep"));
+        assert_eq!(manifest.get("pair").map(|it| it.results_count), Some(2));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn aborted_jobs_are_forgotten() -> Result<(), Box<dyn std::error::Error>> {
+        let controller = controller()?;
+        let state = VmState::new();
+        let aborted = controller.call("double".to_string(), vec!(VmValue::Integer(1)))?;
+        controller.abort(vec!(aborted))?;
+        assert!(controller.get_and_remove_result_of(aborted).is_err());
+        let job = controller.call("double".to_string(), vec!(VmValue::Integer(2)))?;
+        controller.suspend_until_all(&state, vec!(job))?;
+        assert_eq!(controller.get_and_remove_result_of(job)?, Some(VmValue::Integer(4)));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn aborted_jobs_are_cancelled() -> Result<(), Box<dyn std::error::Error>> {
+        let mut controller = ProtocolController::new();
+        connect(&mut controller, FunctionHost::new(1, 0, 0, 0)
+            .streaming_function("spin", 0, 0, 0, |_, context| {
+                while !context.is_cancelled() {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                Err("Cancelled".into())
+            }))?;
+        let aborted = controller.call("spin".to_string(), vec!())?;
+        controller.abort(vec!(aborted))?;
+        for _ in 0..500 {
+            if controller.abandoned.lock().unwrap().is_empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(2));
+            controller.collect_abandoned()?;
+        }
+        assert!(controller.abandoned.lock().unwrap().is_empty());
+        assert!(controller.is_host_connected(0));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn hosts_ignoring_cancellation_are_released() -> Result<(), Box<dyn std::error::Error>> {
+        let (client_reader, host_writer) = std::io::pipe()?;
+        let (host_reader, client_writer) = std::io::pipe()?;
+        let (release, released) = std::sync::mpsc::channel();
+        let client = std::thread::spawn(move || hanging_host(client_reader, client_writer, released, FEATURE_CANCELLATION));
+        let mut controller = ProtocolController::new();
+        controller.set_cancel_timeout(Duration::from_millis(20));
+        controller.add_host(ProtocolHost::connect(Box::new(host_writer), Box::new(host_reader))?, None);
+        let aborted = controller.call("double".to_string(), vec!(VmValue::Integer(1)))?;
+        controller.abort(vec!(aborted))?;
+        assert!(controller.is_host_connected(0));
+        for _ in 0..500 {
+            if !controller.is_host_connected(0) {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(2));
+            controller.collect_abandoned()?;
+        }
+        assert!(!controller.is_host_connected(0));
+        drop(release);
+        assert!(client.join().unwrap().is_ok());
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn hosts_register_at_listener() -> Result<(), Box<dyn std::error::Error>> {
+        let listener = TransportListener::bind(&"tcp://127.0.0.1:0".parse()?)?;
+        let endpoint = listener.local_endpoint()?;
+        let mut controller = ProtocolController::new();
+        assert_eq!(controller.accept_pending(&listener)?, 0);
+        let function_host = std::thread::spawn(move || double_host().serve_endpoint(&endpoint, Duration::from_millis(10), 50));
+        controller.accept(&listener)?;
+        let state = VmState::new();
+        let job = controller.call("double".to_string(), vec!(VmValue::Integer(4)))?;
+        controller.suspend_until_all(&state, vec!(job))?;
+        assert_eq!(controller.get_and_remove_result_of(job)?, Some(VmValue::Integer(8)));
+        drop(controller);
+        assert!(function_host.join().unwrap().is_ok());
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn dropped_connection_is_reconnected() -> Result<(), Box<dyn std::error::Error>> {
+        let listener = TransportListener::bind(&"tcp://127.0.0.1:0".parse()?)?;
+        let endpoint = listener.local_endpoint()?;
+        let (drop_connection, connection_dropped) = std::sync::mpsc::channel::<()>();
+        let server = std::thread::spawn(move || -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            let function_host = std::sync::Arc::new(double_host());
+            let mut first = listener.accept()?;
+            let (reader, writer) = first.split()?;
+            let first_host = function_host.clone();
+            std::thread::spawn(move || first_host.serve(reader, writer));
+            connection_dropped.recv()?;
+            first.shutdown()?;
+            function_host.serve_listener(&listener)?;
+            Ok(())
+        });
+
+        let mut controller = ProtocolController::new();
+        controller.connect(endpoint)?;
+        let state = VmState::new();
+        let job = controller.call("double".to_string(), vec!(VmValue::Integer(2)))?;
+        controller.suspend_until_all(&state, vec!(job))?;
+        assert_eq!(controller.get_and_remove_result_of(job)?, Some(VmValue::Integer(4)));
+
+        let lost = controller.call("sleep".to_string(), vec!(VmValue::Integer(200)))?;
+        drop_connection.send(())?;
+        assert!(controller.suspend_until_all(&state, vec!(lost)).is_err());
+        assert!(!controller.is_host_connected(0));
+
+        let job = controller.call("double".to_string(), vec!(VmValue::Integer(3)))?;
+        assert!(controller.is_host_connected(0));
+        controller.suspend_until_all(&state, vec!(job))?;
+        assert_eq!(controller.get_and_remove_result_of(job)?, Some(VmValue::Integer(6)));
+        drop(controller);
+        assert!(server.join().unwrap().is_ok());
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn unresponsive_hosts_fail_their_jobs() -> Result<(), Box<dyn std::error::Error>> {
+        let (client_reader, host_writer) = std::io::pipe()?;
+        let (host_reader, client_writer) = std::io::pipe()?;
+        let (release, released) = std::sync::mpsc::channel();
+        let client = std::thread::spawn(move || hanging_host(client_reader, client_writer, released, 0));
+        let mut controller = ProtocolController::with_options(fast_heartbeat());
+        controller.add_host(ProtocolHost::connect_with(Box::new(host_writer), Box::new(host_reader), &fast_heartbeat())?, None);
+        let state = VmState::new();
+        let job = controller.call("double".to_string(), vec!(VmValue::Integer(1)))?;
+        let error = controller.suspend_until_all(&state, vec!(job)).unwrap_err();
+        assert_eq!(error.to_string(), "Function host stopped responding before the job completed");
+        assert!(!controller.is_host_connected(0));
+        drop(release);
+        assert!(client.join().unwrap().is_ok());
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn busy_hosts_answer_heartbeats() -> Result<(), Box<dyn std::error::Error>> {
+        let mut controller = ProtocolController::with_options(fast_heartbeat());
+        let (client_reader, host_writer) = std::io::pipe()?;
+        let (host_reader, client_writer) = std::io::pipe()?;
+        std::thread::spawn(move || double_host().serve(Box::new(client_reader), Box::new(client_writer)).unwrap());
+        controller.add_host(ProtocolHost::connect_with(Box::new(host_writer), Box::new(host_reader), &fast_heartbeat())?, None);
+        let state = VmState::new();
+        let job = controller.call("sleep".to_string(), vec!(VmValue::Integer(200)))?;
+        controller.suspend_until_all(&state, vec!(job))?;
+        assert_eq!(controller.get_and_remove_result_of(job)?, Some(VmValue::Null));
+        assert!(controller.is_host_connected(0));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn jobs_of_lost_hosts_are_rescheduled() -> Result<(), Box<dyn std::error::Error>> {
+        let listener = TransportListener::bind(&"tcp://127.0.0.1:0".parse()?)?;
+        let endpoint = listener.local_endpoint()?;
+        let (release, released) = std::sync::mpsc::channel();
+        let server = std::thread::spawn(move || -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            let mut first = listener.accept()?;
+            let (reader, writer) = first.split()?;
+            let hanging = std::thread::spawn(move || hanging_host(reader, writer, released, 0));
+            double_host().serve_listener(&listener)?;
+            hanging.join().map_err(|_| "Hanging host panicked")??;
+            Ok(())
+        });
+
+        let mut controller = ProtocolController::with_options(fast_heartbeat());
+        controller.set_reschedule(true);
+        controller.connect(endpoint)?;
+        let state = VmState::new();
+        let job = controller.call("double".to_string(), vec!(VmValue::Integer(5)))?;
+        controller.suspend_until_all(&state, vec!(job))?;
+        assert_eq!(controller.get_and_remove_result_of(job)?, Some(VmValue::Integer(10)));
+        drop(release);
+        drop(controller);
+        assert!(server.join().unwrap().is_ok());
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn retired_hosts_finish_running_jobs() -> Result<(), Box<dyn std::error::Error>> {
+        let mut controller = controller()?;
+        let state = VmState::new();
+        let job = controller.call("sleep".to_string(), vec!(VmValue::Integer(50)))?;
+        controller.retire_host(0, 5)?;
+        assert!(!controller.is_host_connected(0));
+        controller.suspend_until_all(&state, vec!(job))?;
+        assert_eq!(controller.get_and_remove_result_of(job)?, Some(VmValue::Null));
+        assert!(!controller.has_function("sleep"));
+        // The second host serves double from now on
+        let result = run_str("value = await double(1);", &controller);
+        assert_eq!(result.err().map(|it| it.to_string()), Some("the second host must not be called".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn replaced_hosts_are_retired_once_idle() -> Result<(), Box<dyn std::error::Error>> {
+        let mut controller = controller()?;
+        let state = VmState::new();
+        let job = controller.call("sleep".to_string(), vec!(VmValue::Integer(50)))?;
+        controller.replace_host(0);
+        assert_eq!(controller.release_replaced_hosts()?, 0);
+        assert!(controller.is_host_connected(0));
+        // New calls are served by the second host, while the job finishes at the first
+        let result = run_str("value = await double(1);", &controller);
+        assert_eq!(result.err().map(|it| it.to_string()), Some("the second host must not be called".to_string()));
+        controller.suspend_until_all(&state, vec!(job))?;
+        assert_eq!(controller.get_and_remove_result_of(job)?, Some(VmValue::Null));
+        assert_eq!(controller.release_replaced_hosts()?, 1);
+        assert!(!controller.is_host_connected(0));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn unfinished_jobs_are_migrated() -> Result<(), Box<dyn std::error::Error>> {
+        let (client_reader, host_writer) = std::io::pipe()?;
+        let (host_reader, client_writer) = std::io::pipe()?;
+        let (release, released) = std::sync::mpsc::channel();
+        let client = std::thread::spawn(move || hanging_host(client_reader, client_writer, released, 0));
+        let mut controller = ProtocolController::new();
+        controller.add_host(ProtocolHost::connect(Box::new(host_writer), Box::new(host_reader))?, None);
+        connect(&mut controller, double_host())?;
+        let state = VmState::new();
+        let job = controller.call("double".to_string(), vec!(VmValue::Integer(5)))?;
+        controller.retire_host(0, 0)?;
+        controller.suspend_until_all(&state, vec!(job))?;
+        assert_eq!(controller.get_and_remove_result_of(job)?, Some(VmValue::Integer(10)));
+        drop(release);
+        assert!(client.join().unwrap().is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+use crate::assembler::checker::FunctionSignature;
+use crate::assembler::compiler::compiler::compile_checked;
+use crate::assembler::parser::parser::parse_x39file;
+use crate::controllers::ProtocolController;
+use crate::io::file_watcher::FileWatcher;
+use crate::io::lambda_file::LambdaFile;
+use crate::machine::VmState;
+
+/// Keeps a manifest deployed at a [ProtocolController], reloading it and recompiling the
+/// scripts of its workflows once they change.
+///
+/// Changes are only deployed once the manifest and all scripts are valid, the previous
+/// version staying deployed otherwise. Workflows are run by states instantiated from the
+/// bytecode compiled, states already running keeping the bytecode they started with.
+pub struct Reloader {
+    path: PathBuf,
+    file: LambdaFile,
+    watcher: FileWatcher,
+    /// The bytecode of every workflow, instantiated to run it.
+    scripts: HashMap<String, VmState>,
+}
+
+impl Reloader {
+    /// Loads the manifest at the path provided into the controller and compiles the scripts
+    /// of its workflows.
+    pub fn load(path: &Path, controller: &mut ProtocolController) -> Result<Reloader, Box<dyn Error>> {
+        let mut reloader = Reloader {
+            path: path.to_path_buf(),
+            file: LambdaFile::default(),
+            watcher: FileWatcher::new(),
+            scripts: HashMap::new(),
+        };
+        reloader.watcher.watch(path);
+        reloader.reload_manifest(controller)?;
+        Ok(reloader)
+    }
+
+    pub fn file(&self) -> &LambdaFile {
+        &self.file
+    }
+
+    /// A new state running the latest bytecode of the workflow provided.
+    pub fn instantiate(&self, workflow: &str) -> Option<VmState> {
+        self.scripts.get(workflow).map(|script| script.instantiate())
+    }
+
+    /// Deploys the changes made to the manifest and scripts since the last poll, returning
+    /// whether any were deployed. Errors are returned once per change, the previous version
+    /// staying deployed until the files are changed again.
+    /// Hosts replaced by earlier reloads are retired once their jobs are collected.
+    pub fn poll(&mut self, controller: &mut ProtocolController) -> Result<bool, Box<dyn Error>> {
+        controller.release_replaced_hosts()?;
+        let changed = self.watcher.poll();
+        if changed.is_empty() {
+            return Ok(false);
+        }
+        if changed.contains(&self.path) {
+            self.reload_manifest(controller)?;
+            return Ok(true);
+        }
+        let signatures = Reloader::signatures(&self.file, controller)?;
+        let mut scripts = vec!();
+        for workflow in self.file.workflows.iter().filter(|workflow| changed.contains(&workflow.script)) {
+            scripts.push((workflow.identifier.clone(), Reloader::compile(&workflow.script, &signatures)?));
+        }
+        for (workflow, script) in scripts {
+            info!("Recompiled the script of workflow {}", workflow);
+            self.scripts.insert(workflow, script);
+        }
+        Ok(true)
+    }
+
+    fn reload_manifest(&mut self, controller: &mut ProtocolController) -> Result<(), Box<dyn Error>> {
+        let file = LambdaFile::load(&self.path)?;
+        let signatures = Reloader::signatures(&file, controller)?;
+        let mut scripts = HashMap::new();
+        for workflow in file.workflows.iter() {
+            scripts.insert(workflow.identifier.clone(), Reloader::compile(&workflow.script, &signatures)?);
+        }
+        controller.load(&file)?;
+        info!("Loaded {} with {} functions and {} workflows", self.path.display(), file.functions.len(), file.workflows.len());
+        for workflow in self.file.workflows.iter() {
+            self.watcher.unwatch(&workflow.script);
+        }
+        for workflow in file.workflows.iter() {
+            self.watcher.watch(&workflow.script);
+        }
+        self.file = file;
+        self.scripts = scripts;
+        Ok(())
+    }
+
+    /// The signatures scripts are checked against, being those declared by the manifest and
+    /// those of the functions served by hosts connected otherwise.
+    fn signatures(file: &LambdaFile, controller: &ProtocolController) -> Result<Vec<FunctionSignature>, Box<dyn Error>> {
+        let mut signatures = file.signatures();
+        for signature in controller.capability_manifest()?.signatures() {
+            if file.function(&signature.name).is_none() {
+                signatures.push(signature);
+            }
+        }
+        Ok(signatures)
+    }
+
+    fn compile(path: &Path, signatures: &[FunctionSignature]) -> Result<VmState, Box<dyn Error>> {
+        let input = std::fs::read_to_string(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+        let (remainder, file) = parse_x39file(&input).map_err(|error| format!("{}: {}", path.display(), error))?;
+        if !remainder.trim().is_empty() {
+            let line = input[..input.len() - remainder.len()].lines().count().max(1);
+            return Err(format!("{}:{}: Failed to parse script", path.display(), line).into());
+        }
+        compile_checked(file, signatures).map_err(|error| {
+            warn!("Failed to compile {}: {}", path.display(), error);
+            format!("{}: {}", path.display(), error).into()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+    use std::path::PathBuf;
+    use tracing_test::traced_test;
+    use crate::controllers::ProtocolController;
+    use crate::controllers::reloader::Reloader;
+    use crate::machine::{VmStack, VmState, VmValue};
+
+    fn temp_dir() -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let directory = std::env::temp_dir().join(format!("x39-reloader-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&directory)?;
+        Ok(directory)
+    }
+
+    fn run(mut vm_state: VmState, controller: &ProtocolController) -> Result<Option<VmValue>, Box<dyn std::error::Error>> {
+        let mut vm_stack = VmStack::new();
+        while !vm_state.is_done() {
+            vm_state.step(&mut vm_stack, controller)?;
+        }
+        let slot = vm_state.find_local("value", vm_state.instructions().len()).ok_or("value missing")?;
+        Ok(vm_stack.get_local(slot).cloned())
+    }
+
+    #[test]
+    #[traced_test]
+    fn changed_scripts_are_recompiled() -> Result<(), Box<dyn std::error::Error>> {
+        let directory = temp_dir()?;
+        let manifest = directory.join("lambda.toml");
+        write(&manifest, "[workflows.answer]\nscript = \"answer.x39\"\n")?;
+        write(directory.join("answer.x39"), "value = 1;")?;
+        let mut controller = ProtocolController::new();
+        let mut reloader = Reloader::load(&manifest, &mut controller)?;
+        let running = reloader.instantiate("answer").ok_or("answer missing")?;
+        assert!(!reloader.poll(&mut controller)?);
+        write(directory.join("answer.x39"), "value = 42;")?;
+        assert!(reloader.poll(&mut controller)?);
+        // States running keep the bytecode they started with
+        assert_eq!(run(running, &controller)?, Some(VmValue::Integer(1)));
+        assert_eq!(run(reloader.instantiate("answer").ok_or("answer missing")?, &controller)?, Some(VmValue::Integer(42)));
+        // Invalid scripts keep the previous version deployed
+        write(directory.join("answer.x39"), "value = missing(1);")?;
+        assert!(reloader.poll(&mut controller).is_err());
+        assert_eq!(run(reloader.instantiate("answer").ok_or("answer missing")?, &controller)?, Some(VmValue::Integer(42)));
+        std::fs::remove_dir_all(&directory)?;
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn changed_manifests_are_reloaded() -> Result<(), Box<dyn std::error::Error>> {
+        let directory = temp_dir()?;
+        let manifest = directory.join("lambda.toml");
+        write(&manifest, "[workflows.first]\nscript = \"first.x39\"\n")?;
+        write(directory.join("first.x39"), "value = 1;")?;
+        write(directory.join("second.x39"), "value = 2;")?;
+        let mut controller = ProtocolController::new();
+        let mut reloader = Reloader::load(&manifest, &mut controller)?;
+        write(&manifest, "[workflows.first]\nscript = \"first.x39\"\n\n[workflows.second]\nscript = \"second.x39\"\n")?;
+        assert!(reloader.poll(&mut controller)?);
+        assert_eq!(reloader.file().workflows.len(), 2);
+        assert_eq!(run(reloader.instantiate("second").ok_or("second missing")?, &controller)?, Some(VmValue::Integer(2)));
+        // Invalid manifests keep the previous version deployed
+        write(&manifest, "[workflows.first]\n")?;
+        let error = reloader.poll(&mut controller).err().ok_or("Expected an error")?;
+        assert!(error.to_string().ends_with("1:1: Workflow 'first' lacks a script"), "{}", error);
+        assert!(reloader.instantiate("second").is_some());
+        std::fs::remove_dir_all(&directory)?;
+        Ok(())
+    }
+}
@@ -1,10 +1,27 @@
 use uuid::Uuid;
 use crate::machine::{VmState, VmValue};
 
+/// The next partial result of a job, as emitted by functions streaming their results.
+#[derive(Debug, PartialEq)]
+pub enum PartialResult {
+    Value(VmValue),
+    /// No partial result is available yet, but the job is still running.
+    Pending,
+    /// The job completed and all of its partial results were taken.
+    Finished,
+}
+
 pub trait VmController {
-    fn call(&self, function: String, arg: Option<VmValue>) -> Result<Uuid, Box<dyn std::error::Error>>;
+    fn call(&self, function: String, arguments: Vec<VmValue>) -> Result<Uuid, Box<dyn std::error::Error>>;
     fn get_and_remove_result_of(&self, job: Uuid) -> Result<Option<VmValue>, Box<dyn std::error::Error>>;
     fn suspend_until_all(&self, state: &VmState, jobs: Vec<Uuid>) -> Result<(), Box<dyn std::error::Error>>;
     fn suspend_until_any(&self, state: &VmState, jobs: Vec<Uuid>) -> Result<(), Box<dyn std::error::Error>>;
     fn abort(&self, jobs: Vec<Uuid>) -> Result<(), Box<dyn std::error::Error>>;
+    fn take_partial_result_of(&self, job: Uuid) -> Result<PartialResult, Box<dyn std::error::Error>>;
+    fn suspend_until_partial(&self, state: &VmState, job: Uuid) -> Result<(), Box<dyn std::error::Error>>;
+    /// Outputs a value printed by a script.
+    fn print(&self, value: &VmValue) -> Result<(), Box<dyn std::error::Error>> {
+        println!("{:?}", value);
+        Ok(())
+    }
 }
\ No newline at end of file
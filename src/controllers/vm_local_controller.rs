@@ -1,48 +1,181 @@
+use std::collections::HashMap;
 use std::error::Error;
-use std::process::Command;
+use std::io::Read;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::Duration;
 use uuid::Uuid;
-use crate::controllers::VmController;
+use crate::assembler::checker::function_name;
+use crate::controllers::{PartialResult, VmController};
+use crate::machine::json::{self, JsonOptions};
 use crate::machine::{VmState, VmValue};
 
-pub struct VmLocalController();
+/// The interval the completion of commands is polled at.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// A [VmController] running every function called as a local command named like the function,
+/// eg. `echo("hello")` running `echo hello`.
+///
+/// String arguments are passed as they are, all others as JSON. The output of the command,
+/// without its trailing newline, is the result of the call, being decoded if it is JSON and a
+/// string otherwise. Commands exiting unsuccessfully fail the call with their error output.
+pub struct VmLocalController {
+    jobs: Mutex<HashMap<Uuid, LocalJob>>,
+}
+
+struct LocalJob {
+    command: String,
+    child: Child,
+    stdout: JoinHandle<Vec<u8>>,
+    stderr: JoinHandle<Vec<u8>>,
+    status: Option<ExitStatus>,
+}
 
 impl VmLocalController {
     pub fn new() -> VmLocalController {
-        VmLocalController{}
+        VmLocalController { jobs: Mutex::new(HashMap::new()) }
+    }
+
+    fn argument(value: &VmValue) -> Result<String, Box<dyn Error>> {
+        match value {
+            VmValue::String(string) => Ok(string.to_string()),
+            value => Ok(json::to_string(value, &JsonOptions::default())?),
+        }
+    }
+
+    fn read_to_end(mut reader: impl Read + Send + 'static) -> JoinHandle<Vec<u8>> {
+        std::thread::spawn(move || {
+            let mut output = vec!();
+            let _ = reader.read_to_end(&mut output);
+            output
+        })
+    }
+
+    fn is_completed(&self, job: Uuid) -> Result<bool, Box<dyn Error>> {
+        let mut jobs = self.jobs.lock().map_err(|_| "Job lock poisoned")?;
+        let job = jobs.get_mut(&job).ok_or("Job is unknown")?;
+        if job.status.is_none() {
+            job.status = job.child.try_wait()?;
+        }
+        Ok(job.status.is_some())
+    }
+
+    fn output_to_value(output: Vec<u8>) -> Result<VmValue, Box<dyn Error>> {
+        let output = String::from_utf8(output)?;
+        let output = output.strip_suffix('\n').unwrap_or(&output);
+        let output = output.strip_suffix('\r').unwrap_or(output);
+        Ok(json::from_str(output, &JsonOptions::default()).unwrap_or_else(|_| VmValue::string(output)))
+    }
+}
+
+impl Default for VmLocalController {
+    fn default() -> Self {
+        VmLocalController::new()
     }
 }
 
 impl VmController for VmLocalController {
-    fn call(&self, function: String, arg: Option<VmValue>) -> Result<Uuid, Box<dyn Error>> {
-        let output = if cfg!(target_os = "windows") {
-            Command::new("cmd")
-                .args(["/C", "echo hello"])
-                .output()
-                .expect("failed to execute process")
-        } else {
-            Command::new("sh")
-                .arg("-c")
-                .arg("echo hello")
-                .output()
-                .expect("failed to execute process")
-        };
-        let data = output.stdout;
-        todo!()
+    fn call(&self, function: String, arguments: Vec<VmValue>) -> Result<Uuid, Box<dyn Error>> {
+        let command = function_name(&function).to_string();
+        let arguments = arguments.iter().map(VmLocalController::argument).collect::<Result<Vec<String>, _>>()?;
+        let mut child = Command::new(&command)
+            .args(arguments)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|error| format!("Failed to run {}: {}", command, error))?;
+        let stdout = VmLocalController::read_to_end(child.stdout.take().ok_or("Output of the command is not available")?);
+        let stderr = VmLocalController::read_to_end(child.stderr.take().ok_or("Error output of the command is not available")?);
+        let job = Uuid::new_v4();
+        let mut jobs = self.jobs.lock().map_err(|_| "Job lock poisoned")?;
+        jobs.insert(job, LocalJob { command, child, stdout, stderr, status: None });
+        Ok(job)
     }
 
     fn get_and_remove_result_of(&self, job: Uuid) -> Result<Option<VmValue>, Box<dyn Error>> {
-        todo!()
+        if !self.is_completed(job)? {
+            return Ok(None);
+        }
+        let job = self.jobs.lock().map_err(|_| "Job lock poisoned")?.remove(&job).ok_or("Job is unknown")?;
+        let stdout = job.stdout.join().map_err(|_| "Failed to read the output of the command")?;
+        let stderr = job.stderr.join().map_err(|_| "Failed to read the error output of the command")?;
+        match job.status {
+            Some(status) if status.success() => VmLocalController::output_to_value(stdout).map(Some),
+            _ => Err(format!("{} failed: {}", job.command, String::from_utf8_lossy(&stderr).trim_end()).into()),
+        }
     }
 
-    fn suspend_until_all(&self, state: &VmState, jobs: Vec<Uuid>) -> Result<(), Box<dyn Error>> {
-        todo!()
+    fn suspend_until_all(&self, _state: &VmState, jobs: Vec<Uuid>) -> Result<(), Box<dyn Error>> {
+        for job in jobs {
+            while !self.is_completed(job)? {
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        }
+        Ok(())
     }
 
-    fn suspend_until_any(&self, state: &VmState, jobs: Vec<Uuid>) -> Result<(), Box<dyn Error>> {
-        todo!()
+    fn suspend_until_any(&self, _state: &VmState, jobs: Vec<Uuid>) -> Result<(), Box<dyn Error>> {
+        if jobs.is_empty() {
+            return Ok(());
+        }
+        loop {
+            for job in jobs.iter() {
+                if self.is_completed(*job)? {
+                    return Ok(());
+                }
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
     }
 
     fn abort(&self, jobs: Vec<Uuid>) -> Result<(), Box<dyn Error>> {
-        todo!()
+        let mut running = self.jobs.lock().map_err(|_| "Job lock poisoned")?;
+        for job in jobs {
+            let Some(mut job) = running.remove(&job) else {
+                continue;
+            };
+            if job.status.is_none() {
+                let _ = job.child.kill();
+                let _ = job.child.wait();
+            }
+        }
+        Ok(())
+    }
+
+    fn take_partial_result_of(&self, job: Uuid) -> Result<PartialResult, Box<dyn Error>> {
+        // Commands only have a single result, being available once they completed
+        Ok(if self.is_completed(job)? { PartialResult::Finished } else { PartialResult::Pending })
+    }
+
+    fn suspend_until_partial(&self, state: &VmState, job: Uuid) -> Result<(), Box<dyn Error>> {
+        self.suspend_until_all(state, vec!(job))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_test::traced_test;
+    use crate::controllers::{VmController, VmLocalController};
+    use crate::machine::{VmState, VmValue};
+
+    #[test]
+    #[traced_test]
+    #[cfg(unix)]
+    fn calls_run_local_commands() -> Result<(), Box<dyn std::error::Error>> {
+        let controller = VmLocalController::new();
+        let state = VmState::new();
+        let text = controller.call("echo".to_string(), vec!(VmValue::string("hello")))?;
+        let number = controller.call("echo".to_string(), vec!(VmValue::Integer(42)))?;
+        let failing = controller.call("false".to_string(), vec!())?;
+        controller.suspend_until_all(&state, vec!(text, number, failing))?;
+        assert_eq!(controller.get_and_remove_result_of(text)?, Some(VmValue::string("hello")));
+        assert_eq!(controller.get_and_remove_result_of(number)?, Some(VmValue::Integer(42)));
+        assert!(controller.get_and_remove_result_of(failing).is_err());
+        let slow = controller.call("sleep".to_string(), vec!(VmValue::Integer(5)))?;
+        controller.abort(vec!(slow))?;
+        assert!(controller.get_and_remove_result_of(slow).is_err());
+        Ok(())
     }
-}
\ No newline at end of file
+}
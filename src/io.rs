@@ -1,6 +1,10 @@
+pub mod capability_manifest;
 pub mod lambda_file;
 pub mod function_host;
 pub mod protocol_v1;
+pub mod protocol_v2;
+pub mod transport;
+pub mod file_watcher;
 
 pub use self::lambda_file::*;
 pub use self::function_host::*;
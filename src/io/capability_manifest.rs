@@ -0,0 +1,342 @@
+use std::error::Error;
+use std::path::Path;
+use crate::assembler::checker::FunctionSignature;
+use crate::assembler::parser::parser::TypeAnnotation;
+use crate::io::protocol_v1::protocol_v1::data::{FunctionCapabilitiesResponseMessage, ParameterDescription, SignatureDescription};
+use crate::machine::json::{self, JsonOptions};
+use crate::machine::{VmObject, VmValue};
+
+/// The capabilities of a single function, as reported by its function host.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionCapabilities {
+    pub name: String,
+    pub arguments_required: u8,
+    pub arguments_count: u8,
+    pub results_count: u8,
+    /// Absent if the host does not describe its functions.
+    pub signature: Option<SignatureDescription>,
+}
+
+impl From<&FunctionCapabilitiesResponseMessage> for FunctionCapabilities {
+    fn from(message: &FunctionCapabilitiesResponseMessage) -> Self {
+        FunctionCapabilities {
+            name: message.function_name.clone(),
+            arguments_required: message.arguments_required,
+            arguments_count: message.arguments_count,
+            results_count: message.results_count,
+            signature: message.signature.clone(),
+        }
+    }
+}
+
+impl FunctionCapabilities {
+    /// The signature scripts are checked against. Values not described by a schema are of
+    /// any type, functions with several results produce an array.
+    pub fn signature(&self) -> FunctionSignature {
+        let (parameters, parameter_names, results) = match &self.signature {
+            Some(signature) => (
+                signature.parameters.iter().map(|it| schema_type(&it.schema)).collect(),
+                signature.parameters.iter().map(|it| it.name.clone()).collect(),
+                signature.results.iter().map(|it| schema_type(&it.schema)).collect(),
+            ),
+            None => (
+                vec!(TypeAnnotation::Any; self.arguments_count as usize),
+                vec!(),
+                vec!(TypeAnnotation::Any; self.results_count as usize),
+            ),
+        };
+        let mut results: Vec<TypeAnnotation> = results;
+        let result = match results.len() {
+            0 => TypeAnnotation::Null,
+            1 => results.remove(0),
+            _ => TypeAnnotation::Array(Box::new(TypeAnnotation::Any)),
+        };
+        FunctionSignature {
+            name: self.name.clone(),
+            parameters,
+            parameter_names,
+            arguments_required: self.arguments_required as usize,
+            result,
+        }
+    }
+}
+
+/// The functions provided by the function hosts at some point in time, cached on disk to
+/// check scripts at compile time without connecting to the hosts.
+///
+/// Stored as JSON, the schema of every argument and result being embedded as is.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CapabilityManifest {
+    functions: Vec<FunctionCapabilities>,
+}
+
+impl CapabilityManifest {
+    pub fn new() -> CapabilityManifest {
+        CapabilityManifest { functions: vec!() }
+    }
+
+    /// Adds the function provided, replacing a function of the same name.
+    pub fn insert(&mut self, function: FunctionCapabilities) {
+        match self.functions.iter_mut().find(|it| it.name == function.name) {
+            Some(existing) => *existing = function,
+            None => self.functions.push(function),
+        }
+    }
+
+    pub fn functions(&self) -> &[FunctionCapabilities] {
+        &self.functions
+    }
+
+    pub fn get(&self, name: &str) -> Option<&FunctionCapabilities> {
+        self.functions.iter().find(|it| it.name == name)
+    }
+
+    /// The signatures of all functions, see [FunctionCapabilities::signature].
+    pub fn signatures(&self) -> Vec<FunctionSignature> {
+        self.functions.iter().map(|it| it.signature()).collect()
+    }
+
+    pub fn load(path: &Path) -> Result<CapabilityManifest, Box<dyn Error>> {
+        CapabilityManifest::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> Result<String, Box<dyn Error>> {
+        let functions = self.functions.iter().map(function_to_value).collect();
+        let manifest = object(vec!(("functions", VmValue::array(functions))));
+        Ok(json::to_string(&manifest, &JsonOptions::default())?)
+    }
+
+    pub fn from_json(input: &str) -> Result<CapabilityManifest, Box<dyn Error>> {
+        let manifest = json::from_str(input, &JsonOptions::default())?;
+        let VmValue::Array(functions) = field(&manifest, "functions")? else {
+            return Err("Manifest functions must be an array".into());
+        };
+        let mut manifest = CapabilityManifest::new();
+        for function in functions.iter() {
+            manifest.insert(function_from_value(function)?);
+        }
+        Ok(manifest)
+    }
+}
+
+/// The type a JSON-schema-like description refers to, eg. `{"type": "array", "items": {"type": "integer"}}`.
+/// Schemas that cannot be mapped to a single type are of any type.
+pub fn schema_type(schema: &str) -> TypeAnnotation {
+    match json::from_str(schema, &JsonOptions::default()) {
+        Ok(schema) => value_type(&schema),
+        Err(_) => TypeAnnotation::Any,
+    }
+}
+
+fn value_type(schema: &VmValue) -> TypeAnnotation {
+    let text = |key: &str| match field(schema, key) {
+        Ok(VmValue::String(text)) => Some(text.to_string()),
+        _ => None,
+    };
+    match text("type").as_deref() {
+        Some("null") => TypeAnnotation::Null,
+        Some("boolean") => TypeAnnotation::Boolean,
+        Some("integer") => TypeAnnotation::Integer,
+        Some("number") => TypeAnnotation::Number,
+        Some("string") if text("contentEncoding").as_deref() == Some("base64") => TypeAnnotation::Bytes,
+        Some("string") if text("format").as_deref() == Some("date-time") => TypeAnnotation::Timestamp,
+        Some("string") => TypeAnnotation::String,
+        Some("object") => TypeAnnotation::Object,
+        Some("array") => TypeAnnotation::Array(Box::new(field(schema, "items").map(value_type).unwrap_or(TypeAnnotation::Any))),
+        _ => TypeAnnotation::Any,
+    }
+}
+
+/// The JSON-schema-like description of the type provided, see [schema_type].
+/// Jobs cannot be passed to functions and are described as any value.
+pub fn type_schema(value_type: &TypeAnnotation) -> String {
+    json::to_string(&schema_value(value_type), &JsonOptions::default()).expect("Schemas are always valid JSON")
+}
+
+fn schema_value(value_type: &TypeAnnotation) -> VmValue {
+    let named = |name: &str| ("type", VmValue::string(name));
+    object(match value_type {
+        TypeAnnotation::Any | TypeAnnotation::Job(_) => vec!(),
+        TypeAnnotation::Null => vec!(named("null")),
+        TypeAnnotation::Boolean => vec!(named("boolean")),
+        TypeAnnotation::Integer => vec!(named("integer")),
+        TypeAnnotation::Number => vec!(named("number")),
+        TypeAnnotation::String => vec!(named("string")),
+        TypeAnnotation::Bytes => vec!(named("string"), ("contentEncoding", VmValue::string("base64"))),
+        TypeAnnotation::Timestamp => vec!(named("string"), ("format", VmValue::string("date-time"))),
+        TypeAnnotation::Object => vec!(named("object")),
+        TypeAnnotation::Array(element) => vec!(named("array"), ("items", schema_value(element))),
+    })
+}
+
+fn object(pairs: Vec<(&str, VmValue)>) -> VmValue {
+    let mut object = VmObject::new();
+    for (key, value) in pairs {
+        object.insert(key.to_string(), value);
+    }
+    VmValue::Object(std::sync::Arc::new(object))
+}
+
+fn field<'v>(value: &'v VmValue, key: &str) -> Result<&'v VmValue, Box<dyn Error>> {
+    match value {
+        VmValue::Object(object) => object.get(key).ok_or_else(|| format!("Manifest entry lacks '{}'", key).into()),
+        _ => Err("Manifest entries must be objects".into()),
+    }
+}
+
+fn string_field(value: &VmValue, key: &str) -> Result<String, Box<dyn Error>> {
+    match field(value, key)? {
+        VmValue::String(text) => Ok(text.to_string()),
+        _ => Err(format!("Manifest field '{}' must be a string", key).into()),
+    }
+}
+
+fn integer_field(value: &VmValue, key: &str) -> Result<i64, Box<dyn Error>> {
+    match field(value, key)? {
+        VmValue::Integer(integer) => Ok(*integer),
+        _ => Err(format!("Manifest field '{}' must be an integer", key).into()),
+    }
+}
+
+fn count_field(value: &VmValue, key: &str) -> Result<u8, Box<dyn Error>> {
+    Ok(u8::try_from(integer_field(value, key)?).map_err(|_| format!("Manifest field '{}' is out of range", key))?)
+}
+
+fn parameter_to_value(parameter: &ParameterDescription) -> VmValue {
+    // Schemas are embedded as JSON, those that are not valid JSON are kept as string
+    let schema = json::from_str(&parameter.schema, &JsonOptions::default())
+        .unwrap_or_else(|_| VmValue::string(parameter.schema.clone()));
+    object(vec!(
+        ("name", VmValue::string(parameter.name.clone())),
+        ("schema", schema),
+        ("description", VmValue::string(parameter.description.clone())),
+    ))
+}
+
+fn parameter_from_value(value: &VmValue) -> Result<ParameterDescription, Box<dyn Error>> {
+    let schema = match field(value, "schema")? {
+        VmValue::String(schema) => schema.to_string(),
+        schema => json::to_string(schema, &JsonOptions::default())?,
+    };
+    Ok(ParameterDescription {
+        name: string_field(value, "name")?,
+        schema,
+        description: string_field(value, "description")?,
+    })
+}
+
+fn function_to_value(function: &FunctionCapabilities) -> VmValue {
+    let mut pairs = vec!(
+        ("name", VmValue::string(function.name.clone())),
+        ("arguments_required", VmValue::Integer(function.arguments_required as i64)),
+        ("arguments_count", VmValue::Integer(function.arguments_count as i64)),
+        ("results_count", VmValue::Integer(function.results_count as i64)),
+    );
+    if let Some(signature) = &function.signature {
+        pairs.push(("description", VmValue::string(signature.description.clone())));
+        pairs.push(("idempotent", VmValue::Boolean(signature.idempotent)));
+        pairs.push(("timeout_hint", VmValue::Integer(signature.timeout_hint as i64)));
+        pairs.push(("parameters", VmValue::array(signature.parameters.iter().map(parameter_to_value).collect())));
+        pairs.push(("results", VmValue::array(signature.results.iter().map(parameter_to_value).collect())));
+    }
+    object(pairs)
+}
+
+fn function_from_value(value: &VmValue) -> Result<FunctionCapabilities, Box<dyn Error>> {
+    let parameters = |key: &str| -> Result<Vec<ParameterDescription>, Box<dyn Error>> {
+        match field(value, key)? {
+            VmValue::Array(parameters) => parameters.iter().map(parameter_from_value).collect(),
+            _ => Err(format!("Manifest field '{}' must be an array", key).into()),
+        }
+    };
+    let function = FunctionCapabilities {
+        name: string_field(value, "name")?,
+        arguments_required: count_field(value, "arguments_required")?,
+        arguments_count: count_field(value, "arguments_count")?,
+        results_count: count_field(value, "results_count")?,
+        signature: match field(value, "description") {
+            Err(_) => None,
+            Ok(_) => Some(SignatureDescription {
+                description: string_field(value, "description")?,
+                idempotent: matches!(field(value, "idempotent")?, VmValue::Boolean(true)),
+                timeout_hint: u32::try_from(integer_field(value, "timeout_hint")?).map_err(|_| "Manifest field 'timeout_hint' is out of range")?,
+                parameters: parameters("parameters")?,
+                results: parameters("results")?,
+            }),
+        },
+    };
+    if let Some(signature) = &function.signature {
+        if signature.parameters.len() != function.arguments_count as usize || signature.results.len() != function.results_count as usize {
+            return Err(format!("Signature of '{}' does not describe every argument and result", function.name).into());
+        }
+    }
+    Ok(function)
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_test::traced_test;
+    use crate::assembler::parser::parser::TypeAnnotation;
+    use crate::io::capability_manifest::{CapabilityManifest, FunctionCapabilities, schema_type, type_schema};
+    use crate::io::protocol_v1::protocol_v1::data::{ParameterDescription, SignatureDescription};
+
+    fn fetch() -> FunctionCapabilities {
+        FunctionCapabilities {
+            name: "fetch".to_string(),
+            arguments_required: 1,
+            arguments_count: 2,
+            results_count: 1,
+            signature: Some(SignatureDescription {
+                description: "Downloads a resource".to_string(),
+                idempotent: true,
+                timeout_hint: 30000,
+                parameters: vec!(
+                    ParameterDescription::new("url", r#"{"type":"string"}"#, "The resource to download"),
+                    ParameterDescription::new("headers", r#"{"type":"array","items":{"type":"string"}}"#, ""),
+                ),
+                results: vec!(ParameterDescription::new("body", r#"{"type":"string","contentEncoding":"base64"}"#, "")),
+            }),
+        }
+    }
+
+    #[test]
+    #[traced_test]
+    fn schemas_map_to_types() -> Result<(), Box<dyn std::error::Error>> {
+        let types = [
+            TypeAnnotation::Any,
+            TypeAnnotation::Integer,
+            TypeAnnotation::Bytes,
+            TypeAnnotation::Timestamp,
+            TypeAnnotation::Array(Box::new(TypeAnnotation::Array(Box::new(TypeAnnotation::Number)))),
+        ];
+        for value_type in types {
+            assert_eq!(schema_type(&type_schema(&value_type)), value_type);
+        }
+        assert_eq!(schema_type(r#"{"type": ["string", "null"]}"#), TypeAnnotation::Any);
+        assert_eq!(schema_type("not a schema"), TypeAnnotation::Any);
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn manifests_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+        let mut manifest = CapabilityManifest::new();
+        manifest.insert(fetch());
+        manifest.insert(FunctionCapabilities { name: "pair".to_string(), arguments_required: 0, arguments_count: 0, results_count: 2, signature: None });
+        let loaded = CapabilityManifest::from_json(&manifest.to_json()?)?;
+        assert_eq!(loaded, manifest);
+
+        let signatures = loaded.signatures();
+        assert_eq!(signatures[0].parameters, vec!(TypeAnnotation::String, TypeAnnotation::Array(Box::new(TypeAnnotation::String))));
+        assert_eq!(signatures[0].arguments_required, 1);
+        assert_eq!(signatures[0].result, TypeAnnotation::Bytes);
+        assert_eq!(signatures[1].result, TypeAnnotation::Array(Box::new(TypeAnnotation::Any)));
+        assert!(CapabilityManifest::from_json(r#"{"functions": [{"name": "f"}]}"#).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Detects changes of files by polling their modification time and size, as editors and
+/// deployments may replace files rather than writing them in place.
+#[derive(Debug, Default)]
+pub struct FileWatcher {
+    files: Vec<(PathBuf, Option<(SystemTime, u64)>)>,
+}
+
+impl FileWatcher {
+    pub fn new() -> FileWatcher {
+        FileWatcher::default()
+    }
+
+    /// Starts watching the file provided, its current state not being reported as change.
+    /// Files not existing yet are reported once created.
+    pub fn watch(&mut self, path: &Path) {
+        if self.is_watched(path) {
+            return;
+        }
+        self.files.push((path.to_path_buf(), FileWatcher::stamp(path)));
+    }
+
+    pub fn unwatch(&mut self, path: &Path) {
+        self.files.retain(|(watched, _)| watched != path);
+    }
+
+    pub fn is_watched(&self, path: &Path) -> bool {
+        self.files.iter().any(|(watched, _)| watched == path)
+    }
+
+    /// The files changed, created or removed since they were watched or last polled.
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        let mut changed = vec!();
+        for (path, stamp) in self.files.iter_mut() {
+            let current = FileWatcher::stamp(path);
+            if current != *stamp {
+                *stamp = current;
+                changed.push(path.clone());
+            }
+        }
+        changed
+    }
+
+    fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+        let metadata = std::fs::metadata(path).ok()?;
+        Some((metadata.modified().ok()?, metadata.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use tracing_test::traced_test;
+    use crate::io::file_watcher::FileWatcher;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("x39-{}-{}", uuid::Uuid::new_v4(), name))
+    }
+
+    #[test]
+    #[traced_test]
+    fn changes_are_reported_once() -> Result<(), Box<dyn std::error::Error>> {
+        let path = temp_path("watched.txt");
+        std::fs::write(&path, "a")?;
+        let mut watcher = FileWatcher::new();
+        watcher.watch(&path);
+        assert!(watcher.poll().is_empty());
+        std::fs::write(&path, "ab")?;
+        assert_eq!(watcher.poll(), vec!(path.clone()));
+        assert!(watcher.poll().is_empty());
+        std::fs::remove_file(&path)?;
+        assert_eq!(watcher.poll(), vec!(path.clone()));
+        watcher.unwatch(&path);
+        std::fs::write(&path, "abc")?;
+        assert!(watcher.poll().is_empty());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -1,2 +1,844 @@
-pub trait FunctionHost {
-}
\ No newline at end of file
+use std::collections::{HashMap, VecDeque};
+use std::io::{ErrorKind, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::Duration;
+use tracing::{debug, trace, warn};
+use crate::io::protocol_v1::protocol_v1::{Error, IncomingStream, MAX_OPEN_STREAMS, MAX_STREAM_LENGTH, ProtocolMessage, STREAM_CHUNK_SIZE, write_message, write_stream};
+use crate::io::protocol_v1::protocol_v1::data::*;
+use crate::io::protocol_v2;
+use crate::io::protocol_v2::ValueEncoding;
+use crate::io::transport::{Endpoint, Transport, TransportListener};
+use crate::machine::VmValue;
+
+/// A function served by a [FunctionHost], receiving the arguments of a call and producing
+/// its results. An error is reported to lambda as failed call, carrying the error message.
+pub type FunctionHandler = dyn Fn(Vec<VmValue>) -> Result<Vec<VmValue>, Box<dyn std::error::Error + Send + Sync>> + Send + Sync;
+
+/// A [FunctionHandler] receiving the [CallContext] of the call, allowing it to report partial
+/// results while running, consumed by scripts via `for chunk in stream job { }`, and to stop
+/// early once the call was cancelled.
+pub type StreamingFunctionHandler = dyn Fn(Vec<VmValue>, &CallContext) -> Result<Vec<VmValue>, Box<dyn std::error::Error + Send + Sync>> + Send + Sync;
+
+struct HostedFunction {
+    name: String,
+    arguments_required: u8,
+    arguments_count: u8,
+    results_count: u8,
+    signature: Option<SignatureDescription>,
+    handler: Arc<StreamingFunctionHandler>,
+}
+
+/// The state of a connection agreed on in the handshake, shared with the calls running.
+struct ConnectionState {
+    protocol: AtomicU32,
+    /// The features supported by both lambda and this host.
+    features: AtomicU32,
+    next_stream_id: AtomicU32,
+}
+
+impl ConnectionState {
+    fn encoding(&self) -> ValueEncoding {
+        ValueEncoding::for_protocol(self.protocol.load(Ordering::Relaxed)).unwrap_or(ValueEncoding::Json)
+    }
+
+    fn supports_streams(&self) -> bool {
+        self.features.load(Ordering::Relaxed) & FEATURE_STREAMS != 0
+    }
+
+    fn supports_signatures(&self) -> bool {
+        self.features.load(Ordering::Relaxed) & FEATURE_SIGNATURES != 0
+    }
+
+    fn next_stream_id(&self) -> u32 {
+        self.next_stream_id.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+/// Set once lambda asked to cancel a call. Cancellation is cooperative, handlers are
+/// expected to check the token and return early, eg. with an error.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The context of a running call, sending its partial results to lambda and telling
+/// whether it was cancelled.
+pub struct CallContext {
+    call_request_id: u32,
+    writer: SharedWriter,
+    connection: Arc<ConnectionState>,
+    cancellation: CancellationToken,
+}
+
+impl CallContext {
+    /// Whether lambda accepts partial results. If not, emitting fails.
+    pub fn supports_partial_results(&self) -> bool {
+        self.connection.supports_streams()
+    }
+
+    pub fn cancellation(&self) -> &CancellationToken {
+        &self.cancellation
+    }
+
+    /// Whether lambda asked to cancel the call, in which case its results are discarded.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+
+    /// Sends the value provided as partial result, returning once it was written.
+    pub fn emit(&self, value: &VmValue) -> Result<(), Error> {
+        if !self.supports_partial_results() {
+            return Err("Lambda does not support partial results".into());
+        }
+        let payload = self.connection.encoding().encode(value)?;
+        write_stream(&self.writer, StreamBeginMessage {
+            stream_id: self.connection.next_stream_id(),
+            call_request_id: self.call_request_id,
+            purpose: StreamPurpose::Partial,
+        }, &payload)?;
+        Ok(())
+    }
+}
+
+enum CallState {
+    /// The arguments are being requested from lambda.
+    Receiving { function_index: usize, expected: u8, arguments: Vec<VmValue>, cancellation: CancellationToken },
+    Running { cancellation: CancellationToken },
+    /// The results, encoded, held until lambda closes the call.
+    Completed { results: Vec<Vec<u8>> },
+}
+
+type SharedWriter = Arc<Mutex<Box<dyn Write + Send>>>;
+
+enum ServeEnd {
+    /// Lambda requested termination.
+    Terminated,
+    /// The connection was closed.
+    Disconnected,
+}
+
+/// The worker side of protocol v1, serving the functions registered to lambda.
+///
+/// ```no_run
+/// use x39_lambda::io::function_host::FunctionHost;
+/// use x39_lambda::machine::VmValue;
+///
+/// FunctionHost::new(1, 0, 0, 0)
+///     .function("add", 2, 2, 1, |arguments| match (&arguments[0], &arguments[1]) {
+///         (VmValue::Integer(a), VmValue::Integer(b)) => Ok(vec!(VmValue::Integer(a + b))),
+///         _ => Err("add takes two integers".into()),
+///     })
+///     .serve_stdio()
+///     .unwrap();
+/// ```
+///
+/// Every call runs on its own thread once all of its arguments were received, allowing
+/// lambda to have many calls running at the same time.
+pub struct FunctionHost {
+    version: VersionMessage,
+    functions: Vec<HostedFunction>,
+    max_stream_length: usize,
+}
+
+impl FunctionHost {
+    /// The newest protocol supported, older ones being used if lambda does not support it.
+    const PROTOCOL_VERSION: u32 = protocol_v2::PROTOCOL_VERSION;
+
+    pub fn new(major: u32, minor: u32, build: u32, revision: u32) -> FunctionHost {
+        FunctionHost {
+            version: VersionMessage {
+                major,
+                minor,
+                build,
+                revision,
+                protocol: FunctionHost::PROTOCOL_VERSION,
+                heartbeat_interval: 0,
+                features: FEATURE_STREAMS | FEATURE_CANCELLATION | FEATURE_SIGNATURES,
+            },
+            functions: vec!(),
+            max_stream_length: MAX_STREAM_LENGTH,
+        }
+    }
+
+    /// Sets the maximum number of bytes lambda may send as a single argument stream, the
+    /// connection failing with a protocol error once a stream exceeds it.
+    pub fn max_stream_length(mut self, max_stream_length: usize) -> FunctionHost {
+        self.max_stream_length = max_stream_length;
+        self
+    }
+
+    /// Registers a function. Optional arguments are those between `arguments_required` and
+    /// `arguments_count` and are only passed if lambda provides them.
+    pub fn function<F>(self, name: &str, arguments_required: u8, arguments_count: u8, results_count: u8, handler: F) -> FunctionHost
+        where F: Fn(Vec<VmValue>) -> Result<Vec<VmValue>, Box<dyn std::error::Error + Send + Sync>> + Send + Sync + 'static
+    {
+        self.streaming_function(name, arguments_required, arguments_count, results_count, move |arguments, _| handler(arguments))
+    }
+
+    /// Registers a function receiving the [CallContext] of its calls, which may emit partial
+    /// results while running or stop once cancelled, see [FunctionHost::function].
+    pub fn streaming_function<F>(mut self, name: &str, arguments_required: u8, arguments_count: u8, results_count: u8, handler: F) -> FunctionHost
+        where F: Fn(Vec<VmValue>, &CallContext) -> Result<Vec<VmValue>, Box<dyn std::error::Error + Send + Sync>> + Send + Sync + 'static
+    {
+        self.functions.push(HostedFunction {
+            name: name.to_string(),
+            arguments_required,
+            arguments_count: arguments_count.max(arguments_required),
+            results_count,
+            signature: None,
+            handler: Arc::new(handler),
+        });
+        self
+    }
+
+    /// Describes the function registered last, reported to lambda to check scripts calling it.
+    /// The signature must describe every argument and result of the function.
+    pub fn signature(mut self, signature: SignatureDescription) -> FunctionHost {
+        let function = self.functions.last_mut().expect("A function must be registered before its signature");
+        assert_eq!(signature.parameters.len(), function.arguments_count as usize, "Every argument of '{}' must be described", function.name);
+        assert_eq!(signature.results.len(), function.results_count as usize, "Every result of '{}' must be described", function.name);
+        function.signature = Some(signature);
+        self
+    }
+
+    /// Serves lambda via the stdio of the current process.
+    pub fn serve_stdio(&self) -> Result<(), Error> {
+        self.serve(Box::new(std::io::stdin()), Box::new(std::io::stdout()))
+    }
+
+    /// Serves lambda until it requests termination or closes the connection.
+    pub fn serve(&self, reader: Box<dyn Read + Send>, writer: Box<dyn Write + Send>) -> Result<(), Error> {
+        self.serve_connection(reader, writer)?;
+        Ok(())
+    }
+
+    /// Serves lambda via the transport provided until it requests termination or the
+    /// connection is closed.
+    pub fn serve_transport(&self, mut transport: Box<dyn Transport>) -> Result<(), Error> {
+        let (reader, writer) = transport.split()?;
+        self.serve(reader, writer)
+    }
+
+    /// Waits for lambda to connect, serving one connection after another until lambda
+    /// requests termination.
+    pub fn serve_listener(&self, listener: &TransportListener) -> Result<(), Error> {
+        loop {
+            let mut transport = listener.accept()?;
+            debug!("Accepted connection of {}", transport.peer());
+            let (reader, writer) = transport.split()?;
+            match self.serve_connection(reader, writer) {
+                Ok(ServeEnd::Terminated) => return Ok(()),
+                Ok(ServeEnd::Disconnected) => debug!("Lambda disconnected, awaiting the next connection"),
+                Err(error) => warn!("Connection failed with {}, awaiting the next connection", error),
+            }
+        }
+    }
+
+    /// Registers at lambda listening at the endpoint provided, reconnecting whenever the
+    /// connection drops until lambda requests termination. Gives up after `max_attempts`
+    /// consecutive connection attempts failed.
+    pub fn serve_endpoint(&self, endpoint: &Endpoint, retry_interval: Duration, max_attempts: u32) -> Result<(), Error> {
+        let mut failed_attempts = 0;
+        loop {
+            let mut transport = match endpoint.connect() {
+                Ok(transport) => transport,
+                Err(error) => {
+                    failed_attempts += 1;
+                    if failed_attempts >= max_attempts {
+                        return Err(error.into());
+                    }
+                    std::thread::sleep(retry_interval);
+                    continue;
+                }
+            };
+            failed_attempts = 0;
+            let (reader, writer) = transport.split()?;
+            match self.serve_connection(reader, writer) {
+                Ok(ServeEnd::Terminated) => return Ok(()),
+                Ok(ServeEnd::Disconnected) => debug!("Connection to {} dropped, reconnecting", endpoint),
+                Err(error) => warn!("Connection to {} failed with {}, reconnecting", endpoint, error),
+            }
+            let _ = transport.shutdown();
+            std::thread::sleep(retry_interval);
+        }
+    }
+
+    fn serve_connection(&self, mut reader: Box<dyn Read + Send>, writer: Box<dyn Write + Send>) -> Result<ServeEnd, Error> {
+        let writer: SharedWriter = Arc::new(Mutex::new(writer));
+        let calls: Arc<Mutex<HashMap<u32, CallState>>> = Arc::new(Mutex::new(HashMap::new()));
+        // Argument requests sent, in order, as the argument-response message carries no call-request-id.
+        let mut argument_requests: VecDeque<u32> = VecDeque::new();
+        // Set once lambda asked to quit, refusing new calls until all calls running were closed.
+        let mut draining = false;
+        let connection = Arc::new(ConnectionState {
+            protocol: AtomicU32::new(1),
+            features: AtomicU32::new(0),
+            next_stream_id: AtomicU32::new(0),
+        });
+        let mut incoming: HashMap<u32, IncomingStream> = HashMap::new();
+        loop {
+            let message = match ProtocolMessage::read(&mut reader) {
+                Ok(message) => message,
+                Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(ServeEnd::Disconnected),
+                Err(error) => return Err(error.into()),
+            };
+            trace!("Received {:?}", message.kind());
+            match message {
+                ProtocolMessage::Version(version) => {
+                    debug!("Serving lambda {}.{}.{}.{}", version.major, version.minor, version.build, version.revision);
+                    // Pings are answered by this loop while calls run on their own threads,
+                    // hence any interval proposed is accepted
+                    let protocol = version.protocol.min(FunctionHost::PROTOCOL_VERSION);
+                    FunctionHost::write(&writer, &VersionMessage { protocol, heartbeat_interval: version.heartbeat_interval, ..self.version.clone() })?;
+                    connection.protocol.store(protocol, Ordering::Relaxed);
+                    connection.features.store(version.features & self.version.features, Ordering::Relaxed);
+                }
+                ProtocolMessage::Quit(quit) => {
+                    debug!("Lambda requested to quit within {}s, draining", quit.seconds().max(1));
+                    draining = true;
+                    if calls.lock().map_err(|_| "Call lock poisoned")?.is_empty() {
+                        return Ok(ServeEnd::Terminated);
+                    }
+                }
+                ProtocolMessage::CapabilitiesRequest(_) => {
+                    FunctionHost::write(&writer, &CapabilitiesResponseMessage { functions_count: self.functions.len() as u32 })?;
+                }
+                ProtocolMessage::FunctionCapabilitiesRequest(request) => {
+                    let function = self.functions.get(request.function_requested as usize)
+                        .ok_or("Function requested does not exist")?;
+                    FunctionHost::write(&writer, &FunctionCapabilitiesResponseMessage {
+                        function_index: request.function_requested,
+                        arguments_required: function.arguments_required,
+                        arguments_count: function.arguments_count,
+                        results_count: function.results_count,
+                        function_name: function.name.clone(),
+                        signature: function.signature.clone().filter(|_| connection.supports_signatures()),
+                    })?;
+                }
+                ProtocolMessage::Call(call) => {
+                    let function_index = call.function_index as usize;
+                    if function_index >= self.functions.len() {
+                        // Only the call is failed, as the connection is still usable
+                        FunctionHost::complete(call.call_request_id, Err("Function called does not exist".to_string()), connection.encoding(), &calls, &writer);
+                        continue;
+                    }
+                    if draining {
+                        // The arguments are never requested, failing the call right away
+                        FunctionHost::complete(call.call_request_id, Err("Function host is shutting down".to_string()), connection.encoding(), &calls, &writer);
+                        continue;
+                    }
+                    {
+                        let mut calls = calls.lock().map_err(|_| "Call lock poisoned")?;
+                        calls.insert(call.call_request_id, CallState::Receiving {
+                            function_index,
+                            expected: call.arguments_count,
+                            arguments: Vec::with_capacity(call.arguments_count as usize),
+                            cancellation: CancellationToken::default(),
+                        });
+                    }
+                    if call.arguments_count == 0 {
+                        self.start(call.call_request_id, &calls, &writer, &connection)?;
+                    }
+                    for argument_index in 0..call.arguments_count {
+                        argument_requests.push_back(call.call_request_id);
+                        FunctionHost::write(&writer, &ArgumentRequestMessage { call_request_id: call.call_request_id, argument_index })?;
+                    }
+                }
+                ProtocolMessage::ArgumentResponse(response) => {
+                    let call_request_id = argument_requests.pop_front()
+                        .ok_or("Argument received without being requested")?;
+                    let value = connection.encoding().decode(&response.payload)?;
+                    self.receive_argument(call_request_id, value, &calls, &writer, &connection)?;
+                }
+                ProtocolMessage::StreamBegin(begin) => {
+                    if !connection.supports_streams() {
+                        return Err("Stream received without the feature being negotiated".into());
+                    }
+                    if incoming.len() >= MAX_OPEN_STREAMS || incoming.contains_key(&begin.stream_id) {
+                        return Err("Stream received exceeds the open stream limit or is open already".into());
+                    }
+                    if begin.purpose != StreamPurpose::Argument {
+                        return Err("Only argument streams may be sent to the client".into());
+                    }
+                    // Answers the argument request sent last, like an argument-response message
+                    let call_request_id = argument_requests.pop_front()
+                        .ok_or("Argument stream received without being requested")?;
+                    if call_request_id != begin.call_request_id {
+                        return Err("Argument stream received for a different call than requested".into());
+                    }
+                    incoming.insert(begin.stream_id, IncomingStream::begin(&begin, connection.encoding(), self.max_stream_length));
+                }
+                ProtocolMessage::StreamChunk(chunk) => {
+                    incoming.get_mut(&chunk.stream_id)
+                        .ok_or("Chunk received for an unknown stream")?
+                        .push(chunk)?;
+                }
+                ProtocolMessage::StreamEnd(end) => {
+                    let stream = incoming.remove(&end.stream_id).ok_or("End received for an unknown stream")?;
+                    let call_request_id = stream.call_request_id;
+                    let value = stream.end(&end)?;
+                    self.receive_argument(call_request_id, value, &calls, &writer, &connection)?;
+                }
+                ProtocolMessage::ResultRequest(request) => {
+                    let payload = {
+                        let calls = calls.lock().map_err(|_| "Call lock poisoned")?;
+                        match calls.get(&request.call_request_id) {
+                            Some(CallState::Completed { results }) => results
+                                .get(request.result_index as usize)
+                                .cloned()
+                                .ok_or("Result requested is out of range")?,
+                            _ => return Err("Result requested for a call not completed".into()),
+                        }
+                    };
+                    if connection.supports_streams() && payload.len() > STREAM_CHUNK_SIZE {
+                        write_stream(&writer, StreamBeginMessage {
+                            stream_id: connection.next_stream_id(),
+                            call_request_id: request.call_request_id,
+                            purpose: StreamPurpose::Result,
+                        }, &payload)?;
+                    } else {
+                        FunctionHost::write(&writer, &ResultResponseMessage { payload })?;
+                    }
+                }
+                ProtocolMessage::CloseCall(close) => {
+                    let mut calls = calls.lock().map_err(|_| "Call lock poisoned")?;
+                    calls.remove(&close.call_request_id);
+                    if draining && calls.is_empty() {
+                        debug!("All calls closed, quitting");
+                        return Ok(ServeEnd::Terminated);
+                    }
+                }
+                ProtocolMessage::CancelCall(cancel) => {
+                    let acknowledged = {
+                        let calls = calls.lock().map_err(|_| "Call lock poisoned")?;
+                        match calls.get(&cancel.call_request_id) {
+                            // Acknowledged once the arguments arrived or the handler returned
+                            Some(CallState::Receiving { cancellation, .. }) | Some(CallState::Running { cancellation }) => {
+                                cancellation.cancel();
+                                false
+                            }
+                            _ => true,
+                        }
+                    };
+                    if acknowledged {
+                        FunctionHost::write(&writer, &CancelAckMessage { call_request_id: cancel.call_request_id, status: CancelStatus::NotRunning })?;
+                    }
+                }
+                ProtocolMessage::Ping(ping) => {
+                    FunctionHost::write(&writer, &PongMessage { sequence: ping.sequence })?;
+                }
+                ProtocolMessage::Pong(_) => {}
+                _ => return Err("Message received is not applicable to the client".into()),
+            }
+        }
+    }
+
+    fn write<MESSAGE>(writer: &SharedWriter, message: &MESSAGE) -> Result<(), std::io::Error>
+        where MESSAGE: Message
+    {
+        let mut writer = writer.lock().map_err(|_| std::io::Error::other("Writer lock poisoned"))?;
+        write_message(&mut *writer, message)
+    }
+
+    fn receive_argument(
+        &self,
+        call_request_id: u32,
+        value: VmValue,
+        calls: &Arc<Mutex<HashMap<u32, CallState>>>,
+        writer: &SharedWriter,
+        connection: &Arc<ConnectionState>) -> Result<(), Error> {
+        let is_complete = {
+            let mut calls = calls.lock().map_err(|_| "Call lock poisoned")?;
+            match calls.get_mut(&call_request_id) {
+                Some(CallState::Receiving { expected, arguments, .. }) => {
+                    arguments.push(value);
+                    arguments.len() == *expected as usize
+                }
+                _ => return Err("Argument received for a call not receiving arguments".into()),
+            }
+        };
+        if is_complete {
+            self.start(call_request_id, calls, writer, connection)?;
+        }
+        Ok(())
+    }
+
+    /// Runs the handler of a call on a new thread, reporting its completion once done.
+    fn start(&self, call_request_id: u32, calls: &Arc<Mutex<HashMap<u32, CallState>>>, writer: &SharedWriter, connection: &Arc<ConnectionState>) -> Result<(), Error> {
+        let (function, arguments, cancellation) = {
+            let mut states = calls.lock().map_err(|_| "Call lock poisoned")?;
+            match states.remove(&call_request_id) {
+                Some(CallState::Receiving { cancellation, .. }) if cancellation.is_cancelled() => {
+                    drop(states);
+                    FunctionHost::write(writer, &CancelAckMessage { call_request_id, status: CancelStatus::Cancelled })?;
+                    return Ok(());
+                }
+                Some(CallState::Receiving { function_index, arguments, cancellation, .. }) => {
+                    let function = &self.functions[function_index];
+                    if arguments.len() < function.arguments_required as usize || arguments.len() > function.arguments_count as usize {
+                        // Only the call is failed, as the connection is still usable
+                        drop(states);
+                        FunctionHost::complete(call_request_id, Err("Argument count does not match the function called".to_string()), connection.encoding(), calls, writer);
+                        return Ok(());
+                    }
+                    states.insert(call_request_id, CallState::Running { cancellation: cancellation.clone() });
+                    (function, arguments, cancellation)
+                }
+                _ => return Err("Call started is not receiving arguments".into()),
+            }
+        };
+        let handler = function.handler.clone();
+        let results_count = function.results_count;
+        let calls = calls.clone();
+        let writer = writer.clone();
+        let encoding = connection.encoding();
+        let context = CallContext { call_request_id, writer: writer.clone(), connection: connection.clone(), cancellation };
+        std::thread::spawn(move || {
+            let results = match handler(arguments, &context) {
+                Ok(results) if results.len() != results_count as usize =>
+                    Err(format!("Function produced {} results but declares {}", results.len(), results_count)),
+                Ok(results) => results.iter()
+                    .map(|result| encoding.encode(result))
+                    .collect::<Result<Vec<Vec<u8>>, _>>()
+                    .map_err(|error| error.to_string()),
+                Err(error) => Err(error.to_string()),
+            };
+            FunctionHost::complete(call_request_id, results, encoding, &calls, &writer);
+        });
+        Ok(())
+    }
+
+    /// Stores the results of a call, already encoded, and reports its completion. An error
+    /// fails the call with the message provided. Cancelled calls are acknowledged and
+    /// forgotten instead.
+    fn complete(
+        call_request_id: u32,
+        results: Result<Vec<Vec<u8>>, String>,
+        encoding: ValueEncoding,
+        calls: &Mutex<HashMap<u32, CallState>>,
+        writer: &SharedWriter) {
+        let status = if results.is_ok() { CancelStatus::Completed } else { CancelStatus::Cancelled };
+        let (success, results) = match results {
+            Ok(results) => (true, results),
+            Err(message) => {
+                let error = encoding.encode(&VmValue::string(message))
+                    .expect("Strings can always be encoded");
+                (false, vec!(error))
+            }
+        };
+        let results_count = results.len() as u8;
+        let cancelled = match calls.lock() {
+            Ok(mut calls) => {
+                let cancelled = matches!(calls.get(&call_request_id), Some(CallState::Running { cancellation }) if cancellation.is_cancelled());
+                if cancelled {
+                    calls.remove(&call_request_id);
+                } else {
+                    calls.insert(call_request_id, CallState::Completed { results });
+                }
+                cancelled
+            }
+            Err(_) => false,
+        };
+        let written = if cancelled {
+            debug!("Call {} ended after being cancelled", call_request_id);
+            FunctionHost::write(writer, &CancelAckMessage { call_request_id, status })
+        } else {
+            FunctionHost::write(writer, &CallCompletedMessage { call_request_id, success, results_count })
+        };
+        if let Err(error) = written {
+            warn!("Failed to report completion of call {}: {}", call_request_id, error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use tracing_test::traced_test;
+    use crate::io::function_host::FunctionHost;
+    use crate::io::protocol_v1::protocol_v1::{CallOutcome, Error, HostEvent, ProtocolHost, ProtocolMessage};
+    use crate::io::protocol_v1::protocol_v1::data::*;
+    use crate::io::protocol_v2::ValueEncoding;
+    use crate::machine::VmValue;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn connect(function_host: FunctionHost) -> Result<ProtocolHost, Box<dyn std::error::Error>> {
+        let (client_reader, host_writer) = std::io::pipe()?;
+        let (host_reader, client_writer) = std::io::pipe()?;
+        std::thread::spawn(move || function_host.serve(Box::new(client_reader), Box::new(client_writer)).unwrap());
+        Ok(ProtocolHost::connect(Box::new(host_writer), Box::new(host_reader))?)
+    }
+
+    fn await_outcome(host: &ProtocolHost, call_request_id: u32) -> Option<CallOutcome> {
+        while let Some(event) = host.next_event_timeout(TIMEOUT) {
+            if let HostEvent::CallCompleted { call_request_id: completed, .. } = event {
+                if completed == call_request_id {
+                    return host.take_outcome(call_request_id);
+                }
+            }
+        }
+        None
+    }
+
+    fn math_host() -> FunctionHost {
+        FunctionHost::new(1, 2, 3, 4)
+            .function("add", 2, 2, 1, |arguments| {
+                match (&arguments[0], &arguments[1]) {
+                    (VmValue::Integer(left), VmValue::Integer(right)) => Ok(vec!(VmValue::Integer(left + right))),
+                    _ => Err("add expects two integers".into()),
+                }
+            })
+            .function("constants", 0, 1, 2, |arguments| {
+                let scale = arguments.first().cloned().unwrap_or(VmValue::Integer(1));
+                Ok(vec!(VmValue::Number(std::f64::consts::PI), scale))
+            })
+    }
+
+    #[test]
+    #[traced_test]
+    fn functions_are_reported() -> Result<(), Box<dyn std::error::Error>> {
+        let host = connect(math_host())?;
+        assert_eq!(host.client_version().major, 1);
+        assert_eq!(host.client_version().revision, 4);
+        assert_eq!(host.encoding(), ValueEncoding::Binary);
+        assert_eq!(host.function_index("add"), Some(0));
+        assert_eq!(host.function_index("constants"), Some(1));
+        assert_eq!(host.functions()[1].arguments_required, 0);
+        assert_eq!(host.functions()[1].results_count, 2);
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn calls_are_served() -> Result<(), Box<dyn std::error::Error>> {
+        let host = connect(math_host())?;
+        let add = host.call(0, &[VmValue::Integer(40), VmValue::Integer(2)])?;
+        assert_eq!(await_outcome(&host, add), Some(CallOutcome { success: true, results: vec!(VmValue::Integer(42)) }));
+        let constants = host.call(1, &[])?;
+        assert_eq!(await_outcome(&host, constants), Some(CallOutcome {
+            success: true,
+            results: vec!(VmValue::Number(std::f64::consts::PI), VmValue::Integer(1)),
+        }));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn large_payloads_are_streamed() -> Result<(), Box<dyn std::error::Error>> {
+        let host = connect(FunctionHost::new(1, 0, 0, 0)
+            .function("reverse", 1, 1, 1, |arguments| match &arguments[0] {
+                VmValue::String(text) => Ok(vec!(VmValue::string(text.chars().rev().collect::<String>()))),
+                _ => Err("reverse expects a string".into()),
+            }))?;
+        assert!(host.supports_streams());
+        let text = "lambda".repeat(50000);
+        let reverse = host.call(0, &[VmValue::string(text.clone())])?;
+        assert_eq!(await_outcome(&host, reverse), Some(CallOutcome {
+            success: true,
+            results: vec!(VmValue::string(text.chars().rev().collect::<String>())),
+        }));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn partial_results_arrive_before_completion() -> Result<(), Box<dyn std::error::Error>> {
+        let host = connect(FunctionHost::new(1, 0, 0, 0)
+            .streaming_function("count", 1, 1, 0, |arguments, partial_results| {
+                let VmValue::Integer(count) = arguments[0] else {
+                    return Err("count expects an integer".into());
+                };
+                for value in 0..count {
+                    partial_results.emit(&VmValue::Integer(value))?;
+                }
+                Ok(vec!())
+            }))?;
+        let count = host.call(0, &[VmValue::Integer(3)])?;
+        let mut partials = vec!();
+        while let Some(event) = host.next_event_timeout(TIMEOUT) {
+            match event {
+                HostEvent::PartialResult { call_request_id } if call_request_id == count =>
+                    partials.push(host.take_partial(count).ok_or("Partial result expected")?),
+                HostEvent::CallCompleted { .. } => break,
+                _ => {}
+            }
+        }
+        assert_eq!(partials, vec!(VmValue::Integer(0), VmValue::Integer(1), VmValue::Integer(2)));
+        assert!(!host.has_partial(count));
+        assert_eq!(host.take_outcome(count), Some(CallOutcome { success: true, results: vec!() }));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn cancelled_calls_are_acknowledged() -> Result<(), Box<dyn std::error::Error>> {
+        let host = connect(math_host()
+            .streaming_function("spin", 0, 0, 0, |_, context| {
+                while !context.is_cancelled() {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                Err("Cancelled".into())
+            }))?;
+        assert!(host.supports_cancellation());
+        let spin = host.call(2, &[])?;
+        assert!(host.cancel(spin)?);
+        let mut cancelled = None;
+        while let Some(event) = host.next_event_timeout(TIMEOUT) {
+            if let HostEvent::CallCancelled { call_request_id, status } = event {
+                cancelled = Some((call_request_id, status));
+                break;
+            }
+        }
+        assert_eq!(cancelled, Some((spin, CancelStatus::Cancelled)));
+        assert!(!host.has_call(spin));
+        assert!(!host.cancel(spin)?);
+
+        // Calls completing regardless of the cancellation keep their outcome
+        let add = host.call(0, &[VmValue::Integer(40), VmValue::Integer(2)])?;
+        assert_eq!(await_outcome(&host, add), Some(CallOutcome { success: true, results: vec!(VmValue::Integer(42)) }));
+        assert!(!host.cancel(add)?);
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn signatures_are_reported() -> Result<(), Box<dyn std::error::Error>> {
+        let signature = SignatureDescription {
+            description: "Adds two integers".to_string(),
+            idempotent: true,
+            timeout_hint: 100,
+            parameters: vec!(
+                ParameterDescription::new("left", r#"{"type":"integer"}"#, ""),
+                ParameterDescription::new("right", r#"{"type":"integer"}"#, ""),
+            ),
+            results: vec!(ParameterDescription::new("sum", r#"{"type":"integer"}"#, "")),
+        };
+        let host = connect(math_host()
+            .function("sum", 2, 2, 1, |arguments| Ok(arguments.into_iter().take(1).collect()))
+            .signature(signature.clone()))?;
+        assert_eq!(host.functions()[0].signature, None);
+        assert_eq!(host.functions()[2].signature, Some(signature));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn handler_errors_fail_the_call() -> Result<(), Box<dyn std::error::Error>> {
+        let host = connect(math_host())?;
+        let add = host.call(0, &[VmValue::Integer(40), VmValue::string("2")])?;
+        assert_eq!(await_outcome(&host, add), Some(CallOutcome {
+            success: false,
+            results: vec!(VmValue::string("add expects two integers")),
+        }));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn terminate_ends_serving() -> Result<(), Box<dyn std::error::Error>> {
+        let mut input = vec!();
+        ProtocolMessage::Version(VersionMessage { major: 0, minor: 1, build: 0, revision: 0, protocol: 1, heartbeat_interval: 0, features: 0 }).write(&mut input)?;
+        ProtocolMessage::Quit(Quit::Terminate).write(&mut input)?;
+        ProtocolMessage::CapabilitiesRequest(CapabilitiesRequestMessage {}).write(&mut input)?;
+        let (mut reader, writer) = std::io::pipe()?;
+        math_host().serve(Box::new(std::io::Cursor::new(input)), Box::new(writer))?;
+        assert!(matches!(ProtocolMessage::read(&mut reader)?, ProtocolMessage::Version(_)));
+        // Nothing but the version was answered, closing the pipe once served
+        let mut remainder = vec!();
+        std::io::Read::read_to_end(&mut reader, &mut remainder)?;
+        assert!(remainder.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn quit_drains_running_calls() -> Result<(), Box<dyn std::error::Error>> {
+        let (client_reader, mut writer) = std::io::pipe()?;
+        let (mut reader, client_writer) = std::io::pipe()?;
+        let served = std::thread::spawn(move || math_host().serve(Box::new(client_reader), Box::new(client_writer)));
+        ProtocolMessage::Version(VersionMessage { major: 0, minor: 1, build: 0, revision: 0, protocol: 1, heartbeat_interval: 0, features: 0 }).write(&mut writer)?;
+        // Lambda speaking protocol v1 only keeps receiving JSON
+        assert!(matches!(ProtocolMessage::read(&mut reader)?, ProtocolMessage::Version(VersionMessage { protocol: 1, .. })));
+        ProtocolMessage::Call(CallMessage { function_index: 0, arguments_count: 2, call_request_id: 1 }).write(&mut writer)?;
+        for argument_index in 0..2 {
+            assert_eq!(ProtocolMessage::read(&mut reader)?, ProtocolMessage::ArgumentRequest(ArgumentRequestMessage { call_request_id: 1, argument_index }));
+        }
+
+        // Calls made after quitting are refused, those running are finished
+        ProtocolMessage::Quit(Quit::Additional5s).write(&mut writer)?;
+        ProtocolMessage::Call(CallMessage { function_index: 0, arguments_count: 2, call_request_id: 2 }).write(&mut writer)?;
+        assert_eq!(ProtocolMessage::read(&mut reader)?, ProtocolMessage::CallCompleted(CallCompletedMessage { call_request_id: 2, success: false, results_count: 1 }));
+        ProtocolMessage::ArgumentResponse(ArgumentResponseMessage { payload: "40".as_bytes().to_vec() }).write(&mut writer)?;
+        ProtocolMessage::ArgumentResponse(ArgumentResponseMessage { payload: "2".as_bytes().to_vec() }).write(&mut writer)?;
+        assert_eq!(ProtocolMessage::read(&mut reader)?, ProtocolMessage::CallCompleted(CallCompletedMessage { call_request_id: 1, success: true, results_count: 1 }));
+        ProtocolMessage::ResultRequest(ResultRequestMessage { call_request_id: 1, result_index: 0 }).write(&mut writer)?;
+        assert_eq!(ProtocolMessage::read(&mut reader)?, ProtocolMessage::ResultResponse(ResultResponseMessage { payload: "42".as_bytes().to_vec() }));
+        ProtocolMessage::ResultRequest(ResultRequestMessage { call_request_id: 2, result_index: 0 }).write(&mut writer)?;
+        assert_eq!(ProtocolMessage::read(&mut reader)?, ProtocolMessage::ResultResponse(ResultResponseMessage { payload: "\"Function host is shutting down\"".as_bytes().to_vec() }));
+        ProtocolMessage::CloseCall(CloseCallMessage { call_request_id: 1 }).write(&mut writer)?;
+        assert!(!served.is_finished());
+        ProtocolMessage::CloseCall(CloseCallMessage { call_request_id: 2 }).write(&mut writer)?;
+        served.join().unwrap()?;
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn invalid_calls_fail_without_disconnecting() -> Result<(), Box<dyn std::error::Error>> {
+        let (client_reader, mut writer) = std::io::pipe()?;
+        let (mut reader, client_writer) = std::io::pipe()?;
+        let served = std::thread::spawn(move || math_host().serve(Box::new(client_reader), Box::new(client_writer)));
+        ProtocolMessage::Version(VersionMessage { major: 0, minor: 1, build: 0, revision: 0, protocol: 1, heartbeat_interval: 0, features: 0 }).write(&mut writer)?;
+        assert!(matches!(ProtocolMessage::read(&mut reader)?, ProtocolMessage::Version(_)));
+        // Unknown functions and wrong argument counts fail the call only
+        ProtocolMessage::Call(CallMessage { function_index: 9, arguments_count: 0, call_request_id: 1 }).write(&mut writer)?;
+        assert_eq!(ProtocolMessage::read(&mut reader)?, ProtocolMessage::CallCompleted(CallCompletedMessage { call_request_id: 1, success: false, results_count: 1 }));
+        ProtocolMessage::Call(CallMessage { function_index: 0, arguments_count: 1, call_request_id: 2 }).write(&mut writer)?;
+        assert_eq!(ProtocolMessage::read(&mut reader)?, ProtocolMessage::ArgumentRequest(ArgumentRequestMessage { call_request_id: 2, argument_index: 0 }));
+        ProtocolMessage::ArgumentResponse(ArgumentResponseMessage { payload: "40".as_bytes().to_vec() }).write(&mut writer)?;
+        assert_eq!(ProtocolMessage::read(&mut reader)?, ProtocolMessage::CallCompleted(CallCompletedMessage { call_request_id: 2, success: false, results_count: 1 }));
+        ProtocolMessage::ResultRequest(ResultRequestMessage { call_request_id: 2, result_index: 0 }).write(&mut writer)?;
+        assert_eq!(ProtocolMessage::read(&mut reader)?, ProtocolMessage::ResultResponse(ResultResponseMessage {
+            payload: "\"Argument count does not match the function called\"".as_bytes().to_vec(),
+        }));
+        // Valid calls are served afterwards
+        ProtocolMessage::Call(CallMessage { function_index: 0, arguments_count: 2, call_request_id: 3 }).write(&mut writer)?;
+        for argument_index in 0..2 {
+            assert_eq!(ProtocolMessage::read(&mut reader)?, ProtocolMessage::ArgumentRequest(ArgumentRequestMessage { call_request_id: 3, argument_index }));
+        }
+        ProtocolMessage::ArgumentResponse(ArgumentResponseMessage { payload: "40".as_bytes().to_vec() }).write(&mut writer)?;
+        ProtocolMessage::ArgumentResponse(ArgumentResponseMessage { payload: "2".as_bytes().to_vec() }).write(&mut writer)?;
+        assert_eq!(ProtocolMessage::read(&mut reader)?, ProtocolMessage::CallCompleted(CallCompletedMessage { call_request_id: 3, success: true, results_count: 1 }));
+        drop(writer);
+        served.join().unwrap()?;
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn streams_exceeding_the_maximum_length_fail_the_connection() -> Result<(), Box<dyn std::error::Error>> {
+        let (client_reader, mut writer) = std::io::pipe()?;
+        let (mut reader, client_writer) = std::io::pipe()?;
+        let served = std::thread::spawn(move || math_host().max_stream_length(4).serve(Box::new(client_reader), Box::new(client_writer)));
+        ProtocolMessage::Version(VersionMessage { major: 0, minor: 1, build: 0, revision: 0, protocol: 1, heartbeat_interval: 0, features: FEATURE_STREAMS }).write(&mut writer)?;
+        assert!(matches!(ProtocolMessage::read(&mut reader)?, ProtocolMessage::Version(_)));
+        ProtocolMessage::Call(CallMessage { function_index: 0, arguments_count: 2, call_request_id: 1 }).write(&mut writer)?;
+        for argument_index in 0..2 {
+            assert_eq!(ProtocolMessage::read(&mut reader)?, ProtocolMessage::ArgumentRequest(ArgumentRequestMessage { call_request_id: 1, argument_index }));
+        }
+        ProtocolMessage::StreamBegin(StreamBeginMessage { stream_id: 0, call_request_id: 1, purpose: StreamPurpose::Argument }).write(&mut writer)?;
+        ProtocolMessage::StreamChunk(StreamChunkMessage { stream_id: 0, sequence: 0, data: b"1234567".to_vec() }).write(&mut writer)?;
+        assert!(matches!(served.join().unwrap(), Err(Error::ProtocolError("Stream received exceeds the maximum stream length"))));
+        Ok(())
+    }
+}
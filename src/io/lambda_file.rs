@@ -1,8 +1,868 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use nom::bytes::complete::take_while1;
+use nom::character::complete::{char, digit1, space0};
+use nom::combinator::{opt, recognize};
+use nom::multi::separated_list1;
+use nom::sequence::{delimited, pair};
+use nom::IResult;
+use crate::assembler::checker::FunctionSignature;
+use crate::assembler::parser::parser::{parse_ident, parse_type, TypeAnnotation};
+use crate::assembler::parser_string::parse_string;
+
+/// A deployment manifest, declaring the functions served by function hosts and the workflow
+/// scripts using them. The format is a subset of TOML:
+///
+/// ```toml
+/// # Applied to every function not declaring its own value
+/// [defaults]
+/// timeout = "30s"
+/// retries = 1
+///
+/// [functions.resize]
+/// command = ["python3", "resize.py"]
+/// working_directory = "services/imaging"
+/// environment = { MODE = "fast" }
+/// concurrency = 4
+/// retry_delay = "500ms"
+/// parameters = { image = "bytes", width = "integer", mode = "string" }
+/// arguments_required = 2
+/// result = "bytes"
+///
+/// # Version 2 of resize, [functions.resize] declaring version 1
+/// [functions.resize.2]
+/// command = ["python3", "resize-next.py"]
+///
+/// [routes.resize]
+/// aliases = { stable = 1, canary = 2 }
+/// weights = { 1 = 90, 2 = 10 }
+///
+/// [workflows.nightly]
+/// script = "workflows/nightly.x39"
+/// triggers = ["startup", "every 1h"]
+/// ```
+///
+/// Durations are milliseconds or strings with a unit of `ms`, `s`, `m` or `h`, types are
+/// written like script annotations. Relative paths are resolved against the directory of
+/// the manifest when loaded from a file.
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct LambdaFile {
-    functions: Vec<LambdaFunction>,
+    pub functions: Vec<LambdaFunction>,
+    pub routes: Vec<Route>,
+    pub workflows: Vec<Workflow>,
 }
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct LambdaFunction {
-    identifier: String,
-    disabled: bool,
+    pub identifier: String,
+    /// Several versions of a function may be deployed at once, see [Route].
+    pub version: u32,
+    /// The executable of the function host, followed by its arguments.
+    pub command: Vec<String>,
+    pub working_directory: Option<PathBuf>,
+    pub environment: Vec<(String, String)>,
+    /// The time a call may take before it is cancelled and fails.
+    pub timeout: Option<Duration>,
+    /// How many calls may run at once, further calls waiting for one to complete.
+    pub concurrency: Option<usize>,
+    pub retry: RetryPolicy,
+    /// Disabled functions are not launched and fail when called.
+    pub disabled: bool,
+    /// The names and types of the arguments, in order.
+    pub parameters: Vec<(String, TypeAnnotation)>,
+    pub arguments_required: usize,
+    pub result: TypeAnnotation,
+}
+
+/// How often a failed or timed out call is repeated before the job fails.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub delay: Duration,
+}
+
+/// How the calls of a function are routed to its versions. Calls may select a version by
+/// number or alias, eg. `resize@2` or `resize@stable`, the other calls being split between
+/// the versions by their weights or going to the latest version without weights.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub function: String,
+    pub aliases: Vec<(String, u32)>,
+    /// The versions and their share of the calls not selecting a version.
+    pub weights: Vec<(u32, u32)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Workflow {
+    pub identifier: String,
+    pub script: PathBuf,
+    pub triggers: Vec<Trigger>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trigger {
+    /// Run on request only, the default for workflows without triggers.
+    Manual,
+    /// Run once the functions were loaded.
+    Startup,
+    /// Run repeatedly, waiting the duration provided between the starts.
+    Interval(Duration),
+}
+
+/// An error in a manifest, located by its 1-based line and column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LambdaFileError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl Display for LambdaFileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Error for LambdaFileError {}
+
+impl LambdaFile {
+    pub fn parse(input: &str) -> Result<LambdaFile, LambdaFileError> {
+        let reader = ManifestReader { source: input };
+        let sections = reader.parse_sections()?;
+        reader.build(sections)
+    }
+
+    /// Reads and parses the manifest at the path provided, resolving relative paths against
+    /// its directory.
+    pub fn load(path: &Path) -> Result<LambdaFile, Box<dyn Error>> {
+        let input = std::fs::read_to_string(path)?;
+        let mut file = LambdaFile::parse(&input).map_err(|error| format!("{}:{}", path.display(), error))?;
+        if let Some(directory) = path.parent() {
+            file.resolve(directory);
+        }
+        Ok(file)
+    }
+
+    /// Makes all relative paths relative to the directory provided instead.
+    pub fn resolve(&mut self, directory: &Path) {
+        for function in self.functions.iter_mut() {
+            let working_directory = function.working_directory.take().unwrap_or_default();
+            function.working_directory = Some(directory.join(working_directory));
+        }
+        for workflow in self.workflows.iter_mut() {
+            workflow.script = directory.join(&workflow.script);
+        }
+    }
+
+    /// The latest version of the function provided.
+    pub fn function(&self, identifier: &str) -> Option<&LambdaFunction> {
+        self.functions.iter()
+            .filter(|function| function.identifier == identifier)
+            .max_by_key(|function| function.version)
+    }
+
+    pub fn version(&self, identifier: &str, version: u32) -> Option<&LambdaFunction> {
+        self.functions.iter().find(|function| function.identifier == identifier && function.version == version)
+    }
+
+    pub fn route(&self, function: &str) -> Option<&Route> {
+        self.routes.iter().find(|route| route.function == function)
+    }
+
+    /// The signatures scripts are type checked against. Calls not selecting a version are
+    /// checked against every enabled version the route sends calls to, or the latest enabled
+    /// version if there is none. Functions having several enabled versions additionally have a
+    /// signature for every version and alias selectable, eg. `resize@1` or `resize@stable`.
+    pub fn signatures(&self) -> Vec<FunctionSignature> {
+        let mut signatures: Vec<FunctionSignature> = vec!();
+        let mut identifiers: Vec<&str> = vec!();
+        for function in self.functions.iter() {
+            if !identifiers.contains(&function.identifier.as_str()) {
+                identifiers.push(&function.identifier);
+            }
+        }
+        for identifier in identifiers {
+            let enabled: Vec<&LambdaFunction> = self.functions.iter()
+                .filter(|function| function.identifier == identifier && !function.disabled)
+                .collect();
+            let Some(latest) = enabled.iter().max_by_key(|function| function.version) else {
+                continue;
+            };
+            let enabled_version = |version: u32| enabled.iter().find(|function| function.version == version).copied();
+            let route = self.route(identifier);
+            let mut routed: Vec<&LambdaFunction> = route.iter()
+                .flat_map(|route| route.weights.iter())
+                .filter(|(_, weight)| *weight > 0)
+                .filter_map(|(version, _)| enabled_version(*version))
+                .collect();
+            if routed.is_empty() {
+                routed.push(latest);
+            }
+            routed.sort_by_key(|function| std::cmp::Reverse(function.version));
+            for function in routed {
+                let signature = function.signature();
+                if !signatures.contains(&signature) {
+                    signatures.push(signature);
+                }
+            }
+            if enabled.len() < 2 {
+                continue;
+            }
+            for function in enabled.iter() {
+                signatures.push(FunctionSignature { name: format!("{}@{}", identifier, function.version), ..function.signature() });
+            }
+            for (alias, version) in route.iter().flat_map(|route| route.aliases.iter()) {
+                if let Some(function) = enabled_version(*version) {
+                    signatures.push(FunctionSignature { name: format!("{}@{}", identifier, alias), ..function.signature() });
+                }
+            }
+        }
+        signatures
+    }
+}
+
+impl LambdaFunction {
+    pub fn signature(&self) -> FunctionSignature {
+        FunctionSignature {
+            name: self.identifier.clone(),
+            parameters: self.parameters.iter().map(|(_, annotation)| annotation.clone()).collect(),
+            parameter_names: self.parameters.iter().map(|(name, _)| name.clone()).collect(),
+            arguments_required: self.arguments_required,
+            result: self.result.clone(),
+        }
+    }
+
+    /// The command starting the function host, talking to it via its stdio.
+    pub fn command(&self) -> Command {
+        let mut command = Command::new(&self.command[0]);
+        command.args(&self.command[1..]);
+        if let Some(working_directory) = &self.working_directory {
+            command.current_dir(working_directory);
+        }
+        command.envs(self.environment.iter().map(|(key, value)| (key, value)));
+        command
+    }
+
+    /// Whether both functions are served by the same function host process.
+    pub fn shares_host_with(&self, other: &LambdaFunction) -> bool {
+        self.command == other.command
+            && self.working_directory == other.working_directory
+            && self.environment == other.environment
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ManifestValue {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Located>),
+    Table(Vec<(String, Located)>),
+}
+
+/// A value and the offset it starts at in the manifest.
+#[derive(Debug, Clone, PartialEq)]
+struct Located {
+    offset: usize,
+    value: ManifestValue,
+}
+
+struct Section<'s> {
+    /// The header split at its dots, eg. `["functions", "resize"]`.
+    path: Vec<&'s str>,
+    offset: usize,
+    entries: Vec<(&'s str, usize, Located)>,
+}
+
+struct ManifestReader<'s> {
+    source: &'s str,
+}
+
+fn parse_key(input: &str) -> IResult<&str, &str> {
+    take_while1(|c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-')(input)
+}
+
+fn parse_section_header(input: &str) -> IResult<&str, Vec<&str>> {
+    delimited(
+        pair(char('['), space0),
+        separated_list1(char('.'), parse_key),
+        pair(space0, char(']')),
+    )(input)
+}
+
+fn parse_integer(input: &str) -> IResult<&str, &str> {
+    recognize(pair(opt(char('-')), digit1))(input)
+}
+
+/// Skips whitespace, line breaks and comments.
+fn skip_blank(input: &str) -> &str {
+    let mut input = input.trim_start();
+    while input.starts_with('#') {
+        input = input.find('\n').map_or("", |index| &input[index..]).trim_start();
+    }
+    input
+}
+
+/// Parses durations like `500ms`, `30s`, `5m` or `1h`.
+fn parse_duration(input: &str) -> Option<Duration> {
+    let split = input.find(|c: char| !c.is_ascii_digit())?;
+    let (amount, unit) = input.split_at(split);
+    let amount: u64 = amount.parse().ok()?;
+    match unit {
+        "ms" => Some(Duration::from_millis(amount)),
+        "s" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_secs(amount.checked_mul(60)?)),
+        "h" => Some(Duration::from_secs(amount.checked_mul(60 * 60)?)),
+        _ => None,
+    }
+}
+
+fn parse_trigger(input: &str) -> Option<Trigger> {
+    match input.split_once(' ') {
+        None if input == "manual" => Some(Trigger::Manual),
+        None if input == "startup" => Some(Trigger::Startup),
+        Some(("every", interval)) => parse_duration(interval.trim())
+            .filter(|interval| !interval.is_zero())
+            .map(Trigger::Interval),
+        _ => None,
+    }
+}
+
+const DEFAULTS_KEYS: &[&str] = &["timeout", "concurrency", "retries", "retry_delay"];
+const FUNCTION_KEYS: &[&str] = &[
+    "command", "working_directory", "environment", "timeout", "concurrency", "retries", "retry_delay",
+    "disabled", "parameters", "arguments_required", "result",
+];
+const ROUTE_KEYS: &[&str] = &["aliases", "weights"];
+const WORKFLOW_KEYS: &[&str] = &["script", "triggers"];
+
+impl<'s> ManifestReader<'s> {
+    fn offset(&self, rest: &str) -> usize {
+        self.source.len() - rest.len()
+    }
+
+    fn error(&self, offset: usize, message: impl Into<String>) -> LambdaFileError {
+        let before = &self.source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+        LambdaFileError {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            message: message.into(),
+        }
+    }
+
+    fn parse_sections(&self) -> Result<Vec<Section<'s>>, LambdaFileError> {
+        let mut sections: Vec<Section> = vec!();
+        let mut rest = skip_blank(self.source);
+        while !rest.is_empty() {
+            let offset = self.offset(rest);
+            if rest.starts_with('[') {
+                let (remainder, path) = parse_section_header(rest)
+                    .map_err(|_| self.error(offset, "Expected a section header like [functions.name]"))?;
+                if let Some(existing) = sections.iter().find(|section| section.path == path) {
+                    let line = self.error(existing.offset, "").line;
+                    return Err(self.error(offset, format!("Section [{}] is already declared in line {}", path.join("."), line)));
+                }
+                sections.push(Section { path, offset, entries: vec!() });
+                rest = remainder;
+            } else {
+                let (remainder, key) = parse_key(rest).map_err(|_| self.error(offset, "Expected a key or a section header"))?;
+                let section = sections.last_mut().ok_or_else(|| self.error(offset, "Keys must be declared inside a section"))?;
+                if section.entries.iter().any(|(existing, _, _)| *existing == key) {
+                    return Err(self.error(offset, format!("Key '{}' is already declared in this section", key)));
+                }
+                let remainder = remainder.trim_start_matches([' ', '\t']);
+                let remainder = remainder.strip_prefix('=')
+                    .ok_or_else(|| self.error(self.offset(remainder), format!("Expected '=' after '{}'", key)))?;
+                let (remainder, value) = self.parse_value(remainder.trim_start_matches([' ', '\t']))?;
+                section.entries.push((key, offset, value));
+                rest = remainder;
+            }
+            rest = self.end_of_line(rest)?;
+            rest = skip_blank(rest);
+        }
+        Ok(sections)
+    }
+
+    /// Expects the line to end, optionally with a comment.
+    fn end_of_line(&self, input: &'s str) -> Result<&'s str, LambdaFileError> {
+        let input = input.trim_start_matches([' ', '\t', '\r']);
+        if input.is_empty() || input.starts_with('\n') || input.starts_with('#') {
+            return Ok(input);
+        }
+        Err(self.error(self.offset(input), "Expected the end of the line"))
+    }
+
+    fn parse_value(&self, input: &'s str) -> Result<(&'s str, Located), LambdaFileError> {
+        let offset = self.offset(input);
+        let located = |value| Located { offset, value };
+        if input.starts_with('"') {
+            let (rest, string) = parse_string::<nom::error::Error<&str>>(input)
+                .map_err(|_| self.error(offset, "Expected a closed string"))?;
+            return Ok((rest, located(ManifestValue::String(string))));
+        }
+        if let Some(rest) = input.strip_prefix('[') {
+            let mut elements = vec!();
+            let mut rest = skip_blank(rest);
+            while !rest.starts_with(']') {
+                let (remainder, element) = self.parse_value(rest)?;
+                elements.push(element);
+                rest = self.continue_list(skip_blank(remainder), ']')?;
+            }
+            return Ok((&rest[1..], located(ManifestValue::Array(elements))));
+        }
+        if let Some(rest) = input.strip_prefix('{') {
+            let mut entries: Vec<(String, Located)> = vec!();
+            let mut rest = skip_blank(rest);
+            while !rest.starts_with('}') {
+                let key_offset = self.offset(rest);
+                let (remainder, key) = parse_key(rest).map_err(|_| self.error(key_offset, "Expected a key or '}'"))?;
+                if entries.iter().any(|(existing, _)| existing == key) {
+                    return Err(self.error(key_offset, format!("Key '{}' is already declared in this table", key)));
+                }
+                let remainder = skip_blank(remainder);
+                let remainder = remainder.strip_prefix('=')
+                    .ok_or_else(|| self.error(self.offset(remainder), format!("Expected '=' after '{}'", key)))?;
+                let (remainder, value) = self.parse_value(skip_blank(remainder))?;
+                entries.push((key.to_string(), value));
+                rest = self.continue_list(skip_blank(remainder), '}')?;
+            }
+            return Ok((&rest[1..], located(ManifestValue::Table(entries))));
+        }
+        if let Ok((rest, word)) = parse_key(input) {
+            match word {
+                "true" => return Ok((rest, located(ManifestValue::Boolean(true)))),
+                "false" => return Ok((rest, located(ManifestValue::Boolean(false)))),
+                _ => {}
+            }
+        }
+        if let Ok((rest, integer)) = parse_integer(input) {
+            let integer = integer.parse().map_err(|_| self.error(offset, "Integer is out of range"))?;
+            return Ok((rest, located(ManifestValue::Integer(integer))));
+        }
+        Err(self.error(offset, "Expected a string, integer, boolean, array or table"))
+    }
+
+    /// Consumes the comma separating two elements, returning the input at the next element
+    /// or at the closing character.
+    fn continue_list(&self, input: &'s str, close: char) -> Result<&'s str, LambdaFileError> {
+        if let Some(rest) = input.strip_prefix(',') {
+            return Ok(skip_blank(rest));
+        }
+        if input.starts_with(close) {
+            return Ok(input);
+        }
+        Err(self.error(self.offset(input), format!("Expected ',' or '{}'", close)))
+    }
+
+    fn build(&self, sections: Vec<Section>) -> Result<LambdaFile, LambdaFileError> {
+        let mut file = LambdaFile { functions: vec!(), routes: vec!(), workflows: vec!() };
+        let defaults = sections.iter()
+            .find(|section| section.path == ["defaults"])
+            .map(|section| self.build_function(section, "defaults", 0, None))
+            .transpose()?;
+        for section in sections.iter() {
+            let (identifier, version) = match section.path.as_slice() {
+                ["functions", identifier] => (identifier, 1),
+                ["functions", identifier, version] => (identifier, self.version(section, version)?),
+                _ => continue,
+            };
+            self.check_identifier(section, identifier)?;
+            if file.version(identifier, version).is_some() {
+                return Err(self.error(section.offset, format!("Version {} of function '{}' is already declared", version, identifier)));
+            }
+            file.functions.push(self.build_function(section, identifier, version, defaults.as_ref())?);
+        }
+        for section in sections.iter() {
+            match section.path.as_slice() {
+                ["defaults"] | ["functions", _] | ["functions", _, _] => {}
+                ["routes", identifier] => {
+                    let route = self.build_route(section, identifier, &file)?;
+                    file.routes.push(route);
+                }
+                ["workflows", identifier] => {
+                    self.check_identifier(section, identifier)?;
+                    file.workflows.push(self.build_workflow(section, identifier)?);
+                }
+                path => return Err(self.error(section.offset, format!(
+                    "Unknown section [{}], expected [defaults], [functions.name], [routes.name] or [workflows.name]", path.join(".")))),
+            }
+        }
+        Ok(file)
+    }
+
+    fn version(&self, section: &Section, version: &str) -> Result<u32, LambdaFileError> {
+        version.parse().ok()
+            .filter(|version| *version > 0)
+            .ok_or_else(|| self.error(section.offset, format!("'{}' is not a version, expected a positive integer", version)))
+    }
+
+    fn check_identifier(&self, section: &Section, identifier: &str) -> Result<(), LambdaFileError> {
+        match parse_ident(identifier) {
+            Ok(("", _)) => Ok(()),
+            _ => Err(self.error(section.offset, format!("'{}' is not a valid identifier, as it cannot be used in scripts", identifier))),
+        }
+    }
+
+    fn check_keys(&self, section: &Section, allowed: &[&str]) -> Result<(), LambdaFileError> {
+        match section.entries.iter().find(|(key, _, _)| !allowed.contains(key)) {
+            Some((key, offset, _)) => Err(self.error(*offset, format!("Unknown key '{}', expected one of {}", key, allowed.join(", ")))),
+            None => Ok(()),
+        }
+    }
+
+    /// Builds the function of the section, taking the values not declared from the defaults.
+    /// The `[defaults]` section itself is built as function without command.
+    fn build_function(&self, section: &Section, identifier: &str, version: u32, defaults: Option<&LambdaFunction>) -> Result<LambdaFunction, LambdaFileError> {
+        let is_defaults = section.path == ["defaults"];
+        self.check_keys(section, if is_defaults { DEFAULTS_KEYS } else { FUNCTION_KEYS })?;
+        let get = |key: &str| section.entries.iter().find(|(existing, _, _)| *existing == key).map(|(_, _, value)| value);
+        let command = match get("command") {
+            None if is_defaults => vec!(),
+            None => return Err(self.error(section.offset, format!("Function '{}' lacks a command", identifier))),
+            Some(Located { value: ManifestValue::String(command), .. }) => vec!(command.clone()),
+            Some(value) => self.strings(value, "command")?,
+        };
+        if command.is_empty() && !is_defaults {
+            return Err(self.error(get("command").map_or(section.offset, |it| it.offset), "'command' must not be empty"));
+        }
+        let parameters = match get("parameters") {
+            None => vec!(),
+            Some(value) => self.table(value, "parameters")?.iter()
+                .map(|(name, value)| Ok((name.clone(), self.type_annotation(value)?)))
+                .collect::<Result<Vec<_>, LambdaFileError>>()?,
+        };
+        let arguments_required = match get("arguments_required") {
+            None => parameters.len(),
+            Some(value) => {
+                let required = self.count(value, "arguments_required", 0)?;
+                if required > parameters.len() {
+                    return Err(self.error(value.offset, format!("'arguments_required' exceeds the {} parameters declared", parameters.len())));
+                }
+                required
+            }
+        };
+        let defaults = defaults.cloned().unwrap_or_else(|| LambdaFunction {
+            identifier: String::new(),
+            version: 0,
+            command: vec!(),
+            working_directory: None,
+            environment: vec!(),
+            timeout: None,
+            concurrency: None,
+            retry: RetryPolicy::default(),
+            disabled: false,
+            parameters: vec!(),
+            arguments_required: 0,
+            result: TypeAnnotation::Any,
+        });
+        let function = LambdaFunction {
+            identifier: identifier.to_string(),
+            version,
+            command,
+            working_directory: get("working_directory").map(|value| self.string(value, "working_directory").map(PathBuf::from)).transpose()?,
+            environment: match get("environment") {
+                None => vec!(),
+                Some(value) => self.table(value, "environment")?.iter()
+                    .map(|(key, value)| Ok((key.clone(), self.string(value, key)?)))
+                    .collect::<Result<Vec<_>, LambdaFileError>>()?,
+            },
+            timeout: match get("timeout") {
+                None => defaults.timeout,
+                Some(value) => Some(self.duration(value, "timeout", false)?),
+            },
+            concurrency: match get("concurrency") {
+                None => defaults.concurrency,
+                Some(value) => Some(self.count(value, "concurrency", 1)?),
+            },
+            retry: RetryPolicy {
+                retries: match get("retries") {
+                    None => defaults.retry.retries,
+                    Some(value) => u32::try_from(self.count(value, "retries", 0)?)
+                        .map_err(|_| self.error(value.offset, "'retries' is out of range"))?,
+                },
+                delay: match get("retry_delay") {
+                    None => defaults.retry.delay,
+                    Some(value) => self.duration(value, "retry_delay", true)?,
+                },
+            },
+            disabled: match get("disabled") {
+                None => false,
+                Some(Located { value: ManifestValue::Boolean(disabled), .. }) => *disabled,
+                Some(value) => return Err(self.error(value.offset, "'disabled' must be a boolean")),
+            },
+            parameters,
+            arguments_required,
+            result: match get("result") {
+                None => TypeAnnotation::Any,
+                Some(value) => self.type_annotation(value)?,
+            },
+        };
+        Ok(function)
+    }
+
+    fn build_route(&self, section: &Section, identifier: &str, file: &LambdaFile) -> Result<Route, LambdaFileError> {
+        self.check_keys(section, ROUTE_KEYS)?;
+        if file.function(identifier).is_none() {
+            return Err(self.error(section.offset, format!("Function '{}' is not declared", identifier)));
+        }
+        let get = |key: &str| section.entries.iter().find(|(existing, _, _)| *existing == key).map(|(_, _, value)| value);
+        let declared_version = |value: &Located| -> Result<u32, LambdaFileError> {
+            let version = self.count(value, "aliases", 1)?;
+            u32::try_from(version).ok()
+                .filter(|version| file.version(identifier, *version).is_some())
+                .ok_or_else(|| self.error(value.offset, format!("Version {} of function '{}' is not declared", version, identifier)))
+        };
+        let mut route = Route { function: identifier.to_string(), aliases: vec!(), weights: vec!() };
+        if let Some(value) = get("aliases") {
+            for (alias, version) in self.table(value, "aliases")? {
+                if !matches!(parse_ident(alias), Ok(("", _))) {
+                    return Err(self.error(version.offset, format!("'{}' is not a valid alias", alias)));
+                }
+                route.aliases.push((alias.clone(), declared_version(version)?));
+            }
+        }
+        if let Some(value) = get("weights") {
+            for (version, weight) in self.table(value, "weights")? {
+                let version = version.parse().ok()
+                    .filter(|version| file.version(identifier, *version).is_some())
+                    .ok_or_else(|| self.error(weight.offset, format!("'{}' is not a declared version of function '{}'", version, identifier)))?;
+                let weight = u32::try_from(self.count(weight, "weights", 0)?)
+                    .map_err(|_| self.error(weight.offset, "Weight is out of range"))?;
+                route.weights.push((version, weight));
+            }
+            if route.weights.iter().all(|(_, weight)| *weight == 0) {
+                return Err(self.error(value.offset, "'weights' must not all be zero"));
+            }
+        }
+        Ok(route)
+    }
+
+    fn build_workflow(&self, section: &Section, identifier: &str) -> Result<Workflow, LambdaFileError> {
+        self.check_keys(section, WORKFLOW_KEYS)?;
+        let get = |key: &str| section.entries.iter().find(|(existing, _, _)| *existing == key).map(|(_, _, value)| value);
+        let script = match get("script") {
+            None => return Err(self.error(section.offset, format!("Workflow '{}' lacks a script", identifier))),
+            Some(value) => PathBuf::from(self.string(value, "script")?),
+        };
+        let triggers = match get("triggers") {
+            None => vec!(Trigger::Manual),
+            Some(value) => {
+                let ManifestValue::Array(elements) = &value.value else {
+                    return Err(self.error(value.offset, "'triggers' must be an array of strings"));
+                };
+                elements.iter()
+                    .map(|element| {
+                        let trigger = self.string(element, "triggers")?;
+                        parse_trigger(&trigger).ok_or_else(|| self.error(element.offset, format!(
+                            "Unknown trigger '{}', expected \"manual\", \"startup\" or \"every\" followed by a duration", trigger)))
+                    })
+                    .collect::<Result<Vec<_>, LambdaFileError>>()?
+            }
+        };
+        Ok(Workflow { identifier: identifier.to_string(), script, triggers })
+    }
+
+    fn string(&self, value: &Located, key: &str) -> Result<String, LambdaFileError> {
+        match &value.value {
+            ManifestValue::String(string) => Ok(string.clone()),
+            _ => Err(self.error(value.offset, format!("'{}' must be a string", key))),
+        }
+    }
+
+    fn strings(&self, value: &Located, key: &str) -> Result<Vec<String>, LambdaFileError> {
+        match &value.value {
+            ManifestValue::Array(elements) => elements.iter().map(|element| self.string(element, key)).collect(),
+            _ => Err(self.error(value.offset, format!("'{}' must be a string or an array of strings", key))),
+        }
+    }
+
+    fn table<'v>(&self, value: &'v Located, key: &str) -> Result<&'v [(String, Located)], LambdaFileError> {
+        match &value.value {
+            ManifestValue::Table(entries) => Ok(entries),
+            _ => Err(self.error(value.offset, format!("'{}' must be a table like {{ key = \"value\" }}", key))),
+        }
+    }
+
+    fn count(&self, value: &Located, key: &str, minimum: usize) -> Result<usize, LambdaFileError> {
+        match &value.value {
+            ManifestValue::Integer(integer) => usize::try_from(*integer).ok()
+                .filter(|count| *count >= minimum)
+                .ok_or_else(|| self.error(value.offset, format!("'{}' must be at least {}", key, minimum))),
+            _ => Err(self.error(value.offset, format!("'{}' must be an integer", key))),
+        }
+    }
+
+    fn duration(&self, value: &Located, key: &str, allow_zero: bool) -> Result<Duration, LambdaFileError> {
+        let duration = match &value.value {
+            ManifestValue::Integer(millis) => u64::try_from(*millis).ok().map(Duration::from_millis),
+            ManifestValue::String(duration) => parse_duration(duration),
+            _ => None,
+        };
+        match duration {
+            Some(duration) if allow_zero || !duration.is_zero() => Ok(duration),
+            Some(_) => Err(self.error(value.offset, format!("'{}' must not be zero", key))),
+            None => Err(self.error(value.offset, format!("'{}' must be milliseconds or a duration like \"30s\"", key))),
+        }
+    }
+
+    fn type_annotation(&self, value: &Located) -> Result<TypeAnnotation, LambdaFileError> {
+        let ManifestValue::String(annotation) = &value.value else {
+            return Err(self.error(value.offset, "Types must be strings like \"array<integer>\""));
+        };
+        match parse_type(annotation) {
+            Ok((rest, annotation)) if rest.trim().is_empty() => Ok(annotation),
+            _ => Err(self.error(value.offset, format!("'{}' is not a type", annotation))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+    use tracing_test::traced_test;
+    use crate::assembler::parser::parser::TypeAnnotation;
+    use crate::io::lambda_file::{LambdaFile, LambdaFileError, RetryPolicy, Trigger};
+
+    const MANIFEST: &str = r#"
+        # Applied to every function
+        [defaults]
+        timeout = "30s"
+        retries = 1
+
+        [functions.resize]
+        command = ["python3", "resize.py"]   # started once for both functions
+        working_directory = "imaging"
+        environment = { MODE = "fast", THREADS = "4" }
+        concurrency = 4
+        retry_delay = 500
+        parameters = {
+            image = "bytes",
+            width = "integer",
+            mode = "string",
+        }
+        arguments_required = 2
+        result = "bytes"
+
+        [functions.thumbnail]
+        command = "thumbnail"
+        timeout = 1000
+        retries = 0
+        disabled = true
+
+        [workflows.nightly]
+        script = "nightly.x39"
+        triggers = ["startup", "every 1h"]
+
+        [workflows.cleanup]
+        script = "cleanup.x39"
+    "#;
+
+    fn parse_error(input: &str) -> Result<LambdaFileError, Box<dyn std::error::Error>> {
+        match LambdaFile::parse(input) {
+            Ok(file) => Err(format!("Expected an error but got {:?}", file).into()),
+            Err(error) => Ok(error),
+        }
+    }
+
+    #[test]
+    #[traced_test]
+    fn manifest_is_parsed() -> Result<(), Box<dyn std::error::Error>> {
+        let mut file = LambdaFile::parse(MANIFEST)?;
+        let resize = file.function("resize").ok_or("resize missing")?;
+        assert_eq!(resize.command, vec!("python3", "resize.py"));
+        assert_eq!(resize.environment, vec!(("MODE".to_string(), "fast".to_string()), ("THREADS".to_string(), "4".to_string())));
+        assert_eq!(resize.timeout, Some(Duration::from_secs(30)));
+        assert_eq!(resize.concurrency, Some(4));
+        assert_eq!(resize.retry, RetryPolicy { retries: 1, delay: Duration::from_millis(500) });
+        assert_eq!(resize.parameters[1], ("width".to_string(), TypeAnnotation::Integer));
+        assert_eq!(resize.result, TypeAnnotation::Bytes);
+        let thumbnail = file.function("thumbnail").ok_or("thumbnail missing")?;
+        assert_eq!(thumbnail.command, vec!("thumbnail"));
+        assert_eq!(thumbnail.timeout, Some(Duration::from_secs(1)));
+        assert_eq!(thumbnail.retry.retries, 0);
+        assert!(thumbnail.disabled);
+        assert_eq!(file.workflows[0].triggers, vec!(Trigger::Startup, Trigger::Interval(Duration::from_secs(60 * 60))));
+        assert_eq!(file.workflows[1].triggers, vec!(Trigger::Manual));
+        // Disabled functions cannot be called by scripts
+        let signatures = file.signatures();
+        assert_eq!(signatures.len(), 1);
+        assert_eq!(signatures[0].parameter_names, vec!("image", "width", "mode"));
+        assert_eq!(signatures[0].arguments_required, 2);
+        file.resolve(Path::new("/srv/lambda"));
+        assert_eq!(file.functions[0].working_directory, Some(PathBuf::from("/srv/lambda/imaging")));
+        assert_eq!(file.functions[1].working_directory, Some(PathBuf::from("/srv/lambda")));
+        assert_eq!(file.workflows[0].script, PathBuf::from("/srv/lambda/nightly.x39"));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn errors_are_located() -> Result<(), Box<dyn std::error::Error>> {
+        let error = parse_error("[functions.f]\ncommand = \"f\"\ntimeout \"1s\"\n")?;
+        assert_eq!((error.line, error.column), (3, 9));
+        assert_eq!(error.to_string(), "3:9: Expected '=' after 'timeout'");
+        let error = parse_error("[functions.f]\ncommand = \"f\"\nretries = 1 2\n")?;
+        assert_eq!(error.to_string(), "3:13: Expected the end of the line");
+        let error = parse_error("[functions.f]\ncommand = [\"f\",\n  1]\n")?;
+        assert_eq!(error.to_string(), "3:3: 'command' must be a string");
+        let error = parse_error("[functions.f]\ncommand = \"f\"\n\n[functions.f]\n")?;
+        assert_eq!(error.to_string(), "4:1: Section [functions.f] is already declared in line 1");
+        let error = parse_error("command = \"f\"\n")?;
+        assert_eq!(error.to_string(), "1:1: Keys must be declared inside a section");
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn invalid_declarations_error() -> Result<(), Box<dyn std::error::Error>> {
+        let message = |input: &str| parse_error(input).map(|error| error.message);
+        assert_eq!(message("[functions.f]\n")?, "Function 'f' lacks a command");
+        assert_eq!(message("[functions.f]\ncommand = []\n")?, "'command' must not be empty");
+        assert_eq!(message("[functions.f]\ncommand = \"f\"\ntimeout = 0\n")?, "'timeout' must not be zero");
+        assert_eq!(message("[functions.f]\ncommand = \"f\"\nconcurrency = 0\n")?, "'concurrency' must be at least 1");
+        assert_eq!(message("[functions.f]\ncommand = \"f\"\nresult = \"list\"\n")?, "'list' is not a type");
+        assert_eq!(message("[functions.f]\ncommand = \"f\"\nparameters = { a = \"any\" }\narguments_required = 2\n")?,
+            "'arguments_required' exceeds the 1 parameters declared");
+        assert_eq!(message("[functions.f]\ncommand = \"f\"\nargs = []\n")?.split(',').next(), Some("Unknown key 'args'"));
+        assert_eq!(message("[functions.my_function]\ncommand = \"f\"\n")?, "'my_function' is not a valid identifier, as it cannot be used in scripts");
+        assert_eq!(message("[defaults]\ncommand = \"f\"\n")?.split(',').next(), Some("Unknown key 'command'"));
+        assert_eq!(message("[workflows.w]\nscript = \"w.x39\"\ntriggers = [\"hourly\"]\n")?,
+            "Unknown trigger 'hourly', expected \"manual\", \"startup\" or \"every\" followed by a duration");
+        assert_eq!(message("[services.s]\n")?, "Unknown section [services.s], expected [defaults], [functions.name], [routes.name] or [workflows.name]");
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn versions_and_routes_are_parsed() -> Result<(), Box<dyn std::error::Error>> {
+        let file = LambdaFile::parse(concat!(
+            "[functions.resize]\ncommand = \"resize-v1\"\n",
+            "[functions.resize.3]\ncommand = \"resize-v3\"\nparameters = { image = \"bytes\" }\n",
+            "[routes.resize]\naliases = { stable = 1, canary = 3 }\nweights = { 1 = 9, 3 = 1 }\n",
+        ))?;
+        assert_eq!(file.function("resize").map(|it| it.version), Some(3));
+        assert_eq!(file.version("resize", 1).map(|it| it.command.clone()), Some(vec!("resize-v1".to_string())));
+        let route = file.route("resize").ok_or("route missing")?;
+        assert_eq!(route.aliases, vec!(("stable".to_string(), 1), ("canary".to_string(), 3)));
+        assert_eq!(route.weights, vec!((1, 9), (3, 1)));
+        // Calls not selecting a version are checked against both versions weighted
+        let signatures = file.signatures();
+        let names: Vec<&str> = signatures.iter().map(|it| it.name.as_str()).collect();
+        assert_eq!(names, vec!("resize", "resize", "resize@1", "resize@3", "resize@stable", "resize@canary"));
+        assert_eq!(signatures[0].parameter_names, vec!("image"));
+        assert!(signatures[1].parameter_names.is_empty());
+        assert_eq!(signatures[5].parameter_names, vec!("image"));
+        let message = |input: &str| parse_error(input).map(|error| error.message);
+        assert_eq!(message("[functions.f]\ncommand = \"f\"\n[routes.f]\naliases = { stable = 2 }\n")?,
+            "Version 2 of function 'f' is not declared");
+        assert_eq!(message("[functions.f]\ncommand = \"f\"\n[routes.f]\nweights = { 1 = 0 }\n")?,
+            "'weights' must not all be zero");
+        assert_eq!(message("[routes.f]\n")?, "Function 'f' is not declared");
+        Ok(())
+    }
 }
-// ToDo: Implement parsing and create protocol classes
\ No newline at end of file
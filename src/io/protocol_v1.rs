@@ -1,7 +1,18 @@
 pub mod protocol_v1 {
+    use std::collections::{HashMap, VecDeque};
+    use std::fmt::{Display, Formatter};
     use std::io::{ErrorKind, Read, Write};
-    use crate::io::protocol_v1::protocol_v1::data::{MessageKind, Message, VersionMessage, Quit};
-    use crate::io::protocol_v1::protocol_v1::data::MessageKind::Version;
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc::{channel, sync_channel, Receiver, RecvTimeoutError, Sender, SyncSender};
+    use std::thread::JoinHandle;
+    use std::time::{Duration, Instant};
+    use tracing::{debug, trace, warn};
+    use crate::io::protocol_v1::protocol_v1::data::*;
+    use crate::io::protocol_v2;
+    use crate::io::protocol_v2::ValueEncoding;
+    use crate::io::transport::Transport;
+    use crate::machine::json::JsonError;
+    use crate::machine::VmValue;
 
     mod io {
         use std::io::{Write, Read};
@@ -115,19 +126,61 @@ pub mod protocol_v1 {
         }
 
         pub fn read_string(reader: &mut dyn Read, length: usize) -> Result<String, std::io::Error> {
-            let mut text: String = String::with_capacity(length);
-            // ToDo: Find a better way to convert unknown encoding text with as little overhead as possible into rust String to remove unsafe block
-            let buff = unsafe { text.as_bytes_mut() };
-            reader.read_exact(buff)?;
-            Ok(text)
+            let buff = read_bytes(reader, length)?;
+            String::from_utf8(buff).map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "String received is not valid UTF-8"))
+        }
+
+        pub fn read_bytes(reader: &mut dyn Read, length: usize) -> Result<Vec<u8>, std::io::Error> {
+            // Read via take, so a corrupt length cannot cause a huge allocation up front
+            let mut buff = Vec::new();
+            reader.take(length as u64).read_to_end(&mut buff)?;
+            if buff.len() != length {
+                return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Data received is shorter than announced"));
+            }
+            Ok(buff)
+        }
+
+        /// Reads a trailing u8 added in later revisions of a message, None if absent.
+        pub fn read_optional_u8(reader: &mut dyn Read) -> Result<Option<u8>, std::io::Error> {
+            let mut buff = Vec::with_capacity(size_of::<u8>());
+            reader.take(size_of::<u8>() as u64).read_to_end(&mut buff)?;
+            Ok(buff.first().copied())
+        }
+
+        /// Writes the string prefixed with its length as u16.
+        pub fn write_prefixed_string(writer: &mut dyn Write, text: &str) -> Result<(), std::io::Error> {
+            let length = u16::try_from(text.len())
+                .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "String exceeds the maximum length"))?;
+            write_u16(writer, length)?;
+            writer.write_all(text.as_bytes())
+        }
+
+        /// Reads a string prefixed with its length as u16, rejecting strings longer than `max_length`.
+        pub fn read_prefixed_string(reader: &mut dyn Read, max_length: usize) -> Result<String, std::io::Error> {
+            let length = read_u16(reader)? as usize;
+            if length > max_length {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "String exceeds the maximum length"));
+            }
+            read_string(reader, length)
+        }
+
+        /// Reads a trailing u32 added in later revisions of a message, 0 if absent.
+        pub fn read_optional_u32(reader: &mut dyn Read) -> Result<u32, std::io::Error> {
+            let mut buff = Vec::with_capacity(size_of::<u32>());
+            reader.take(size_of::<u32>() as u64).read_to_end(&mut buff)?;
+            match buff.as_slice() {
+                [] => Ok(0),
+                [a, b, c, d] => Ok(u32::from_le_bytes([*a, *b, *c, *d])),
+                _ => Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Optional field is truncated")),
+            }
         }
     }
 
-    mod data {
+    pub mod data {
         use std::io::{Error, Read, Write};
         use crate::io::protocol_v1::protocol_v1;
 
-        #[derive(PartialEq)]
+        #[derive(Debug, Clone, Copy, PartialEq)]
         #[repr(u16)]
         pub enum MessageKind {
             Version = 0,
@@ -143,6 +196,42 @@ pub mod protocol_v1 {
             ResultRequest = 10,
             ResultResponse = 11,
             CloseCall = 12,
+            Ping = 13,
+            Pong = 14,
+            StreamBegin = 15,
+            StreamChunk = 16,
+            StreamEnd = 17,
+            CancelCall = 18,
+            CancelAck = 19,
+        }
+
+        impl MessageKind {
+            /// The kind of the message id provided, None for ids unknown to this implementation.
+            pub fn from_id(id: u16) -> Option<MessageKind> {
+                match id {
+                    0 => Some(MessageKind::Version),
+                    1 => Some(MessageKind::Quit),
+                    2 => Some(MessageKind::CapabilitiesRequest),
+                    3 => Some(MessageKind::CapabilitiesResponse),
+                    4 => Some(MessageKind::FunctionCapabilitiesRequest),
+                    5 => Some(MessageKind::FunctionCapabilitiesResponse),
+                    6 => Some(MessageKind::Call),
+                    7 => Some(MessageKind::ArgumentRequest),
+                    8 => Some(MessageKind::ArgumentResponse),
+                    9 => Some(MessageKind::CallCompleted),
+                    10 => Some(MessageKind::ResultRequest),
+                    11 => Some(MessageKind::ResultResponse),
+                    12 => Some(MessageKind::CloseCall),
+                    13 => Some(MessageKind::Ping),
+                    14 => Some(MessageKind::Pong),
+                    15 => Some(MessageKind::StreamBegin),
+                    16 => Some(MessageKind::StreamChunk),
+                    17 => Some(MessageKind::StreamEnd),
+                    18 => Some(MessageKind::CancelCall),
+                    19 => Some(MessageKind::CancelAck),
+                    _ => None,
+                }
+            }
         }
 
         impl Into<u16> for MessageKind {
@@ -161,10 +250,27 @@ pub mod protocol_v1 {
                     MessageKind::ResultRequest => 10,
                     MessageKind::ResultResponse => 11,
                     MessageKind::CloseCall => 12,
+                    MessageKind::Ping => 13,
+                    MessageKind::Pong => 14,
+                    MessageKind::StreamBegin => 15,
+                    MessageKind::StreamChunk => 16,
+                    MessageKind::StreamEnd => 17,
+                    MessageKind::CancelCall => 18,
+                    MessageKind::CancelAck => 19,
                 }
             }
         }
 
+        /// The maximum length of a function name, in bytes.
+        pub const MAX_FUNCTION_NAME_LENGTH: usize = 10000;
+
+        /// Feature flag of the version message, set if streams are supported.
+        pub const FEATURE_STREAMS: u32 = 1;
+        /// Feature flag of the version message, set if calls can be cancelled.
+        pub const FEATURE_CANCELLATION: u32 = 2;
+        /// Feature flag of the version message, set if functions are described by signatures.
+        pub const FEATURE_SIGNATURES: u32 = 4;
+
         pub trait Message {
             const KIND: MessageKind;
             fn new() -> Self;
@@ -173,12 +279,19 @@ pub mod protocol_v1 {
             fn deserialize(&mut self, reader: &mut dyn Read) -> Result<(), Error>;
         }
 
+        #[derive(Debug, Clone, PartialEq)]
         pub struct VersionMessage {
             pub major: u32,
             pub minor: u32,
             pub build: u32,
             pub revision: u32,
             pub protocol: u32,
+            /// The heartbeat interval in milliseconds, 0 if heartbeats are disabled.
+            /// Absent in version messages of peers predating heartbeats.
+            pub heartbeat_interval: u32,
+            /// The features supported, eg. [FEATURE_STREAMS]. Absent in version messages of
+            /// peers predating features.
+            pub features: u32,
         }
 
         impl Message for VersionMessage {
@@ -191,11 +304,13 @@ pub mod protocol_v1 {
                     build: 0,
                     revision: 0,
                     protocol: 0,
+                    heartbeat_interval: 0,
+                    features: 0,
                 }
             }
 
             fn length(&self) -> usize {
-                20
+                28
             }
 
             fn serialize(&self, writer: &mut dyn Write) -> Result<(), Error> {
@@ -204,6 +319,8 @@ pub mod protocol_v1 {
                 protocol_v1::io::write_u32(writer, self.build)?;
                 protocol_v1::io::write_u32(writer, self.revision)?;
                 protocol_v1::io::write_u32(writer, self.protocol)?;
+                protocol_v1::io::write_u32(writer, self.heartbeat_interval)?;
+                protocol_v1::io::write_u32(writer, self.features)?;
                 Ok(())
             }
 
@@ -213,10 +330,13 @@ pub mod protocol_v1 {
                 self.build = protocol_v1::io::read_u32(reader)?;
                 self.revision = protocol_v1::io::read_u32(reader)?;
                 self.protocol = protocol_v1::io::read_u32(reader)?;
+                self.heartbeat_interval = protocol_v1::io::read_optional_u32(reader)?;
+                self.features = protocol_v1::io::read_optional_u32(reader)?;
                 Ok(())
             }
         }
 
+        #[derive(Debug, Clone, Copy, PartialEq)]
         #[repr(u8)]
         pub enum Quit {
             Terminate = 0,
@@ -281,6 +401,21 @@ pub mod protocol_v1 {
             Additional59s = 59,
         }
 
+        impl Quit {
+            /// The quit message for the seconds provided, where 0 is [Quit::Terminate].
+            /// Returns None for more than 59 seconds.
+            pub fn from_seconds(seconds: u8) -> Option<Quit> {
+                let mut quit = Quit::new();
+                quit.deserialize(&mut [seconds].as_slice()).ok()?;
+                Some(quit)
+            }
+
+            /// The seconds granted or requested, 0 for [Quit::Terminate].
+            pub fn seconds(self) -> u8 {
+                self as u8
+            }
+        }
+
         impl Message for Quit {
             const KIND: MessageKind = MessageKind::Quit;
 
@@ -289,7 +424,7 @@ pub mod protocol_v1 {
             }
 
             fn length(&self) -> usize {
-                20
+                1
             }
 
             fn serialize(&self, writer: &mut dyn Write) -> Result<(), Error> {
@@ -428,6 +563,7 @@ pub mod protocol_v1 {
             }
         }
 
+        #[derive(Debug, Clone, PartialEq)]
         pub struct CapabilitiesRequestMessage {}
 
         impl Message for CapabilitiesRequestMessage {
@@ -450,6 +586,7 @@ pub mod protocol_v1 {
             }
         }
 
+        #[derive(Debug, Clone, PartialEq)]
         pub struct CapabilitiesResponseMessage {
             pub functions_count: u32,
         }
@@ -478,6 +615,7 @@ pub mod protocol_v1 {
             }
         }
 
+        #[derive(Debug, Clone, PartialEq)]
         pub struct FunctionCapabilitiesRequestMessage {
             pub function_requested: u32,
         }
@@ -506,16 +644,72 @@ pub mod protocol_v1 {
             }
         }
 
+        /// Describes an argument or result of a function.
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct ParameterDescription {
+            pub name: String,
+            /// The JSON-schema-like type of the value, eg. `{"type": "array", "items": {"type": "integer"}}`.
+            pub schema: String,
+            pub description: String,
+        }
+
+        impl ParameterDescription {
+            pub fn new(name: &str, schema: &str, description: &str) -> ParameterDescription {
+                ParameterDescription {
+                    name: name.to_string(),
+                    schema: schema.to_string(),
+                    description: description.to_string(),
+                }
+            }
+
+            fn length(&self) -> usize {
+                6 + self.name.len() + self.schema.len() + self.description.len()
+            }
+
+            fn serialize(&self, writer: &mut dyn Write) -> Result<(), Error> {
+                protocol_v1::io::write_prefixed_string(writer, &self.name)?;
+                protocol_v1::io::write_prefixed_string(writer, &self.schema)?;
+                protocol_v1::io::write_prefixed_string(writer, &self.description)?;
+                Ok(())
+            }
+
+            fn deserialize(reader: &mut dyn Read) -> Result<ParameterDescription, Error> {
+                Ok(ParameterDescription {
+                    name: protocol_v1::io::read_prefixed_string(reader, MAX_FUNCTION_NAME_LENGTH)?,
+                    schema: protocol_v1::io::read_prefixed_string(reader, u16::MAX as usize)?,
+                    description: protocol_v1::io::read_prefixed_string(reader, u16::MAX as usize)?,
+                })
+            }
+        }
+
+        /// The structured signature of a function, only sent if both peers support
+        /// [FEATURE_SIGNATURES].
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct SignatureDescription {
+            pub description: String,
+            /// Whether calling the function repeatedly with the same arguments has no further effect,
+            /// allowing calls to be retried.
+            pub idempotent: bool,
+            /// How long a call is expected to take at most, in milliseconds, 0 if unknown.
+            pub timeout_hint: u32,
+            /// One per argument, `arguments_count` in total.
+            pub parameters: Vec<ParameterDescription>,
+            /// One per result, `results_count` in total.
+            pub results: Vec<ParameterDescription>,
+        }
+
+        #[derive(Debug, Clone, PartialEq)]
         pub struct FunctionCapabilitiesResponseMessage {
             pub function_index: u32,
             pub arguments_required: u8,
             pub arguments_count: u8,
             pub results_count: u8,
             pub function_name: String,
+            pub signature: Option<SignatureDescription>,
         }
 
         impl Message for FunctionCapabilitiesResponseMessage {
-            const KIND: MessageKind = MessageKind::FunctionCapabilitiesRequest;
+            const KIND: MessageKind = MessageKind::FunctionCapabilitiesResponse;
 
             fn new() -> Self {
                 FunctionCapabilitiesResponseMessage {
@@ -524,11 +718,17 @@ pub mod protocol_v1 {
                     arguments_count: 0,
                     results_count: 0,
                     function_name: "".to_string(),
+                    signature: None,
                 }
             }
 
             fn length(&self) -> usize {
-                9 + self.function_name.len()
+                let signature = match &self.signature {
+                    None => 0,
+                    Some(signature) => 7 + signature.description.len()
+                        + signature.parameters.iter().chain(signature.results.iter()).map(|it| it.length()).sum::<usize>(),
+                };
+                9 + self.function_name.len() + signature
             }
 
             fn serialize(&self, writer: &mut dyn Write) -> Result<(), Error> {
@@ -539,6 +739,17 @@ pub mod protocol_v1 {
                 protocol_v1::io::write_u8(writer, self.results_count)?;
                 protocol_v1::io::write_u16(writer, name_length)?;
                 protocol_v1::io::write_string(writer, &self.function_name, 0, name_length as usize)?;
+                if let Some(signature) = &self.signature {
+                    if signature.parameters.len() != self.arguments_count as usize || signature.results.len() != self.results_count as usize {
+                        return Err(Error::new(std::io::ErrorKind::InvalidInput, "Signature does not describe every argument and result"));
+                    }
+                    protocol_v1::io::write_u8(writer, signature.idempotent as u8)?;
+                    protocol_v1::io::write_u32(writer, signature.timeout_hint)?;
+                    protocol_v1::io::write_prefixed_string(writer, &signature.description)?;
+                    for parameter in signature.parameters.iter().chain(signature.results.iter()) {
+                        parameter.serialize(writer)?;
+                    }
+                }
                 Ok(())
             }
 
@@ -548,11 +759,30 @@ pub mod protocol_v1 {
                 self.arguments_count = protocol_v1::io::read_u8(reader)?;
                 self.results_count = protocol_v1::io::read_u8(reader)?;
                 let name_length = protocol_v1::io::read_u16(reader)?;
+                if name_length as usize > MAX_FUNCTION_NAME_LENGTH {
+                    return Err(Error::new(std::io::ErrorKind::InvalidData, "Function name exceeds the maximum length"));
+                }
                 self.function_name = protocol_v1::io::read_string(reader, name_length as usize)?;
+                // The signature is a trailing addition, absent unless both peers support it
+                self.signature = match protocol_v1::io::read_optional_u8(reader)? {
+                    None => None,
+                    Some(flags) => {
+                        let timeout_hint = protocol_v1::io::read_u32(reader)?;
+                        let description = protocol_v1::io::read_prefixed_string(reader, u16::MAX as usize)?;
+                        let parameters = (0..self.arguments_count)
+                            .map(|_| ParameterDescription::deserialize(reader))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        let results = (0..self.results_count)
+                            .map(|_| ParameterDescription::deserialize(reader))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        Some(SignatureDescription { description, idempotent: flags & 1 != 0, timeout_hint, parameters, results })
+                    }
+                };
                 Ok(())
             }
         }
 
+        #[derive(Debug, Clone, PartialEq)]
         pub struct CallMessage {
             pub function_index: u32,
             pub arguments_count: u8,
@@ -589,6 +819,7 @@ pub mod protocol_v1 {
             }
         }
 
+        #[derive(Debug, Clone, PartialEq)]
         pub struct ArgumentRequestMessage {
             pub call_request_id: u32,
             pub argument_index: u8,
@@ -621,8 +852,10 @@ pub mod protocol_v1 {
             }
         }
 
+        #[derive(Debug, Clone, PartialEq)]
         pub struct ArgumentResponseMessage {
-            pub json: String,
+            /// The value, encoded as agreed on in the version message.
+            pub payload: Vec<u8>,
         }
 
         impl Message for ArgumentResponseMessage {
@@ -630,28 +863,28 @@ pub mod protocol_v1 {
 
             fn new() -> Self {
                 ArgumentResponseMessage {
-                    json: "".to_string(),
+                    payload: vec!(),
                 }
             }
 
             fn length(&self) -> usize {
-                4 + self.json.len()
+                4 + self.payload.len()
             }
 
             fn serialize(&self, writer: &mut dyn Write) -> Result<(), Error> {
-                let json_length = self.json.len() as u32;
-                protocol_v1::io::write_u32(writer, json_length)?;
-                protocol_v1::io::write_string(writer, &self.json, 0, json_length as usize)?;
+                protocol_v1::io::write_u32(writer, self.payload.len() as u32)?;
+                writer.write_all(&self.payload)?;
                 Ok(())
             }
 
             fn deserialize(&mut self, reader: &mut dyn Read) -> Result<(), Error> {
-                let json_length = protocol_v1::io::read_u32(reader)?;
-                self.json = protocol_v1::io::read_string(reader, json_length as usize)?;
+                let payload_length = protocol_v1::io::read_u32(reader)?;
+                self.payload = protocol_v1::io::read_bytes(reader, payload_length as usize)?;
                 Ok(())
             }
         }
 
+        #[derive(Debug, Clone, PartialEq)]
         pub struct CallCompletedMessage {
             pub call_request_id: u32,
             pub success: bool,
@@ -688,6 +921,7 @@ pub mod protocol_v1 {
             }
         }
 
+        #[derive(Debug, Clone, PartialEq)]
         pub struct ResultRequestMessage {
             pub call_request_id: u32,
             pub result_index: u8,
@@ -720,8 +954,10 @@ pub mod protocol_v1 {
             }
         }
 
+        #[derive(Debug, Clone, PartialEq)]
         pub struct ResultResponseMessage {
-            pub json: String,
+            /// The value, encoded as agreed on in the version message.
+            pub payload: Vec<u8>,
         }
 
         impl Message for ResultResponseMessage {
@@ -729,28 +965,28 @@ pub mod protocol_v1 {
 
             fn new() -> Self {
                 ResultResponseMessage {
-                    json: "".to_string(),
+                    payload: vec!(),
                 }
             }
 
             fn length(&self) -> usize {
-                4 + self.json.len()
+                4 + self.payload.len()
             }
 
             fn serialize(&self, writer: &mut dyn Write) -> Result<(), Error> {
-                let json_length = self.json.len() as u32;
-                protocol_v1::io::write_u32(writer, json_length)?;
-                protocol_v1::io::write_string(writer, &self.json, 0, json_length as usize)?;
+                protocol_v1::io::write_u32(writer, self.payload.len() as u32)?;
+                writer.write_all(&self.payload)?;
                 Ok(())
             }
 
             fn deserialize(&mut self, reader: &mut dyn Read) -> Result<(), Error> {
-                let json_length = protocol_v1::io::read_u32(reader)?;
-                self.json = protocol_v1::io::read_string(reader, json_length as usize)?;
+                let payload_length = protocol_v1::io::read_u32(reader)?;
+                self.payload = protocol_v1::io::read_bytes(reader, payload_length as usize)?;
                 Ok(())
             }
         }
 
+        #[derive(Debug, Clone, PartialEq)]
         pub struct CloseCallMessage {
             pub call_request_id: u32,
         }
@@ -778,13 +1014,294 @@ pub mod protocol_v1 {
                 Ok(())
             }
         }
+
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct PingMessage {
+            pub sequence: u32,
+        }
+
+        impl Message for PingMessage {
+            const KIND: MessageKind = MessageKind::Ping;
+
+            fn new() -> Self {
+                PingMessage {
+                    sequence: 0,
+                }
+            }
+
+            fn length(&self) -> usize {
+                4
+            }
+
+            fn serialize(&self, writer: &mut dyn Write) -> Result<(), Error> {
+                protocol_v1::io::write_u32(writer, self.sequence)?;
+                Ok(())
+            }
+
+            fn deserialize(&mut self, reader: &mut dyn Read) -> Result<(), Error> {
+                self.sequence = protocol_v1::io::read_u32(reader)?;
+                Ok(())
+            }
+        }
+
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct PongMessage {
+            pub sequence: u32,
+        }
+
+        impl Message for PongMessage {
+            const KIND: MessageKind = MessageKind::Pong;
+
+            fn new() -> Self {
+                PongMessage {
+                    sequence: 0,
+                }
+            }
+
+            fn length(&self) -> usize {
+                4
+            }
+
+            fn serialize(&self, writer: &mut dyn Write) -> Result<(), Error> {
+                protocol_v1::io::write_u32(writer, self.sequence)?;
+                Ok(())
+            }
+
+            fn deserialize(&mut self, reader: &mut dyn Read) -> Result<(), Error> {
+                self.sequence = protocol_v1::io::read_u32(reader)?;
+                Ok(())
+            }
+        }
+
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct CancelCallMessage {
+            pub call_request_id: u32,
+        }
+
+        impl Message for CancelCallMessage {
+            const KIND: MessageKind = MessageKind::CancelCall;
+
+            fn new() -> Self {
+                CancelCallMessage {
+                    call_request_id: 0,
+                }
+            }
+
+            fn length(&self) -> usize {
+                4
+            }
+
+            fn serialize(&self, writer: &mut dyn Write) -> Result<(), Error> {
+                protocol_v1::io::write_u32(writer, self.call_request_id)?;
+                Ok(())
+            }
+
+            fn deserialize(&mut self, reader: &mut dyn Read) -> Result<(), Error> {
+                self.call_request_id = protocol_v1::io::read_u32(reader)?;
+                Ok(())
+            }
+        }
+
+        /// How a call asked to be cancelled ended.
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        #[repr(u8)]
+        pub enum CancelStatus {
+            /// The call stopped early, its results are discarded.
+            Cancelled = 0,
+            /// The call completed regardless, its results are discarded.
+            Completed = 1,
+            /// The call was not running, eg. as it completed before the cancellation arrived.
+            NotRunning = 2,
+        }
+
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct CancelAckMessage {
+            pub call_request_id: u32,
+            pub status: CancelStatus,
+        }
+
+        impl Message for CancelAckMessage {
+            const KIND: MessageKind = MessageKind::CancelAck;
+
+            fn new() -> Self {
+                CancelAckMessage {
+                    call_request_id: 0,
+                    status: CancelStatus::Cancelled,
+                }
+            }
+
+            fn length(&self) -> usize {
+                5
+            }
+
+            fn serialize(&self, writer: &mut dyn Write) -> Result<(), Error> {
+                protocol_v1::io::write_u32(writer, self.call_request_id)?;
+                protocol_v1::io::write_u8(writer, self.status as u8)?;
+                Ok(())
+            }
+
+            fn deserialize(&mut self, reader: &mut dyn Read) -> Result<(), Error> {
+                self.call_request_id = protocol_v1::io::read_u32(reader)?;
+                self.status = match protocol_v1::io::read_u8(reader)? {
+                    0 => CancelStatus::Cancelled,
+                    1 => CancelStatus::Completed,
+                    2 => CancelStatus::NotRunning,
+                    _ => return Err(Error::new(std::io::ErrorKind::InvalidData, "Cancel status received is out of the valid value range.")),
+                };
+                Ok(())
+            }
+        }
+
+        /// What the value carried by a stream is used as.
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        #[repr(u8)]
+        pub enum StreamPurpose {
+            /// Answers the argument request sent last, in place of an argument-response message.
+            Argument = 0,
+            /// Answers the result request sent last, in place of a result-response message.
+            Result = 1,
+            /// A partial result of a call still running.
+            Partial = 2,
+        }
+
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct StreamBeginMessage {
+            pub stream_id: u32,
+            pub call_request_id: u32,
+            pub purpose: StreamPurpose,
+        }
+
+        impl Message for StreamBeginMessage {
+            const KIND: MessageKind = MessageKind::StreamBegin;
+
+            fn new() -> Self {
+                StreamBeginMessage {
+                    stream_id: 0,
+                    call_request_id: 0,
+                    purpose: StreamPurpose::Argument,
+                }
+            }
+
+            fn length(&self) -> usize {
+                9
+            }
+
+            fn serialize(&self, writer: &mut dyn Write) -> Result<(), Error> {
+                protocol_v1::io::write_u32(writer, self.stream_id)?;
+                protocol_v1::io::write_u32(writer, self.call_request_id)?;
+                protocol_v1::io::write_u8(writer, self.purpose as u8)?;
+                Ok(())
+            }
+
+            fn deserialize(&mut self, reader: &mut dyn Read) -> Result<(), Error> {
+                self.stream_id = protocol_v1::io::read_u32(reader)?;
+                self.call_request_id = protocol_v1::io::read_u32(reader)?;
+                self.purpose = match protocol_v1::io::read_u8(reader)? {
+                    0 => StreamPurpose::Argument,
+                    1 => StreamPurpose::Result,
+                    2 => StreamPurpose::Partial,
+                    _ => return Err(Error::new(std::io::ErrorKind::InvalidData, "Stream purpose received is out of the valid value range.")),
+                };
+                Ok(())
+            }
+        }
+
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct StreamChunkMessage {
+            pub stream_id: u32,
+            /// The index of the chunk within its stream, starting at 0.
+            pub sequence: u32,
+            pub data: Vec<u8>,
+        }
+
+        impl Message for StreamChunkMessage {
+            const KIND: MessageKind = MessageKind::StreamChunk;
+
+            fn new() -> Self {
+                StreamChunkMessage {
+                    stream_id: 0,
+                    sequence: 0,
+                    data: vec!(),
+                }
+            }
+
+            fn length(&self) -> usize {
+                4 + 4 + 4 + self.data.len()
+            }
+
+            fn serialize(&self, writer: &mut dyn Write) -> Result<(), Error> {
+                protocol_v1::io::write_u32(writer, self.stream_id)?;
+                protocol_v1::io::write_u32(writer, self.sequence)?;
+                protocol_v1::io::write_u32(writer, self.data.len() as u32)?;
+                writer.write_all(&self.data)?;
+                Ok(())
+            }
+
+            fn deserialize(&mut self, reader: &mut dyn Read) -> Result<(), Error> {
+                self.stream_id = protocol_v1::io::read_u32(reader)?;
+                self.sequence = protocol_v1::io::read_u32(reader)?;
+                let data_length = protocol_v1::io::read_u32(reader)?;
+                self.data = protocol_v1::io::read_bytes(reader, data_length as usize)?;
+                Ok(())
+            }
+        }
+
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct StreamEndMessage {
+            pub stream_id: u32,
+            /// The number of chunks sent.
+            pub chunks: u32,
+        }
+
+        impl Message for StreamEndMessage {
+            const KIND: MessageKind = MessageKind::StreamEnd;
+
+            fn new() -> Self {
+                StreamEndMessage {
+                    stream_id: 0,
+                    chunks: 0,
+                }
+            }
+
+            fn length(&self) -> usize {
+                8
+            }
+
+            fn serialize(&self, writer: &mut dyn Write) -> Result<(), Error> {
+                protocol_v1::io::write_u32(writer, self.stream_id)?;
+                protocol_v1::io::write_u32(writer, self.chunks)?;
+                Ok(())
+            }
+
+            fn deserialize(&mut self, reader: &mut dyn Read) -> Result<(), Error> {
+                self.stream_id = protocol_v1::io::read_u32(reader)?;
+                self.chunks = protocol_v1::io::read_u32(reader)?;
+                Ok(())
+            }
+        }
     }
 
+    #[derive(Debug)]
     pub enum Error {
         ProtocolError(&'static str),
         IoError(std::io::Error),
+        Json(JsonError),
+        Timeout(&'static str),
     }
 
+    impl Display for Error {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Error::ProtocolError(message) => write!(f, "Protocol error: {}", message),
+                Error::IoError(error) => write!(f, "I/O error: {}", error),
+                Error::Json(error) => write!(f, "JSON error: {}", error),
+                Error::Timeout(message) => write!(f, "Timeout: {}", message),
+            }
+        }
+    }
+
+    impl std::error::Error for Error {}
+
     impl From<std::io::Error> for Error {
         fn from(value: std::io::Error) -> Self {
             Error::IoError(value)
@@ -797,91 +1314,1453 @@ pub mod protocol_v1 {
         }
     }
 
-    pub struct ProtocolHost<'a> {
-        writer: &'a mut dyn Write,
-        reader: &'a mut dyn Read,
-        client_version: VersionMessage,
+    impl From<JsonError> for Error {
+        fn from(value: JsonError) -> Self {
+            Error::Json(value)
+        }
     }
 
-    impl<'a> ProtocolHost<'a> {
-        const FRAME_VERSION: u8 = 1;
-        const PROTOCOL_VERSION: u32 = 1;
-        fn write<MESSAGE>(&mut self, message: MESSAGE)
-                          -> Result<(), std::io::Error>
-            where MESSAGE: Message
-        {
-            let msg_length = message.length();
-            if msg_length > u32::MAX as usize {
-                return Err(std::io::Error::new(ErrorKind::InvalidData, "The message size exceeds the maximum size of u32"));
-            }
-            io::write_u8(self.writer, 0)?;
-            io::write_u8(self.writer, ProtocolHost::FRAME_VERSION)?;
-            io::write_u16(self.writer, MESSAGE::KIND.into())?;
-            io::write_u32(self.writer, msg_length as u32)?;
-            message.serialize(self.writer)?;
+    const FRAME_VERSION: u8 = 0;
+    const HEADER_LENGTH: usize = 8;
+    /// The maximum length of a message body accepted by default, in bytes.
+    pub const MAX_MESSAGE_LENGTH: usize = 16 * 1024 * 1024;
+
+    /// Writes the message, including its frame header, as a single write to the writer provided.
+    pub fn write_message<MESSAGE>(writer: &mut dyn Write, message: &MESSAGE) -> Result<(), std::io::Error>
+        where MESSAGE: Message
+    {
+        let msg_length = message.length();
+        if msg_length > MAX_MESSAGE_LENGTH {
+            return Err(std::io::Error::new(ErrorKind::InvalidData, "The message size exceeds the maximum message size"));
+        }
+        let mut buffer: Vec<u8> = Vec::with_capacity(HEADER_LENGTH + msg_length);
+        io::write_u8(&mut buffer, 0)?;
+        io::write_u8(&mut buffer, FRAME_VERSION)?;
+        io::write_u16(&mut buffer, MESSAGE::KIND.into())?;
+        io::write_u32(&mut buffer, msg_length as u32)?;
+        message.serialize(&mut buffer)?;
+        if buffer.len() != HEADER_LENGTH + msg_length {
+            return Err(std::io::Error::new(ErrorKind::InvalidData, "The message length does not match its serialized size"));
+        }
+        writer.write_all(&buffer)?;
+        writer.flush()
+    }
+
+    /// Reads a frame header, returning the message id and the length of the body.
+    pub fn read_header(reader: &mut dyn Read) -> Result<(u16, u32), std::io::Error>
+    {
+        let zero = io::read_u8(reader)?;
+        if zero != 0 {
+            return Err(std::io::Error::new(ErrorKind::InvalidData, "Frame does not start with a zero-byte"));
+        }
+        let frame_version = io::read_u8(reader)?;
+        if frame_version != FRAME_VERSION {
+            return Err(std::io::Error::new(ErrorKind::InvalidData, "Unsupported frame version"));
+        }
+        let message_id = io::read_u16(reader)?;
+        let message_length = io::read_u32(reader)?;
+        Ok((message_id, message_length))
+    }
+
+    /// Reads a frame of a known message kind, skipping frames of unknown kinds.
+    pub fn read_frame(reader: &mut dyn Read, max_length: usize) -> Result<(MessageKind, Vec<u8>), std::io::Error> {
+        loop {
+            let (message_id, message_length) = read_header(reader)?;
+            let message_length = message_length as usize;
+            if message_length > max_length {
+                return Err(std::io::Error::new(ErrorKind::InvalidData, "The message size exceeds the maximum message size"));
+            }
+            let mut body = Vec::new();
+            reader.take(message_length as u64).read_to_end(&mut body)?;
+            if body.len() != message_length {
+                return Err(std::io::Error::new(ErrorKind::UnexpectedEof, "Frame ended before the message was complete"));
+            }
+            match MessageKind::from_id(message_id) {
+                Some(kind) => return Ok((kind, body)),
+                None => trace!("Skipping message of unknown kind {} ({} bytes)", message_id, message_length),
+            }
+        }
+    }
+
+    /// Decodes the body of a frame, which has to be consumed completely.
+    fn decode_body<MESSAGE>(body: &[u8]) -> Result<MESSAGE, std::io::Error>
+        where MESSAGE: Message {
+        let mut reader = body;
+        let mut message = MESSAGE::new();
+        message.deserialize(&mut reader)?;
+        if !reader.is_empty() {
+            return Err(std::io::Error::new(ErrorKind::InvalidData, "Message is shorter than its frame"));
+        }
+        Ok(message)
+    }
+
+    /// Any message of the protocol, allowing to read a message without knowing its kind in advance.
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum ProtocolMessage {
+        Version(VersionMessage),
+        Quit(Quit),
+        CapabilitiesRequest(CapabilitiesRequestMessage),
+        CapabilitiesResponse(CapabilitiesResponseMessage),
+        FunctionCapabilitiesRequest(FunctionCapabilitiesRequestMessage),
+        FunctionCapabilitiesResponse(FunctionCapabilitiesResponseMessage),
+        Call(CallMessage),
+        ArgumentRequest(ArgumentRequestMessage),
+        ArgumentResponse(ArgumentResponseMessage),
+        CallCompleted(CallCompletedMessage),
+        ResultRequest(ResultRequestMessage),
+        ResultResponse(ResultResponseMessage),
+        CloseCall(CloseCallMessage),
+        Ping(PingMessage),
+        Pong(PongMessage),
+        StreamBegin(StreamBeginMessage),
+        StreamChunk(StreamChunkMessage),
+        StreamEnd(StreamEndMessage),
+        CancelCall(CancelCallMessage),
+        CancelAck(CancelAckMessage),
+    }
+
+    impl ProtocolMessage {
+        pub fn kind(&self) -> MessageKind {
+            match self {
+                ProtocolMessage::Version(_) => MessageKind::Version,
+                ProtocolMessage::Quit(_) => MessageKind::Quit,
+                ProtocolMessage::CapabilitiesRequest(_) => MessageKind::CapabilitiesRequest,
+                ProtocolMessage::CapabilitiesResponse(_) => MessageKind::CapabilitiesResponse,
+                ProtocolMessage::FunctionCapabilitiesRequest(_) => MessageKind::FunctionCapabilitiesRequest,
+                ProtocolMessage::FunctionCapabilitiesResponse(_) => MessageKind::FunctionCapabilitiesResponse,
+                ProtocolMessage::Call(_) => MessageKind::Call,
+                ProtocolMessage::ArgumentRequest(_) => MessageKind::ArgumentRequest,
+                ProtocolMessage::ArgumentResponse(_) => MessageKind::ArgumentResponse,
+                ProtocolMessage::CallCompleted(_) => MessageKind::CallCompleted,
+                ProtocolMessage::ResultRequest(_) => MessageKind::ResultRequest,
+                ProtocolMessage::ResultResponse(_) => MessageKind::ResultResponse,
+                ProtocolMessage::CloseCall(_) => MessageKind::CloseCall,
+                ProtocolMessage::Ping(_) => MessageKind::Ping,
+                ProtocolMessage::Pong(_) => MessageKind::Pong,
+                ProtocolMessage::StreamBegin(_) => MessageKind::StreamBegin,
+                ProtocolMessage::StreamChunk(_) => MessageKind::StreamChunk,
+                ProtocolMessage::StreamEnd(_) => MessageKind::StreamEnd,
+                ProtocolMessage::CancelCall(_) => MessageKind::CancelCall,
+                ProtocolMessage::CancelAck(_) => MessageKind::CancelAck,
+            }
+        }
+
+        /// Reads the next message from the reader provided, blocking until it is complete.
+        pub fn read(reader: &mut dyn Read) -> Result<ProtocolMessage, std::io::Error> {
+            ProtocolMessage::read_limited(reader, MAX_MESSAGE_LENGTH)
+        }
+
+        /// Reads the next message, rejecting messages longer than `max_length` bytes.
+        /// Messages of unknown kinds are skipped.
+        pub fn read_limited(reader: &mut dyn Read, max_length: usize) -> Result<ProtocolMessage, std::io::Error> {
+            let (kind, body) = read_frame(reader, max_length)?;
+            let reader = body.as_slice();
+            Ok(match kind {
+                MessageKind::Version => ProtocolMessage::Version(decode_body(reader)?),
+                MessageKind::Quit => ProtocolMessage::Quit(decode_body(reader)?),
+                MessageKind::CapabilitiesRequest => ProtocolMessage::CapabilitiesRequest(decode_body(reader)?),
+                MessageKind::CapabilitiesResponse => ProtocolMessage::CapabilitiesResponse(decode_body(reader)?),
+                MessageKind::FunctionCapabilitiesRequest => ProtocolMessage::FunctionCapabilitiesRequest(decode_body(reader)?),
+                MessageKind::FunctionCapabilitiesResponse => ProtocolMessage::FunctionCapabilitiesResponse(decode_body(reader)?),
+                MessageKind::Call => ProtocolMessage::Call(decode_body(reader)?),
+                MessageKind::ArgumentRequest => ProtocolMessage::ArgumentRequest(decode_body(reader)?),
+                MessageKind::ArgumentResponse => ProtocolMessage::ArgumentResponse(decode_body(reader)?),
+                MessageKind::CallCompleted => ProtocolMessage::CallCompleted(decode_body(reader)?),
+                MessageKind::ResultRequest => ProtocolMessage::ResultRequest(decode_body(reader)?),
+                MessageKind::ResultResponse => ProtocolMessage::ResultResponse(decode_body(reader)?),
+                MessageKind::CloseCall => ProtocolMessage::CloseCall(decode_body(reader)?),
+                MessageKind::Ping => ProtocolMessage::Ping(decode_body(reader)?),
+                MessageKind::Pong => ProtocolMessage::Pong(decode_body(reader)?),
+                MessageKind::StreamBegin => ProtocolMessage::StreamBegin(decode_body(reader)?),
+                MessageKind::StreamChunk => ProtocolMessage::StreamChunk(decode_body(reader)?),
+                MessageKind::StreamEnd => ProtocolMessage::StreamEnd(decode_body(reader)?),
+                MessageKind::CancelCall => ProtocolMessage::CancelCall(decode_body(reader)?),
+                MessageKind::CancelAck => ProtocolMessage::CancelAck(decode_body(reader)?),
+            })
+        }
+
+        pub fn write(&self, writer: &mut dyn Write) -> Result<(), std::io::Error> {
+            match self {
+                ProtocolMessage::Version(message) => write_message(writer, message),
+                ProtocolMessage::Quit(message) => write_message(writer, message),
+                ProtocolMessage::CapabilitiesRequest(message) => write_message(writer, message),
+                ProtocolMessage::CapabilitiesResponse(message) => write_message(writer, message),
+                ProtocolMessage::FunctionCapabilitiesRequest(message) => write_message(writer, message),
+                ProtocolMessage::FunctionCapabilitiesResponse(message) => write_message(writer, message),
+                ProtocolMessage::Call(message) => write_message(writer, message),
+                ProtocolMessage::ArgumentRequest(message) => write_message(writer, message),
+                ProtocolMessage::ArgumentResponse(message) => write_message(writer, message),
+                ProtocolMessage::CallCompleted(message) => write_message(writer, message),
+                ProtocolMessage::ResultRequest(message) => write_message(writer, message),
+                ProtocolMessage::ResultResponse(message) => write_message(writer, message),
+                ProtocolMessage::CloseCall(message) => write_message(writer, message),
+                ProtocolMessage::Ping(message) => write_message(writer, message),
+                ProtocolMessage::Pong(message) => write_message(writer, message),
+                ProtocolMessage::StreamBegin(message) => write_message(writer, message),
+                ProtocolMessage::StreamChunk(message) => write_message(writer, message),
+                ProtocolMessage::StreamEnd(message) => write_message(writer, message),
+                ProtocolMessage::CancelCall(message) => write_message(writer, message),
+                ProtocolMessage::CancelAck(message) => write_message(writer, message),
+            }
+        }
+    }
+
+    /// The size of the chunks JSON payloads are split into. Payloads larger than a chunk are
+    /// streamed if the peer supports streams.
+    pub const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+    /// The maximum number of streams a peer may have open at the same time.
+    pub const MAX_OPEN_STREAMS: usize = 64;
+    /// The default maximum number of bytes a peer may send as a single stream.
+    pub const MAX_STREAM_LENGTH: usize = 256 * 1024 * 1024;
+    /// The number of chunks queued for the decoder of a stream before receiving more waits for it.
+    const STREAM_QUEUE_LENGTH: usize = 16;
+
+    /// Sends the encoded value provided as stream, writing every chunk separately so other
+    /// messages can be sent in between.
+    pub fn write_stream(writer: &Mutex<Box<dyn Write + Send>>, begin: StreamBeginMessage, payload: &[u8]) -> Result<(), std::io::Error> {
+        let write = |message: &ProtocolMessage| -> Result<(), std::io::Error> {
+            let mut writer = writer.lock().map_err(|_| std::io::Error::other("Writer lock poisoned"))?;
+            message.write(&mut *writer)
+        };
+        let stream_id = begin.stream_id;
+        write(&ProtocolMessage::StreamBegin(begin))?;
+        let mut chunks: u32 = 0;
+        for data in payload.chunks(STREAM_CHUNK_SIZE) {
+            write(&ProtocolMessage::StreamChunk(StreamChunkMessage { stream_id, sequence: chunks, data: data.to_vec() }))?;
+            chunks += 1;
+        }
+        write(&ProtocolMessage::StreamEnd(StreamEndMessage { stream_id, chunks }))
+    }
+
+    /// Reads the chunks of a stream as they arrive, ending once the sender is dropped.
+    struct ChunkReader {
+        chunks: Receiver<Vec<u8>>,
+        current: Vec<u8>,
+        position: usize,
+    }
+
+    impl Read for ChunkReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            while self.position >= self.current.len() {
+                match self.chunks.recv() {
+                    Ok(chunk) => {
+                        self.current = chunk;
+                        self.position = 0;
+                    }
+                    Err(_) => return Ok(0),
+                }
+            }
+            let length = buf.len().min(self.current.len() - self.position);
+            buf[..length].copy_from_slice(&self.current[self.position..self.position + length]);
+            self.position += length;
+            Ok(length)
+        }
+    }
+
+    /// A stream being received, whose value is decoded on a dedicated thread while its
+    /// chunks arrive, so the payload is never held in memory as a whole.
+    pub struct IncomingStream {
+        pub call_request_id: u32,
+        pub purpose: StreamPurpose,
+        next_sequence: u32,
+        /// The number of bytes received so far and the most the stream may hold.
+        received: usize,
+        max_length: usize,
+        chunks: SyncSender<Vec<u8>>,
+        decoder: JoinHandle<Result<VmValue, Error>>,
+    }
+
+    impl IncomingStream {
+        /// Begins receiving a stream, failing it once more than `max_length` bytes arrive.
+        pub fn begin(begin: &StreamBeginMessage, encoding: ValueEncoding, max_length: usize) -> IncomingStream {
+            let (chunks, receiver) = sync_channel(STREAM_QUEUE_LENGTH);
+            let decoder = std::thread::spawn(move || {
+                let reader = ChunkReader { chunks: receiver, current: vec!(), position: 0 };
+                encoding.decode_reader(reader)
+            });
+            IncomingStream {
+                call_request_id: begin.call_request_id,
+                purpose: begin.purpose,
+                next_sequence: 0,
+                received: 0,
+                max_length,
+                chunks,
+                decoder,
+            }
+        }
+
+        pub fn push(&mut self, chunk: StreamChunkMessage) -> Result<(), Error> {
+            if chunk.sequence != self.next_sequence {
+                return Err("Stream chunk received out of sequence".into());
+            }
+            self.next_sequence += 1;
+            self.received = self.received.saturating_add(chunk.data.len());
+            if self.received > self.max_length {
+                return Err("Stream received exceeds the maximum stream length".into());
+            }
+            // The decoder stops early on malformed values, which is reported once the stream ends
+            let _ = self.chunks.send(chunk.data);
             Ok(())
         }
-        fn read_header(&mut self) -> Result<(data::MessageKind, u32), std::io::Error>
-        {
-            let _ = io::read_u8(self.reader)?;
-            let frame_version = io::read_u8(self.reader)?;
-            if frame_version != ProtocolHost::FRAME_VERSION {
-                return Err(std::io::Error::new(ErrorKind::InvalidData, "Unsupported frame version"));
-            }
-            let message_id = io::read_u16(self.reader)?;
-            let message_length = io::read_u32(self.reader)?;
-            let message = match message_id {
-                0 => MessageKind::Version,
-                1 => MessageKind::Quit,
-                2 => MessageKind::CapabilitiesRequest,
-                3 => MessageKind::CapabilitiesResponse,
-                4 => MessageKind::FunctionCapabilitiesRequest,
-                5 => MessageKind::FunctionCapabilitiesResponse,
-                6 => MessageKind::Call,
-                7 => MessageKind::ArgumentRequest,
-                8 => MessageKind::ArgumentResponse,
-                9 => MessageKind::CallCompleted,
-                10 => MessageKind::ResultRequest,
-                11 => MessageKind::ResultResponse,
-                12 => MessageKind::CloseCall,
-                _ => return Err(std::io::Error::new(ErrorKind::InvalidData, "Unknown message id")),
-            };
-            Ok((message, message_length))
+
+        /// Waits for the value to be decoded completely.
+        pub fn end(self, end: &StreamEndMessage) -> Result<VmValue, Error> {
+            if end.chunks != self.next_sequence {
+                return Err("Stream ended with chunks missing".into());
+            }
+            drop(self.chunks);
+            self.decoder.join().map_err(|_| "Stream decoder panicked")?
         }
-        fn read_full<MESSAGE>(&mut self) -> Result<MESSAGE, Error>
-            where MESSAGE: Message {
-            let (id, _) = self.read_header()?;
-            self.read(id)
+    }
+
+    /// The outcome of a call, available once all results were received from the client.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct CallOutcome {
+        pub success: bool,
+        /// The results of the call. If the call was not successful, this holds the error
+        /// reported by the client as single value.
+        pub results: Vec<VmValue>,
+    }
+
+    /// Notifications emitted by the message loop of a [ProtocolHost].
+    #[derive(Debug)]
+    pub enum HostEvent {
+        /// A call completed and its outcome can be taken via [ProtocolHost::take_outcome].
+        CallCompleted { call_request_id: u32, success: bool },
+        /// A partial result of a call still running arrived and can be taken via
+        /// [ProtocolHost::take_partial].
+        PartialResult { call_request_id: u32 },
+        /// A call asked to be cancelled via [ProtocolHost::cancel] ended and was forgotten.
+        CallCancelled { call_request_id: u32, status: CancelStatus },
+        /// The client asked for additional seconds to shut down, extending the deadline
+        /// of [ProtocolHost::drain].
+        QuitExtensionRequested { seconds: u8 },
+        /// The connection ended, either because the client closed it or due to the error provided.
+        /// A client not answering heartbeats is disconnected with [Error::Timeout].
+        /// All calls still running are lost.
+        Disconnected(Option<Error>),
+    }
+
+    /// Options of a connection to a client.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct HostOptions {
+        /// The heartbeat interval proposed to the client, None to disable heartbeats.
+        /// The client may answer with a longer interval or disable heartbeats.
+        pub heartbeat_interval: Option<Duration>,
+        /// The number of heartbeat intervals the client may stay silent before it is considered dead.
+        pub missed_heartbeats: u32,
+        /// The maximum number of bytes the client may send as a single stream, the connection
+        /// failing with a protocol error once a stream exceeds it.
+        pub max_stream_length: usize,
+    }
+
+    impl Default for HostOptions {
+        fn default() -> Self {
+            HostOptions {
+                heartbeat_interval: Some(Duration::from_secs(5)),
+                missed_heartbeats: 3,
+                max_stream_length: MAX_STREAM_LENGTH,
+            }
         }
-        fn read<MESSAGE>(&mut self, id: MessageKind) -> Result<MESSAGE, Error>
+    }
+
+    enum CallState {
+        Running { arguments: Vec<Vec<u8>> },
+        /// Asked to be cancelled at the time provided, the arguments still being served as
+        /// the client may request them before learning about the cancellation.
+        Cancelling { arguments: Vec<Vec<u8>>, requested: Instant },
+        Collecting { success: bool, expected: u8, results: Vec<VmValue> },
+        Completed(CallOutcome),
+    }
+
+    struct HostState {
+        calls: HashMap<u32, CallState>,
+        /// Result requests sent, in order, as the result-response message carries no call-request-id.
+        result_requests: VecDeque<u32>,
+        next_call_request_id: u32,
+        connected: bool,
+        /// When the last message of the client was received.
+        last_received: Instant,
+        /// Whether the client was disconnected as it stopped answering heartbeats.
+        unresponsive: bool,
+        /// When the client was asked to quit and the deadline it has to quit by.
+        drain: Option<(Instant, Instant)>,
+        /// The features supported by both sides, eg. [FEATURE_STREAMS].
+        features: u32,
+        encoding: ValueEncoding,
+        next_stream_id: u32,
+        streams: HashMap<u32, IncomingStream>,
+        max_stream_length: usize,
+        /// Partial results received but not yet taken, per call.
+        partials: HashMap<u32, VecDeque<VmValue>>,
+    }
+
+    /// The server side of a connection to a function-hosting client.
+    ///
+    /// After the handshake, all messages of the client are read by a dedicated thread which
+    /// answers argument requests, collects results and closes calls on its own. Many calls
+    /// may be running at the same time, their completion is reported as [HostEvent].
+    pub struct ProtocolHost {
+        writer: Arc<Mutex<Box<dyn Write + Send>>>,
+        state: Arc<Mutex<HostState>>,
+        events: Receiver<HostEvent>,
+        reader_thread: Option<JoinHandle<()>>,
+        client_version: VersionMessage,
+        functions: Vec<FunctionCapabilitiesResponseMessage>,
+        transport: Option<Box<dyn Transport>>,
+        heartbeat_interval: Option<Duration>,
+        encoding: ValueEncoding,
+        /// Dropped to stop the heartbeat thread.
+        heartbeat_stop: Option<Sender<()>>,
+    }
+
+    impl ProtocolHost {
+        /// The protocol proposed to clients, which may answer with an older one.
+        const PROTOCOL_VERSION: u32 = protocol_v2::PROTOCOL_VERSION;
+        /// The longest time a client may take to quit, including extensions requested.
+        const MAX_QUIT_TIME: Duration = Duration::from_secs(60);
+
+        fn write<MESSAGE>(writer: &Mutex<Box<dyn Write + Send>>, message: &MESSAGE) -> Result<(), std::io::Error>
+            where MESSAGE: Message
+        {
+            let mut writer = writer.lock().map_err(|_| std::io::Error::other("Writer lock poisoned"))?;
+            write_message(&mut *writer, message)
+        }
+        fn read_full<MESSAGE>(reader: &mut dyn Read) -> Result<MESSAGE, Error>
             where MESSAGE: Message {
-            if id != MESSAGE::KIND {
+            let (kind, body) = read_frame(reader, MAX_MESSAGE_LENGTH)?;
+            if kind != MESSAGE::KIND {
                 return Err("Different message was expected at this point".into());
             }
-            let mut message = MESSAGE::new();
-            message.deserialize(self.reader)?;
-            return Ok(message);
+            Ok(decode_body(&body)?)
         }
 
+        /// Performs the handshake with the client, queries its functions and starts the
+        /// message loop, using the default [HostOptions].
+        pub fn connect(writer: Box<dyn Write + Send>, reader: Box<dyn Read + Send>) -> Result<ProtocolHost, Error> {
+            ProtocolHost::connect_with(writer, reader, &HostOptions::default())
+        }
 
-        pub fn connect(writer: &'a mut dyn Write, reader: &'a mut dyn Read) -> Result<ProtocolHost<'a>, Error> {
-            let mut host = ProtocolHost { reader, writer, client_version: VersionMessage::new() };
-
-            host.write(VersionMessage {
+        /// Performs the handshake with the client, queries its functions and starts the
+        /// message loop as well as the heartbeat, if the client agreed to one.
+        pub fn connect_with(writer: Box<dyn Write + Send>, mut reader: Box<dyn Read + Send>, options: &HostOptions) -> Result<ProtocolHost, Error> {
+            let writer = Arc::new(Mutex::new(writer));
+            let proposed_interval = options.heartbeat_interval
+                .map(|interval| interval.as_millis().min(u32::MAX as u128) as u32)
+                .unwrap_or(0);
+            ProtocolHost::write(&writer, &VersionMessage {
                 major: 0,
                 minor: 1,
                 build: 0,
                 revision: 0,
                 protocol: ProtocolHost::PROTOCOL_VERSION,
+                heartbeat_interval: proposed_interval,
+                features: FEATURE_STREAMS | FEATURE_CANCELLATION | FEATURE_SIGNATURES,
             })?;
-            host.client_version = host.read_full()?;
-            if host.client_version.protocol != ProtocolHost::PROTOCOL_VERSION {
-                host.write(Quit::Terminate)?;
-                // ToDo: implement proper termination protocol, awaiting termination of the process attached by inducing the quit message from the caller
-                return Err("Client-Protocol version mismatch".into());
+            let client_version: VersionMessage = ProtocolHost::read_full(&mut reader)?;
+            let encoding = match ValueEncoding::for_protocol(client_version.protocol) {
+                Some(encoding) if client_version.protocol <= ProtocolHost::PROTOCOL_VERSION => encoding,
+                _ => {
+                    ProtocolHost::write(&writer, &Quit::Terminate)?;
+                    // ToDo: implement proper termination protocol, awaiting termination of the process attached by inducing the quit message from the caller
+                    return Err("Client-Protocol version mismatch".into());
+                }
+            };
+            debug!("Client speaks protocol v{}", client_version.protocol);
+            let heartbeat_interval = match (proposed_interval, client_version.heartbeat_interval) {
+                (0, _) | (_, 0) => None,
+                (proposed, answered) => Some(Duration::from_millis(proposed.max(answered) as u64)),
+            };
+
+            ProtocolHost::write(&writer, &CapabilitiesRequestMessage {})?;
+            let capabilities: CapabilitiesResponseMessage = ProtocolHost::read_full(&mut reader)?;
+            let mut functions = Vec::with_capacity(capabilities.functions_count as usize);
+            for function_requested in 0..capabilities.functions_count {
+                ProtocolHost::write(&writer, &FunctionCapabilitiesRequestMessage { function_requested })?;
+                let function: FunctionCapabilitiesResponseMessage = ProtocolHost::read_full(&mut reader)?;
+                debug!("Client provides function {} as {}", function.function_name, function.function_index);
+                functions.push(function);
             }
+
+            let state = Arc::new(Mutex::new(HostState {
+                calls: HashMap::new(),
+                result_requests: VecDeque::new(),
+                next_call_request_id: 0,
+                connected: true,
+                last_received: Instant::now(),
+                unresponsive: false,
+                drain: None,
+                features: client_version.features & (FEATURE_STREAMS | FEATURE_CANCELLATION | FEATURE_SIGNATURES),
+                encoding,
+                next_stream_id: 0,
+                streams: HashMap::new(),
+                max_stream_length: options.max_stream_length,
+                partials: HashMap::new(),
+            }));
+            let (sender, events) = channel();
+            let heartbeat_stop = heartbeat_interval.map(|interval| {
+                let (stop, stopped) = channel();
+                let writer = writer.clone();
+                let state = state.clone();
+                let sender = sender.clone();
+                let missed_heartbeats = options.missed_heartbeats.max(1);
+                debug!("Sending heartbeats every {:?}", interval);
+                std::thread::spawn(move || ProtocolHost::heartbeat_loop(interval, missed_heartbeats, stopped, writer, state, sender));
+                stop
+            });
+            let reader_thread = {
+                let writer = writer.clone();
+                let state = state.clone();
+                std::thread::spawn(move || ProtocolHost::message_loop(reader, writer, state, sender))
+            };
+            Ok(ProtocolHost {
+                writer,
+                state,
+                events,
+                reader_thread: Some(reader_thread),
+                client_version,
+                functions,
+                transport: None,
+                heartbeat_interval,
+                encoding,
+                heartbeat_stop,
+            })
+        }
+
+        /// Connects via the transport provided, which is kept open until the host is dropped
+        /// or [ProtocolHost::disconnect] is called.
+        pub fn connect_transport(transport: Box<dyn Transport>) -> Result<ProtocolHost, Error> {
+            ProtocolHost::connect_transport_with(transport, &HostOptions::default())
+        }
+
+        /// Connects via the transport provided using the options provided.
+        pub fn connect_transport_with(mut transport: Box<dyn Transport>, options: &HostOptions) -> Result<ProtocolHost, Error> {
+            let (reader, writer) = transport.split()?;
+            let mut host = ProtocolHost::connect_with(writer, reader, options)?;
+            debug!("Connected to {}", transport.peer());
+            host.transport = Some(transport);
             Ok(host)
         }
-        // ToDo: Write async-based threading model in here to "host" the actual protocol read/writes and decide whether to notify (event-based) or get polled from the outside.
+
+        /// Closes the transport the host was connected with, ending the message loop.
+        /// Hosts connected via stdio are considered disconnected right away, the message loop
+        /// ending once their process exits.
+        pub fn disconnect(&self) -> Result<(), Error> {
+            if let Ok(mut state) = self.state.lock() {
+                state.connected = false;
+            }
+            if let Some(transport) = self.transport.as_ref() {
+                transport.shutdown()?;
+            }
+            Ok(())
+        }
+
+        fn message_loop(
+            mut reader: Box<dyn Read + Send>,
+            writer: Arc<Mutex<Box<dyn Write + Send>>>,
+            state: Arc<Mutex<HostState>>,
+            sender: Sender<HostEvent>) {
+            let error = loop {
+                let message = match ProtocolMessage::read(&mut reader) {
+                    Ok(message) => message,
+                    Err(error) if error.kind() == ErrorKind::UnexpectedEof => break None,
+                    Err(error) => break Some(error.into()),
+                };
+                trace!("Received {:?}", message.kind());
+                if let Ok(mut state) = state.lock() {
+                    state.last_received = Instant::now();
+                }
+                if let Err(error) = ProtocolHost::handle_message(message, &writer, &state, &sender) {
+                    warn!("Terminating connection due to {}", error);
+                    let _ = ProtocolHost::write(&writer, &Quit::Terminate);
+                    break Some(error);
+                }
+            };
+            let unresponsive = match state.lock() {
+                Ok(mut state) => {
+                    state.connected = false;
+                    state.unresponsive
+                }
+                Err(_) => false,
+            };
+            // The heartbeat reported the disconnect already
+            if !unresponsive {
+                let _ = sender.send(HostEvent::Disconnected(error));
+            }
+        }
+
+        /// Pings the client every interval, disconnecting it once it stayed silent for
+        /// `missed_heartbeats` intervals. Ends once `stopped` is dropped or the client disconnected.
+        fn heartbeat_loop(
+            interval: Duration,
+            missed_heartbeats: u32,
+            stopped: Receiver<()>,
+            writer: Arc<Mutex<Box<dyn Write + Send>>>,
+            state: Arc<Mutex<HostState>>,
+            sender: Sender<HostEvent>) {
+            let mut sequence: u32 = 0;
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                {
+                    let Ok(mut state) = state.lock() else {
+                        return;
+                    };
+                    if !state.connected {
+                        return;
+                    }
+                    let silence = state.last_received.elapsed();
+                    if silence > interval * missed_heartbeats {
+                        warn!("Client did not respond for {:?}, considering it dead", silence);
+                        state.connected = false;
+                        state.unresponsive = true;
+                        let _ = sender.send(HostEvent::Disconnected(Some(Error::Timeout("Client stopped responding to heartbeats"))));
+                        return;
+                    }
+                }
+                if ProtocolHost::write(&writer, &PingMessage { sequence }).is_err() {
+                    return;
+                }
+                sequence = sequence.wrapping_add(1);
+            }
+        }
+
+        fn handle_message(
+            message: ProtocolMessage,
+            writer: &Mutex<Box<dyn Write + Send>>,
+            state: &Mutex<HostState>,
+            sender: &Sender<HostEvent>) -> Result<(), Error> {
+            match message {
+                ProtocolMessage::ArgumentRequest(request) => {
+                    let (payload, stream_id) = {
+                        let mut state = state.lock().map_err(|_| "State lock poisoned")?;
+                        let payload = match state.calls.get(&request.call_request_id) {
+                            Some(CallState::Running { arguments }) | Some(CallState::Cancelling { arguments, .. }) => arguments
+                                .get(request.argument_index as usize)
+                                .cloned()
+                                .ok_or("Argument requested is out of range")?,
+                            _ => return Err("Argument requested for a call not running".into()),
+                        };
+                        let stream_id = if state.features & FEATURE_STREAMS != 0 && payload.len() > STREAM_CHUNK_SIZE {
+                            let stream_id = state.next_stream_id;
+                            state.next_stream_id = state.next_stream_id.wrapping_add(1);
+                            Some(stream_id)
+                        } else {
+                            None
+                        };
+                        (payload, stream_id)
+                    };
+                    match stream_id {
+                        Some(stream_id) => write_stream(writer, StreamBeginMessage {
+                            stream_id,
+                            call_request_id: request.call_request_id,
+                            purpose: StreamPurpose::Argument,
+                        }, &payload)?,
+                        None => ProtocolHost::write(writer, &ArgumentResponseMessage { payload })?,
+                    }
+                }
+                ProtocolMessage::CallCompleted(completed) => {
+                    if !completed.success && completed.results_count != 1 {
+                        return Err("Failed calls must report exactly one result".into());
+                    }
+                    {
+                        let mut state = state.lock().map_err(|_| "State lock poisoned")?;
+                        let call = state.calls.get_mut(&completed.call_request_id)
+                            .ok_or("Completion received for an unknown call")?;
+                        if matches!(call, CallState::Cancelling { .. }) {
+                            // Completed before the cancellation arrived, the results are not needed anymore
+                            state.calls.remove(&completed.call_request_id);
+                            drop(state);
+                            ProtocolHost::write(writer, &CloseCallMessage { call_request_id: completed.call_request_id })?;
+                            let _ = sender.send(HostEvent::CallCancelled { call_request_id: completed.call_request_id, status: CancelStatus::Completed });
+                            return Ok(());
+                        }
+                        if !matches!(call, CallState::Running { .. }) {
+                            return Err("Completion received for a call not running".into());
+                        }
+                        *call = CallState::Collecting {
+                            success: completed.success,
+                            expected: completed.results_count,
+                            results: Vec::with_capacity(completed.results_count as usize),
+                        };
+                        for _ in 0..completed.results_count {
+                            state.result_requests.push_back(completed.call_request_id);
+                        }
+                    }
+                    for result_index in 0..completed.results_count {
+                        ProtocolHost::write(writer, &ResultRequestMessage { call_request_id: completed.call_request_id, result_index })?;
+                    }
+                    if completed.results_count == 0 {
+                        ProtocolHost::finish_call(completed.call_request_id, writer, state, sender)?;
+                    }
+                }
+                ProtocolMessage::ResultResponse(response) => {
+                    let (call_request_id, encoding) = {
+                        let mut state = state.lock().map_err(|_| "State lock poisoned")?;
+                        (state.result_requests.pop_front().ok_or("Result received without being requested")?, state.encoding)
+                    };
+                    let value = encoding.decode(&response.payload)?;
+                    ProtocolHost::receive_result(call_request_id, value, writer, state, sender)?;
+                }
+                ProtocolMessage::StreamBegin(begin) => {
+                    let mut state = state.lock().map_err(|_| "State lock poisoned")?;
+                    if state.features & FEATURE_STREAMS == 0 {
+                        return Err("Stream received without the feature being negotiated".into());
+                    }
+                    if state.streams.len() >= MAX_OPEN_STREAMS || state.streams.contains_key(&begin.stream_id) {
+                        return Err("Stream received exceeds the open stream limit or is open already".into());
+                    }
+                    match begin.purpose {
+                        StreamPurpose::Argument => return Err("Argument stream received by the server".into()),
+                        // Answers the result request sent last, like a result-response message
+                        StreamPurpose::Result => {
+                            let call_request_id = state.result_requests.pop_front()
+                                .ok_or("Result stream received without being requested")?;
+                            if call_request_id != begin.call_request_id {
+                                return Err("Result stream received for a different call than requested".into());
+                            }
+                        }
+                        StreamPurpose::Partial => {
+                            if !matches!(state.calls.get(&begin.call_request_id), Some(CallState::Running { .. }) | Some(CallState::Cancelling { .. })) {
+                                return Err("Partial result received for a call not running".into());
+                            }
+                        }
+                    }
+                    let stream = IncomingStream::begin(&begin, state.encoding, state.max_stream_length);
+                    state.streams.insert(begin.stream_id, stream);
+                }
+                ProtocolMessage::StreamChunk(chunk) => {
+                    let mut state = state.lock().map_err(|_| "State lock poisoned")?;
+                    state.streams.get_mut(&chunk.stream_id)
+                        .ok_or("Chunk received for an unknown stream")?
+                        .push(chunk)?;
+                }
+                ProtocolMessage::StreamEnd(end) => {
+                    let stream = {
+                        let mut state = state.lock().map_err(|_| "State lock poisoned")?;
+                        state.streams.remove(&end.stream_id).ok_or("End received for an unknown stream")?
+                    };
+                    let (call_request_id, purpose) = (stream.call_request_id, stream.purpose);
+                    let value = stream.end(&end)?;
+                    match purpose {
+                        StreamPurpose::Result => ProtocolHost::receive_result(call_request_id, value, writer, state, sender)?,
+                        _ => {
+                            {
+                                let mut state = state.lock().map_err(|_| "State lock poisoned")?;
+                                // Partials of calls completed meanwhile are of no use anymore
+                                if !matches!(state.calls.get(&call_request_id), Some(CallState::Running { .. })) {
+                                    return Ok(());
+                                }
+                                state.partials.entry(call_request_id).or_default().push_back(value);
+                            }
+                            let _ = sender.send(HostEvent::PartialResult { call_request_id });
+                        }
+                    }
+                }
+                ProtocolMessage::Quit(quit) => {
+                    {
+                        let mut state = state.lock().map_err(|_| "State lock poisoned")?;
+                        if let Some((started, deadline)) = state.drain.as_mut() {
+                            *deadline = (*deadline + Duration::from_secs(quit.seconds() as u64))
+                                .min(*started + ProtocolHost::MAX_QUIT_TIME);
+                        }
+                    }
+                    let _ = sender.send(HostEvent::QuitExtensionRequested { seconds: quit.seconds() });
+                }
+                ProtocolMessage::CancelAck(ack) => {
+                    let cancelled = {
+                        let mut state = state.lock().map_err(|_| "State lock poisoned")?;
+                        match state.calls.get(&ack.call_request_id) {
+                            Some(CallState::Cancelling { .. }) => state.calls.remove(&ack.call_request_id).is_some(),
+                            // The completion arrived first, the call was closed already
+                            _ => false,
+                        }
+                    };
+                    if cancelled {
+                        let _ = sender.send(HostEvent::CallCancelled { call_request_id: ack.call_request_id, status: ack.status });
+                    }
+                }
+                ProtocolMessage::Ping(ping) => {
+                    ProtocolHost::write(writer, &PongMessage { sequence: ping.sequence })?;
+                }
+                // Receiving the pong already counts as sign of life
+                ProtocolMessage::Pong(_) => {}
+                _ => return Err("Message received is not applicable to the server".into()),
+            }
+            Ok(())
+        }
+
+        fn receive_result(
+            call_request_id: u32,
+            value: VmValue,
+            writer: &Mutex<Box<dyn Write + Send>>,
+            state: &Mutex<HostState>,
+            sender: &Sender<HostEvent>) -> Result<(), Error> {
+            let is_complete = {
+                let mut state = state.lock().map_err(|_| "State lock poisoned")?;
+                match state.calls.get_mut(&call_request_id) {
+                    Some(CallState::Collecting { expected, results, .. }) => {
+                        results.push(value);
+                        results.len() == *expected as usize
+                    }
+                    _ => return Err("Result received for a call not completed".into()),
+                }
+            };
+            if is_complete {
+                ProtocolHost::finish_call(call_request_id, writer, state, sender)?;
+            }
+            Ok(())
+        }
+
+        fn finish_call(
+            call_request_id: u32,
+            writer: &Mutex<Box<dyn Write + Send>>,
+            state: &Mutex<HostState>,
+            sender: &Sender<HostEvent>) -> Result<(), Error> {
+            let success = {
+                let mut state = state.lock().map_err(|_| "State lock poisoned")?;
+                let call = state.calls.get_mut(&call_request_id).ok_or("Call finished is unknown")?;
+                let CallState::Collecting { success, results, .. } = call else {
+                    return Err("Call finished is not collecting results".into());
+                };
+                let outcome = CallOutcome { success: *success, results: std::mem::take(results) };
+                let success = outcome.success;
+                *call = CallState::Completed(outcome);
+                success
+            };
+            ProtocolHost::write(writer, &CloseCallMessage { call_request_id })?;
+            let _ = sender.send(HostEvent::CallCompleted { call_request_id, success });
+            Ok(())
+        }
+
+        pub fn client_version(&self) -> &VersionMessage {
+            &self.client_version
+        }
+
+        /// The functions reported by the client during the handshake.
+        pub fn functions(&self) -> &[FunctionCapabilitiesResponseMessage] {
+            &self.functions
+        }
+
+        pub fn function_index(&self, name: &str) -> Option<u32> {
+            self.functions.iter()
+                .find(|function| function.function_name == name)
+                .map(|function| function.function_index)
+        }
+
+        pub fn is_connected(&self) -> bool {
+            self.state.lock().map(|state| state.connected).unwrap_or(false)
+        }
+
+        /// Whether the client was disconnected as it stopped answering heartbeats.
+        pub fn is_unresponsive(&self) -> bool {
+            self.state.lock().map(|state| state.unresponsive).unwrap_or(false)
+        }
+
+        /// How values are transferred, depending on the protocol the client speaks.
+        pub fn encoding(&self) -> ValueEncoding {
+            self.encoding
+        }
+
+        /// The heartbeat interval agreed on with the client, None if heartbeats are disabled.
+        pub fn heartbeat_interval(&self) -> Option<Duration> {
+            self.heartbeat_interval
+        }
+
+        /// Starts a call of the function provided without waiting for it to complete,
+        /// returning the call-request-id identifying the call.
+        pub fn call(&self, function_index: u32, arguments: &[VmValue]) -> Result<u32, Error> {
+            let function = self.functions.iter()
+                .find(|function| function.function_index == function_index)
+                .ok_or("Function called is not provided by the client")?;
+            if arguments.len() < function.arguments_required as usize || arguments.len() > function.arguments_count as usize {
+                return Err("Argument count does not match the function called".into());
+            }
+            let arguments = arguments.iter()
+                .map(|argument| self.encoding.encode(argument))
+                .collect::<Result<Vec<Vec<u8>>, Error>>()?;
+            let arguments_count = arguments.len() as u8;
+            let call_request_id = {
+                let mut state = self.state.lock().map_err(|_| "State lock poisoned")?;
+                if !state.connected {
+                    return Err("Client is disconnected".into());
+                }
+                if state.drain.is_some() {
+                    return Err("Client is shutting down".into());
+                }
+                let call_request_id = state.next_call_request_id;
+                state.next_call_request_id = state.next_call_request_id.wrapping_add(1);
+                state.calls.insert(call_request_id, CallState::Running { arguments });
+                call_request_id
+            };
+            ProtocolHost::write(&self.writer, &CallMessage { function_index, arguments_count, call_request_id })?;
+            Ok(call_request_id)
+        }
+
+        /// Asks the client to stop a running call, returning whether it was asked. Calls not
+        /// running anymore, or of clients not supporting cancellation, cannot be cancelled.
+        /// The call is forgotten once the client acknowledged, reported as [HostEvent::CallCancelled].
+        pub fn cancel(&self, call_request_id: u32) -> Result<bool, Error> {
+            {
+                let mut state = self.state.lock().map_err(|_| "State lock poisoned")?;
+                if state.features & FEATURE_CANCELLATION == 0 || !state.connected {
+                    return Ok(false);
+                }
+                let Some(call) = state.calls.get_mut(&call_request_id) else {
+                    return Ok(false);
+                };
+                let CallState::Running { arguments } = call else {
+                    return Ok(false);
+                };
+                *call = CallState::Cancelling { arguments: std::mem::take(arguments), requested: Instant::now() };
+                state.partials.remove(&call_request_id);
+            }
+            ProtocolHost::write(&self.writer, &CancelCallMessage { call_request_id })?;
+            Ok(true)
+        }
+
+        /// Whether the call is known, ie. neither its outcome was taken nor its cancellation acknowledged.
+        pub fn has_call(&self, call_request_id: u32) -> bool {
+            self.state.lock().map(|state| state.calls.contains_key(&call_request_id)).unwrap_or(false)
+        }
+
+        /// Whether the call was asked to be cancelled longer than the timeout provided ago
+        /// without the client acknowledging.
+        pub fn is_cancel_overdue(&self, call_request_id: u32, timeout: Duration) -> bool {
+            self.state.lock()
+                .map(|state| matches!(state.calls.get(&call_request_id),
+                    Some(CallState::Cancelling { requested, .. }) if requested.elapsed() > timeout))
+                .unwrap_or(false)
+        }
+
+        pub fn is_completed(&self, call_request_id: u32) -> bool {
+            self.state.lock()
+                .map(|state| matches!(state.calls.get(&call_request_id), Some(CallState::Completed(_))))
+                .unwrap_or(false)
+        }
+
+        /// Whether a partial result of the call is waiting to be taken.
+        pub fn has_partial(&self, call_request_id: u32) -> bool {
+            self.state.lock()
+                .map(|state| state.partials.get(&call_request_id).is_some_and(|partials| !partials.is_empty()))
+                .unwrap_or(false)
+        }
+
+        /// Removes and returns the oldest partial result of a call not yet taken.
+        pub fn take_partial(&self, call_request_id: u32) -> Option<VmValue> {
+            let mut state = self.state.lock().ok()?;
+            let partials = state.partials.get_mut(&call_request_id)?;
+            let value = partials.pop_front();
+            if partials.is_empty() {
+                state.partials.remove(&call_request_id);
+            }
+            value
+        }
+
+        /// Whether the client supports streams, sending large payloads in chunks and
+        /// reporting partial results.
+        pub fn supports_streams(&self) -> bool {
+            self.state.lock().map(|state| state.features & FEATURE_STREAMS != 0).unwrap_or(false)
+        }
+
+        /// Whether the client supports cancelling calls via [ProtocolHost::cancel].
+        pub fn supports_cancellation(&self) -> bool {
+            self.state.lock().map(|state| state.features & FEATURE_CANCELLATION != 0).unwrap_or(false)
+        }
+
+        /// Removes and returns the outcome of a completed call. Returns None if the call is
+        /// unknown or still running. Partial results not taken are discarded.
+        pub fn take_outcome(&self, call_request_id: u32) -> Option<CallOutcome> {
+            let mut state = self.state.lock().ok()?;
+            match state.calls.get(&call_request_id) {
+                Some(CallState::Completed(_)) => match state.calls.remove(&call_request_id) {
+                    Some(CallState::Completed(outcome)) => {
+                        state.partials.remove(&call_request_id);
+                        Some(outcome)
+                    }
+                    _ => None,
+                },
+                _ => None,
+            }
+        }
+
+        /// Returns the next event without blocking.
+        pub fn try_next_event(&self) -> Option<HostEvent> {
+            self.events.try_recv().ok()
+        }
+
+        /// Returns the next event, waiting at most for the timeout provided.
+        pub fn next_event_timeout(&self, timeout: Duration) -> Option<HostEvent> {
+            match self.events.recv_timeout(timeout) {
+                Ok(event) => Some(event),
+                Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => None,
+            }
+        }
+
+        /// Asks the client to terminate, granting it one second.
+        pub fn quit(&self) -> Result<(), Error> {
+            self.drain(0)
+        }
+
+        /// Asks the client to quit within the seconds provided, at least one. The client
+        /// refuses new calls but finishes the calls running, closing the connection once all
+        /// calls were closed. It may ask for up to 60 seconds in total.
+        pub fn drain(&self, grace_seconds: u8) -> Result<(), Error> {
+            let quit = Quit::from_seconds(grace_seconds.min(59)).ok_or("Grace period is out of range")?;
+            {
+                let mut state = self.state.lock().map_err(|_| "State lock poisoned")?;
+                let started = Instant::now();
+                let deadline = started + Duration::from_secs(quit.seconds().max(1) as u64);
+                state.drain.get_or_insert((started, deadline));
+            }
+            ProtocolHost::write(&self.writer, &quit)?;
+            Ok(())
+        }
+
+        /// The time the client has to quit by, if it was asked to.
+        pub fn drain_deadline(&self) -> Option<Instant> {
+            self.state.lock().ok()?.drain.map(|(_, deadline)| deadline)
+        }
+
+        /// Waits for the message loop to end, which happens once the client closed the connection.
+        pub fn join(&mut self) {
+            if let Some(reader_thread) = self.reader_thread.take() {
+                let _ = reader_thread.join();
+            }
+        }
+    }
+
+    impl Drop for ProtocolHost {
+        fn drop(&mut self) {
+            self.heartbeat_stop.take();
+            let _ = self.disconnect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, PipeReader, PipeWriter, Write};
+    use std::sync::Mutex;
+    use std::thread::JoinHandle;
+    use std::time::Duration;
+    use proptest::prelude::*;
+    use tracing_test::traced_test;
+    use crate::io::protocol_v1::protocol_v1::data::*;
+    use crate::io::protocol_v1::protocol_v1::{write_stream, CallOutcome, Error, HostEvent, HostOptions, IncomingStream, ProtocolHost, ProtocolMessage, MAX_STREAM_LENGTH, STREAM_CHUNK_SIZE};
+    use crate::io::protocol_v2::ValueEncoding;
+    use crate::machine::VmValue;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// The client thread, returning the call-request-ids closed by the host.
+    type EchoClient = JoinHandle<Vec<u32>>;
+
+    fn echo_function() -> FunctionCapabilitiesResponseMessage {
+        FunctionCapabilitiesResponseMessage {
+            function_index: 7,
+            arguments_required: 1,
+            arguments_count: 1,
+            results_count: 1,
+            function_name: "echo".to_string(),
+            signature: None,
+        }
+    }
+
+    /// Answers the handshake of the host as client providing the echo function.
+    fn client_handshake(reader: &mut PipeReader, writer: &mut PipeWriter, heartbeat_interval: u32) -> Result<(), Box<dyn std::error::Error>> {
+        assert!(matches!(ProtocolMessage::read(reader)?, ProtocolMessage::Version(_)));
+        ProtocolMessage::Version(VersionMessage { major: 1, minor: 0, build: 0, revision: 0, protocol: 1, heartbeat_interval, features: FEATURE_STREAMS }).write(writer)?;
+        assert!(matches!(ProtocolMessage::read(reader)?, ProtocolMessage::CapabilitiesRequest(_)));
+        ProtocolMessage::CapabilitiesResponse(CapabilitiesResponseMessage { functions_count: 1 }).write(writer)?;
+        assert_eq!(ProtocolMessage::read(reader)?, ProtocolMessage::FunctionCapabilitiesRequest(FunctionCapabilitiesRequestMessage { function_requested: 0 }));
+        ProtocolMessage::FunctionCapabilitiesResponse(echo_function()).write(writer)?;
+        Ok(())
+    }
+
+    /// Spawns a client accepting `calls` calls of the echo function before completing them
+    /// in reverse order, closing the connection once all calls were closed by the host.
+    fn spawn_echo_client(calls: usize) -> Result<(ProtocolHost, EchoClient), Box<dyn std::error::Error>> {
+        let (mut client_reader, host_writer) = std::io::pipe()?;
+        let (host_reader, mut client_writer) = std::io::pipe()?;
+        let client = std::thread::spawn(move || {
+            client_handshake(&mut client_reader, &mut client_writer, 0).unwrap();
+            let mut pending_arguments = vec!();
+            let mut arguments = vec!();
+            let mut closed = vec!();
+            while closed.len() < calls {
+                match ProtocolMessage::read(&mut client_reader).unwrap() {
+                    ProtocolMessage::Call(call) => {
+                        assert_eq!(call.function_index, 7);
+                        pending_arguments.push(call.call_request_id);
+                        ProtocolMessage::ArgumentRequest(ArgumentRequestMessage { call_request_id: call.call_request_id, argument_index: 0 })
+                            .write(&mut client_writer).unwrap();
+                    }
+                    ProtocolMessage::ArgumentResponse(response) => {
+                        arguments.push((pending_arguments.remove(0), response.payload));
+                        if arguments.len() == calls {
+                            for (call_request_id, _) in arguments.iter().rev() {
+                                ProtocolMessage::CallCompleted(CallCompletedMessage { call_request_id: *call_request_id, success: true, results_count: 1 })
+                                    .write(&mut client_writer).unwrap();
+                            }
+                        }
+                    }
+                    ProtocolMessage::ResultRequest(request) => {
+                        let (_, payload) = arguments.iter().find(|(id, _)| *id == request.call_request_id).unwrap();
+                        ProtocolMessage::ResultResponse(ResultResponseMessage { payload: payload.clone() })
+                            .write(&mut client_writer).unwrap();
+                    }
+                    ProtocolMessage::CloseCall(close) => closed.push(close.call_request_id),
+                    message => panic!("Unexpected message {:?}", message),
+                }
+            }
+            closed
+        });
+        let host = ProtocolHost::connect(Box::new(host_writer), Box::new(host_reader))?;
+        Ok((host, client))
+    }
+
+    #[test]
+    #[traced_test]
+    fn message_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+        let messages = vec!(
+            ProtocolMessage::Quit(Quit::Additional5s),
+            ProtocolMessage::FunctionCapabilitiesResponse(echo_function()),
+            ProtocolMessage::ResultResponse(ResultResponseMessage { payload: r#"{"text":"äöü"}"#.as_bytes().to_vec() }),
+            ProtocolMessage::CloseCall(CloseCallMessage { call_request_id: 42 }),
+        );
+        let mut buffer = vec!();
+        for message in &messages {
+            message.write(&mut buffer)?;
+        }
+        let mut reader = buffer.as_slice();
+        for message in messages {
+            assert_eq!(ProtocolMessage::read(&mut reader)?, message);
+        }
+        assert!(reader.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn handshake_queries_functions() -> Result<(), Box<dyn std::error::Error>> {
+        let (mut host, client) = spawn_echo_client(0)?;
+        assert_eq!(host.client_version().major, 1);
+        assert_eq!(host.functions(), &[echo_function()]);
+        assert_eq!(host.function_index("echo"), Some(7));
+        assert_eq!(host.function_index("missing"), None);
+        assert_eq!(host.encoding(), ValueEncoding::Json);
+        client.join().unwrap();
+        host.join();
+        assert!(matches!(host.next_event_timeout(TIMEOUT), Some(HostEvent::Disconnected(None))));
+        assert!(!host.is_connected());
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn concurrent_calls_are_dispatched_by_id() -> Result<(), Box<dyn std::error::Error>> {
+        let (mut host, client) = spawn_echo_client(3)?;
+        let mut call_request_ids = vec!();
+        for index in 0..3 {
+            call_request_ids.push(host.call(7, &[VmValue::Integer(index)])?);
+        }
+        assert!(host.call(7, &[]).is_err());
+        assert!(host.call(8, &[VmValue::Null]).is_err());
+
+        let mut completed = vec!();
+        while completed.len() < 3 {
+            match host.next_event_timeout(TIMEOUT) {
+                Some(HostEvent::CallCompleted { call_request_id, success }) => {
+                    assert!(success);
+                    completed.push(call_request_id);
+                }
+                event => panic!("Unexpected event {:?}", event),
+            }
+        }
+        // The client completes the calls in reverse order
+        call_request_ids.reverse();
+        assert_eq!(completed, call_request_ids);
+        for (index, call_request_id) in call_request_ids.iter().rev().enumerate() {
+            assert!(host.is_completed(*call_request_id));
+            assert_eq!(host.take_outcome(*call_request_id), Some(CallOutcome { success: true, results: vec!(VmValue::Integer(index as i64)) }));
+            assert_eq!(host.take_outcome(*call_request_id), None);
+        }
+        let mut closed = client.join().unwrap();
+        closed.sort();
+        call_request_ids.sort();
+        assert_eq!(closed, call_request_ids);
+        host.join();
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn unexpected_message_disconnects() -> Result<(), Box<dyn std::error::Error>> {
+        let (mut client_reader, host_writer) = std::io::pipe()?;
+        let (host_reader, mut client_writer) = std::io::pipe()?;
+        let client = std::thread::spawn(move || {
+            client_handshake(&mut client_reader, &mut client_writer, 0).unwrap();
+            ProtocolMessage::ArgumentRequest(ArgumentRequestMessage { call_request_id: 1, argument_index: 0 })
+                .write(&mut client_writer).unwrap();
+            ProtocolMessage::read(&mut client_reader).unwrap()
+        });
+        let host = ProtocolHost::connect(Box::new(host_writer), Box::new(host_reader))?;
+        assert_eq!(client.join().unwrap(), ProtocolMessage::Quit(Quit::Terminate));
+        assert!(matches!(host.next_event_timeout(TIMEOUT), Some(HostEvent::Disconnected(Some(_)))));
+        assert!(host.call(7, &[VmValue::Null]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn unknown_protocols_are_refused() -> Result<(), Box<dyn std::error::Error>> {
+        let (mut client_reader, host_writer) = std::io::pipe()?;
+        let (host_reader, mut client_writer) = std::io::pipe()?;
+        let client = std::thread::spawn(move || {
+            let ProtocolMessage::Version(proposed) = ProtocolMessage::read(&mut client_reader).unwrap() else {
+                panic!("Version expected");
+            };
+            ProtocolMessage::Version(VersionMessage { protocol: 3, ..proposed.clone() }).write(&mut client_writer).unwrap();
+            (proposed.protocol, ProtocolMessage::read(&mut client_reader).unwrap())
+        });
+        assert!(ProtocolHost::connect(Box::new(host_writer), Box::new(host_reader)).is_err());
+        assert_eq!(client.join().unwrap(), (2, ProtocolMessage::Quit(Quit::Terminate)));
+        Ok(())
+    }
+
+    fn arb_quit() -> impl Strategy<Value = Quit> {
+        (0u8..60).prop_map(|value| {
+            let mut quit = Quit::new();
+            quit.deserialize(&mut [value].as_slice()).unwrap();
+            quit
+        })
+    }
+
+    fn arb_signature() -> impl Strategy<Value = SignatureDescription> {
+        let parameter = (".{0,16}", ".{0,32}", ".{0,32}").prop_map(|(name, schema, description)| ParameterDescription { name, schema, description });
+        (".{0,64}", any::<bool>(), any::<u32>(), prop::collection::vec(parameter.clone(), 0..4), prop::collection::vec(parameter, 0..4))
+            .prop_map(|(description, idempotent, timeout_hint, parameters, results)| SignatureDescription { description, idempotent, timeout_hint, parameters, results })
+    }
+
+    fn arb_message() -> impl Strategy<Value = ProtocolMessage> {
+        prop_oneof![
+            any::<[u32; 7]>().prop_map(|[major, minor, build, revision, protocol, heartbeat_interval, features]|
+                ProtocolMessage::Version(VersionMessage { major, minor, build, revision, protocol, heartbeat_interval, features })),
+            arb_quit().prop_map(ProtocolMessage::Quit),
+            Just(ProtocolMessage::CapabilitiesRequest(CapabilitiesRequestMessage {})),
+            any::<u32>().prop_map(|functions_count| ProtocolMessage::CapabilitiesResponse(CapabilitiesResponseMessage { functions_count })),
+            any::<u32>().prop_map(|function_requested| ProtocolMessage::FunctionCapabilitiesRequest(FunctionCapabilitiesRequestMessage { function_requested })),
+            (any::<u32>(), any::<u8>(), any::<u8>(), any::<u8>(), ".{0,64}").prop_map(|(function_index, arguments_required, arguments_count, results_count, function_name)|
+                ProtocolMessage::FunctionCapabilitiesResponse(FunctionCapabilitiesResponseMessage { function_index, arguments_required, arguments_count, results_count, function_name, signature: None })),
+            (any::<u32>(), any::<u8>(), ".{0,64}", arb_signature()).prop_map(|(function_index, arguments_required, function_name, signature)|
+                ProtocolMessage::FunctionCapabilitiesResponse(FunctionCapabilitiesResponseMessage {
+                    function_index,
+                    arguments_required,
+                    arguments_count: signature.parameters.len() as u8,
+                    results_count: signature.results.len() as u8,
+                    function_name,
+                    signature: Some(signature),
+                })),
+            (any::<u32>(), any::<u8>(), any::<u32>()).prop_map(|(function_index, arguments_count, call_request_id)|
+                ProtocolMessage::Call(CallMessage { function_index, arguments_count, call_request_id })),
+            (any::<u32>(), any::<u8>()).prop_map(|(call_request_id, argument_index)|
+                ProtocolMessage::ArgumentRequest(ArgumentRequestMessage { call_request_id, argument_index })),
+            prop::collection::vec(any::<u8>(), 0..64).prop_map(|payload| ProtocolMessage::ArgumentResponse(ArgumentResponseMessage { payload })),
+            (any::<u32>(), any::<bool>(), any::<u8>()).prop_map(|(call_request_id, success, results_count)|
+                ProtocolMessage::CallCompleted(CallCompletedMessage { call_request_id, success, results_count })),
+            (any::<u32>(), any::<u8>()).prop_map(|(call_request_id, result_index)|
+                ProtocolMessage::ResultRequest(ResultRequestMessage { call_request_id, result_index })),
+            prop::collection::vec(any::<u8>(), 0..64).prop_map(|payload| ProtocolMessage::ResultResponse(ResultResponseMessage { payload })),
+            any::<u32>().prop_map(|call_request_id| ProtocolMessage::CloseCall(CloseCallMessage { call_request_id })),
+            any::<u32>().prop_map(|sequence| ProtocolMessage::Ping(PingMessage { sequence })),
+            any::<u32>().prop_map(|sequence| ProtocolMessage::Pong(PongMessage { sequence })),
+            (any::<u32>(), any::<u32>(), prop_oneof![Just(StreamPurpose::Argument), Just(StreamPurpose::Result), Just(StreamPurpose::Partial)])
+                .prop_map(|(stream_id, call_request_id, purpose)| ProtocolMessage::StreamBegin(StreamBeginMessage { stream_id, call_request_id, purpose })),
+            (any::<u32>(), any::<u32>(), prop::collection::vec(any::<u8>(), 0..256)).prop_map(|(stream_id, sequence, data)|
+                ProtocolMessage::StreamChunk(StreamChunkMessage { stream_id, sequence, data })),
+            (any::<u32>(), any::<u32>()).prop_map(|(stream_id, chunks)| ProtocolMessage::StreamEnd(StreamEndMessage { stream_id, chunks })),
+            any::<u32>().prop_map(|call_request_id| ProtocolMessage::CancelCall(CancelCallMessage { call_request_id })),
+            (any::<u32>(), prop_oneof![Just(CancelStatus::Cancelled), Just(CancelStatus::Completed), Just(CancelStatus::NotRunning)])
+                .prop_map(|(call_request_id, status)| ProtocolMessage::CancelAck(CancelAckMessage { call_request_id, status })),
+        ]
+    }
+
+    fn frame(message_id: u16, body: &[u8]) -> Vec<u8> {
+        let mut frame = vec!(0, 0);
+        frame.extend_from_slice(&message_id.to_le_bytes());
+        frame.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        frame.extend_from_slice(body);
+        frame
+    }
+
+    proptest! {
+        #[test]
+        fn messages_round_trip(messages in prop::collection::vec(arb_message(), 1..8)) {
+            let mut buffer = vec!();
+            for message in &messages {
+                message.write(&mut buffer).unwrap();
+            }
+            let mut reader = buffer.as_slice();
+            for message in messages {
+                prop_assert_eq!(ProtocolMessage::read(&mut reader).unwrap(), message);
+            }
+            prop_assert!(reader.is_empty());
+        }
+
+        #[test]
+        fn garbage_never_panics(input in any::<Vec<u8>>()) {
+            let mut reader = input.as_slice();
+            while ProtocolMessage::read(&mut reader).is_ok() {}
+        }
+
+        #[test]
+        fn garbage_bodies_never_panic(message_id in 0u16..16, body in any::<Vec<u8>>()) {
+            let _ = ProtocolMessage::read(&mut frame(message_id, &body).as_slice());
+        }
+
+        #[test]
+        fn truncated_frames_error(message in arb_message(), cut in any::<prop::sample::Index>()) {
+            let mut buffer = vec!();
+            message.write(&mut buffer).unwrap();
+            buffer.truncate(cut.index(buffer.len()));
+            prop_assert!(ProtocolMessage::read(&mut buffer.as_slice()).is_err());
+        }
+    }
+
+    #[test]
+    #[traced_test]
+    fn declared_length_must_match_body() -> Result<(), Box<dyn std::error::Error>> {
+        // A close-call message carries exactly four bytes
+        assert_eq!(ProtocolMessage::read(&mut frame(12, &[1, 0, 0, 0]).as_slice())?, ProtocolMessage::CloseCall(CloseCallMessage { call_request_id: 1 }));
+        assert_eq!(ProtocolMessage::read(&mut frame(12, &[1, 0, 0, 0, 0]).as_slice()).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(ProtocolMessage::read(&mut frame(12, &[1, 0, 0]).as_slice()).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+        // The json length inside the body exceeds the frame
+        assert!(ProtocolMessage::read(&mut frame(8, &[255, 255, 255, 255, b'{']).as_slice()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn unknown_kinds_are_skipped() -> Result<(), Box<dyn std::error::Error>> {
+        let mut input = frame(4711, b"from the future");
+        input.extend(frame(12, &[7, 0, 0, 0]));
+        assert_eq!(ProtocolMessage::read(&mut input.as_slice())?, ProtocolMessage::CloseCall(CloseCallMessage { call_request_id: 7 }));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn frames_are_validated() -> Result<(), Box<dyn std::error::Error>> {
+        let mut oversized = frame(8, &[]);
+        oversized[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(ProtocolMessage::read(&mut oversized.as_slice()).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert!(ProtocolMessage::read_limited(&mut frame(12, &[7, 0, 0, 0]).as_slice(), 3).is_err());
+        let mut not_zero = frame(12, &[7, 0, 0, 0]);
+        not_zero[0] = 1;
+        assert!(ProtocolMessage::read(&mut not_zero.as_slice()).is_err());
+        let mut name_too_long = vec!(0; 7);
+        name_too_long.extend_from_slice(&10001u16.to_le_bytes());
+        name_too_long.extend(vec!(b'a'; 10001));
+        assert!(ProtocolMessage::read(&mut frame(5, &name_too_long).as_slice()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn legacy_version_is_accepted() -> Result<(), Box<dyn std::error::Error>> {
+        let mut legacy = frame(0, &[1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0, 1, 0, 0, 0]);
+        assert_eq!(ProtocolMessage::read(&mut legacy.as_slice())?, ProtocolMessage::Version(VersionMessage {
+            major: 1, minor: 2, build: 3, revision: 4, protocol: 1, heartbeat_interval: 0, features: 0,
+        }));
+        legacy.truncate(legacy.len() - 2);
+        legacy[4] = 18;
+        assert!(ProtocolMessage::read(&mut legacy.as_slice()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn streams_are_reassembled() -> Result<(), Box<dyn std::error::Error>> {
+        let value = VmValue::array(vec!(VmValue::string("chunked"); 20000));
+        let payload = ValueEncoding::Binary.encode(&value)?;
+        let (mut reader, writer) = std::io::pipe()?;
+        let writer: Mutex<Box<dyn Write + Send>> = Mutex::new(Box::new(writer));
+        let sent = {
+            let payload = payload.clone();
+            std::thread::spawn(move || write_stream(&writer, StreamBeginMessage { stream_id: 3, call_request_id: 5, purpose: StreamPurpose::Partial }, &payload))
+        };
+        let ProtocolMessage::StreamBegin(begin) = ProtocolMessage::read(&mut reader)? else {
+            return Err("Stream begin expected".into());
+        };
+        let mut stream = IncomingStream::begin(&begin, ValueEncoding::Binary, MAX_STREAM_LENGTH);
+        let end = loop {
+            match ProtocolMessage::read(&mut reader)? {
+                ProtocolMessage::StreamChunk(chunk) => {
+                    assert!(chunk.data.len() <= STREAM_CHUNK_SIZE);
+                    stream.push(chunk)?;
+                }
+                ProtocolMessage::StreamEnd(end) => break end,
+                message => return Err(format!("Unexpected message {:?}", message).into()),
+            }
+        };
+        sent.join().unwrap()?;
+        assert_eq!(end.chunks as usize, payload.len().div_ceil(STREAM_CHUNK_SIZE));
+        assert_eq!((stream.call_request_id, stream.purpose), (5, StreamPurpose::Partial));
+        assert_eq!(stream.end(&end)?, value);
+
+        // Chunks have to arrive in order and completely
+        let begin = StreamBeginMessage { stream_id: 0, call_request_id: 0, purpose: StreamPurpose::Result };
+        let mut stream = IncomingStream::begin(&begin, ValueEncoding::Json, MAX_STREAM_LENGTH);
+        assert!(stream.push(StreamChunkMessage { stream_id: 0, sequence: 1, data: b"1".to_vec() }).is_err());
+        let mut stream = IncomingStream::begin(&begin, ValueEncoding::Json, MAX_STREAM_LENGTH);
+        stream.push(StreamChunkMessage { stream_id: 0, sequence: 0, data: b"[1,".to_vec() })?;
+        assert!(stream.end(&StreamEndMessage { stream_id: 0, chunks: 2 }).is_err());
+        let mut stream = IncomingStream::begin(&begin, ValueEncoding::Json, MAX_STREAM_LENGTH);
+        stream.push(StreamChunkMessage { stream_id: 0, sequence: 0, data: b"[1,".to_vec() })?;
+        assert!(stream.end(&StreamEndMessage { stream_id: 0, chunks: 1 }).is_err());
+
+        // Streams may not exceed their maximum length
+        let mut stream = IncomingStream::begin(&begin, ValueEncoding::Json, 4);
+        stream.push(StreamChunkMessage { stream_id: 0, sequence: 0, data: b"[1,".to_vec() })?;
+        assert!(matches!(stream.push(StreamChunkMessage { stream_id: 0, sequence: 1, data: b"2]".to_vec() }), Err(Error::ProtocolError(_))));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn silent_clients_are_disconnected() -> Result<(), Box<dyn std::error::Error>> {
+        let (mut client_reader, host_writer) = std::io::pipe()?;
+        let (host_reader, mut client_writer) = std::io::pipe()?;
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        let client = std::thread::spawn(move || {
+            client_handshake(&mut client_reader, &mut client_writer, 30).unwrap();
+            ProtocolMessage::Ping(PingMessage { sequence: 9 }).write(&mut client_writer).unwrap();
+            let mut ponged = false;
+            let mut pinged = false;
+            while !ponged || !pinged {
+                match ProtocolMessage::read(&mut client_reader).unwrap() {
+                    ProtocolMessage::Pong(pong) => {
+                        assert_eq!(pong.sequence, 9);
+                        ponged = true;
+                    }
+                    ProtocolMessage::Ping(ping) => {
+                        ProtocolMessage::Pong(PongMessage { sequence: ping.sequence }).write(&mut client_writer).unwrap();
+                        pinged = true;
+                    }
+                    message => panic!("Unexpected message {:?}", message),
+                }
+            }
+            // Hang, keeping the connection open
+            let _ = released.recv();
+        });
+        let options = HostOptions { heartbeat_interval: Some(Duration::from_millis(20)), missed_heartbeats: 2, ..HostOptions::default() };
+        let host = ProtocolHost::connect_with(Box::new(host_writer), Box::new(host_reader), &options)?;
+        assert_eq!(host.heartbeat_interval(), Some(Duration::from_millis(30)));
+        assert!(matches!(host.next_event_timeout(TIMEOUT), Some(HostEvent::Disconnected(Some(Error::Timeout(_))))));
+        assert!(!host.is_connected());
+        assert!(host.is_unresponsive());
+        assert!(host.call(7, &[VmValue::Null]).is_err());
+        drop(release);
+        client.join().unwrap();
+        Ok(())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,318 @@
+//! Protocol v2 shares the frames and messages of [protocol_v1](crate::io::protocol_v1::protocol_v1),
+//! but transfers arguments, results and streams as compact binary values instead of JSON.
+//! The protocol is negotiated via the `protocol` field of the version message, peers
+//! supporting v1 only keep using JSON.
+
+use std::io::{BufReader, ErrorKind, Read, Write};
+use uuid::Uuid;
+use crate::io::protocol_v1::protocol_v1::Error;
+use crate::machine::json::{self, JsonOptions};
+use crate::machine::{VmObject, VmValue};
+
+pub const PROTOCOL_VERSION: u32 = 2;
+/// The maximum nesting of arrays and objects.
+pub const MAX_DEPTH: usize = 128;
+
+const TAG_NULL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_INTEGER: u8 = 3;
+const TAG_NUMBER: u8 = 4;
+const TAG_STRING: u8 = 5;
+const TAG_BYTES: u8 = 6;
+const TAG_ARRAY: u8 = 7;
+const TAG_OBJECT: u8 = 8;
+const TAG_TIMESTAMP: u8 = 9;
+const TAG_JOB: u8 = 10;
+
+/// How values are transferred, depending on the protocol agreed on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueEncoding {
+    /// JSON text as documented in `src/io/ReadMe.md`, used by protocol v1.
+    Json,
+    /// Tagged binary values, used by protocol v2.
+    Binary,
+}
+
+impl ValueEncoding {
+    /// The encoding of the protocol version provided, None if the version is not supported.
+    pub fn for_protocol(protocol: u32) -> Option<ValueEncoding> {
+        match protocol {
+            1 => Some(ValueEncoding::Json),
+            PROTOCOL_VERSION => Some(ValueEncoding::Binary),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self, value: &VmValue) -> Result<Vec<u8>, Error> {
+        match self {
+            ValueEncoding::Json => Ok(json::to_string(value, &JsonOptions::default())?.into_bytes()),
+            ValueEncoding::Binary => Ok(to_vec(value)),
+        }
+    }
+
+    pub fn decode(&self, payload: &[u8]) -> Result<VmValue, Error> {
+        self.decode_reader(payload)
+    }
+
+    /// Reads a single value, which must span the whole input.
+    pub fn decode_reader<R: Read>(&self, reader: R) -> Result<VmValue, Error> {
+        match self {
+            ValueEncoding::Json => Ok(json::decode(reader, &JsonOptions::default())?),
+            ValueEncoding::Binary => Ok(decode(reader)?),
+        }
+    }
+}
+
+/// Writes the value in binary encoding.
+///
+/// Every value starts with a tag byte. Lengths and integers are written as LEB128
+/// varints, integers and timestamps being zigzag encoded first.
+pub fn encode<W: Write>(value: &VmValue, writer: &mut W) -> Result<(), std::io::Error> {
+    match value {
+        VmValue::Null => writer.write_all(&[TAG_NULL])?,
+        VmValue::Boolean(false) => writer.write_all(&[TAG_FALSE])?,
+        VmValue::Boolean(true) => writer.write_all(&[TAG_TRUE])?,
+        VmValue::Integer(integer) => {
+            writer.write_all(&[TAG_INTEGER])?;
+            write_varint(writer, zigzag(*integer))?;
+        }
+        VmValue::Number(number) => {
+            writer.write_all(&[TAG_NUMBER])?;
+            writer.write_all(&number.to_le_bytes())?;
+        }
+        VmValue::String(string) => {
+            writer.write_all(&[TAG_STRING])?;
+            write_varint(writer, string.len() as u64)?;
+            writer.write_all(string.as_bytes())?;
+        }
+        VmValue::Bytes(bytes) => {
+            writer.write_all(&[TAG_BYTES])?;
+            write_varint(writer, bytes.len() as u64)?;
+            writer.write_all(bytes)?;
+        }
+        VmValue::Array(array) => {
+            writer.write_all(&[TAG_ARRAY])?;
+            write_varint(writer, array.len() as u64)?;
+            for element in array.iter() {
+                encode(element, writer)?;
+            }
+        }
+        VmValue::Object(object) => {
+            writer.write_all(&[TAG_OBJECT])?;
+            write_varint(writer, object.len() as u64)?;
+            for pair in object.iter() {
+                write_varint(writer, pair.key.len() as u64)?;
+                writer.write_all(pair.key.as_bytes())?;
+                encode(&pair.value, writer)?;
+            }
+        }
+        VmValue::Timestamp(micros) => {
+            writer.write_all(&[TAG_TIMESTAMP])?;
+            write_varint(writer, zigzag(*micros))?;
+        }
+        VmValue::Job(uuid) => {
+            writer.write_all(&[TAG_JOB])?;
+            writer.write_all(uuid.as_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+pub fn to_vec(value: &VmValue) -> Vec<u8> {
+    let mut output = vec!();
+    encode(value, &mut output).expect("Writing to a vector cannot fail");
+    output
+}
+
+/// Reads a single binary value, which must span the whole input.
+pub fn decode<R: Read>(reader: R) -> Result<VmValue, std::io::Error> {
+    let mut reader = BufReader::new(reader);
+    let value = decode_value(&mut reader, 0)?;
+    let mut trailing = [0u8; 1];
+    if reader.read(&mut trailing)? != 0 {
+        return Err(invalid("Binary value is followed by trailing bytes"));
+    }
+    Ok(value)
+}
+
+pub fn from_slice(input: &[u8]) -> Result<VmValue, std::io::Error> {
+    decode(input)
+}
+
+fn invalid(message: &'static str) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, message)
+}
+
+fn decode_value(reader: &mut dyn Read, depth: usize) -> Result<VmValue, std::io::Error> {
+    Ok(match read_u8(reader)? {
+        TAG_NULL => VmValue::Null,
+        TAG_FALSE => VmValue::Boolean(false),
+        TAG_TRUE => VmValue::Boolean(true),
+        TAG_INTEGER => VmValue::Integer(unzigzag(read_varint(reader)?)),
+        TAG_NUMBER => {
+            let mut buffer = [0u8; 8];
+            reader.read_exact(&mut buffer)?;
+            VmValue::Number(f64::from_le_bytes(buffer))
+        }
+        TAG_STRING => VmValue::string(read_string(reader)?),
+        TAG_BYTES => {
+            let length = read_varint(reader)?;
+            VmValue::bytes(read_exact(reader, length)?)
+        }
+        TAG_ARRAY => {
+            if depth >= MAX_DEPTH {
+                return Err(invalid("Binary value nesting exceeds the limit"));
+            }
+            let count = read_varint(reader)?;
+            // Grown while reading, so a corrupt count cannot cause a huge allocation up front
+            let mut array = Vec::new();
+            for _ in 0..count {
+                array.push(decode_value(reader, depth + 1)?);
+            }
+            VmValue::array(array)
+        }
+        TAG_OBJECT => {
+            if depth >= MAX_DEPTH {
+                return Err(invalid("Binary value nesting exceeds the limit"));
+            }
+            let count = read_varint(reader)?;
+            let mut object = VmObject::new();
+            for _ in 0..count {
+                let key = read_string(reader)?;
+                let value = decode_value(reader, depth + 1)?;
+                if object.insert(key, value).is_some() {
+                    return Err(invalid("Binary object contains a key more than once"));
+                }
+            }
+            VmValue::Object(std::sync::Arc::new(object))
+        }
+        TAG_TIMESTAMP => VmValue::Timestamp(unzigzag(read_varint(reader)?)),
+        TAG_JOB => {
+            let mut buffer = [0u8; 16];
+            reader.read_exact(&mut buffer)?;
+            VmValue::Job(Uuid::from_bytes(buffer))
+        }
+        _ => return Err(invalid("Binary value has an unknown tag")),
+    })
+}
+
+fn read_u8(reader: &mut dyn Read) -> Result<u8, std::io::Error> {
+    let mut buffer = [0u8; 1];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer[0])
+}
+
+fn read_exact(reader: &mut dyn Read, length: u64) -> Result<Vec<u8>, std::io::Error> {
+    let mut buffer = Vec::new();
+    reader.take(length).read_to_end(&mut buffer)?;
+    if buffer.len() as u64 != length {
+        return Err(std::io::Error::new(ErrorKind::UnexpectedEof, "Binary value is shorter than announced"));
+    }
+    Ok(buffer)
+}
+
+fn read_string(reader: &mut dyn Read) -> Result<String, std::io::Error> {
+    let length = read_varint(reader)?;
+    String::from_utf8(read_exact(reader, length)?).map_err(|_| invalid("Binary string is not valid UTF-8"))
+}
+
+fn write_varint<W: Write>(writer: &mut W, mut value: u64) -> Result<(), std::io::Error> {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            return writer.write_all(&[byte]);
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_varint(reader: &mut dyn Read) -> Result<u64, std::io::Error> {
+    let mut value: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = read_u8(reader)?;
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("Varint exceeds 64 bits"))
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use tracing_test::traced_test;
+    use uuid::Uuid;
+    use crate::io::protocol_v2::{from_slice, to_vec, ValueEncoding};
+    use crate::machine::{VmPair, VmValue};
+
+    fn arb_value() -> impl Strategy<Value = VmValue> {
+        let leaf = prop_oneof![
+            Just(VmValue::Null),
+            any::<bool>().prop_map(VmValue::Boolean),
+            any::<i64>().prop_map(VmValue::Integer),
+            any::<f64>().prop_filter("NaN never compares equal", |number| !number.is_nan()).prop_map(VmValue::Number),
+            ".{0,16}".prop_map(VmValue::string),
+            prop::collection::vec(any::<u8>(), 0..16).prop_map(VmValue::bytes),
+            any::<i64>().prop_map(VmValue::Timestamp),
+            any::<u128>().prop_map(|uuid| VmValue::Job(Uuid::from_u128(uuid))),
+        ];
+        leaf.prop_recursive(4, 32, 4, |inner| prop_oneof![
+            prop::collection::vec(inner.clone(), 0..4).prop_map(VmValue::array),
+            prop::collection::btree_map("[a-z$]{0,6}", inner, 0..4).prop_map(|pairs| VmValue::object(pairs.into_iter()
+                .map(|(key, value)| VmPair { key, value })
+                .collect())),
+        ])
+    }
+
+    proptest! {
+        #[test]
+        fn values_round_trip(value in arb_value()) {
+            prop_assert_eq!(from_slice(&to_vec(&value)).unwrap(), value);
+        }
+
+        #[test]
+        fn garbage_never_panics(bytes in prop::collection::vec(any::<u8>(), 0..64)) {
+            let _ = from_slice(&bytes);
+        }
+    }
+
+    #[test]
+    #[traced_test]
+    fn values_are_compact() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(to_vec(&VmValue::Integer(-1)), vec!(3, 1));
+        assert_eq!(to_vec(&VmValue::Integer(300)), vec!(3, 0xD8, 0x04));
+        assert_eq!(to_vec(&VmValue::string("λ")), vec!(5, 2, 0xCE, 0xBB));
+        assert_eq!(to_vec(&VmValue::array(vec!(VmValue::Null, VmValue::Boolean(true)))), vec!(7, 2, 0, 2));
+        // Integers keep their type, unlike JSON numbers written by other languages
+        assert_eq!(ValueEncoding::Binary.decode(&to_vec(&VmValue::Number(2.0)))?, VmValue::Number(2.0));
+        assert_eq!(ValueEncoding::Json.decode(b"2")?, VmValue::Integer(2));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn malformed_values_error() -> Result<(), Box<dyn std::error::Error>> {
+        assert!(from_slice(&[]).is_err());
+        assert!(from_slice(&[11]).is_err());
+        assert!(from_slice(&[0, 0]).is_err());
+        assert!(from_slice(&[5, 4, b'a']).is_err());
+        assert!(from_slice(&[5, 1, 0xFF]).is_err());
+        assert!(from_slice(&[3, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]).is_err());
+        assert!(from_slice(&[8, 2, 1, b'a', 0, 1, b'a', 0]).is_err());
+        assert!(from_slice(&[7; 200]).is_err());
+        // Counts are not trusted for allocation
+        assert!(from_slice(&[7, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,289 @@
+use std::fmt::{Display, Formatter};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// The reading and writing half of a connection, as consumed by the protocol.
+pub type Halves = (Box<dyn Read + Send>, Box<dyn Write + Send>);
+
+/// An owned, bidirectional connection between lambda and a function host.
+pub trait Transport: Send {
+    /// Hands out the reading and writing half of the connection. May only be called once.
+    fn split(&mut self) -> Result<Halves, std::io::Error>;
+    /// Closes the connection, causing both ends to read the end of the stream.
+    fn shutdown(&self) -> Result<(), std::io::Error>;
+    /// A human-readable description of the other end of the connection.
+    fn peer(&self) -> String;
+}
+
+/// A transport over a pair of pipes, eg. the stdio of a child process.
+pub struct PipeTransport {
+    halves: Option<Halves>,
+    peer: String,
+}
+
+impl PipeTransport {
+    pub fn new(reader: Box<dyn Read + Send>, writer: Box<dyn Write + Send>, peer: &str) -> PipeTransport {
+        PipeTransport {
+            halves: Some((reader, writer)),
+            peer: peer.to_string(),
+        }
+    }
+}
+
+impl Transport for PipeTransport {
+    fn split(&mut self) -> Result<Halves, std::io::Error> {
+        self.halves.take().ok_or_else(|| std::io::Error::other("Transport was split already"))
+    }
+    fn shutdown(&self) -> Result<(), std::io::Error> {
+        // Pipes close once both halves are dropped
+        Ok(())
+    }
+    fn peer(&self) -> String {
+        self.peer.clone()
+    }
+}
+
+pub struct TcpTransport {
+    stream: TcpStream,
+}
+
+impl Transport for TcpTransport {
+    fn split(&mut self) -> Result<Halves, std::io::Error> {
+        Ok((Box::new(self.stream.try_clone()?), Box::new(self.stream.try_clone()?)))
+    }
+    fn shutdown(&self) -> Result<(), std::io::Error> {
+        self.stream.shutdown(Shutdown::Both)
+    }
+    fn peer(&self) -> String {
+        match self.stream.peer_addr() {
+            Ok(address) => format!("tcp://{}", address),
+            Err(_) => "tcp://<unknown>".to_string(),
+        }
+    }
+}
+
+#[cfg(unix)]
+pub struct UnixTransport {
+    stream: UnixStream,
+}
+
+#[cfg(unix)]
+impl Transport for UnixTransport {
+    fn split(&mut self) -> Result<Halves, std::io::Error> {
+        Ok((Box::new(self.stream.try_clone()?), Box::new(self.stream.try_clone()?)))
+    }
+    fn shutdown(&self) -> Result<(), std::io::Error> {
+        self.stream.shutdown(Shutdown::Both)
+    }
+    fn peer(&self) -> String {
+        match self.stream.peer_addr().ok().and_then(|it| it.as_pathname().map(|path| path.to_path_buf())) {
+            Some(path) => format!("unix://{}", path.display()),
+            None => "unix://<unnamed>".to_string(),
+        }
+    }
+}
+
+/// An address lambda and function hosts can meet at, written as `tcp://127.0.0.1:4000` or
+/// `unix:///run/lambda.sock`.
+///
+/// As the protocol carries no authentication, TCP is restricted to loopback addresses.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Endpoint {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl Endpoint {
+    fn check(&self) -> Result<(), std::io::Error> {
+        match self {
+            Endpoint::Tcp(address) if !address.ip().is_loopback() =>
+                Err(std::io::Error::new(ErrorKind::InvalidInput, "TCP transports are restricted to loopback addresses")),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn connect(&self) -> Result<Box<dyn Transport>, std::io::Error> {
+        self.check()?;
+        match self {
+            Endpoint::Tcp(address) => {
+                let stream = TcpStream::connect(address)?;
+                stream.set_nodelay(true)?;
+                Ok(Box::new(TcpTransport { stream }))
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(path) => Ok(Box::new(UnixTransport { stream: UnixStream::connect(path)? })),
+            #[cfg(not(unix))]
+            Endpoint::Unix(_) => Err(std::io::Error::new(ErrorKind::Unsupported, "Unix sockets are not supported on this platform")),
+        }
+    }
+}
+
+impl Display for Endpoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Endpoint::Tcp(address) => write!(f, "tcp://{}", address),
+            Endpoint::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(address) = s.strip_prefix("tcp://") {
+            let address = address.parse().map_err(|_| "Invalid TCP address")?;
+            Ok(Endpoint::Tcp(address))
+        } else if let Some(path) = s.strip_prefix("unix://") {
+            if path.is_empty() {
+                return Err("Unix socket path is empty");
+            }
+            Ok(Endpoint::Unix(PathBuf::from(path)))
+        } else {
+            Err("Endpoints have to start with tcp:// or unix://")
+        }
+    }
+}
+
+/// Accepts connections at an [Endpoint], used by lambda to let function hosts register
+/// themselves and by function hosts waiting for lambda to connect.
+pub enum TransportListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl TransportListener {
+    /// Binds the endpoint provided. A stale unix socket file is replaced.
+    pub fn bind(endpoint: &Endpoint) -> Result<TransportListener, std::io::Error> {
+        endpoint.check()?;
+        match endpoint {
+            Endpoint::Tcp(address) => Ok(TransportListener::Tcp(TcpListener::bind(address)?)),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                if path.exists() && UnixStream::connect(path).is_err() {
+                    std::fs::remove_file(path)?;
+                }
+                Ok(TransportListener::Unix(UnixListener::bind(path)?, path.clone()))
+            }
+            #[cfg(not(unix))]
+            Endpoint::Unix(_) => Err(std::io::Error::new(ErrorKind::Unsupported, "Unix sockets are not supported on this platform")),
+        }
+    }
+
+    /// The endpoint actually bound, eg. resolving the port when binding port 0.
+    pub fn local_endpoint(&self) -> Result<Endpoint, std::io::Error> {
+        match self {
+            TransportListener::Tcp(listener) => Ok(Endpoint::Tcp(listener.local_addr()?)),
+            #[cfg(unix)]
+            TransportListener::Unix(_, path) => Ok(Endpoint::Unix(path.clone())),
+        }
+    }
+
+    /// Blocks until a connection arrives.
+    pub fn accept(&self) -> Result<Box<dyn Transport>, std::io::Error> {
+        match self {
+            TransportListener::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nonblocking(false)?;
+                stream.set_nodelay(true)?;
+                Ok(Box::new(TcpTransport { stream }))
+            }
+            #[cfg(unix)]
+            TransportListener::Unix(listener, _) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nonblocking(false)?;
+                Ok(Box::new(UnixTransport { stream }))
+            }
+        }
+    }
+
+    /// Returns a pending connection without blocking, if any.
+    pub fn try_accept(&self) -> Result<Option<Box<dyn Transport>>, std::io::Error> {
+        self.set_nonblocking(true)?;
+        let result = self.accept();
+        self.set_nonblocking(false)?;
+        match result {
+            Ok(transport) => Ok(Some(transport)),
+            Err(error) if error.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> Result<(), std::io::Error> {
+        match self {
+            TransportListener::Tcp(listener) => listener.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            TransportListener::Unix(listener, _) => listener.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+impl Drop for TransportListener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let TransportListener::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use tracing_test::traced_test;
+    use crate::io::transport::{Endpoint, TransportListener};
+
+    fn echo_once(endpoint: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let listener = TransportListener::bind(&endpoint.parse()?)?;
+        assert!(listener.try_accept()?.is_none());
+        let endpoint = listener.local_endpoint()?;
+        let client = std::thread::spawn(move || -> Result<Vec<u8>, std::io::Error> {
+            let mut transport = endpoint.connect()?;
+            let (mut reader, mut writer) = transport.split()?;
+            writer.write_all(b"lambda")?;
+            let mut buffer = vec!();
+            reader.read_to_end(&mut buffer)?;
+            Ok(buffer)
+        });
+        let mut transport = listener.accept()?;
+        let (mut reader, mut writer) = transport.split()?;
+        let mut buffer = [0; 6];
+        reader.read_exact(&mut buffer)?;
+        writer.write_all(&buffer)?;
+        transport.shutdown()?;
+        assert_eq!(client.join().unwrap()?, b"lambda");
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn tcp_loopback() -> Result<(), Box<dyn std::error::Error>> {
+        echo_once("tcp://127.0.0.1:0")
+    }
+
+    #[test]
+    #[traced_test]
+    #[cfg(unix)]
+    fn unix_socket() -> Result<(), Box<dyn std::error::Error>> {
+        let path = std::env::temp_dir().join(format!("lambda-{}.sock", uuid::Uuid::new_v4()));
+        echo_once(&format!("unix://{}", path.display()))?;
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn endpoints_parse() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!("tcp://127.0.0.1:4000".parse::<Endpoint>()?, Endpoint::Tcp("127.0.0.1:4000".parse()?));
+        assert_eq!("unix:///run/lambda.sock".parse::<Endpoint>()?.to_string(), "unix:///run/lambda.sock");
+        assert!("http://localhost".parse::<Endpoint>().is_err());
+        assert!("unix://".parse::<Endpoint>().is_err());
+        assert!(TransportListener::bind(&"tcp://0.0.0.0:0".parse()?).is_err());
+        Ok(())
+    }
+}
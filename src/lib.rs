@@ -0,0 +1,10 @@
+//! The x39 lambda runtime, being the virtual machine running scripts, the assembler compiling
+//! them and the protocols talking to function hosts. Function binaries link this library to
+//! serve their functions via [io::function_host].
+#![allow(dead_code)]
+
+pub mod machine;
+pub mod assembler;
+pub mod controllers;
+pub mod io;
+pub mod cli;
@@ -1,7 +1,11 @@
 pub mod memory;
 pub mod opcode;
 pub mod instruction_arg;
+pub mod json;
 pub mod serializer;
+pub mod disassembler;
+pub mod timestamp;
+pub mod vm_object;
 pub mod vm_stack;
 pub mod vm_state;
 pub mod vm_value;
@@ -9,6 +13,7 @@ pub mod vm_value;
 pub use self::memory::*;
 pub use self::vm_value::*;
 pub use self::instruction_arg::*;
+pub use self::vm_object::*;
 pub use self::vm_stack::*;
 pub use self::vm_state::*;
 pub use self::opcode::OpCode;
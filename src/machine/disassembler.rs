@@ -0,0 +1,119 @@
+//! The listing of the bytecode of a state, as shown by `lambda disasm` and the repl.
+//!
+//! Instructions are listed with their opcode and argument, followed by a comment resolving the
+//! argument where possible, being the value pushed, the name of the local accessed or the index
+//! of the instruction jumped to.
+
+use std::fmt::Write;
+use crate::machine::json::{self, JsonOptions};
+use crate::machine::{Instruction, InstructionArg, OpCode};
+use super::vm_state::VmState;
+
+/// The width instructions are padded to before their comment.
+const INSTRUCTION_WIDTH: usize = 24;
+
+impl VmState {
+    /// Lists the values, locals and instructions of the state.
+    pub fn disassemble(&self) -> String {
+        let mut listing = String::new();
+        let _ = self.write_disassembly(&mut listing);
+        listing
+    }
+
+    fn write_disassembly(&self, listing: &mut String) -> std::fmt::Result {
+        writeln!(listing, "Values: {}", self.values().len())?;
+        for (index, value) in self.values().iter().enumerate() {
+            writeln!(listing, "    {:04}: {}", index, value_to_string(self, index as u16).unwrap_or_else(|| format!("{:?}", value)))?;
+        }
+        let mut locals: Vec<_> = self.locals().iter().collect();
+        locals.sort_by_key(|local| (local.slot, local.from));
+        writeln!(listing, "Locals: {}", locals.len())?;
+        for local in locals {
+            let to = match local.to {
+                usize::MAX => "end".to_string(),
+                to => format!("{:04}", to),
+            };
+            writeln!(listing, "    {:04}: {} ({:04}..{})", local.slot, local.name, local.from, to)?;
+        }
+        writeln!(listing, "Instructions: {}", self.instructions().len())?;
+        for (index, instruction) in self.instructions().iter().enumerate() {
+            let text = match &instruction.arg {
+                InstructionArg::Empty => format!("{:?}", instruction.opcode),
+                InstructionArg::Unsigned(unsigned) => format!("{:?} {}", instruction.opcode, unsigned),
+                InstructionArg::Signed(signed) => format!("{:?} {:+}", instruction.opcode, signed),
+                InstructionArg::Type(value_type) => format!("{:?} {:?}", instruction.opcode, value_type),
+            };
+            match self.comment(index, instruction) {
+                Some(comment) => writeln!(listing, "    {:04}: {:<width$} ; {}", index, text, comment, width = INSTRUCTION_WIDTH)?,
+                None => writeln!(listing, "    {:04}: {}", index, text)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves the argument of the instruction at the index provided.
+    fn comment(&self, index: usize, instruction: &Instruction) -> Option<String> {
+        match (instruction.opcode, &instruction.arg) {
+            (OpCode::PushValueU16, InstructionArg::Unsigned(value)) => value_to_string(self, *value),
+            (OpCode::LoadLocal | OpCode::StoreLocal | OpCode::AddLocal, InstructionArg::Unsigned(slot)) =>
+                self.local_name(*slot, index).map(|name| name.to_string()),
+            (OpCode::Jump | OpCode::JumpIfFalse | OpCode::JumpIfTrue | OpCode::JumpIterate | OpCode::JumpIteratePair | OpCode::JumpStream,
+                InstructionArg::Signed(offset)) =>
+                // Jumps are relative to the instruction following them
+                (index + 1).checked_add_signed(*offset as isize).map(|target| format!("to {:04}", target)),
+            _ => None,
+        }
+    }
+}
+
+fn value_to_string(vm_state: &VmState, index: u16) -> Option<String> {
+    let value = vm_state.values().get(index as usize)?;
+    json::to_string(value, &JsonOptions::default()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_test::traced_test;
+
+    #[test]
+    #[traced_test]
+    fn instructions_are_listed_with_their_arguments_resolved() -> Result<(), Box<dyn std::error::Error>> {
+        let (_, file) = crate::assembler::parser::parser::parse_x39file(
+            "let total: integer = 0; for i in [1, 2] { total += i; } job = start resize(total);")?;
+        let vm_state = crate::assembler::compiler::compiler::compile(file)?;
+        assert_eq!(vm_state.disassemble(), "\
+Values: 4
+    0000: 0
+    0001: 1
+    0002: 2
+    0003: \"resize\"
+Locals: 3
+    0000: total (0002..end)
+    0001: i (0010..0016)
+    0001: job (0020..end)
+Instructions: 21
+    0000: PushValueU16 0           ; 0
+    0001: AssertType Integer
+    0002: StoreLocal 0             ; total
+    0003: PushEmptyArray
+    0004: PushValueU16 1           ; 1
+    0005: AppendArrayPush
+    0006: PushValueU16 2           ; 2
+    0007: AppendArrayPush
+    0008: PushValueU16 0           ; 0
+    0009: JumpIterate +7           ; to 0017
+    0010: StoreLocal 1             ; i
+    0011: LoadLocal 1              ; i
+    0012: AddLocal 0               ; total
+    0013: LoadLocal 0              ; total
+    0014: AssertType Integer
+    0015: Pop
+    0016: Jump -8                  ; to 0009
+    0017: PushValueU16 3           ; \"resize\"
+    0018: LoadLocal 0              ; total
+    0019: Call 1
+    0020: StoreLocal 1             ; job
+");
+        Ok(())
+    }
+}
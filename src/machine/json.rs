@@ -0,0 +1,615 @@
+//! Lossless conversion of [VmValue]s from and to JSON, following the value mapping
+//! documented in `src/io/ReadMe.md`.
+//!
+//! Values that JSON cannot represent (bytes, timestamps and jobs) are written as objects
+//! with a single `$`-prefixed key, object keys starting with `$` are escaped by prefixing
+//! another `$`.
+
+use std::io::{BufReader, Bytes, Read, Write};
+use std::iter::Peekable;
+use std::sync::Arc;
+use uuid::Uuid;
+use crate::machine::timestamp::{format_rfc3339, parse_rfc3339};
+use crate::machine::{VmObject, VmValue};
+
+const TAG_BYTES: &str = "$bytes";
+const TAG_TIMESTAMP: &str = "$timestamp";
+const TAG_JOB: &str = "$job";
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Debug, Clone)]
+pub struct JsonOptions {
+    /// The maximum nesting of arrays and objects, tagged values not counting as nesting.
+    pub max_depth: usize,
+}
+
+impl Default for JsonOptions {
+    fn default() -> Self {
+        JsonOptions {
+            max_depth: 128,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum JsonError {
+    Io(std::io::Error),
+    /// The input ended before the value was complete.
+    UnexpectedEnd,
+    /// The input contained a byte not allowed at the given offset.
+    UnexpectedByte { byte: u8, offset: usize },
+    /// A string was not valid UTF-8 or contained an invalid escape sequence.
+    InvalidString,
+    /// A number was malformed.
+    InvalidNumber(String),
+    /// A number being NaN or infinite was to be written.
+    NonFiniteNumber,
+    /// A tagged value had an unknown tag or malformed content.
+    InvalidTag(String),
+    /// Arrays and objects were nested deeper than allowed.
+    DepthLimitExceeded(usize),
+}
+
+impl std::fmt::Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsonError::Io(error) => write!(f, "I/O error: {}", error),
+            JsonError::UnexpectedEnd => write!(f, "Unexpected end of JSON input"),
+            JsonError::UnexpectedByte { byte, offset } => write!(f, "Unexpected byte 0x{:02X} at offset {}", byte, offset),
+            JsonError::InvalidString => write!(f, "Invalid JSON string"),
+            JsonError::InvalidNumber(number) => write!(f, "Invalid JSON number '{}'", number),
+            JsonError::NonFiniteNumber => write!(f, "Numbers which are not finite cannot be written as JSON"),
+            JsonError::InvalidTag(tag) => write!(f, "Invalid tagged value '{}'", tag),
+            JsonError::DepthLimitExceeded(depth) => write!(f, "JSON nesting exceeds the limit of {}", depth),
+        }
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+impl From<std::io::Error> for JsonError {
+    fn from(error: std::io::Error) -> Self {
+        JsonError::Io(error)
+    }
+}
+
+/// Writes the value as JSON to the writer provided.
+pub fn encode<W: Write>(value: &VmValue, writer: &mut W, options: &JsonOptions) -> Result<(), JsonError> {
+    encode_value(value, writer, options, 0)
+}
+
+pub fn to_string(value: &VmValue, options: &JsonOptions) -> Result<String, JsonError> {
+    let mut output = vec!();
+    encode(value, &mut output, options)?;
+    Ok(String::from_utf8(output).expect("JSON output is always valid UTF-8"))
+}
+
+/// Reads a single JSON value from the reader provided, which must not contain anything
+/// but whitespace after the value.
+pub fn decode<R: Read>(reader: R, options: &JsonOptions) -> Result<VmValue, JsonError> {
+    let mut decoder = Decoder {
+        input: BufReader::new(reader).bytes().peekable(),
+        offset: 0,
+        options,
+    };
+    let value = decoder.decode_value(0)?;
+    decoder.skip_whitespace()?;
+    match decoder.next()? {
+        None => Ok(value),
+        Some(byte) => Err(JsonError::UnexpectedByte { byte, offset: decoder.offset - 1 }),
+    }
+}
+
+pub fn from_str(input: &str, options: &JsonOptions) -> Result<VmValue, JsonError> {
+    decode(input.as_bytes(), options)
+}
+
+fn encode_value<W: Write>(value: &VmValue, writer: &mut W, options: &JsonOptions, depth: usize) -> Result<(), JsonError> {
+    match value {
+        VmValue::Null => writer.write_all(b"null")?,
+        VmValue::Boolean(flag) => writer.write_all(if *flag { b"true" } else { b"false" })?,
+        VmValue::Integer(integer) => write!(writer, "{}", integer)?,
+        VmValue::Number(number) => {
+            if !number.is_finite() {
+                return Err(JsonError::NonFiniteNumber);
+            }
+            // Debug output is the shortest representation reading back to the same number
+            // and always contains a fraction or exponent, keeping it apart from integers.
+            write!(writer, "{:?}", number)?
+        }
+        VmValue::String(string) => encode_string(string, writer)?,
+        VmValue::Array(array) => {
+            if depth >= options.max_depth {
+                return Err(JsonError::DepthLimitExceeded(options.max_depth));
+            }
+            writer.write_all(b"[")?;
+            for (index, element) in array.iter().enumerate() {
+                if index > 0 {
+                    writer.write_all(b",")?;
+                }
+                encode_value(element, writer, options, depth + 1)?;
+            }
+            writer.write_all(b"]")?;
+        }
+        VmValue::Object(object) => {
+            if depth >= options.max_depth {
+                return Err(JsonError::DepthLimitExceeded(options.max_depth));
+            }
+            writer.write_all(b"{")?;
+            for (index, pair) in object.iter().enumerate() {
+                if index > 0 {
+                    writer.write_all(b",")?;
+                }
+                if pair.key.starts_with('$') {
+                    encode_string(&format!("${}", pair.key), writer)?;
+                } else {
+                    encode_string(&pair.key, writer)?;
+                }
+                writer.write_all(b":")?;
+                encode_value(&pair.value, writer, options, depth + 1)?;
+            }
+            writer.write_all(b"}")?;
+        }
+        VmValue::Bytes(bytes) => encode_tagged(TAG_BYTES, &encode_base64(bytes), writer)?,
+        VmValue::Timestamp(timestamp) => encode_tagged(TAG_TIMESTAMP, &format_rfc3339(*timestamp), writer)?,
+        VmValue::Job(uuid) => encode_tagged(TAG_JOB, &uuid.hyphenated().to_string(), writer)?,
+    }
+    Ok(())
+}
+
+fn encode_tagged<W: Write>(tag: &str, content: &str, writer: &mut W) -> Result<(), JsonError> {
+    writer.write_all(b"{")?;
+    encode_string(tag, writer)?;
+    writer.write_all(b":")?;
+    encode_string(content, writer)?;
+    writer.write_all(b"}")?;
+    Ok(())
+}
+
+fn encode_string<W: Write>(string: &str, writer: &mut W) -> Result<(), JsonError> {
+    writer.write_all(b"\"")?;
+    let mut start = 0;
+    for (index, c) in string.char_indices() {
+        let escape: Option<&[u8]> = match c {
+            '"' => Some(b"\\\""),
+            '\\' => Some(b"\\\\"),
+            '\n' => Some(b"\\n"),
+            '\r' => Some(b"\\r"),
+            '\t' => Some(b"\\t"),
+            '\u{08}' => Some(b"\\b"),
+            '\u{0C}' => Some(b"\\f"),
+            c if (c as u32) < 0x20 => None,
+            _ => continue,
+        };
+        writer.write_all(&string.as_bytes()[start..index])?;
+        match escape {
+            Some(escape) => writer.write_all(escape)?,
+            None => write!(writer, "\\u{:04x}", c as u32)?,
+        }
+        start = index + c.len_utf8();
+    }
+    writer.write_all(&string.as_bytes()[start..])?;
+    writer.write_all(b"\"")?;
+    Ok(())
+}
+
+fn encode_base64(bytes: &[u8]) -> String {
+    let mut output = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+fn decode_base64(input: &str) -> Option<Vec<u8>> {
+    if !input.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut output = Vec::with_capacity(input.len() / 4 * 3);
+    let chunks: Vec<&[u8]> = input.as_bytes().chunks(4).collect();
+    for (index, chunk) in chunks.iter().enumerate() {
+        let padding = chunk.iter().rev().take_while(|b| **b == b'=').count();
+        if padding > 2 || (padding > 0 && index + 1 != chunks.len()) {
+            return None;
+        }
+        let mut n = 0u32;
+        for b in &chunk[..4 - padding] {
+            let digit = BASE64_ALPHABET.iter().position(|it| it == b)? as u32;
+            n = n << 6 | digit;
+        }
+        n <<= 6 * padding as u32;
+        let decoded = [(n >> 16) as u8, (n >> 8) as u8, n as u8];
+        output.extend_from_slice(&decoded[..3 - padding]);
+    }
+    Some(output)
+}
+
+struct Decoder<'o, R: Read> {
+    input: Peekable<Bytes<BufReader<R>>>,
+    offset: usize,
+    options: &'o JsonOptions,
+}
+
+impl<'o, R: Read> Decoder<'o, R> {
+    fn next(&mut self) -> Result<Option<u8>, JsonError> {
+        match self.input.next() {
+            None => Ok(None),
+            Some(byte) => {
+                self.offset += 1;
+                Ok(Some(byte?))
+            }
+        }
+    }
+    fn peek(&mut self) -> Result<Option<u8>, JsonError> {
+        match self.input.peek() {
+            None => Ok(None),
+            Some(Ok(byte)) => Ok(Some(*byte)),
+            Some(Err(_)) => Err(self.input.next().unwrap().unwrap_err().into()),
+        }
+    }
+    fn expect_next(&mut self) -> Result<u8, JsonError> {
+        self.next()?.ok_or(JsonError::UnexpectedEnd)
+    }
+    fn expect(&mut self, expected: u8) -> Result<(), JsonError> {
+        let byte = self.expect_next()?;
+        match byte == expected {
+            true => Ok(()),
+            false => Err(JsonError::UnexpectedByte { byte, offset: self.offset - 1 }),
+        }
+    }
+    fn expect_literal(&mut self, literal: &[u8]) -> Result<(), JsonError> {
+        for expected in literal {
+            self.expect(*expected)?;
+        }
+        Ok(())
+    }
+    fn skip_whitespace(&mut self) -> Result<(), JsonError> {
+        while let Some(b' ' | b'\t' | b'\r' | b'\n') = self.peek()? {
+            self.next()?;
+        }
+        Ok(())
+    }
+
+    fn decode_value(&mut self, depth: usize) -> Result<VmValue, JsonError> {
+        self.skip_whitespace()?;
+        match self.peek()?.ok_or(JsonError::UnexpectedEnd)? {
+            b'n' => {
+                self.expect_literal(b"null")?;
+                Ok(VmValue::Null)
+            }
+            b't' => {
+                self.expect_literal(b"true")?;
+                Ok(VmValue::Boolean(true))
+            }
+            b'f' => {
+                self.expect_literal(b"false")?;
+                Ok(VmValue::Boolean(false))
+            }
+            b'"' => Ok(VmValue::string(self.decode_string()?)),
+            b'[' => self.decode_array(depth),
+            b'{' => self.decode_object(depth),
+            b'-' | b'0'..=b'9' => self.decode_number(),
+            byte => Err(JsonError::UnexpectedByte { byte, offset: self.offset }),
+        }
+    }
+
+    fn decode_number(&mut self) -> Result<VmValue, JsonError> {
+        let mut text = String::new();
+        let mut is_integer = true;
+        while let Some(byte @ (b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')) = self.peek()? {
+            is_integer &= byte.is_ascii_digit() || byte == b'-';
+            text.push(byte as char);
+            self.next()?;
+        }
+        if !is_valid_number(text.as_bytes()) {
+            return Err(JsonError::InvalidNumber(text));
+        }
+        if is_integer {
+            if let Ok(integer) = text.parse::<i64>() {
+                return Ok(VmValue::Integer(integer));
+            }
+        }
+        match text.parse::<f64>() {
+            Ok(number) if number.is_finite() => Ok(VmValue::Number(number)),
+            _ => Err(JsonError::InvalidNumber(text)),
+        }
+    }
+
+    fn decode_hex4(&mut self) -> Result<u32, JsonError> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let digit = (self.expect_next()? as char).to_digit(16).ok_or(JsonError::InvalidString)?;
+            value = value << 4 | digit;
+        }
+        Ok(value)
+    }
+
+    fn decode_string(&mut self) -> Result<String, JsonError> {
+        self.expect(b'"')?;
+        let mut bytes = vec!();
+        loop {
+            match self.expect_next()? {
+                b'"' => break,
+                b'\\' => {
+                    let c = match self.expect_next()? {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{08}',
+                        b'f' => '\u{0C}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let high = self.decode_hex4()?;
+                            let code = match high {
+                                0xD800..=0xDBFF => {
+                                    self.expect_literal(b"\\u").map_err(|_| JsonError::InvalidString)?;
+                                    let low = self.decode_hex4()?;
+                                    if !(0xDC00..=0xDFFF).contains(&low) {
+                                        return Err(JsonError::InvalidString);
+                                    }
+                                    0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+                                }
+                                code => code,
+                            };
+                            char::from_u32(code).ok_or(JsonError::InvalidString)?
+                        }
+                        _ => return Err(JsonError::InvalidString),
+                    };
+                    let mut buffer = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                }
+                byte if byte < 0x20 => return Err(JsonError::InvalidString),
+                byte => bytes.push(byte),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| JsonError::InvalidString)
+    }
+
+    fn decode_array(&mut self, depth: usize) -> Result<VmValue, JsonError> {
+        if depth >= self.options.max_depth {
+            return Err(JsonError::DepthLimitExceeded(self.options.max_depth));
+        }
+        self.expect(b'[')?;
+        let mut array = vec!();
+        self.skip_whitespace()?;
+        if self.peek()? == Some(b']') {
+            self.next()?;
+            return Ok(VmValue::array(array));
+        }
+        loop {
+            array.push(self.decode_value(depth + 1)?);
+            self.skip_whitespace()?;
+            match self.expect_next()? {
+                b',' => continue,
+                b']' => break,
+                byte => return Err(JsonError::UnexpectedByte { byte, offset: self.offset - 1 }),
+            }
+        }
+        Ok(VmValue::array(array))
+    }
+
+    fn decode_object(&mut self, depth: usize) -> Result<VmValue, JsonError> {
+        self.expect(b'{')?;
+        let mut object = VmObject::new();
+        let mut tagged = None;
+        self.skip_whitespace()?;
+        if self.peek()? == Some(b'}') {
+            if depth >= self.options.max_depth {
+                return Err(JsonError::DepthLimitExceeded(self.options.max_depth));
+            }
+            self.next()?;
+            return Ok(VmValue::Object(Arc::new(object)));
+        }
+        loop {
+            self.skip_whitespace()?;
+            let key = self.decode_string()?;
+            self.skip_whitespace()?;
+            self.expect(b':')?;
+            if object.is_empty() && tagged.is_none() && key.starts_with('$') && !key.starts_with("$$") {
+                self.skip_whitespace()?;
+                let content = self.decode_string().map_err(|_| JsonError::InvalidTag(key.clone()))?;
+                tagged = Some((key, content));
+            } else {
+                if depth >= self.options.max_depth {
+                    return Err(JsonError::DepthLimitExceeded(self.options.max_depth));
+                }
+                if let Some((key, _)) = tagged {
+                    return Err(JsonError::InvalidTag(key));
+                }
+                let value = self.decode_value(depth + 1)?;
+                let key = match key.strip_prefix('$') {
+                    Some(unescaped) if unescaped.starts_with('$') => unescaped.to_string(),
+                    _ => key,
+                };
+                object.insert(key, value);
+            }
+            self.skip_whitespace()?;
+            match self.expect_next()? {
+                b',' => continue,
+                b'}' => break,
+                byte => return Err(JsonError::UnexpectedByte { byte, offset: self.offset - 1 }),
+            }
+        }
+        match tagged {
+            None => Ok(VmValue::Object(Arc::new(object))),
+            Some((tag, content)) => decode_tagged(tag, content),
+        }
+    }
+}
+
+fn decode_tagged(tag: String, content: String) -> Result<VmValue, JsonError> {
+    let value = match tag.as_str() {
+        TAG_BYTES => decode_base64(&content).map(VmValue::bytes),
+        TAG_TIMESTAMP => parse_rfc3339(&content).map(VmValue::Timestamp),
+        TAG_JOB => Uuid::parse_str(&content).ok().map(VmValue::Job),
+        _ => None,
+    };
+    value.ok_or(JsonError::InvalidTag(tag))
+}
+
+/// Whether the text is a number as defined by the JSON grammar.
+fn is_valid_number(text: &[u8]) -> bool {
+    let mut index = 0;
+    let digits = |index: &mut usize| {
+        let start = *index;
+        while *index < text.len() && text[*index].is_ascii_digit() {
+            *index += 1;
+        }
+        *index - start
+    };
+    if text.first() == Some(&b'-') {
+        index += 1;
+    }
+    match text.get(index) {
+        Some(b'0') => index += 1,
+        Some(b'1'..=b'9') => { digits(&mut index); }
+        _ => return false,
+    }
+    if text.get(index) == Some(&b'.') {
+        index += 1;
+        if digits(&mut index) == 0 {
+            return false;
+        }
+    }
+    if let Some(b'e' | b'E') = text.get(index) {
+        index += 1;
+        if let Some(b'+' | b'-') = text.get(index) {
+            index += 1;
+        }
+        if digits(&mut index) == 0 {
+            return false;
+        }
+    }
+    index == text.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use tracing_test::traced_test;
+    use uuid::Uuid;
+    use crate::machine::json::*;
+    use crate::machine::{VmPair, VmValue};
+
+    fn round_trip(value: &VmValue) -> Result<VmValue, JsonError> {
+        let options = JsonOptions::default();
+        from_str(&to_string(value, &options)?, &options)
+    }
+
+    fn arb_value() -> impl Strategy<Value = VmValue> {
+        let leaf = prop_oneof![
+            Just(VmValue::Null),
+            any::<bool>().prop_map(VmValue::Boolean),
+            any::<i64>().prop_map(VmValue::Integer),
+            any::<f64>().prop_filter("finite", |it| it.is_finite()).prop_map(VmValue::Number),
+            any::<String>().prop_map(VmValue::string),
+            any::<Vec<u8>>().prop_map(VmValue::bytes),
+            (-62_135_596_800_000_000i64..253_402_300_799_999_999i64).prop_map(VmValue::Timestamp),
+            any::<u128>().prop_map(|it| VmValue::Job(Uuid::from_u128(it))),
+        ];
+        leaf.prop_recursive(4, 64, 8, |inner| prop_oneof![
+            prop::collection::vec(inner.clone(), 0..8).prop_map(VmValue::array),
+            prop::collection::vec(("\\$?\\$?[a-z]{0,4}", inner), 0..8).prop_map(|pairs| {
+                VmValue::object(pairs.into_iter().map(|(key, value)| VmPair { key, value }).collect())
+            }),
+        ])
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip_is_lossless(value in arb_value()) {
+            prop_assert_eq!(round_trip(&value).unwrap(), value);
+        }
+
+        #[test]
+        fn decode_never_panics(input in any::<Vec<u8>>()) {
+            let _ = decode(input.as_slice(), &JsonOptions::default());
+        }
+    }
+
+    #[test]
+    #[traced_test]
+    fn numbers_keep_their_kind() -> Result<(), Box<dyn std::error::Error>> {
+        let options = JsonOptions::default();
+        assert_eq!(to_string(&VmValue::Number(1.0), &options)?, "1.0");
+        assert_eq!(to_string(&VmValue::Integer(1), &options)?, "1");
+        assert_eq!(from_str("9007199254740993", &options)?, VmValue::Integer(9007199254740993));
+        assert_eq!(from_str("1e2", &options)?, VmValue::Number(100.0));
+        assert_eq!(from_str("-0.5", &options)?, VmValue::Number(-0.5));
+        assert_eq!(from_str("18446744073709551616", &options)?, VmValue::Number(18446744073709551616.0));
+        assert!(matches!(to_string(&VmValue::Number(f64::NAN), &options), Err(JsonError::NonFiniteNumber)));
+        for invalid in ["01", "1.", ".5", "-", "1e", "+1", "1e999"] {
+            assert!(from_str(invalid, &options).is_err(), "{} was accepted", invalid);
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn non_json_values_are_tagged() -> Result<(), Box<dyn std::error::Error>> {
+        let options = JsonOptions::default();
+        let job = Uuid::from_u128(1);
+        assert_eq!(to_string(&VmValue::Job(job), &options)?, r#"{"$job":"00000000-0000-0000-0000-000000000001"}"#);
+        assert_eq!(to_string(&VmValue::bytes(b"lambda".to_vec()), &options)?, r#"{"$bytes":"bGFtYmRh"}"#);
+        assert_eq!(to_string(&VmValue::Timestamp(0), &options)?, r#"{"$timestamp":"1970-01-01T00:00:00Z"}"#);
+        assert!(matches!(from_str(r#"{"$unknown":"x"}"#, &options), Err(JsonError::InvalidTag(_))));
+        assert!(matches!(from_str(r#"{"$job":"x"}"#, &options), Err(JsonError::InvalidTag(_))));
+        assert!(matches!(from_str(r#"{"$bytes":"AA==","a":1}"#, &options), Err(JsonError::InvalidTag(_))));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn dollar_keys_are_escaped() -> Result<(), Box<dyn std::error::Error>> {
+        let options = JsonOptions::default();
+        let value = VmValue::object(vec!(VmPair { key: "$job".to_string(), value: VmValue::Null }));
+        assert_eq!(to_string(&value, &options)?, r#"{"$$job":null}"#);
+        assert_eq!(from_str(r#"{ "$$job" : null }"#, &options)?, value);
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn strings_are_escaped() -> Result<(), Box<dyn std::error::Error>> {
+        let options = JsonOptions::default();
+        let value = VmValue::string("\"quoted\"\\\n\u{01}ä😀");
+        assert_eq!(to_string(&value, &options)?, "\"\\\"quoted\\\"\\\\\\n\\u0001ä😀\"");
+        assert_eq!(from_str(r#""😀ä\/""#, &options)?, VmValue::string("😀ä/"));
+        assert!(from_str(r#""\ud83d""#, &options).is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn depth_limit_is_enforced() -> Result<(), Box<dyn std::error::Error>> {
+        let options = JsonOptions { max_depth: 2 };
+        assert!(from_str("[[1]]", &options).is_ok());
+        assert!(matches!(from_str("[[[1]]]", &options), Err(JsonError::DepthLimitExceeded(2))));
+        assert!(matches!(from_str(r#"{"a":{"b":{}}}"#, &options), Err(JsonError::DepthLimitExceeded(2))));
+        let nested = VmValue::array(vec!(VmValue::array(vec!(VmValue::array(vec!())))));
+        assert!(matches!(to_string(&nested, &options), Err(JsonError::DepthLimitExceeded(2))));
+        // Tagged values do not count as nesting
+        assert!(from_str(r#"[[{"$bytes":""}]]"#, &options).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn trailing_data_errors() -> Result<(), Box<dyn std::error::Error>> {
+        let options = JsonOptions::default();
+        assert_eq!(from_str(" [1, true] \n", &options)?, VmValue::array(vec!(VmValue::Integer(1), VmValue::Boolean(true))));
+        assert!(matches!(from_str("[1] 2", &options), Err(JsonError::UnexpectedByte { byte: b'2', offset: 4 })));
+        assert!(matches!(from_str("[1,", &options), Err(JsonError::UnexpectedEnd)));
+        Ok(())
+    }
+}
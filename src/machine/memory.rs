@@ -1,8 +1,9 @@
 // Copyright x39
 
+use std::sync::Arc;
 use uuid::Uuid;
 use serde::{Serialize, Deserialize};
-use crate::machine::InstructionArg;
+use crate::machine::{InstructionArg, VmObject};
 use crate::machine::OpCode::Pop;
 use super::OpCode;
 
@@ -42,26 +43,58 @@ impl Instruction {
         };
     }
 
-    pub fn op_assign() -> Instruction {
+    pub fn op_load_local(slot: u16) -> Instruction {
         return Instruction {
-            opcode: OpCode::Assign,
-            arg: InstructionArg::Empty,
+            opcode: OpCode::LoadLocal,
+            arg: InstructionArg::Unsigned(slot),
+        };
+    }
+
+    pub fn op_store_local(slot: u16) -> Instruction {
+        return Instruction {
+            opcode: OpCode::StoreLocal,
+            arg: InstructionArg::Unsigned(slot),
         };
     }
 
-    pub fn op_get_variable_of_type(value_type: VmValueType) -> Instruction {
+    pub fn op_assert_type(value_type: VmValueType) -> Instruction {
         return Instruction {
-            opcode: OpCode::Assign,
+            opcode: OpCode::AssertType,
             arg: InstructionArg::Type(value_type),
         };
     }
 
+    pub fn op_add_local(slot: u16) -> Instruction {
+        return Instruction {
+            opcode: OpCode::AddLocal,
+            arg: InstructionArg::Unsigned(slot),
+        };
+    }
+
     pub fn op_append_array_push() -> Instruction {
         return Instruction {
             opcode: OpCode::AppendArrayPush,
             arg: InstructionArg::Empty,
         };
     }
+    pub fn op_append_property_push() -> Instruction {
+        return Instruction {
+            opcode: OpCode::AppendPropertyPush,
+            arg: InstructionArg::Empty,
+        };
+    }
+    pub fn op_jump_iterate_pair(index: i16) -> Instruction {
+        return Instruction {
+            opcode: OpCode::JumpIteratePair,
+            arg: InstructionArg::Signed(index),
+        };
+    }
+    pub fn op_jump_stream(index: i16) -> Instruction {
+        return Instruction {
+            opcode: OpCode::JumpStream,
+            arg: InstructionArg::Signed(index),
+        };
+    }
     pub fn op_jump_iterate(index: i16) -> Instruction {
         return Instruction {
             opcode: OpCode::JumpIterate,
@@ -92,12 +125,6 @@ impl Instruction {
             arg: InstructionArg::Empty,
         };
     }
-    pub fn op_get_variable() -> Instruction {
-        return Instruction {
-            opcode: OpCode::GetVariable,
-            arg: InstructionArg::Empty,
-        };
-    }
     pub fn op_print_to_console() -> Instruction {
         return Instruction {
             opcode: OpCode::PrintToConsole,
@@ -128,10 +155,16 @@ impl Instruction {
             arg: InstructionArg::Empty,
         };
     }
-    pub fn op_call() -> Instruction {
+    pub fn op_call(arguments_count: u16) -> Instruction {
         return Instruction {
             opcode: OpCode::Call,
-            arg: InstructionArg::Empty,
+            arg: InstructionArg::Unsigned(arguments_count),
+        };
+    }
+    pub fn op_unpack(count: u16) -> Instruction {
+        return Instruction {
+            opcode: OpCode::Unpack,
+            arg: InstructionArg::Unsigned(count),
         };
     }
     pub fn op_call_no_arg() -> Instruction {
@@ -193,6 +226,57 @@ pub enum VmValueType {
     Array,
     ArrayOfJobs,
     Job,
+    String,
+    Number,
+    Boolean,
+    Object,
+    Integer,
+    Bytes,
+    Timestamp,
+}
+
+impl VmValueType {
+    /// All types, indexed by their encoding in bytecode files.
+    pub const ALL: [VmValueType; 11] = [
+        VmValueType::Null,
+        VmValueType::Array,
+        VmValueType::ArrayOfJobs,
+        VmValueType::Job,
+        VmValueType::String,
+        VmValueType::Number,
+        VmValueType::Boolean,
+        VmValueType::Object,
+        VmValueType::Integer,
+        VmValueType::Bytes,
+        VmValueType::Timestamp,
+    ];
+
+    pub fn from_u8(byte: u8) -> Option<VmValueType> {
+        VmValueType::ALL.get(byte as usize).cloned()
+    }
+}
+
+/// Debug information mapping a local variable slot back to its name
+/// for the range of instructions (`from` inclusive, `to` exclusive) the variable is visible in.
+#[derive(Debug)]
+#[derive(PartialEq, Clone)]
+#[derive(Serialize, Deserialize)]
+pub struct VmLocalInfo {
+    pub name: String,
+    pub slot: u16,
+    pub from: usize,
+    pub to: usize,
+}
+
+/// Debug information mapping the instructions from `from` on, up to those of the next statement,
+/// back to the line and column of the statement they were compiled from, both starting at 1.
+#[derive(Debug)]
+#[derive(PartialEq, Clone)]
+#[derive(Serialize, Deserialize)]
+pub struct VmLineInfo {
+    pub from: usize,
+    pub line: usize,
+    pub column: usize,
 }
 
 #[derive(Debug)]
@@ -203,15 +287,24 @@ pub struct VmPair {
     pub value: VmValue,
 }
 
+/// A value of the virtual machine.
+///
+/// Strings, arrays and objects are reference counted, making clones cheap.
+/// Mutation goes through [Arc::make_mut], copying the contents only if they are shared,
+/// so scripts still observe value semantics.
 #[derive(Debug)]
 #[derive(PartialEq, Clone)]
 #[derive(Serialize, Deserialize)]
 pub enum VmValue {
     Null,
-    String(String),
+    String(Arc<String>),
     Number(f64),
-    Array(Vec<VmValue>),
+    Array(Arc<Vec<VmValue>>),
     Boolean(bool),
-    Object(Vec<VmPair>),
+    Object(Arc<VmObject>),
     Job(Uuid),
+    Integer(i64),
+    Bytes(Arc<Vec<u8>>),
+    /// Microseconds since the unix epoch (UTC).
+    Timestamp(i64),
 }
@@ -21,11 +21,15 @@ pub enum OpCode {
     PushEmptyArray,
     /// PUSH a new, empty object to the stack.
     PushEmptyObject,
-    /// POP a string and PUSH a variable
-    GetVariable,
-    /// POP a string and PUSH a variable after checking type::ARG or ERROR if variable is not
-    /// of type.
-    GetVariableOfType,
+    /// PUSH the value of the local variable at slot u16::ARG.
+    LoadLocal,
+    /// POP a value and store it in the local variable at slot u16::ARG.
+    StoreLocal,
+    /// POP a value and add it to the local variable at slot u16::ARG in place,
+    /// appending to arrays and ERROR if the types cannot be added (see `VmValue::add_assign`).
+    AddLocal,
+    /// PEEK a value and ERROR if it is not of type::ARG.
+    AssertType,
     /// POP a job and halt the execution until it completed.
     Await,
     /// POP a job and abort its scheduled execution if possible.
@@ -36,8 +40,9 @@ pub enum OpCode {
     AwaitAny,
     /// POP an array of jobs and halt the execution until all have completed.
     AwaitAll,
-    /// POP a string to interpret as function name and POP a value to pass and PUSH a job,
-    /// executing the function, passing the argument.
+    /// POP u16::ARG values to pass, the last argument being on top, and POP a string to
+    /// interpret as function name and PUSH a job, executing the function, passing the arguments.
+    /// A missing ARG passes a single value.
     Call,
     /// POP a string to interpret as function name and PUSH a job,
     /// executing the function.
@@ -46,11 +51,9 @@ pub enum OpCode {
     /// to the array and PUSH the array back onto the stack.
     AppendArrayPush,
     /// POP a value from the stack and POP a string from the stack and POP an object from the stack
-    /// and append a property to the object with the string as key and the value as value and
-    /// PUSH the object back onto the stack.
+    /// and set the property of the object with the string as key to the value, overwriting
+    /// any existing value, and PUSH the object back onto the stack.
     AppendPropertyPush,
-    /// POP a string and POP a value and assign the value to a variable named as the string.
-    Assign,
     /// POP a value from the stack and dispose of it immediate.
     Pop,
     /// Jump i16::ARG instructions.
@@ -69,8 +72,72 @@ pub enum OpCode {
     /// If index out of range:
     /// Jump i16::ARG instructions.
     JumpIterate,
+    /// Specialized jump instruction for foreach support, iterating keys and values.
+    /// -0: POP an index
+    /// -1: POP an array or object
+    /// If array or object has index elements:
+    /// 0: PUSH array or object
+    /// 1: PUSH index + 1
+    /// 2: PUSH key at index of object or index itself for arrays
+    /// 3: PUSH value at index of array or object
+    /// If index out of range:
+    /// Jump i16::ARG instructions.
+    JumpIteratePair,
+    /// Specialized jump instruction for iterating the partial results of a job.
+    /// -0: POP a job
+    /// If a partial result of the job is available:
+    /// 0: PUSH job
+    /// 1: PUSH partial result
+    /// If the job completed and all partial results were taken:
+    /// Jump i16::ARG instructions.
+    /// Otherwise, halt the execution until either is the case.
+    JumpStream,
+    /// POP an array and PUSH its elements in order, the last element being on top.
+    /// ERROR if the array does not have exactly u16::ARG elements.
+    Unpack,
     /// POP 2 elements and PUSH them in reverse order.
     Swap2,
     /// POP a value and print it to console
     PrintToConsole,
-}
\ No newline at end of file
+}
+
+impl OpCode {
+    /// All opcodes, indexed by their encoding in bytecode files.
+    pub const ALL: [OpCode; 31] = [
+        OpCode::NoOp,
+        OpCode::Exit,
+        OpCode::PushValueU16,
+        OpCode::PushTrue,
+        OpCode::PushFalse,
+        OpCode::PushNull,
+        OpCode::PushEmptyArray,
+        OpCode::PushEmptyObject,
+        OpCode::LoadLocal,
+        OpCode::StoreLocal,
+        OpCode::AddLocal,
+        OpCode::AssertType,
+        OpCode::Await,
+        OpCode::Abort,
+        OpCode::AbortAll,
+        OpCode::AwaitAny,
+        OpCode::AwaitAll,
+        OpCode::Call,
+        OpCode::CallNoArg,
+        OpCode::AppendArrayPush,
+        OpCode::AppendPropertyPush,
+        OpCode::Pop,
+        OpCode::Jump,
+        OpCode::JumpIfFalse,
+        OpCode::JumpIfTrue,
+        OpCode::JumpIterate,
+        OpCode::JumpIteratePair,
+        OpCode::JumpStream,
+        OpCode::Unpack,
+        OpCode::Swap2,
+        OpCode::PrintToConsole,
+    ];
+
+    pub fn from_u8(byte: u8) -> Option<OpCode> {
+        OpCode::ALL.get(byte as usize).copied()
+    }
+}
@@ -1,4 +1,194 @@
+//! The bytecode file format (`.x39b`), storing compiled scripts to run them without the source.
+//!
+//! All numbers are little endian. A file starts with the magic `X39B` and the format version
+//! as u16, followed by the value list, the instructions and the debug information of locals and
+//! lines, the latter lacking in format version 1.
+//! Lists are prefixed with their length as u32, strings with their length in bytes as u32,
+//! the instruction range of locals and the first instruction of lines are stored as u64, the
+//! line and column as u32.
+//! Values are stored as their lossless JSON encoding, see [crate::machine::json].
+
+use std::error::Error;
+use std::io::{Read, Write};
+use crate::machine::json::{self, JsonOptions};
+use crate::machine::{Instruction, InstructionArg, OpCode, VmLineInfo, VmLocalInfo, VmValueType};
 use super::vm_state::VmState;
 
+const MAGIC: &[u8; 4] = b"X39B";
+const FORMAT_VERSION: u16 = 2;
+/// The format version lacking the debug information of lines, which is still read.
+const FORMAT_VERSION_WITHOUT_LINES: u16 = 1;
+
+const ARG_EMPTY: u8 = 0;
+const ARG_UNSIGNED: u8 = 1;
+const ARG_SIGNED: u8 = 2;
+const ARG_TYPE: u8 = 3;
+
 impl VmState {
-}
\ No newline at end of file
+    /// Writes the bytecode of the state, not including its progress.
+    pub fn write_bytecode(&self, writer: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        write_length(writer, self.values().len())?;
+        for value in self.values() {
+            write_string(writer, &json::to_string(value, &JsonOptions::default())?)?;
+        }
+        write_length(writer, self.instructions().len())?;
+        for instruction in self.instructions() {
+            writer.write_all(&[instruction.opcode as u8])?;
+            match &instruction.arg {
+                InstructionArg::Empty => writer.write_all(&[ARG_EMPTY])?,
+                InstructionArg::Unsigned(unsigned) => {
+                    writer.write_all(&[ARG_UNSIGNED])?;
+                    writer.write_all(&unsigned.to_le_bytes())?;
+                }
+                InstructionArg::Signed(signed) => {
+                    writer.write_all(&[ARG_SIGNED])?;
+                    writer.write_all(&signed.to_le_bytes())?;
+                }
+                InstructionArg::Type(value_type) => writer.write_all(&[ARG_TYPE, value_type.clone() as u8])?,
+            }
+        }
+        write_length(writer, self.locals().len())?;
+        for local in self.locals() {
+            write_string(writer, &local.name)?;
+            writer.write_all(&local.slot.to_le_bytes())?;
+            writer.write_all(&(local.from as u64).to_le_bytes())?;
+            writer.write_all(&(local.to as u64).to_le_bytes())?;
+        }
+        write_length(writer, self.lines().len())?;
+        for line in self.lines() {
+            writer.write_all(&(line.from as u64).to_le_bytes())?;
+            writer.write_all(&u32::try_from(line.line).unwrap_or(u32::MAX).to_le_bytes())?;
+            writer.write_all(&u32::try_from(line.column).unwrap_or(u32::MAX).to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Reads bytecode written by [VmState::write_bytecode] into a new state.
+    pub fn read_bytecode(reader: &mut dyn Read) -> Result<VmState, Box<dyn Error>> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic).map_err(|_| "Not a bytecode file")?;
+        if &magic != MAGIC {
+            return Err("Not a bytecode file".into());
+        }
+        let version = u16::from_le_bytes(read_array(reader)?);
+        if version != FORMAT_VERSION && version != FORMAT_VERSION_WITHOUT_LINES {
+            return Err(format!("Bytecode format version {} is not supported", version).into());
+        }
+        let mut vm_state = VmState::new();
+        for index in 0..read_length(reader)? {
+            let value = json::from_str(&read_string(reader)?, &JsonOptions::default())?;
+            if vm_state.value_index(value) as usize != index {
+                return Err("Bytecode contains a value twice".into());
+            }
+        }
+        for _ in 0..read_length(reader)? {
+            let [opcode, arg] = read_array(reader)?;
+            let opcode = OpCode::from_u8(opcode).ok_or_else(|| format!("Unknown opcode {}", opcode))?;
+            let arg = match arg {
+                ARG_EMPTY => InstructionArg::Empty,
+                ARG_UNSIGNED => InstructionArg::Unsigned(u16::from_le_bytes(read_array(reader)?)),
+                ARG_SIGNED => InstructionArg::Signed(i16::from_le_bytes(read_array(reader)?)),
+                ARG_TYPE => {
+                    let [value_type] = read_array(reader)?;
+                    InstructionArg::Type(VmValueType::from_u8(value_type).ok_or_else(|| format!("Unknown type {}", value_type))?)
+                }
+                _ => return Err(format!("Unknown instruction argument {}", arg).into()),
+            };
+            vm_state.push_instruction(Instruction { opcode, arg });
+        }
+        for _ in 0..read_length(reader)? {
+            let name = read_string(reader)?;
+            let slot = u16::from_le_bytes(read_array(reader)?);
+            let from = u64::from_le_bytes(read_array(reader)?).try_into().unwrap_or(usize::MAX);
+            let to = u64::from_le_bytes(read_array(reader)?).try_into().unwrap_or(usize::MAX);
+            vm_state.push_local_info(VmLocalInfo { name, slot, from, to });
+        }
+        if version == FORMAT_VERSION_WITHOUT_LINES {
+            return Ok(vm_state);
+        }
+        for _ in 0..read_length(reader)? {
+            let from = u64::from_le_bytes(read_array(reader)?).try_into().unwrap_or(usize::MAX);
+            let line = u32::from_le_bytes(read_array(reader)?) as usize;
+            let column = u32::from_le_bytes(read_array(reader)?) as usize;
+            vm_state.push_line_info(VmLineInfo { from, line, column });
+        }
+        Ok(vm_state)
+    }
+}
+
+fn write_length(writer: &mut dyn Write, length: usize) -> Result<(), Box<dyn Error>> {
+    let length = u32::try_from(length).map_err(|_| "Bytecode exceeds the size supported")?;
+    writer.write_all(&length.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_string(writer: &mut dyn Write, string: &str) -> Result<(), Box<dyn Error>> {
+    write_length(writer, string.len())?;
+    writer.write_all(string.as_bytes())?;
+    Ok(())
+}
+
+fn read_array<const N: usize>(reader: &mut dyn Read) -> Result<[u8; N], Box<dyn Error>> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes).map_err(|_| "Bytecode ended unexpectedly")?;
+    Ok(bytes)
+}
+
+fn read_length(reader: &mut dyn Read) -> Result<usize, Box<dyn Error>> {
+    Ok(u32::from_le_bytes(read_array(reader)?) as usize)
+}
+
+fn read_string(reader: &mut dyn Read) -> Result<String, Box<dyn Error>> {
+    let length = read_length(reader)?;
+    let mut bytes = vec!();
+    reader.take(length as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != length {
+        return Err("Bytecode ended unexpectedly".into());
+    }
+    Ok(String::from_utf8(bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_test::traced_test;
+    use crate::machine::{OpCode, VmState, VmValueType};
+
+    #[test]
+    #[traced_test]
+    fn bytecode_round_trips() -> Result<(), Box<dyn std::error::Error>> {
+        let (_, file) = crate::assembler::parser::parser::parse_x39file(
+            "let x: integer = 1; data = { \"at\": t\"2022-10-24T11:37:00Z\", \"raw\": x\"00ff\" }; for item in [1, 2.5] { x += 1; }")?;
+        let vm_state = crate::assembler::compiler::compiler::compile(file)?;
+        let mut bytes = vec!();
+        vm_state.write_bytecode(&mut bytes)?;
+        let read = VmState::read_bytecode(&mut bytes.as_slice())?;
+        assert_eq!(read.values(), vm_state.values());
+        assert_eq!(read.instructions(), vm_state.instructions());
+        assert_eq!(read.locals(), vm_state.locals());
+        assert_eq!(read.lines(), vm_state.lines());
+        // Bytecode written before lines were stored is read without them
+        let locals_end = bytes.len() - 4 - vm_state.lines().len() * 16;
+        let mut without_lines = bytes[..locals_end].to_vec();
+        without_lines[4..6].copy_from_slice(&1u16.to_le_bytes());
+        let read = VmState::read_bytecode(&mut without_lines.as_slice())?;
+        assert_eq!((read.instructions(), read.lines()), (vm_state.instructions(), &[][..]));
+        assert_eq!(VmState::read_bytecode(&mut &bytes[..bytes.len() - 1]).err().map(|it| it.to_string()),
+            Some("Bytecode ended unexpectedly".to_string()));
+        assert_eq!(VmState::read_bytecode(&mut &b"X39A"[..]).err().map(|it| it.to_string()),
+            Some("Not a bytecode file".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn encodings_match_declaration_order() {
+        for (index, opcode) in OpCode::ALL.iter().enumerate() {
+            assert_eq!(*opcode as usize, index);
+        }
+        for (index, value_type) in VmValueType::ALL.iter().enumerate() {
+            assert_eq!(value_type.clone() as usize, index);
+        }
+    }
+}
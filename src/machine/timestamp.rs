@@ -0,0 +1,173 @@
+//! Conversion of [VmValue::Timestamp](crate::machine::VmValue::Timestamp) values, being
+//! microseconds since the unix epoch (UTC), from and to RFC 3339 strings.
+
+const MICROS_PER_SECOND: i64 = 1_000_000;
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// Days since the unix epoch of the given proleptic gregorian calendar date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The proleptic gregorian calendar date (year, month, day) of the given days since the unix epoch.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 => if is_leap_year(year) { 29 } else { 28 },
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn parse_digits(input: &str, from: usize, count: usize) -> Option<i64> {
+    let digits = input.get(from..from + count)?;
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// Parses an RFC 3339 date-time, eg. `2022-10-24T13:37:00.5+02:00`, into microseconds
+/// since the unix epoch. Fractions beyond microseconds are truncated.
+pub fn parse_rfc3339(input: &str) -> Option<i64> {
+    let bytes = input.as_bytes();
+    if bytes.len() < 20
+        || bytes[4] != b'-' || bytes[7] != b'-'
+        || !matches!(bytes[10], b'T' | b't' | b' ')
+        || bytes[13] != b':' || bytes[16] != b':' {
+        return None;
+    }
+    let year = parse_digits(input, 0, 4)?;
+    let month = parse_digits(input, 5, 2)?;
+    let day = parse_digits(input, 8, 2)?;
+    let hour = parse_digits(input, 11, 2)?;
+    let minute = parse_digits(input, 14, 2)?;
+    let second = parse_digits(input, 17, 2)?;
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month)
+        || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let mut index = 19;
+    let mut micros = 0;
+    if bytes[index] == b'.' {
+        index += 1;
+        let start = index;
+        while index < bytes.len() && bytes[index].is_ascii_digit() {
+            if index - start < 6 {
+                micros = micros * 10 + (bytes[index] - b'0') as i64;
+            }
+            index += 1;
+        }
+        if index == start {
+            return None;
+        }
+        for _ in (index - start)..6 {
+            micros *= 10;
+        }
+    }
+
+    let offset_seconds = match input.get(index..)? {
+        "Z" | "z" => 0,
+        offset if offset.len() == 6 && offset.as_bytes()[3] == b':' => {
+            let sign = match offset.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let offset_hour = parse_digits(offset, 1, 2)?;
+            let offset_minute = parse_digits(offset, 4, 2)?;
+            if offset_hour > 23 || offset_minute > 59 {
+                return None;
+            }
+            sign * (offset_hour * 3600 + offset_minute * 60)
+        }
+        _ => return None,
+    };
+
+    let seconds = days_from_civil(year, month, day) * SECONDS_PER_DAY
+        + hour * 3600 + minute * 60 + second - offset_seconds;
+    seconds.checked_mul(MICROS_PER_SECOND)?.checked_add(micros)
+}
+
+/// Formats microseconds since the unix epoch as RFC 3339 date-time in UTC,
+/// eg. `2022-10-24T11:37:00.500000Z`. The fraction is omitted if zero.
+pub fn format_rfc3339(micros: i64) -> String {
+    let seconds = micros.div_euclid(MICROS_PER_SECOND);
+    let fraction = micros.rem_euclid(MICROS_PER_SECOND);
+    let days = seconds.div_euclid(SECONDS_PER_DAY);
+    let second_of_day = seconds.rem_euclid(SECONDS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+    let mut output = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year, month, day,
+        second_of_day / 3600, second_of_day / 60 % 60, second_of_day % 60);
+    if fraction != 0 {
+        output.push_str(&format!(".{:06}", fraction));
+    }
+    output.push('Z');
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_test::traced_test;
+    use super::*;
+
+    #[test]
+    #[traced_test]
+    fn parse_epoch() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), Some(0));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn parse_with_fraction_and_offset() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(parse_rfc3339("2022-10-24T13:37:00.5+02:00"), Some(1_666_611_420_500_000));
+        assert_eq!(parse_rfc3339("1969-12-31T23:59:59.999999Z"), Some(-1));
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn parse_invalid_is_none() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(parse_rfc3339("2022-02-29T00:00:00Z"), None);
+        assert_eq!(parse_rfc3339("2022-10-24 13:37:00"), None);
+        assert_eq!(parse_rfc3339("2022-10-24T13:37:00."), None);
+        assert_eq!(parse_rfc3339("2022-10-24T13:37:00+0200"), None);
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn format_round_trips() -> Result<(), Box<dyn std::error::Error>> {
+        for micros in [0, -1, 1_666_611_420_500_000, 951_782_400_000_000, -62_135_596_800_000_000] {
+            assert_eq!(parse_rfc3339(&format_rfc3339(micros)), Some(micros));
+        }
+        assert_eq!(format_rfc3339(1_666_611_420_500_000), "2022-10-24T11:37:00.500000Z");
+        Ok(())
+    }
+}
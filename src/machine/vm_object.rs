@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use crate::machine::{VmPair, VmValue};
+
+/// An object of the virtual machine, mapping unique keys to values.
+///
+/// Keys keep the order they were first inserted in. Inserting an existing key overwrites
+/// the value in place. Two objects are equal if they hold the same pairs in the same order.
+///
+/// Serializes as a sequence of [VmPair]s.
+#[derive(Debug)]
+#[derive(Clone, Default)]
+#[derive(Serialize, Deserialize)]
+#[serde(from = "Vec<VmPair>", into = "Vec<VmPair>")]
+pub struct VmObject {
+    entries: Vec<VmPair>,
+    index: HashMap<String, usize>,
+}
+
+impl VmObject {
+    pub fn new() -> VmObject {
+        VmObject {
+            entries: vec!(),
+            index: HashMap::new(),
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.index.contains_key(key)
+    }
+    pub fn get(&self, key: &str) -> Option<&VmValue> {
+        self.index.get(key).map(|index| &self.entries[*index].value)
+    }
+    /// Returns the pair at the position provided, in insertion order.
+    pub fn get_index(&self, index: usize) -> Option<&VmPair> {
+        self.entries.get(index)
+    }
+    /// Inserts the value under the key provided, returning the value previously stored
+    /// under that key. An overwritten key keeps its original position.
+    pub fn insert(&mut self, key: String, value: VmValue) -> Option<VmValue> {
+        match self.index.get(&key) {
+            Some(index) => Some(std::mem::replace(&mut self.entries[*index].value, value)),
+            None => {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push(VmPair { key, value });
+                None
+            }
+        }
+    }
+    pub fn iter(&self) -> std::slice::Iter<'_, VmPair> {
+        self.entries.iter()
+    }
+}
+
+impl PartialEq for VmObject {
+    fn eq(&self, other: &Self) -> bool {
+        self.entries == other.entries
+    }
+}
+
+impl From<Vec<VmPair>> for VmObject {
+    /// Builds an object from the pairs provided, later pairs overwriting earlier ones
+    /// with the same key.
+    fn from(pairs: Vec<VmPair>) -> Self {
+        let mut object = VmObject::new();
+        for pair in pairs {
+            object.insert(pair.key, pair.value);
+        }
+        object
+    }
+}
+
+impl From<VmObject> for Vec<VmPair> {
+    fn from(object: VmObject) -> Self {
+        object.entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_test::traced_test;
+    use crate::machine::*;
+
+    #[test]
+    #[traced_test]
+    fn insert_overwrites_existing_key_in_place() -> Result<(), Box<dyn std::error::Error>> {
+        let mut object = VmObject::new();
+        assert_eq!(object.insert("a".into(), VmValue::Number(1.0)), None);
+        assert_eq!(object.insert("b".into(), VmValue::Number(2.0)), None);
+        assert_eq!(object.insert("a".into(), VmValue::Number(3.0)), Some(VmValue::Number(1.0)));
+        let keys: Vec<&str> = object.iter().map(|pair| pair.key.as_str()).collect();
+        assert_eq!(keys, vec!["a", "b"]);
+        assert_eq!(object.get("a"), Some(&VmValue::Number(3.0)));
+        assert_eq!(object.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn get_missing_key_is_none() -> Result<(), Box<dyn std::error::Error>> {
+        let object = VmObject::from(vec!(VmPair { key: "a".into(), value: VmValue::Null }));
+        assert!(object.contains_key("a"));
+        assert_eq!(object.get("b"), None);
+        assert_eq!(object.get_index(1), None);
+        Ok(())
+    }
+
+    #[test]
+    #[traced_test]
+    fn from_pairs_keeps_last_duplicate() -> Result<(), Box<dyn std::error::Error>> {
+        let object = VmObject::from(vec!(
+            VmPair { key: "a".into(), value: VmValue::Number(1.0) },
+            VmPair { key: "b".into(), value: VmValue::Number(2.0) },
+            VmPair { key: "a".into(), value: VmValue::Number(3.0) },
+        ));
+        let pairs: Vec<VmPair> = object.into();
+        assert_eq!(pairs, vec!(
+            VmPair { key: "a".into(), value: VmValue::Number(3.0) },
+            VmPair { key: "b".into(), value: VmValue::Number(2.0) },
+        ));
+        Ok(())
+    }
+}
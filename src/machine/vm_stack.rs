@@ -1,16 +1,41 @@
+use std::sync::Arc;
 use uuid::Uuid;
-use crate::machine::{VmPair, VmValue};
+use crate::machine::{VmObject, VmValue};
 
 pub struct VmStack {
     data: Vec<VmValue>,
-    variables: Vec<VmPair>,
+    locals: Vec<VmValue>,
+}
+
+impl std::fmt::Debug for VmStack {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "Values: {}", self.data.len())?;
+        for (index, it) in self.data.iter().enumerate() {
+            write!(f, "    {:04}: ", index)?;
+            it.fmt(f)?;
+            writeln!(f)?;
+        }
+        writeln!(f, "Locals: {}", self.locals.len())?;
+        for (index, it) in self.locals.iter().enumerate() {
+            write!(f, "    {:04}: ", index)?;
+            it.fmt(f)?;
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for VmStack {
+    fn default() -> Self {
+        VmStack::new()
+    }
 }
 
 impl VmStack {
     pub fn new() -> VmStack {
         return VmStack {
             data: vec!(),
-            variables: vec!(),
+            locals: vec!(),
         };
     }
     pub fn push_value(&mut self, value: VmValue) {
@@ -25,7 +50,7 @@ impl VmStack {
         }
     }
 
-    pub fn pop_object(&mut self) -> Result<Vec<VmPair>, &'static str> {
+    pub fn pop_object(&mut self) -> Result<Arc<VmObject>, &'static str> {
         let candidate = self.pop_value()?;
         match candidate {
             VmValue::Object(object) => Ok(object),
@@ -33,7 +58,7 @@ impl VmStack {
         }
     }
 
-    pub fn pop_array(&mut self) -> Result<Vec<VmValue>, &'static str> {
+    pub fn pop_array(&mut self) -> Result<Arc<Vec<VmValue>>, &'static str> {
         let candidate = self.pop_value()?;
         match candidate {
             VmValue::Array(array) => Ok(array),
@@ -41,7 +66,7 @@ impl VmStack {
         }
     }
 
-    pub fn pop_string(&mut self) -> Result<String, &'static str> {
+    pub fn pop_string(&mut self) -> Result<Arc<String>, &'static str> {
         let candidate = self.pop_value()?;
         match candidate {
             VmValue::String(string) => Ok(string),
@@ -57,6 +82,14 @@ impl VmStack {
         }
     }
 
+    pub fn pop_integer(&mut self) -> Result<i64, &'static str> {
+        let candidate = self.pop_value()?;
+        match candidate {
+            VmValue::Integer(i) => Ok(i),
+            _ => Err("Failed to pop INTEGER value from stack"),
+        }
+    }
+
     pub fn pop_bool(&mut self) -> Result<bool, &'static str> {
         let candidate = self.pop_value()?;
         match candidate {
@@ -65,27 +98,23 @@ impl VmStack {
         }
     }
 
-    pub fn get_variable<S>(&self, name: S) -> Option<VmValue> where S: Into<String> {
-        let key = name.into();
-        for vm_pair in self.variables.iter() {
-            if vm_pair.key == key {
-                return Some(vm_pair.value.clone());
-            }
-        }
-        return None;
+    pub fn get_local(&self, slot: u16) -> Option<&VmValue> {
+        self.locals.get(slot as usize)
     }
-    pub fn set_variable<S>(&mut self, name: S, value: VmValue) where S: Into<String> {
-        let key = name.into();
-        for mut vm_pair in self.variables.iter_mut() {
-            if vm_pair.key == key {
-                vm_pair.value = value;
-                return;
-            }
+    pub fn set_local(&mut self, slot: u16, value: VmValue) {
+        let index = slot as usize;
+        if index >= self.locals.len() {
+            self.locals.resize(index + 1, VmValue::Null);
         }
-        self.variables.push(VmPair {
-            key,
-            value,
-        });
+        self.locals[index] = value;
+    }
+    /// Grants mutable access to the local variable at the slot provided, allowing
+    /// in-place mutation of reference counted values that are not shared.
+    pub fn get_local_mut(&mut self, slot: u16) -> Option<&mut VmValue> {
+        self.locals.get_mut(slot as usize)
+    }
+    pub fn locals_len(&self) -> usize {
+        self.locals.len()
     }
 
     pub fn pop_job(&mut self) -> Result<Uuid, &'static str> {
@@ -104,9 +133,9 @@ impl VmStack {
         let mut jobs: Vec<Uuid> = vec!();
         match array_value {
             VmValue::Array(array) => {
-                for value in array {
+                for value in array.iter() {
                     match value {
-                        VmValue::Job(uuid) => jobs.push(uuid),
+                        VmValue::Job(uuid) => jobs.push(*uuid),
                         _ => return Err("Not all elements in array are of type job.")
                     }
                 }
@@ -128,7 +157,7 @@ mod tests {
     #[traced_test]
     fn new_creates_empty_stack() -> Result<(), Box<dyn std::error::Error>> {
         let stack = VmStack::new();
-        match stack.data.len() == 0 && stack.variables.len() == 0 {
+        match stack.data.is_empty() && stack.locals.is_empty() {
             false => Err("VmStack::new() creates non-empty stack".into()),
             true => Ok(()),
         }
@@ -181,12 +210,12 @@ mod tests {
     #[traced_test]
     fn pop_object_correct_type_no_error() -> Result<(), Box<dyn std::error::Error>> {
         let mut stack = VmStack::new();
-        stack.push_value(VmValue::Object(vec!(VmPair {
+        stack.push_value(VmValue::object(vec!(VmPair {
             key: "abc".into(),
             value: VmValue::Null,
         })));
         match stack.pop_object() {
-            Ok(v) => if v.len() == 1 && v[0].key == "abc" && v[0].value.is_null() {
+            Ok(v) => if v.len() == 1 && v.get("abc").is_some_and(|value| value.is_null()) {
                 Ok(())
             } else {
                 Err("pop_object with VmValue::Object returned a value \
@@ -211,7 +240,7 @@ mod tests {
     #[traced_test]
     fn pop_array_correct_type_no_error() -> Result<(), Box<dyn std::error::Error>> {
         let mut stack = VmStack::new();
-        stack.push_value(VmValue::Array(vec!(VmValue::Null)));
+        stack.push_value(VmValue::array(vec!(VmValue::Null)));
         match stack.pop_array() {
             Ok(v) => if v.len() == 1 && v[0].is_null() {
                 Ok(())
@@ -238,9 +267,9 @@ mod tests {
     #[traced_test]
     fn pop_string_correct_type_no_error() -> Result<(), Box<dyn std::error::Error>> {
         let mut stack = VmStack::new();
-        stack.push_value(VmValue::String("FooBar".into()));
+        stack.push_value(VmValue::string("FooBar"));
         match stack.pop_string() {
-            Ok(v) => if v == "FooBar" {
+            Ok(v) => if *v == "FooBar" {
                 Ok(())
             } else {
                 Err("pop_string with VmValue::String returned a value \
@@ -277,6 +306,33 @@ mod tests {
         }
     }
 
+    #[test]
+    #[traced_test]
+    fn pop_integer_wrong_type_errors() -> Result<(), Box<dyn std::error::Error>> {
+        let mut stack = VmStack::new();
+        stack.push_value(VmValue::Number(1.0));
+        match stack.pop_integer() {
+            Ok(_) => Err("pop_integer with VmValue::Number returned valid integer".into()),
+            Err(_) => Ok(()),
+        }
+    }
+
+    #[test]
+    #[traced_test]
+    fn pop_integer_correct_type_no_error() -> Result<(), Box<dyn std::error::Error>> {
+        let mut stack = VmStack::new();
+        stack.push_value(VmValue::Integer(i64::MAX));
+        match stack.pop_integer() {
+            Ok(v) => if v == i64::MAX {
+                Ok(())
+            } else {
+                Err("pop_integer with VmValue::Integer returned a value \
+                but the value does not hold the expected integer".into())
+            },
+            Err(_) => Err("pop_integer with VmValue::Integer returned an error".into()),
+        }
+    }
+
     #[test]
     #[traced_test]
     fn pop_bool_wrong_type_errors() -> Result<(), Box<dyn std::error::Error>> {
@@ -334,62 +390,56 @@ mod tests {
 
     #[test]
     #[traced_test]
-    fn get_variable_empty_not_existing() -> Result<(), Box<dyn std::error::Error>> {
+    fn get_local_empty_not_existing() -> Result<(), Box<dyn std::error::Error>> {
         let stack = VmStack::new();
-        match stack.get_variable("foobar") {
-            Some(_) => Err("get_variable returned a value for foobar \
-            but no variable exists on the stack.".into()),
+        match stack.get_local(0) {
+            Some(_) => Err("get_local returned a value for slot 0 \
+            but no local exists on the stack.".into()),
             None => Ok(()),
         }
     }
 
     #[test]
     #[traced_test]
-    fn get_variable_filled_not_existing() -> Result<(), Box<dyn std::error::Error>> {
+    fn get_local_filled_not_existing() -> Result<(), Box<dyn std::error::Error>> {
         let mut stack = VmStack::new();
-        stack.variables.push(VmPair {
-            key: "barfoo".into(),
-            value: VmValue::Null,
-        });
-        match stack.get_variable("foobar") {
-            Some(_) => Err("get_variable returned a value for foobar \
-            but no variable exists on the stack.".into()),
+        stack.locals.push(VmValue::Null);
+        match stack.get_local(1) {
+            Some(_) => Err("get_local returned a value for slot 1 \
+            but no such local exists on the stack.".into()),
             None => Ok(()),
         }
     }
 
     #[test]
     #[traced_test]
-    fn get_variable_existing() -> Result<(), Box<dyn std::error::Error>> {
+    fn get_local_existing() -> Result<(), Box<dyn std::error::Error>> {
         let mut stack = VmStack::new();
-        stack.variables.push(VmPair {
-            key: "foobar".into(),
-            value: VmValue::Boolean(true),
-        });
-        match stack.get_variable("foobar") {
+        stack.locals.push(VmValue::Boolean(true));
+        match stack.get_local(0) {
             Some(v) => match v {
-                VmValue::Boolean(flag) => if flag == true {
+                VmValue::Boolean(flag) => if *flag {
                     Ok(())
                 } else {
-                    Err("get_variable returned a value for foobar \
+                    Err("get_local returned a value for slot 0 \
                 but the value does not hold the expected true bool".into())
                 },
-                _ => Err("get_variable returned a value for foobar \
+                _ => Err("get_local returned a value for slot 0 \
                 but the value does not hold the expected type".into())
             },
-            None => Err("get_variable returned no value for foobar \
-            but should have as the variable exists.".into()),
+            None => Err("get_local returned no value for slot 0 \
+            but should have as the local exists.".into()),
         }
     }
 
     #[test]
     #[traced_test]
-    fn get_variable_empty_not_creating_variable() -> Result<(), Box<dyn std::error::Error>> {
+    fn get_local_empty_not_creating_local() -> Result<(), Box<dyn std::error::Error>> {
         let stack = VmStack::new();
-        let len = stack.variables.len();
-        stack.get_variable("foobar");
-        if stack.variables.len() != len {
-            Err("get_variable created a variable when it should not have been able to.".into())
+        let len = stack.locals.len();
+        stack.get_local(0);
+        if stack.locals.len() != len {
+            Err("get_local created a local when it should not have been able to.".into())
         } else {
             Ok(())
         }
@@ -397,12 +447,11 @@ mod tests {
 
     #[test]
     #[traced_test]
-    fn set_variable_empty_not_existing_creates_new_pair() -> Result<(), Box<dyn std::error::Error>> {
+    fn set_local_empty_not_existing_creates_slot() -> Result<(), Box<dyn std::error::Error>> {
         let mut stack = VmStack::new();
-        let len = stack.variables.len();
-        stack.set_variable("foobar", VmValue::Null);
-        if stack.variables.len() != len + 1 {
-            Err("set_variable did not create a new VmPair in variables section of stack.".into())
+        stack.set_local(0, VmValue::Boolean(true));
+        if stack.locals.len() != 1 {
+            Err("set_local did not create a new slot in locals section of stack.".into())
         } else {
             Ok(())
         }
@@ -410,16 +459,11 @@ mod tests {
 
     #[test]
     #[traced_test]
-    fn set_variable_filled_not_existing_creates_new_pair() -> Result<(), Box<dyn std::error::Error>> {
+    fn set_local_beyond_end_fills_with_null() -> Result<(), Box<dyn std::error::Error>> {
         let mut stack = VmStack::new();
-        stack.variables.push(VmPair {
-            key: "barfoo".into(),
-            value: VmValue::Boolean(true),
-        });
-        let len = stack.variables.len();
-        stack.set_variable("foobar", VmValue::Null);
-        if stack.variables.len() != len + 1 {
-            Err("set_variable did not create a new VmPair in variables section of stack.".into())
+        stack.set_local(2, VmValue::Boolean(true));
+        if stack.locals != vec![VmValue::Null, VmValue::Null, VmValue::Boolean(true)] {
+            Err("set_local did not fill the skipped slots with null.".into())
         } else {
             Ok(())
         }
@@ -427,17 +471,14 @@ mod tests {
 
     #[test]
     #[traced_test]
-    fn set_variable_existing_no_new_pair() -> Result<(), Box<dyn std::error::Error>> {
+    fn set_local_existing_no_new_slot() -> Result<(), Box<dyn std::error::Error>> {
         let mut stack = VmStack::new();
-        stack.variables.push(VmPair {
-            key: "foobar".into(),
-            value: VmValue::Boolean(true),
-        });
-        let len = stack.variables.len();
-        stack.set_variable("foobar", VmValue::Null);
-        if stack.variables.len() != len {
-            Err("set_variable created a new VmPair in variables section of stack even \
-            though a matching pair existed.".into())
+        stack.locals.push(VmValue::Boolean(true));
+        let len = stack.locals.len();
+        stack.set_local(0, VmValue::Null);
+        if stack.locals.len() != len {
+            Err("set_local created a new slot in locals section of stack even \
+            though the slot existed.".into())
         } else {
             Ok(())
         }
@@ -445,31 +486,28 @@ mod tests {
 
     #[test]
     #[traced_test]
-    fn set_variable_existing_value_matches_expected() -> Result<(), Box<dyn std::error::Error>> {
+    fn set_local_existing_value_matches_expected() -> Result<(), Box<dyn std::error::Error>> {
         let mut stack = VmStack::new();
-        stack.variables.push(VmPair {
-            key: "foobar".into(),
-            value: VmValue::Boolean(true),
-        });
-        stack.set_variable("foobar", VmValue::Null);
-        match stack.get_variable("foobar") {
+        stack.locals.push(VmValue::Boolean(true));
+        stack.set_local(0, VmValue::Null);
+        match stack.get_local(0) {
             Some(v) => match v {
                 VmValue::Null => {}
-                _ => return Err("set_variable did not update the value to the expected value.".into()),
+                _ => return Err("set_local did not update the value to the expected value.".into()),
             },
-            _ => return Err("set_variable erased variable instead of setting it.".into())
+            _ => return Err("set_local erased local instead of setting it.".into())
         };
-        stack.set_variable("foobar", VmValue::Boolean(true));
-        match stack.get_variable("foobar") {
+        stack.set_local(0, VmValue::Boolean(true));
+        match stack.get_local(0) {
             Some(v) => match v {
-                VmValue::Boolean(flag) => if flag == true {
+                VmValue::Boolean(flag) => if *flag {
                     Ok(())
                 } else {
-                    Err("set_variable did update the value to the expected type but it does not contain the expected value.".into())
+                    Err("set_local did update the value to the expected type but it does not contain the expected value.".into())
                 },
-                _ => Err("set_variable did not update the value to the expected value.".into()),
+                _ => Err("set_local did not update the value to the expected value.".into()),
             },
-            _ => Err("set_variable erased variable instead of setting it.".into())
+            _ => Err("set_local erased local instead of setting it.".into())
         }
     }
-}
\ No newline at end of file
+}
@@ -1,8 +1,9 @@
 use std::borrow::{Borrow};
-use crate::machine::{Instruction, InstructionArg, OpCode, VmPair, VmStack, VmValue};
+use std::sync::Arc;
+use crate::machine::{Instruction, InstructionArg, OpCode, VmLineInfo, VmLocalInfo, VmStack, VmValue};
 use serde::{Serialize, Deserialize};
 use uuid::{Uuid};
-use crate::controllers::VmController;
+use crate::controllers::{PartialResult, VmController};
 
 #[derive(Serialize, Deserialize)]
 pub struct VmState {
@@ -11,6 +12,8 @@ pub struct VmState {
     function_list: Vec<String>,
     instructions: Vec<Instruction>,
     instruction_index: usize,
+    locals: Vec<VmLocalInfo>,
+    lines: Vec<VmLineInfo>,
 }
 
 pub enum VmExecResult {
@@ -30,7 +33,11 @@ impl std::fmt::Debug for VmState {
         for (index, it) in self.function_list.iter().enumerate() {
             writeln!(f, "    {:04}: {}", index, it)?;
         }
-        writeln!(f, "Instructions: {} (at {})", self.value_list.len(), self.instruction_index)?;
+        writeln!(f, "Locals: {}", self.locals.len())?;
+        for it in self.locals.iter() {
+            writeln!(f, "    {:04}: {} ({:04}..{:04})", it.slot, it.name, it.from, it.to)?;
+        }
+        writeln!(f, "Instructions: {} (at {})", self.instructions.len(), self.instruction_index)?;
         for (index, it) in self.instructions.iter().enumerate() {
             write!(f, "    {:04}: ", index)?;
             it.fmt(f)?;
@@ -40,6 +47,12 @@ impl std::fmt::Debug for VmState {
     }
 }
 
+impl Default for VmState {
+    fn default() -> Self {
+        VmState::new()
+    }
+}
+
 impl VmState
 {
     pub fn new() -> VmState {
@@ -49,6 +62,8 @@ impl VmState
             function_list: vec!(),
             value_list: vec!(),
             instruction_index: 0,
+            locals: vec!(),
+            lines: vec!(),
         };
     }
 
@@ -67,9 +82,66 @@ impl VmState
         }
         ret.unwrap() as u16
     }
+    pub fn values(&self) -> &[VmValue] {
+        &self.value_list
+    }
     pub fn push_instruction(&mut self, inst: Instruction) {
         self.instructions.push(inst);
     }
+    pub fn push_local_info(&mut self, info: VmLocalInfo) {
+        self.locals.push(info);
+    }
+    pub fn locals(&self) -> &[VmLocalInfo] {
+        &self.locals
+    }
+    /// Adds the position of the statement compiled from the instruction provided on, replacing
+    /// that of a statement not compiled to any instruction.
+    pub fn push_line_info(&mut self, info: VmLineInfo) {
+        if self.lines.last().is_some_and(|last| last.from == info.from) {
+            self.lines.pop();
+        }
+        self.lines.push(info);
+    }
+    pub fn lines(&self) -> &[VmLineInfo] {
+        &self.lines
+    }
+    /// The position of the statement the instruction executed last was compiled from, eg. to
+    /// locate the error of a step. As positions only exist in debug information, this is meant
+    /// for tooling.
+    pub fn last_position(&self) -> Option<&VmLineInfo> {
+        let instruction_index = self.instruction_index.checked_sub(1)?;
+        self.lines.iter().rev().find(|it| it.from <= instruction_index)
+    }
+    /// Finds the slot of the innermost local named as provided that is visible at the given
+    /// instruction index. As names only exist in debug information, this is meant for tooling.
+    pub fn find_local(&self, name: &str, instruction_index: usize) -> Option<u16> {
+        self.locals.iter()
+            .filter(|it| it.name == name && it.from <= instruction_index && instruction_index < it.to)
+            .max_by_key(|it| it.from)
+            .map(|it| it.slot)
+    }
+    /// Finds the name of the innermost local in the slot provided that is visible at the given
+    /// instruction index, being the counterpart of [VmState::find_local].
+    pub fn local_name(&self, slot: u16, instruction_index: usize) -> Option<&str> {
+        self.locals.iter()
+            .filter(|it| it.slot == slot && it.from <= instruction_index && instruction_index < it.to)
+            .max_by_key(|it| it.from)
+            .map(|it| it.name.as_str())
+    }
+    /// The name of the variable whose value is asserted by the AssertType at the index provided,
+    /// being stored to the variable after or loaded from it before the assertion.
+    fn asserted_variable(&self, instruction_index: usize) -> Option<&str> {
+        let next = self.instructions.get(instruction_index + 1)
+            .filter(|it| it.opcode == OpCode::StoreLocal)
+            .map(|it| (it, instruction_index + 1));
+        let previous = instruction_index.checked_sub(1)
+            .and_then(|index| self.instructions.get(index))
+            .filter(|it| it.opcode == OpCode::LoadLocal)
+            .map(|it| (it, instruction_index));
+        let (instruction, visible_at) = next.or(previous)?;
+        let slot = instruction.arg.clone().get_unsigned().ok()?;
+        self.local_name(slot, visible_at)
+    }
     pub fn instructions(&self) -> &[Instruction] {
         return self.instructions.borrow();
     }
@@ -82,6 +154,24 @@ impl VmState
     pub fn is_done(&self) -> bool {
         self.instructions.len() <= self.instruction_index
     }
+    /// Continues running at the instruction provided, eg. to run the statements appended to a
+    /// script after its earlier statements ran on the same stack.
+    pub fn resume_at(&mut self, instruction_index: usize) {
+        self.instruction_index = instruction_index;
+    }
+    /// Creates a new state running the bytecode of this one from the start, which stays
+    /// unaffected by the script being recompiled afterwards.
+    pub fn instantiate(&self) -> VmState {
+        VmState {
+            id: Uuid::new_v4(),
+            value_list: self.value_list.clone(),
+            function_list: self.function_list.clone(),
+            instructions: self.instructions.clone(),
+            instruction_index: 0,
+            locals: self.locals.clone(),
+            lines: self.lines.clone(),
+        }
+    }
     fn next_instruction(&mut self) -> Result<Instruction, &'static str> {
         if self.is_done()
         { return Err("End of instructions reached"); }
@@ -127,52 +217,56 @@ impl VmState
                 stack.push_value(VmValue::Null);
             }
             OpCode::PushEmptyArray => {
-                stack.push_value(VmValue::Array(vec![]));
+                stack.push_value(VmValue::array(vec![]));
             }
             OpCode::PushEmptyObject => {
-                stack.push_value(VmValue::Object(vec![]));
+                stack.push_value(VmValue::object(vec![]));
             }
-            OpCode::GetVariable => {
-                let key = stack.pop_string()?;
-                let variable = match stack.get_variable(key) {
-                    Some(v) => v,
-                    None => return Err("GetVariable found no variable with the name provided.".into()),
+            OpCode::LoadLocal => {
+                let slot = instruction.arg.get_unsigned()?;
+                let variable = match stack.get_local(slot) {
+                    Some(v) => v.clone(),
+                    None => return Err("LoadLocal found no local in the slot provided.".into()),
                 };
                 stack.push_value(variable);
             }
-            OpCode::GetVariableOfType => {
+            OpCode::StoreLocal => {
+                let slot = instruction.arg.get_unsigned()?;
+                let value = stack.pop_value()?;
+                stack.set_local(slot, value);
+            }
+            OpCode::AddLocal => {
+                let slot = instruction.arg.get_unsigned()?;
+                let value = stack.pop_value()?;
+                match stack.get_local_mut(slot) {
+                    Some(local) => local.add_assign(value)?,
+                    None => return Err("AddLocal found no local in the slot provided.".into()),
+                }
+            }
+            OpCode::AssertType => {
                 let expected_type = instruction.arg.get_vm_type()?;
-                let key = stack.pop_string()?;
-                let variable = match stack.get_variable(key) {
-                    Some(v) => v,
-                    None => return Err("GetVariableOfType found no variable with the name provided.".into()),
-                };
-                if !variable.is_type(expected_type) {
-                    return Err("GetVariableOfType found variable but the type was not matching the expected.".into());
+                let value = stack.pop_value()?;
+                if !value.is_type(expected_type.clone()) {
+                    return Err(match self.asserted_variable(self.instruction_index - 1) {
+                        Some(name) => format!("AssertType found a value not matching the expected type {:?} of variable '{}'.", expected_type, name).into(),
+                        None => format!("AssertType found a value not matching the expected type {:?}.", expected_type).into(),
+                    });
                 }
-                stack.push_value(variable);
+                stack.push_value(value);
             }
             OpCode::AppendArrayPush => {
                 let value = stack.pop_value()?;
                 let mut array = stack.pop_array()?;
-                array.push(value);
+                Arc::make_mut(&mut array).push(value);
                 stack.push_value(VmValue::Array(array));
             }
             OpCode::AppendPropertyPush => {
                 let value = stack.pop_value()?;
                 let key = stack.pop_string()?;
                 let mut object = stack.pop_object()?;
-                object.push(VmPair {
-                    key,
-                    value,
-                });
+                Arc::make_mut(&mut object).insert(Arc::unwrap_or_clone(key), value);
                 stack.push_value(VmValue::Object(object));
             }
-            OpCode::Assign => {
-                let key = stack.pop_string()?;
-                let value = stack.pop_value()?;
-                stack.set_variable(key, value);
-            }
             OpCode::Pop => { stack.pop_value()?; }
             OpCode::Jump => {
                 let i = instruction.arg.get_signed()?;
@@ -193,38 +287,76 @@ impl VmState
                 }
             }
             OpCode::JumpIterate => {
-                let index = stack.pop_number()?;
+                let index = stack.pop_integer()?;
                 let array_or_object = stack.pop_value()?;
-                match array_or_object {
-                    VmValue::Array(array) => {
-                        if array.len() > (index + 1.0) as usize {
-                            let el_opt = array.get(index as usize);
-                            match el_opt {
-                                None => {}
-                                Some(el) => {
-                                    let clone = el.clone();
-                                    stack.push_value(VmValue::Array(array));
-                                    stack.push_value(VmValue::Number(index + 1.0));
-                                    stack.push_value(clone);
-                                }
-                            }
-                        }
+                let element = match &array_or_object {
+                    VmValue::Array(array) => array.get(index as usize).cloned(),
+                    VmValue::Object(object) => object.get_index(index as usize).map(|pair| pair.value.clone()),
+                    _ => return Err("JumpIterate failed to pop either array or object from stack.".into()),
+                };
+                match element {
+                    Some(element) => {
+                        stack.push_value(array_or_object);
+                        stack.push_value(VmValue::Integer(index + 1));
+                        stack.push_value(element);
                     }
-                    VmValue::Object(object) => {
-                        if object.len() > (index + 1.0) as usize {
-                            let el_opt = object.get(index as usize);
-                            match el_opt {
-                                None => {}
-                                Some(el) => {
-                                    let clone = el.clone();
-                                    stack.push_value(VmValue::Object(object));
-                                    stack.push_value(VmValue::Number(index + 1.0));
-                                    stack.push_value(clone.value);
-                                }
-                            }
-                        }
+                    None => {
+                        let i = instruction.arg.get_signed()?;
+                        self.jump_instruction_index(i)?;
                     }
-                    _ => return Err("JumpIterate failed to pop either array or object from stack.".into()),
+                }
+            }
+            OpCode::JumpIteratePair => {
+                let index = stack.pop_integer()?;
+                let array_or_object = stack.pop_value()?;
+                let element = match &array_or_object {
+                    VmValue::Array(array) => array.get(index as usize)
+                        .map(|value| (VmValue::Integer(index), value.clone())),
+                    VmValue::Object(object) => object.get_index(index as usize)
+                        .map(|pair| (VmValue::string(pair.key.as_str()), pair.value.clone())),
+                    _ => return Err("JumpIteratePair failed to pop either array or object from stack.".into()),
+                };
+                match element {
+                    Some((key, value)) => {
+                        stack.push_value(array_or_object);
+                        stack.push_value(VmValue::Integer(index + 1));
+                        stack.push_value(key);
+                        stack.push_value(value);
+                    }
+                    None => {
+                        let i = instruction.arg.get_signed()?;
+                        self.jump_instruction_index(i)?;
+                    }
+                }
+            }
+            OpCode::JumpStream => {
+                let job_uuid = stack.pop_job()?;
+                match controller.take_partial_result_of(job_uuid)? {
+                    PartialResult::Value(value) => {
+                        stack.push_value(VmValue::Job(job_uuid));
+                        stack.push_value(value);
+                    }
+                    PartialResult::Finished => {
+                        let i = instruction.arg.get_signed()?;
+                        self.jump_instruction_index(i)?;
+                    }
+                    PartialResult::Pending => {
+                        // Rewind, so the jump is executed again once the execution resumes
+                        stack.push_value(VmValue::Job(job_uuid));
+                        self.instruction_index -= 1;
+                        controller.suspend_until_partial(self, job_uuid)?;
+                        return Ok(VmExecResult::Suspended);
+                    }
+                }
+            }
+            OpCode::Unpack => {
+                let count = instruction.arg.get_unsigned()?;
+                let array = stack.pop_array()?;
+                if array.len() != count as usize {
+                    return Err("Unpack failed because the array does not have as many elements as expected".into());
+                }
+                for value in array.iter() {
+                    stack.push_value(value.clone());
                 }
             }
             OpCode::Swap2 => {
@@ -235,7 +367,7 @@ impl VmState
             }
             OpCode::PrintToConsole => {
                 let value = stack.pop_value()?;
-                println!("{:?}", value);
+                controller.print(&value)?;
             }
 
             OpCode::Await => {
@@ -245,6 +377,9 @@ impl VmState
                 if let Some(value) = optional_value {
                     stack.push_value(value);
                 } else {
+                    // Rewind, so the await is executed again once the execution resumes
+                    stack.push_value(VmValue::Job(job_uuid));
+                    self.instruction_index -= 1;
                     controller.suspend_until_any(self, vec!(job_uuid))?;
                     return Ok(VmExecResult::Suspended);
                 }
@@ -266,14 +401,23 @@ impl VmState
                 controller.suspend_until_all(self, jobs)?;
             }
             OpCode::Call => {
+                // States compiled before calls took several arguments always pass one
+                let arguments_count = match instruction.arg {
+                    InstructionArg::Empty => 1,
+                    arg => arg.get_unsigned()?,
+                };
+                let mut arguments = Vec::with_capacity(arguments_count as usize);
+                for _ in 0..arguments_count {
+                    arguments.push(stack.pop_value()?);
+                }
+                arguments.reverse();
                 let function_name = stack.pop_string()?;
-                let value = stack.pop_value()?;
-                let job = controller.call(function_name, Some(value))?;
+                let job = controller.call(Arc::unwrap_or_clone(function_name), arguments)?;
                 stack.push_value(VmValue::Job(job));
             }
             OpCode::CallNoArg => {
                 let function_name = stack.pop_string()?;
-                let job = controller.call(function_name, None)?;
+                let job = controller.call(Arc::unwrap_or_clone(function_name), vec!())?;
                 stack.push_value(VmValue::Job(job));
             }
         };
@@ -282,7 +426,7 @@ impl VmState
 
     fn jump_instruction_index(&mut self, i: i16) -> Result<(), &'static str> {
         if i.is_negative() {
-            let new_index_opt = self.instruction_index.checked_sub((-i.abs()) as usize);
+            let new_index_opt = self.instruction_index.checked_sub(i.unsigned_abs() as usize);
             match new_index_opt {
                 Some(new_index) => { self.instruction_index = new_index; }
                 None => { return Err("Jump failed because the resulting index would be out of range"); }
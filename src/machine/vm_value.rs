@@ -1,6 +1,19 @@
-use crate::machine::{VmValue, VmValueType};
+use std::sync::Arc;
+use crate::machine::{VmObject, VmPair, VmValue, VmValueType};
 
 impl VmValue {
+    pub fn string<S: Into<String>>(string: S) -> VmValue {
+        VmValue::String(Arc::new(string.into()))
+    }
+    pub fn array(array: Vec<VmValue>) -> VmValue {
+        VmValue::Array(Arc::new(array))
+    }
+    pub fn object(object: Vec<VmPair>) -> VmValue {
+        VmValue::Object(Arc::new(VmObject::from(object)))
+    }
+    pub fn bytes(bytes: Vec<u8>) -> VmValue {
+        VmValue::Bytes(Arc::new(bytes))
+    }
     pub fn is_job(&self) -> bool {
         match self {
             VmValue::Null => false,
@@ -10,6 +23,9 @@ impl VmValue {
             VmValue::Boolean(_) => false,
             VmValue::Object(_) => false,
             VmValue::Job(_) => true,
+            VmValue::Integer(_) => false,
+            VmValue::Bytes(_) => false,
+            VmValue::Timestamp(_) => false,
         }
     }
     pub fn is_array(&self) -> bool {
@@ -21,9 +37,12 @@ impl VmValue {
             VmValue::Boolean(_) => false,
             VmValue::Object(_) => false,
             VmValue::Job(_) => false,
+            VmValue::Integer(_) => false,
+            VmValue::Bytes(_) => false,
+            VmValue::Timestamp(_) => false,
         }
     }
-    fn is_string(&self) -> bool {
+    pub fn is_string(&self) -> bool {
         match self {
             VmValue::Null => false,
             VmValue::String(_) => true,
@@ -32,6 +51,37 @@ impl VmValue {
             VmValue::Boolean(_) => false,
             VmValue::Object(_) => false,
             VmValue::Job(_) => false,
+            VmValue::Integer(_) => false,
+            VmValue::Bytes(_) => false,
+            VmValue::Timestamp(_) => false,
+        }
+    }
+    pub fn is_number(&self) -> bool {
+        match self {
+            VmValue::Null => false,
+            VmValue::String(_) => false,
+            VmValue::Number(_) => true,
+            VmValue::Array(_) => false,
+            VmValue::Boolean(_) => false,
+            VmValue::Object(_) => false,
+            VmValue::Job(_) => false,
+            VmValue::Integer(_) => false,
+            VmValue::Bytes(_) => false,
+            VmValue::Timestamp(_) => false,
+        }
+    }
+    pub fn is_integer(&self) -> bool {
+        match self {
+            VmValue::Null => false,
+            VmValue::String(_) => false,
+            VmValue::Number(_) => false,
+            VmValue::Array(_) => false,
+            VmValue::Boolean(_) => false,
+            VmValue::Object(_) => false,
+            VmValue::Job(_) => false,
+            VmValue::Integer(_) => true,
+            VmValue::Bytes(_) => false,
+            VmValue::Timestamp(_) => false,
         }
     }
     pub fn is_object(&self) -> bool {
@@ -43,6 +93,9 @@ impl VmValue {
             VmValue::Boolean(_) => false,
             VmValue::Object(_) => true,
             VmValue::Job(_) => false,
+            VmValue::Integer(_) => false,
+            VmValue::Bytes(_) => false,
+            VmValue::Timestamp(_) => false,
         }
     }
     pub fn is_null(&self) -> bool {
@@ -54,6 +107,37 @@ impl VmValue {
             VmValue::Boolean(_) => false,
             VmValue::Object(_) => false,
             VmValue::Job(_) => false,
+            VmValue::Integer(_) => false,
+            VmValue::Bytes(_) => false,
+            VmValue::Timestamp(_) => false,
+        }
+    }
+    pub fn is_bytes(&self) -> bool {
+        match self {
+            VmValue::Null => false,
+            VmValue::String(_) => false,
+            VmValue::Number(_) => false,
+            VmValue::Array(_) => false,
+            VmValue::Boolean(_) => false,
+            VmValue::Object(_) => false,
+            VmValue::Job(_) => false,
+            VmValue::Integer(_) => false,
+            VmValue::Bytes(_) => true,
+            VmValue::Timestamp(_) => false,
+        }
+    }
+    pub fn is_timestamp(&self) -> bool {
+        match self {
+            VmValue::Null => false,
+            VmValue::String(_) => false,
+            VmValue::Number(_) => false,
+            VmValue::Array(_) => false,
+            VmValue::Boolean(_) => false,
+            VmValue::Object(_) => false,
+            VmValue::Job(_) => false,
+            VmValue::Integer(_) => false,
+            VmValue::Bytes(_) => false,
+            VmValue::Timestamp(_) => true,
         }
     }
     pub fn is_array_of_jobs(&self) -> bool {
@@ -63,7 +147,7 @@ impl VmValue {
             VmValue::Number(_) => false,
             VmValue::Array(arr) => {
                 let mut all_jobs = true;
-                for arr_value in arr {
+                for arr_value in arr.iter() {
                     if !arr_value.is_job()
                     {
                         all_jobs = false;
@@ -75,6 +159,9 @@ impl VmValue {
             VmValue::Boolean(_) => false,
             VmValue::Object(_) => false,
             VmValue::Job(_) => false,
+            VmValue::Integer(_) => false,
+            VmValue::Bytes(_) => false,
+            VmValue::Timestamp(_) => false,
         }
     }
     pub fn is_boolean(&self) -> bool {
@@ -86,6 +173,9 @@ impl VmValue {
             VmValue::Boolean(_) => true,
             VmValue::Object(_) => false,
             VmValue::Job(_) => false,
+            VmValue::Integer(_) => false,
+            VmValue::Bytes(_) => false,
+            VmValue::Timestamp(_) => false,
         }
     }
     pub fn is_type(&self, value_type: VmValueType) -> bool {
@@ -94,6 +184,49 @@ impl VmValue {
             VmValueType::Array => self.is_array(),
             VmValueType::ArrayOfJobs => self.is_array_of_jobs(),
             VmValueType::Job => self.is_job(),
+            VmValueType::String => self.is_string(),
+            VmValueType::Number => self.is_number(),
+            VmValueType::Boolean => self.is_boolean(),
+            VmValueType::Object => self.is_object(),
+            VmValueType::Integer => self.is_integer(),
+            VmValueType::Bytes => self.is_bytes(),
+            VmValueType::Timestamp => self.is_timestamp(),
+        }
+    }
+    /// Adds the value provided to this value in place, following the promotion rules:
+    ///
+    /// | this      | other     | result                                          |
+    /// |-----------|-----------|-------------------------------------------------|
+    /// | Array     | any       | Array with other appended                       |
+    /// | String    | String    | String concatenation                            |
+    /// | Bytes     | Bytes     | Bytes concatenation                             |
+    /// | Integer   | Integer   | Integer, erroring on overflow                   |
+    /// | Integer   | Number    | Number                                          |
+    /// | Number    | Integer   | Number                                          |
+    /// | Number    | Number    | Number                                          |
+    /// | Timestamp | Integer   | Timestamp, other being microseconds             |
+    ///
+    /// Any other combination is an error, leaving this value untouched.
+    pub fn add_assign(&mut self, other: VmValue) -> Result<(), &'static str> {
+        match (self, other) {
+            (VmValue::Array(array), other) => Arc::make_mut(array).push(other),
+            (VmValue::String(string), VmValue::String(other)) => Arc::make_mut(string).push_str(&other),
+            (VmValue::Bytes(bytes), VmValue::Bytes(other)) => Arc::make_mut(bytes).extend_from_slice(&other),
+            (VmValue::Integer(integer), VmValue::Integer(other)) => {
+                *integer = integer.checked_add(other).ok_or("Integer addition overflowed")?;
+            }
+            (this @ VmValue::Integer(_), VmValue::Number(other)) => {
+                if let VmValue::Integer(integer) = this {
+                    *this = VmValue::Number(*integer as f64 + other);
+                }
+            }
+            (VmValue::Number(number), VmValue::Integer(other)) => *number += other as f64,
+            (VmValue::Number(number), VmValue::Number(other)) => *number += other,
+            (VmValue::Timestamp(timestamp), VmValue::Integer(other)) => {
+                *timestamp = timestamp.checked_add(other).ok_or("Timestamp addition overflowed")?;
+            }
+            _ => return Err("Values cannot be added as their types are incompatible"),
         }
+        Ok(())
     }
-}
\ No newline at end of file
+}